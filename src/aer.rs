@@ -0,0 +1,92 @@
+use bitflags::Flags;
+use serde::{Serialize, Serializer};
+
+use crate::{
+    bits::*,
+    dev::PciDevice,
+    error::{NvTrustError, Result},
+};
+
+/// The error registers of the Advanced Error Reporting capability of a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AerStatus {
+    #[serde(serialize_with = "names")]
+    pub uncorrectable: PciUncorrectableErrors,
+    #[serde(serialize_with = "names")]
+    pub uncorrectable_mask: PciUncorrectableErrors,
+    /// The uncorrectable errors that are fatal; the others are non-fatal.
+    #[serde(serialize_with = "names")]
+    pub severity: PciUncorrectableErrors,
+    #[serde(serialize_with = "names")]
+    pub correctable: PciCorrectableErrors,
+    #[serde(serialize_with = "names")]
+    pub correctable_mask: PciCorrectableErrors,
+}
+
+impl AerStatus {
+    /// Whether any error is logged.
+    pub fn has_errors(&self) -> bool {
+        !self.uncorrectable.is_empty() || !self.correctable.is_empty()
+    }
+
+    /// The uncorrectable errors that are fatal.
+    pub fn fatal(&self) -> PciUncorrectableErrors {
+        self.uncorrectable & self.severity
+    }
+}
+
+/// The names of the set bits, e.g., `["POISON_TLP"]`. Bits that are not defined are left out.
+pub fn bit_names<F: Flags>(flags: &F) -> Vec<&'static str> {
+    flags.iter_names().map(|(name, _)| name).collect()
+}
+
+fn names<F: Flags, S: Serializer>(
+    flags: &F,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(bit_names(flags))
+}
+
+impl PciDevice {
+    fn aer_offset(&self) -> Result<u64> {
+        self.find_ext_cap(PCI_EXT_CAP_ID_ERR)?
+            .ok_or(NvTrustError::NotSupported {
+                what: "AER".to_string(),
+                device: self.get_bdf().to_string(),
+                reason: "it has no Advanced Error Reporting capability".to_string(),
+            })
+    }
+
+    /// Read the AER status, mask and severity registers.
+    pub fn aer_status(&self) -> Result<AerStatus> {
+        let offset = self.aer_offset()?;
+        let read = |reg| self.read_config32(offset + reg);
+
+        Ok(AerStatus {
+            uncorrectable: PciUncorrectableErrors::from_bits_retain(read(PCI_ERR_UNCOR_STATUS)?),
+            uncorrectable_mask: PciUncorrectableErrors::from_bits_retain(read(PCI_ERR_UNCOR_MASK)?),
+            severity: PciUncorrectableErrors::from_bits_retain(read(PCI_ERR_UNCOR_SEVER)?),
+            correctable: PciCorrectableErrors::from_bits_retain(read(PCI_ERR_COR_STATUS)?),
+            correctable_mask: PciCorrectableErrors::from_bits_retain(read(PCI_ERR_COR_MASK)?),
+        })
+    }
+
+    /// Clear the logged errors, e.g., after recovering from a fault, and return them.
+    ///
+    /// The status bits are sticky across resets and cleared by writing 1s to them.
+    pub fn clear_aer(&self) -> Result<AerStatus> {
+        let offset = self.aer_offset()?;
+        let status = self.aer_status()?;
+
+        self.write_config32(offset + PCI_ERR_UNCOR_STATUS, status.uncorrectable.bits())?;
+        self.write_config32(offset + PCI_ERR_COR_STATUS, status.correctable.bits())?;
+        log::info!(
+            "Cleared the AER status of {}: uncorrectable 0x{:08x}, correctable 0x{:08x}",
+            self.get_bdf(),
+            status.uncorrectable.bits(),
+            status.correctable.bits()
+        );
+
+        Ok(status)
+    }
+}
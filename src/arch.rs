@@ -0,0 +1,263 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bits::*,
+    falcon::{self, Falcon},
+};
+
+/// The GPU architectures we support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Arch {
+    /// GA100, e.g., A100. Ampere has no CC mode and no FSP.
+    Ampere,
+    /// GH100, e.g., H100.
+    Hopper,
+    /// GB100, e.g., B200.
+    Blackwell,
+    /// LS10, the third-generation NVSwitch of HGX H100 baseboards.
+    NvSwitch,
+}
+
+/// The supported devices by device ID, with their marketing names as in pci.ids.
+pub const DEVICES: &[(u16, &str, Arch)] = &[
+    (NVIDIA_AMPERE_A100, "A100 SXM4 40GB", Arch::Ampere),
+    (0x20b2, "A100 SXM4 80GB", Arch::Ampere),
+    (0x20b3, "A100 SXM 64GB", Arch::Ampere),
+    (0x20b5, "A100 PCIe 80GB", Arch::Ampere),
+    (0x20f1, "A100 PCIe 40GB", Arch::Ampere),
+    (0x20f3, "A800 SXM4 80GB", Arch::Ampere),
+    (0x20f5, "A800 PCIe 80GB", Arch::Ampere),
+    (0x2321, "H100L 94GB", Arch::Hopper),
+    (0x2322, "H800 PCIe", Arch::Hopper),
+    (0x2324, "H800", Arch::Hopper),
+    (0x2330, "H100 SXM5 80GB", Arch::Hopper),
+    (NVIDIA_HOPPER_H100, "H100 PCIe", Arch::Hopper),
+    (0x2339, "H100 SXM5 94GB", Arch::Hopper),
+    (0x233a, "H800L 94GB", Arch::Hopper),
+    (0x233d, "H100 96GB", Arch::Hopper),
+    (0x2342, "GH200 120GB / 480GB", Arch::Hopper),
+    (NVIDIA_BLACKWELL_B200, "B200", Arch::Blackwell),
+    (0x2941, "HGX GB200", Arch::Blackwell),
+    (NVIDIA_NVSWITCH_LS10, "H100 NVSwitch", Arch::NvSwitch),
+];
+
+/// The FSP registers of an architecture.
+#[derive(Debug, Clone, Copy)]
+pub struct FspRegs {
+    pub falcon: Falcon,
+    /// Command queue head of channel `i` is at `queue_head + i * 8`.
+    pub queue_head: u64,
+    /// Command queue tail of channel `i` is at `queue_tail + i * 8`.
+    pub queue_tail: u64,
+    /// Message queue head of channel `i` is at `msgq_head + i * 8`.
+    pub msgq_head: u64,
+    /// Message queue tail of channel `i` is at `msgq_tail + i * 8`.
+    pub msgq_tail: u64,
+    /// The scratch registers the FSP reports its status in.
+    pub scratch_group_2: u64,
+}
+
+/// The PRAMIN window into VRAM.
+#[derive(Debug, Clone, Copy)]
+pub struct PraminRegs {
+    /// The register selecting the VRAM address of the window, in 64KB units.
+    pub window: u64,
+    /// The BAR0 offset of the window.
+    pub start: u64,
+    /// The length of the window.
+    pub len: u64,
+}
+
+/// The registers whose offsets differ between architectures.
+///
+/// Code that touches any of these goes through [`crate::dev::GpuObject::regs`] rather than the raw
+/// constants, so that adding an architecture only takes a new implementation here.
+pub trait ArchRegs: Sync {
+    /// The register reporting the active CC mode, or `None` if CC is not supported.
+    fn cc_mode(&self, is_vf: bool) -> Option<u64>;
+
+    /// The register, value and mask that tell that the GPU has finished booting.
+    fn boot_done(&self) -> (u64, u32, u32);
+
+    /// The FSP registers, or `None` if the architecture has no FSP.
+    fn fsp(&self) -> Option<FspRegs>;
+
+    fn pramin(&self) -> PraminRegs;
+
+    /// The low and high registers of the nanosecond timer.
+    fn timer(&self) -> (u64, u64);
+}
+
+const PRAMIN: PraminRegs = PraminRegs {
+    window: NV_HOST_MEM,
+    start: NV_PMC_PRAMIN_START,
+    len: NV_PMC_PRAMIN_LEN,
+};
+
+const FSP: FspRegs = FspRegs {
+    falcon: falcon::FSP,
+    queue_head: NV_FSP_QUEUE_HEAD,
+    queue_tail: NV_FSP_QUEUE_TAIL,
+    msgq_head: NV_FSP_MSGQ_HEAD,
+    msgq_tail: NV_FSP_MSGQ_TAIL,
+    scratch_group_2: NV_FSP_SCRATCH_GROUP_2,
+};
+
+pub struct AmpereRegs;
+
+impl ArchRegs for AmpereRegs {
+    fn cc_mode(&self, _is_vf: bool) -> Option<u64> {
+        None
+    }
+
+    fn boot_done(&self) -> (u64, u32, u32) {
+        (
+            NV_PGC6_AON_SECURE_SCRATCH_GROUP_05,
+            NV_PGC6_AON_SECURE_SCRATCH_GROUP_05_BOOT_DONE,
+            NV_PGC6_AON_SECURE_SCRATCH_GROUP_05_BOOT_DONE,
+        )
+    }
+
+    fn fsp(&self) -> Option<FspRegs> {
+        None
+    }
+
+    fn pramin(&self) -> PraminRegs {
+        PRAMIN
+    }
+
+    fn timer(&self) -> (u64, u64) {
+        (NV_PTIMER_TIME_0, NV_PTIMER_TIME_1)
+    }
+}
+
+pub struct HopperRegs;
+
+impl ArchRegs for HopperRegs {
+    fn cc_mode(&self, is_vf: bool) -> Option<u64> {
+        Some(if is_vf { NV_VF_CC_MODE } else { NV_CC_MODE })
+    }
+
+    fn boot_done(&self) -> (u64, u32, u32) {
+        (
+            NV_THERM_I2CS_SCRATCH,
+            NV_THERM_I2CS_SCRATCH_FSP_BOOT_COMPLETE,
+            0xffffffff,
+        )
+    }
+
+    fn fsp(&self) -> Option<FspRegs> {
+        Some(FSP)
+    }
+
+    fn pramin(&self) -> PraminRegs {
+        PRAMIN
+    }
+
+    fn timer(&self) -> (u64, u64) {
+        (NV_PTIMER_TIME_0, NV_PTIMER_TIME_1)
+    }
+}
+
+/// Blackwell keeps the Hopper layout for all the registers we touch; it gets its own type so that
+/// the offsets can diverge in one place once they do.
+pub struct BlackwellRegs;
+
+impl ArchRegs for BlackwellRegs {
+    fn cc_mode(&self, is_vf: bool) -> Option<u64> {
+        HopperRegs.cc_mode(is_vf)
+    }
+
+    fn boot_done(&self) -> (u64, u32, u32) {
+        HopperRegs.boot_done()
+    }
+
+    fn fsp(&self) -> Option<FspRegs> {
+        HopperRegs.fsp()
+    }
+
+    fn pramin(&self) -> PraminRegs {
+        HopperRegs.pramin()
+    }
+
+    fn timer(&self) -> (u64, u64) {
+        HopperRegs.timer()
+    }
+}
+
+/// LS10 shares the FSP and the secure scratch layout of GH100. An NVSwitch has no CC mode of its
+/// own, though: of the PRC knobs of its baseboard it takes only the PPCIe ones, see
+/// [`crate::fsp::PrcKnob::supported_on`].
+pub struct NvSwitchRegs;
+
+impl ArchRegs for NvSwitchRegs {
+    fn cc_mode(&self, _is_vf: bool) -> Option<u64> {
+        // NVSwitches have no SR-IOV.
+        HopperRegs.cc_mode(false)
+    }
+
+    fn boot_done(&self) -> (u64, u32, u32) {
+        HopperRegs.boot_done()
+    }
+
+    fn fsp(&self) -> Option<FspRegs> {
+        HopperRegs.fsp()
+    }
+
+    fn pramin(&self) -> PraminRegs {
+        HopperRegs.pramin()
+    }
+
+    fn timer(&self) -> (u64, u64) {
+        HopperRegs.timer()
+    }
+}
+
+impl Arch {
+    pub fn from_device_id(device_id: u16) -> Option<Self> {
+        DEVICES
+            .iter()
+            .find(|(id, _, _)| *id == device_id)
+            .map(|(_, _, arch)| *arch)
+    }
+
+    /// Get the architecture from the chip id in `NV_PMC_BOOT_0`, which also works for VFs whose
+    /// device IDs are not in [`DEVICES`].
+    pub fn from_boot0(boot0: u32) -> Option<Self> {
+        match (boot0 >> NV_PMC_BOOT_0_CHIP_ID_SHIFT) & NV_PMC_BOOT_0_CHIP_ID_MASK {
+            0x170 => Some(Arch::Ampere),
+            0x180 => Some(Arch::Hopper),
+            0x1a0 => Some(Arch::Blackwell),
+            _ => None,
+        }
+    }
+
+    pub fn regs(&self) -> &'static dyn ArchRegs {
+        match self {
+            Arch::Ampere => &AmpereRegs,
+            Arch::Hopper => &HopperRegs,
+            Arch::Blackwell => &BlackwellRegs,
+            Arch::NvSwitch => &NvSwitchRegs,
+        }
+    }
+
+    /// Whether the GPU supports CC, and hence has the FSP and the CC registers.
+    #[inline]
+    pub fn has_cc(&self) -> bool {
+        self.regs().cc_mode(false).is_some() && self.regs().fsp().is_some()
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arch::Ampere => write!(f, "ampere"),
+            Arch::Hopper => write!(f, "hopper"),
+            Arch::Blackwell => write!(f, "blackwell"),
+            Arch::NvSwitch => write!(f, "nvswitch"),
+        }
+    }
+}
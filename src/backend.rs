@@ -0,0 +1,820 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+use rustix::{fd::OwnedFd, fs, io};
+
+use crate::{
+    bits::*,
+    dev::{Bar, Mapping},
+    error::{NvTrustError, Result},
+    fsp, preflight, trace,
+    vfio::VfioDevice,
+};
+
+/// How a device is accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// The BARs are mapped from `/dev/mem` at their physical addresses.
+    DevMem,
+    /// The BARs are mapped from the sysfs `resource<N>` files.
+    Resource,
+    /// The device is bound to vfio-pci and accessed through its VFIO device file.
+    Vfio,
+    /// An in-memory device, see [`MockBackend`].
+    Mock,
+    /// A device replayed from an MMIO trace, see [`crate::trace::ReplayBackend`].
+    Replay,
+    /// Only the sysfs config space, e.g., of a PCIe port; the BARs are not mapped.
+    Config,
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendKind::DevMem => write!(f, "/dev/mem"),
+            BackendKind::Resource => write!(f, "sysfs resource"),
+            BackendKind::Vfio => write!(f, "vfio"),
+            BackendKind::Mock => write!(f, "mock"),
+            BackendKind::Replay => write!(f, "replay"),
+            BackendKind::Config => write!(f, "config space"),
+        }
+    }
+}
+
+/// The backend every device is opened with from now on, instead of picking one per device.
+static PREFERRED: OnceLock<BackendKind> = OnceLock::new();
+
+/// Open all the devices with the given backend from now on, e.g., as configured by the user.
+pub fn set_preferred(kind: BackendKind) -> Result<()> {
+    if !matches!(
+        kind,
+        BackendKind::DevMem | BackendKind::Resource | BackendKind::Vfio
+    ) {
+        return Err(NvTrustError::InvalidArgument(format!(
+            "cannot open devices with the {kind} backend"
+        )));
+    }
+
+    PREFERRED
+        .set(kind)
+        .map_err(|_| NvTrustError::InvalidArgument("the backend is already selected".to_string()))
+}
+
+/// The access to the config space, the BARs and the reset of a PCI device, which is all that
+/// [`crate::dev::PciDevice`] and [`crate::dev::GpuObject`] need of the hardware.
+///
+/// BAR offsets are dword aligned, and the callers check them against the BAR sizes.
+pub trait DeviceBackend: fmt::Debug + Send + Sync {
+    fn kind(&self) -> BackendKind;
+
+    /// The BARs of the device, as listed by [`parse_bars`].
+    fn bars(&self) -> Result<[Bar; 6]>;
+
+    /// Read the config space at `offset`, returning how many bytes were read, as only root can
+    /// read past the first 256 bytes.
+    fn read_config(&self, offset: u64, buf: &mut [u8]) -> Result<usize>;
+
+    fn write_config(&self, offset: u64, data: &[u8]) -> Result<()>;
+
+    fn read32(&self, bar: usize, offset: u64) -> Result<u32>;
+
+    fn write32(&self, bar: usize, offset: u64, value: u32) -> Result<()>;
+
+    /// Read `buf.len()` bytes, a multiple of 4, at `offset` of a BAR.
+    fn read_bar(&self, bar: usize, offset: u64, buf: &mut [u8]) -> Result<()> {
+        for (i, dword) in buf.chunks_exact_mut(4).enumerate() {
+            dword.copy_from_slice(&self.read32(bar, offset + i as u64 * 4)?.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    /// Reset the function, e.g., with an FLR.
+    fn reset(&self) -> Result<()>;
+}
+
+/// Open the backend for the device at the given sysfs path: the one set by [`set_preferred`], if
+/// any, or VFIO if it is bound to vfio-pci, or else `/dev/mem`, or the sysfs resource files when
+/// `/dev/mem` is denied, e.g., on a locked-down kernel.
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Arc<dyn DeviceBackend>> {
+    let path = path.as_ref();
+    let driver = std::fs::read_link(path.join("driver"))
+        .ok()
+        .and_then(|link| link.file_name().map(|s| s.to_string_lossy().to_string()));
+
+    let backend: Arc<dyn DeviceBackend> = if let Some(kind) = PREFERRED.get() {
+        match kind {
+            BackendKind::Vfio => Arc::new(VfioBackend::open(path)?),
+            BackendKind::Resource => Arc::new(ResourceBackend::open(path)?),
+            _ => Arc::new(DevMemBackend::open(path)?),
+        }
+    } else if driver.as_deref() == Some("vfio-pci") {
+        Arc::new(VfioBackend::open(path)?)
+    } else if let Err(io::Errno::PERM | io::Errno::ACCESS) =
+        fs::open(MEM_FILE, fs::OFlags::RDWR, fs::Mode::empty())
+    {
+        log::debug!(
+            "Mapping {} through sysfs: {}",
+            path.display(),
+            preflight::devmem_blocker()
+        );
+        Arc::new(ResourceBackend::open(path)?)
+    } else {
+        Arc::new(DevMemBackend::open(path)?)
+    };
+
+    let bdf = path.file_name().unwrap_or_default().to_string_lossy();
+    Ok(trace::wrap(&bdf, backend))
+}
+
+/// Parse the sysfs `resource` file of a device into its memory BARs, in order.
+pub fn parse_bars(resource: &str) -> Result<[Bar; 6]> {
+    let mut bars = [Bar::default(); 6];
+
+    let mut i = 0;
+    for (resource, bar) in resource.lines().take(6).enumerate() {
+        log::debug!("BAR {}: {}", i, bar);
+        let bar = bar
+            .split(" ")
+            .map(|s| s.replace("0x", "").to_string())
+            .collect::<Vec<_>>();
+        let addr = u64::from_str_radix(&bar[0], 16)?;
+        let end = u64::from_str_radix(&bar[1], 16)?;
+        let flags = u64::from_str_radix(&bar[2], 16)?;
+
+        // If the flag's bit 0 is set, then the BAR is not a MMIO BAR.
+        if flags & 0x1 == 0 {
+            // If the address is not 0, then the BAR is valid.
+            if addr != 0 {
+                let size = end - addr + 1;
+                let is_64 = (flags >> 1) & 0x3 == 0x2;
+
+                bars[i] = Bar {
+                    addr,
+                    size,
+                    is_64,
+                    resource,
+                };
+
+                i += 1;
+            }
+        }
+    }
+
+    Ok(bars)
+}
+
+/// The sysfs files of a device: the config space, the BARs and the reset.
+#[derive(Debug)]
+struct Sysfs {
+    path: PathBuf,
+    config: OwnedFd,
+}
+
+impl Sysfs {
+    fn open(path: &Path) -> Result<Self> {
+        // Only root can write the config space.
+        let config = path.join("config");
+        let config = fs::open(&config, fs::OFlags::RDWR, fs::Mode::empty())
+            .or_else(|_| fs::open(&config, fs::OFlags::RDONLY, fs::Mode::empty()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            config,
+        })
+    }
+
+    fn bdf(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    fn bars(&self) -> Result<[Bar; 6]> {
+        parse_bars(&std::fs::read_to_string(self.path.join("resource"))?)
+    }
+
+    fn read_config(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        Ok(io::pread(&self.config, buf, offset)?)
+    }
+
+    fn write_config(&self, offset: u64, data: &[u8]) -> Result<()> {
+        io::pwrite(&self.config, data, offset)?;
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        std::fs::write(self.path.join("reset"), b"1")?;
+        Ok(())
+    }
+}
+
+/// The backends that map the BARs: BAR0 is mapped whole on first use and kept, while the other
+/// BARs, which may span all of VRAM, are mapped a window at a time.
+trait Mmio: fmt::Debug + Send + Sync {
+    const KIND: BackendKind;
+
+    fn bars(&self) -> &[Bar; 6];
+
+    fn bar0(&self) -> &OnceLock<Mapping>;
+
+    /// Map `len` bytes at the page-aligned `offset` of a BAR.
+    fn map(&self, bar: usize, offset: u64, len: usize, writable: bool) -> Result<Mapping>;
+
+    fn read_config(&self, offset: u64, buf: &mut [u8]) -> Result<usize>;
+
+    fn write_config(&self, offset: u64, data: &[u8]) -> Result<()>;
+
+    fn reset(&self) -> Result<()>;
+
+    fn mapped_bar0(&self) -> Result<&Mapping> {
+        if let Some(mapping) = self.bar0().get() {
+            return Ok(mapping);
+        }
+
+        // Racing threads may both map it; the loser's mapping is dropped.
+        let mapping = self.map(0, 0, self.bars()[0].size as _, true)?;
+        let _ = self.bar0().set(mapping);
+        Ok(self.bar0().get().unwrap())
+    }
+
+    /// Map the pages covering `len` bytes at `offset` of a BAR, returning the mapping and where
+    /// the offset is in it.
+    fn window(
+        &self,
+        bar: usize,
+        offset: u64,
+        len: usize,
+        writable: bool,
+    ) -> Result<(Mapping, u64)> {
+        let base = offset & !(PAGE_SIZE - 1);
+        let delta = offset - base;
+
+        Ok((self.map(bar, base, delta as usize + len, writable)?, delta))
+    }
+}
+
+impl<T: Mmio> DeviceBackend for T {
+    fn kind(&self) -> BackendKind {
+        T::KIND
+    }
+
+    fn bars(&self) -> Result<[Bar; 6]> {
+        Ok(*Mmio::bars(self))
+    }
+
+    fn read_config(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        Mmio::read_config(self, offset, buf)
+    }
+
+    fn write_config(&self, offset: u64, data: &[u8]) -> Result<()> {
+        Mmio::write_config(self, offset, data)
+    }
+
+    fn read32(&self, bar: usize, offset: u64) -> Result<u32> {
+        if bar == 0 {
+            return Ok(self.mapped_bar0()?.read_volatile(offset));
+        }
+
+        let (mapping, delta) = self.window(bar, offset, 4, false)?;
+        Ok(mapping.read_volatile(delta))
+    }
+
+    fn write32(&self, bar: usize, offset: u64, value: u32) -> Result<()> {
+        if bar == 0 {
+            self.mapped_bar0()?.write_volatile(offset, value);
+            return Ok(());
+        }
+
+        let (mapping, delta) = self.window(bar, offset, 4, true)?;
+        mapping.write_volatile(delta, value);
+        Ok(())
+    }
+
+    fn read_bar(&self, bar: usize, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let window;
+        let (mapping, delta) = if bar == 0 {
+            (self.mapped_bar0()?, offset)
+        } else {
+            let (mapping, delta) = self.window(bar, offset, buf.len(), false)?;
+            window = mapping;
+            (&window, delta)
+        };
+
+        for (i, dword) in buf.chunks_exact_mut(4).enumerate() {
+            dword.copy_from_slice(
+                &mapping
+                    .read_volatile::<u32>(delta + i as u64 * 4)
+                    .to_le_bytes(),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        Mmio::reset(self)
+    }
+}
+
+/// Map the BARs from `/dev/mem` at their physical addresses.
+#[derive(Debug)]
+pub struct DevMemBackend {
+    sysfs: Sysfs,
+    bars: [Bar; 6],
+    bar0: OnceLock<Mapping>,
+}
+
+impl DevMemBackend {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let sysfs = Sysfs::open(path.as_ref())?;
+        let bars = sysfs.bars()?;
+
+        Ok(Self {
+            sysfs,
+            bars,
+            bar0: OnceLock::new(),
+        })
+    }
+}
+
+impl Mmio for DevMemBackend {
+    const KIND: BackendKind = BackendKind::DevMem;
+
+    fn bars(&self) -> &[Bar; 6] {
+        &self.bars
+    }
+
+    fn bar0(&self) -> &OnceLock<Mapping> {
+        &self.bar0
+    }
+
+    fn map(&self, bar: usize, offset: u64, len: usize, writable: bool) -> Result<Mapping> {
+        Mapping::new(
+            &format!("BAR{bar} of {}", self.sysfs.bdf()),
+            self.bars[bar].addr + offset,
+            len,
+            writable,
+        )
+    }
+
+    fn read_config(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.sysfs.read_config(offset, buf)
+    }
+
+    fn write_config(&self, offset: u64, data: &[u8]) -> Result<()> {
+        self.sysfs.write_config(offset, data)
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.sysfs.reset()
+    }
+}
+
+/// Access the config space and the reset of a device through sysfs, without mapping its BARs.
+#[derive(Debug)]
+pub struct ConfigBackend {
+    sysfs: Sysfs,
+}
+
+impl ConfigBackend {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self {
+            sysfs: Sysfs::open(path.as_ref())?,
+        })
+    }
+
+    fn unmapped(&self, bar: usize) -> NvTrustError {
+        NvTrustError::NotSupported {
+            what: format!("BAR{bar}"),
+            device: self.sysfs.bdf(),
+            reason: "only its config space is open".to_string(),
+        }
+    }
+}
+
+impl DeviceBackend for ConfigBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Config
+    }
+
+    fn bars(&self) -> Result<[Bar; 6]> {
+        self.sysfs.bars()
+    }
+
+    fn read_config(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.sysfs.read_config(offset, buf)
+    }
+
+    fn write_config(&self, offset: u64, data: &[u8]) -> Result<()> {
+        self.sysfs.write_config(offset, data)
+    }
+
+    fn read32(&self, bar: usize, _: u64) -> Result<u32> {
+        Err(self.unmapped(bar))
+    }
+
+    fn write32(&self, bar: usize, _: u64, _: u32) -> Result<()> {
+        Err(self.unmapped(bar))
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.sysfs.reset()
+    }
+}
+
+/// Map the BARs from the sysfs `resource<N>` files, which keeps working when `/dev/mem` is
+/// locked down.
+#[derive(Debug)]
+pub struct ResourceBackend {
+    sysfs: Sysfs,
+    bars: [Bar; 6],
+    bar0: OnceLock<Mapping>,
+}
+
+impl ResourceBackend {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let sysfs = Sysfs::open(path.as_ref())?;
+        let bars = sysfs.bars()?;
+
+        Ok(Self {
+            sysfs,
+            bars,
+            bar0: OnceLock::new(),
+        })
+    }
+}
+
+impl Mmio for ResourceBackend {
+    const KIND: BackendKind = BackendKind::Resource;
+
+    fn bars(&self) -> &[Bar; 6] {
+        &self.bars
+    }
+
+    fn bar0(&self) -> &OnceLock<Mapping> {
+        &self.bar0
+    }
+
+    fn map(&self, bar: usize, offset: u64, len: usize, writable: bool) -> Result<Mapping> {
+        let resource = self.bars[bar].resource;
+        Mapping::from_resource(
+            &format!("BAR{bar} of {}", self.sysfs.bdf()),
+            self.sysfs.path.join(format!("resource{resource}")),
+            offset,
+            len,
+            writable,
+        )
+    }
+
+    fn read_config(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.sysfs.read_config(offset, buf)
+    }
+
+    fn write_config(&self, offset: u64, data: &[u8]) -> Result<()> {
+        self.sysfs.write_config(offset, data)
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.sysfs.reset()
+    }
+}
+
+/// Access the device through vfio-pci, so that it sits behind the IOMMU.
+#[derive(Debug)]
+pub struct VfioBackend {
+    vfio: VfioDevice,
+    bars: [Bar; 6],
+    bar0: OnceLock<Mapping>,
+}
+
+impl VfioBackend {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let bars = parse_bars(&std::fs::read_to_string(path.join("resource"))?)?;
+
+        Ok(Self {
+            vfio: VfioDevice::open(path)?,
+            bars,
+            bar0: OnceLock::new(),
+        })
+    }
+}
+
+impl Mmio for VfioBackend {
+    const KIND: BackendKind = BackendKind::Vfio;
+
+    fn bars(&self) -> &[Bar; 6] {
+        &self.bars
+    }
+
+    fn bar0(&self) -> &OnceLock<Mapping> {
+        &self.bar0
+    }
+
+    fn map(&self, bar: usize, offset: u64, len: usize, writable: bool) -> Result<Mapping> {
+        // The VFIO regions of the BARs are indexed like the BAR registers.
+        self.vfio
+            .map_region(self.bars[bar].resource as _, offset, len, writable)
+    }
+
+    /// The config space is a region of the device file.
+    fn read_config(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.vfio
+            .read_region(VFIO_PCI_CONFIG_REGION_INDEX, offset, buf)?;
+        Ok(buf.len())
+    }
+
+    fn write_config(&self, offset: u64, data: &[u8]) -> Result<()> {
+        self.vfio
+            .write_region(VFIO_PCI_CONFIG_REGION_INDEX, offset, data)
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.vfio.reset()
+    }
+}
+
+/// An in-memory device for testing the GPU logic without hardware.
+///
+/// It is seeded from a fixture directory laid out like the sysfs directory of a device: a binary
+/// `config` file, a `resource` file and a `bar0` file of `<offset> <value>` lines, in hex, with
+/// `#` comments. Registers that are not in the fixture read as 0; writes are stored, read back and
+/// logged. The FSP can be played too, see [`MockBackend::emulate_fsp`].
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    config: Mutex<Vec<u8>>,
+    bars: [Bar; 6],
+    regs: Mutex<HashMap<(usize, u64), u32>>,
+    writes: Mutex<Vec<(usize, u64, u32)>>,
+    resets: AtomicUsize,
+    fsp: Mutex<MockFsp>,
+}
+
+/// Answers a message of the given NVDM type with the NVDM type and payload of the response.
+pub type FspHandler = Box<dyn Fn(u8, &[u32]) -> (u8, Vec<u32>) + Send + Sync>;
+
+/// The FSP of a Hopper GPU as [`MockBackend::emulate_fsp`] plays it: the EMEM ports, the queues
+/// and the MCTP packets, with the messages left to a handler.
+#[derive(Default)]
+struct MockFsp {
+    handler: Option<FspHandler>,
+    emem: HashMap<u32, u32>,
+    /// The messages received so far, as NVDM type and payload.
+    received: Vec<(u8, Vec<u32>)>,
+    /// The message being reassembled from its packets.
+    message: Vec<u32>,
+    /// The packets of the response the host has yet to consume, by channel.
+    responses: HashMap<u64, Vec<Vec<u32>>>,
+}
+
+impl fmt::Debug for MockFsp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockFsp")
+            .field("emulated", &self.handler.is_some())
+            .field("received", &self.received)
+            .finish()
+    }
+}
+
+impl MockBackend {
+    pub fn from_fixture<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        let config = std::fs::read(dir.join("config"))?;
+        let bars = parse_bars(&std::fs::read_to_string(dir.join("resource"))?)?;
+
+        let mut regs = HashMap::new();
+        let parse = |s: &str| u64::from_str_radix(s.trim_start_matches("0x"), 16);
+        for line in std::fs::read_to_string(dir.join("bar0"))?.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (offset, value) = line.split_once(char::is_whitespace).ok_or_else(|| {
+                NvTrustError::InvalidArgument(format!("invalid register line {line:?}"))
+            })?;
+            regs.insert((0, parse(offset)?), parse(value.trim())? as u32);
+        }
+
+        Ok(Self {
+            config: Mutex::new(config),
+            bars,
+            regs: Mutex::new(regs),
+            ..Default::default()
+        })
+    }
+
+    /// Set a register without logging the write, e.g., to play the part of the firmware.
+    pub fn set32(&self, bar: usize, offset: u64, value: u32) {
+        self.regs.lock().unwrap().insert((bar, offset), value);
+    }
+
+    /// The register writes so far, as `(bar, offset, value)`, in order.
+    pub fn writes(&self) -> Vec<(usize, u64, u32)> {
+        self.writes.lock().unwrap().clone()
+    }
+
+    /// The number of resets so far.
+    pub fn resets(&self) -> usize {
+        self.resets.load(Ordering::Relaxed)
+    }
+
+    /// Play the part of the FSP, answering every message the host sends with the handler.
+    pub fn emulate_fsp(&self, handler: FspHandler) {
+        self.fsp.lock().unwrap().handler = Some(handler);
+    }
+
+    /// The messages the emulated FSP received so far, as NVDM type and payload.
+    pub fn fsp_messages(&self) -> Vec<(u8, Vec<u32>)> {
+        self.fsp.lock().unwrap().received.clone()
+    }
+
+    fn get32(&self, offset: u64) -> u32 {
+        self.regs
+            .lock()
+            .unwrap()
+            .get(&(0, offset))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Get the EMEM channel of an FSP EMEM port data register.
+    fn fsp_emem_port(offset: u64) -> Option<u64> {
+        let port = offset.checked_sub(NV_FSP_EMEM_BASE + NV_FALCON_EMEMD)?;
+        (port % 8 == 0 && port < 8 * 8).then_some(port / 8)
+    }
+
+    /// Read or write the EMEM through the data register of a port, moving the offset of the port
+    /// on as the control register asks.
+    fn fsp_emem_access(&self, fsp: &mut MockFsp, channel: u64, value: Option<u32>) -> u32 {
+        let ememc = NV_FSP_EMEM_BASE + NV_FALCON_EMEMC + channel * 8;
+        let control = self.get32(ememc);
+        let offset = control & 0xfffc;
+
+        let data = match value {
+            Some(value) => {
+                fsp.emem.insert(offset, value);
+                value
+            }
+            None => fsp.emem.get(&offset).copied().unwrap_or(0),
+        };
+
+        let increment = match value {
+            Some(_) => NV_FALCON_EMEMC_AINCW,
+            None => NV_FALCON_EMEMC_AINCR,
+        };
+        if control & increment != 0 {
+            self.set32(0, ememc, (control & !0xfffc) | ((offset + 4) & 0xfffc));
+        }
+
+        data
+    }
+
+    /// Take the packet the host queued on the channel, and answer the message once it is whole.
+    fn fsp_command(&self, fsp: &mut MockFsp, channel: u64, head: u32) {
+        let tail = self.get32(NV_FSP_QUEUE_TAIL + channel * 8);
+        let packet = (head..=tail)
+            .step_by(4)
+            .map(|offset| fsp.emem.get(&offset).copied().unwrap_or(0))
+            .collect::<Vec<_>>();
+        // The queue drains once the FSP has taken the packet.
+        self.set32(0, NV_FSP_QUEUE_TAIL + channel * 8, head);
+
+        if packet[0] & MCTP_HEADER_SOM != 0 {
+            fsp.message.clear();
+        }
+        fsp.message.extend_from_slice(&packet[1..]);
+        if packet[0] & MCTP_HEADER_EOM == 0 || fsp.message.is_empty() {
+            return;
+        }
+
+        let nvdm_type = (fsp.message[0] >> MCTP_MSG_HEADER_NVDM_TYPE_SHIFT) as u8;
+        let payload = fsp.message[1..].to_vec();
+        let (response_type, response) = (fsp.handler.as_ref().unwrap())(nvdm_type, &payload);
+        fsp.received.push((nvdm_type, payload));
+
+        let mut message = vec![
+            MCTP_MSG_HEADER_TYPE_VENDOR_PCI
+                | ((NVIDIA_VENDOR_ID as u32) << MCTP_MSG_HEADER_VENDOR_ID_SHIFT)
+                | ((response_type as u32) << MCTP_MSG_HEADER_NVDM_TYPE_SHIFT),
+        ];
+        message.extend(response);
+        let mut packets = fsp::mctp_packets(&message);
+        packets.reverse();
+        fsp.responses.insert(channel, packets);
+        self.fsp_respond(fsp, channel);
+    }
+
+    /// Put the next packet of the response into the message queue of the channel.
+    fn fsp_respond(&self, fsp: &mut MockFsp, channel: u64) {
+        let Some(packet) = fsp.responses.get_mut(&channel).and_then(Vec::pop) else {
+            return;
+        };
+
+        let base = (channel * NV_FSP_EMEM_CHANNEL_SIZE) as u32;
+        for (i, dword) in packet.iter().enumerate() {
+            fsp.emem.insert(base + i as u32 * 4, *dword);
+        }
+        self.set32(0, NV_FSP_MSGQ_HEAD + channel * 8, base);
+        self.set32(
+            0,
+            NV_FSP_MSGQ_TAIL + channel * 8,
+            base + (packet.len() as u32 - 1) * 4,
+        );
+    }
+}
+
+impl DeviceBackend for MockBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Mock
+    }
+
+    fn bars(&self) -> Result<[Bar; 6]> {
+        Ok(self.bars)
+    }
+
+    fn read_config(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let config = self.config.lock().unwrap();
+        let data = config.get(offset as usize..).unwrap_or_default();
+        let len = buf.len().min(data.len());
+        buf[..len].copy_from_slice(&data[..len]);
+
+        Ok(len)
+    }
+
+    fn write_config(&self, offset: u64, data: &[u8]) -> Result<()> {
+        let mut config = self.config.lock().unwrap();
+        let end = offset as usize + data.len();
+        if end > config.len() {
+            config.resize(end, 0);
+        }
+        config[offset as usize..end].copy_from_slice(data);
+
+        Ok(())
+    }
+
+    fn read32(&self, bar: usize, offset: u64) -> Result<u32> {
+        let mut fsp = self.fsp.lock().unwrap();
+        if let (0, true, Some(channel)) = (bar, fsp.handler.is_some(), Self::fsp_emem_port(offset))
+        {
+            return Ok(self.fsp_emem_access(&mut fsp, channel, None));
+        }
+        drop(fsp);
+
+        Ok(self
+            .regs
+            .lock()
+            .unwrap()
+            .get(&(bar, offset))
+            .copied()
+            .unwrap_or(0))
+    }
+
+    fn write32(&self, bar: usize, offset: u64, value: u32) -> Result<()> {
+        self.writes.lock().unwrap().push((bar, offset, value));
+
+        let mut fsp = self.fsp.lock().unwrap();
+        if bar != 0 || fsp.handler.is_none() {
+            drop(fsp);
+            self.set32(bar, offset, value);
+            return Ok(());
+        }
+
+        if let Some(channel) = Self::fsp_emem_port(offset) {
+            self.fsp_emem_access(&mut fsp, channel, Some(value));
+            return Ok(());
+        }
+        self.set32(bar, offset, value);
+
+        let channel = |base: u64| {
+            offset
+                .checked_sub(base)
+                .filter(|port| port % 8 == 0 && *port < 8 * 8)
+                .map(|port| port / 8)
+        };
+        if let Some(channel) = channel(NV_FSP_QUEUE_HEAD) {
+            self.fsp_command(&mut fsp, channel, value);
+        } else if let Some(channel) = channel(NV_FSP_MSGQ_TAIL) {
+            // The host consumed a packet, so the next one can go.
+            if value == self.get32(NV_FSP_MSGQ_HEAD + channel * 8) {
+                self.fsp_respond(&mut fsp, channel);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.resets.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
@@ -1,35 +1,359 @@
+use std::{fmt, str::FromStr};
+
 use bitflags::bitflags;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 pub const NVIDIA_VENDOR_ID: u16 = 0x10de;
 pub const NVIDIA_HOPPER_H100: u16 = 0x2331;
+pub const NVIDIA_AMPERE_A100: u16 = 0x20b0;
+pub const NVIDIA_BLACKWELL_B200: u16 = 0x2901;
+pub const NVIDIA_NVSWITCH_LS10: u16 = 0x22a3;
 pub const MEM_FILE: &str = "/dev/mem";
 pub const IOMEM_FILE: &str = "/proc/iomem";
+pub const IOMMU_CLASS: &str = "/sys/class/iommu";
 pub const PCI_DEVICES: &str = "/sys/bus/pci/devices";
+pub const PCI_DRIVERS: &str = "/sys/bus/pci/drivers";
+/// The drivers that drive the registers of a GPU themselves, unlike, e.g., vfio-pci.
+pub const GPU_DRIVERS: [&str; 2] = ["nvidia", "nouveau"];
+/// The PCI classes of the GPUs and of the NVSwitches.
+pub const PCI_CLASS_DISPLAY_VGA: u32 = 0x030000;
+pub const PCI_CLASS_DISPLAY_3D: u32 = 0x030200;
+pub const PCI_CLASS_BRIDGE_OTHER: u32 = 0x068000;
+pub const VFIO_DIR: &str = "/dev/vfio";
+pub const VFIO_CONTAINER: &str = "/dev/vfio/vfio";
+pub const FABRIC_MANAGER_BIN: &str = "nv-fabricmanager";
+pub const FABRIC_MANAGER_CONFIG: &str = "/usr/share/nvidia/nvswitch/fabricmanager.cfg";
+pub const NVIDIA_MODULE_VERSION: &str = "/sys/module/nvidia/version";
+/// The per-GPU directories of the nvidia driver, by BDF.
+pub const NVIDIA_PROC_GPUS: &str = "/proc/driver/nvidia/gpus";
+pub const NVIDIA_DRIVER_VERSION: &str = "/proc/driver/nvidia/version";
+pub const KERNEL_RELEASE: &str = "/proc/sys/kernel/osrelease";
+pub const KVM_AMD_SEV_SNP: &str = "/sys/module/kvm_amd/parameters/sev_snp";
+pub const KVM_INTEL_TDX: &str = "/sys/module/kvm_intel/parameters/tdx";
+/// The oldest host kernel with the SEV-SNP and TDX host support that CC GPUs are validated with.
+pub const HOST_MIN_KERNEL: (u32, u32) = (6, 8);
+pub const KERNEL_CMDLINE: &str = "/proc/cmdline";
+pub const LOCKDOWN_FILE: &str = "/sys/kernel/security/lockdown";
+pub const SWIOTLB_NSLABS: &str = "/sys/kernel/debug/swiotlb/io_tlb_nslabs";
+/// The size of a SWIOTLB slab, `1 << IO_TLB_SHIFT`.
+pub const SWIOTLB_SLAB_SIZE: u64 = 1 << 11;
+/// The bounce buffer a CC guest should have per GPU: all DMA of a CC GPU goes through it.
+pub const SWIOTLB_MIN_PER_GPU: u64 = 512 << 20;
+pub const PERSISTENCE_STATE_FILE: &str = "/var/lib/nvtrust/persistence.state";
+pub const HISTORY_DIR: &str = "/var/lib/nvtrust/history";
+pub const ATTESTATION_STATUS_FILE: &str = "/run/nvtrust/status.json";
+/// The per-BDF lock files of the devices nvtrust operates on.
+pub const LOCK_DIR: &str = "/run/nvtrust";
+/// The pinned NVIDIA device identity root CA, as published in the NVIDIA nvtrust repository.
+pub const NVIDIA_DEVICE_ROOT_CA: &str = "/etc/nvtrust/nvidia_device_root.pem";
+/// The GPU attestation endpoint of the NVIDIA Remote Attestation Service (NRAS).
+pub const NRAS_GPU_URL: &str = "https://nras.attestation.nvidia.com/v3/attest/gpu";
+/// The NVIDIA RIM service, which serves the RIM of an ID at `<url>/<id>`.
+pub const RIM_SERVICE_URL: &str = "https://rim.attestation.nvidia.com/v1/rim";
+pub const RIM_CACHE_DIR: &str = "/var/lib/nvtrust/rim";
+pub const CONFIG_FILE: &str = "/etc/nvtrust/config.toml";
+/// The pinned root CA of the RIM signing certificates, as published in the NVIDIA nvtrust
+/// repository.
+pub const NVIDIA_RIM_ROOT_CA: &str = "/etc/nvtrust/nvidia_rim_root.pem";
+
+// The XML signature (XML-DSig) algorithms of the SWID RIMs.
+pub const XMLDSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+pub const XMLDSIG_ENVELOPED_SIGNATURE: &str =
+    "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+pub const XMLDSIG_EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+pub const XMLDSIG_EXC_C14N_WITH_COMMENTS: &str =
+    "http://www.w3.org/2001/10/xml-exc-c14n#WithComments";
+pub const XMLDSIG_SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+pub const XMLDSIG_SHA384: &str = "http://www.w3.org/2001/04/xmldsig-more#sha384";
+pub const XMLDSIG_SHA512: &str = "http://www.w3.org/2001/04/xmlenc#sha512";
+pub const XMLDSIG_ECDSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha256";
+pub const XMLDSIG_ECDSA_SHA384: &str = "http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha384";
+pub const XMLDSIG_RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+pub const XMLDSIG_RSA_SHA384: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha384";
+pub const XMLDSIG_RSA_SHA512: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha512";
+pub const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+/// The prefix of the claims read from the registers of the GPU rather than from its signed
+/// evidence.
+pub const UNVERIFIED_CLAIM_PREFIX: &str = "unverified.";
+/// The configfs-tsm interface of confidential VMs to their attestation reports.
+pub const TSM_REPORT_DIR: &str = "/sys/kernel/config/tsm/report";
+pub const TSM_REPORT_DATA_SIZE: usize = 64;
+pub const SEV_GUEST_DEVICE: &str = "/dev/sev-guest";
+pub const SNP_REPORT_DATA_SIZE: usize = 64;
+pub const SNP_REPORT_RESP_SIZE: usize = 4000;
+/// The offset of the report in the response to SNP_GET_REPORT, after the status and the size.
+pub const SNP_REPORT_OFFSET: usize = 0x20;
+pub const TDX_GUEST_DEVICE: &str = "/dev/tdx_guest";
+pub const TDX_REPORT_DATA_SIZE: usize = 64;
+pub const TDX_REPORT_SIZE: usize = 1024;
+/// The driver the kernel binds in Arm CCA realms, which also provides the configfs-tsm reports.
+pub const ARM_CCA_GUEST_DRIVER: &str = "/sys/bus/platform/drivers/arm-cca-guest";
+/// The EAT profile of the tokens we issue.
+pub const EAT_PROFILE: &str = "https://github.com/hiroki-chen/nvtrust-rs/eat/gpu";
 
 // Some important registers.
 pub const NV_PMC_BOOT_0: u64 = 0x0;
+/// Bits 28:20 of [`NV_PMC_BOOT_0`] hold the chip id, e.g., 0x170 for GA100 and 0x180 for GH100.
+pub const NV_PMC_BOOT_0_CHIP_ID_SHIFT: u32 = 20;
+pub const NV_PMC_BOOT_0_CHIP_ID_MASK: u32 = 0x1ff;
+pub const NV_PMC_BOOT_1: u64 = 0x4;
+/// The nanosecond timer; read TIME_1, TIME_0 and TIME_1 again to detect a carry.
+pub const NV_PTIMER_TIME_0: u64 = 0x9400;
+pub const NV_PTIMER_TIME_1: u64 = 0x9410;
+/// Bits 17:16 of [`NV_PMC_BOOT_1`] tell whether we are a physical GPU, a paravirtualized one or a
+/// SR-IOV virtual function.
+pub const NV_PMC_BOOT_1_VGPU_SHIFT: u32 = 16;
+pub const NV_PMC_BOOT_1_VGPU_MASK: u32 = 0x3;
+pub const NV_PMC_BOOT_1_VGPU_VF: u32 = 0x2;
 pub const NV_PMC_ENABLE: u64 = 0x200;
 pub const NV_PMC_DEVICE_ENABLE: u64 = 0x600;
 /// Specify the base address of the physical address of the GPU that the host wants to read
 /// through the MMIO space (see [`NV_PMC_PRAMIN_START`] - [`NV_PMC_PRAMIN_END`]).
+///
+/// The base is given in 64KB units.
 pub const NV_HOST_MEM: u64 = 0x1700;
+pub const NV_HOST_MEM_WINDOW_SHIFT: u64 = 16;
+pub const NV_HOST_MEM_WINDOW_MASK: u64 = (1 << NV_HOST_MEM_WINDOW_SHIFT) - 1;
+/// The PROM window, which exposes the VBIOS flash in BAR0 while the ROM shadow is disabled.
 pub const NV_PROM_DATA: u64 = 0x300000;
+pub const NV_PROM_SIZE: u64 = 1 << 20;
+/// The mirror of the PCI config register 0x50 in BAR0, whose bit 0 shadows the ROM from VRAM
+/// instead of reading it from the flash.
+pub const NV_PBUS_PCI_NV_20: u64 = 0x88050;
+/// The size of the BAR0 windows that the CC firewall opens or blocks as a whole.
+pub const NV_FIREWALL_WINDOW_SIZE: u64 = 0x1000;
+pub const NV_PBUS_PCI_NV_20_ROM_SHADOW_ENABLED: u32 = 0x1;
 pub const NV_CC_MODE: u64 = 0x1182cc;
+/// Set in [`NV_CC_MODE`] while the GPU or NVSwitch is in PPCIe mode.
+pub const NV_CC_MODE_PPCIE: u32 = 1 << 2;
 pub const NV_PMC_PRAMIN_LEN: u64 = 1 << 20;
 pub const NV_PMC_PRAMIN_START: u64 = 0x700000;
 pub const NV_PMC_PRAMIN_END: u64 = NV_PMC_PRAMIN_START + NV_PMC_PRAMIN_LEN;
 pub const NV_MMIO_ERROR_PREFIX: u64 = 0xbadf;
+/// The prefix of the error codes of the host interface, e.g., [`NvidiaMmioErrorCode::NONEXISTENT_REG`].
+pub const NV_MMIO_ERROR_PREFIX_HOST: u64 = 0xbad00;
+// Secure scratch registers, which stay readable when the BAR0 firewall is up.
+pub const NV_PGC6_AON_SECURE_SCRATCH_GROUP_05: u64 = 0x118234;
+/// The bits of [`NV_PGC6_AON_SECURE_SCRATCH_GROUP_05`] set once an Ampere GPU has finished booting.
+pub const NV_PGC6_AON_SECURE_SCRATCH_GROUP_05_BOOT_DONE: u32 = 0x3ff;
+/// Bits 7:0 of [`NV_PGC6_AON_SECURE_SCRATCH_GROUP_05`] hold the GFW boot progress, which reads
+/// [`NV_GFW_BOOT_PROGRESS_COMPLETED`] once devinit has finished.
+pub const NV_GFW_BOOT_PROGRESS_MASK: u32 = 0xff;
+pub const NV_GFW_BOOT_PROGRESS_COMPLETED: u32 = 0xff;
+pub const NV_PGC6_AON_SECURE_SCRATCH_GROUP_20: u64 = NV_CC_MODE;
+pub const NV_PBUS_SW_SCRATCH: u64 = 0x1580;
+/// Written to 0xff by the FSP once it has finished booting.
+pub const NV_THERM_I2CS_SCRATCH: u64 = 0x200bc;
+pub const NV_THERM_I2CS_SCRATCH_FSP_BOOT_COMPLETE: u32 = 0xff;
+/// The per-device identity (PDI) fuses, low and high dwords.
+pub const NV_FUSE_OPT_PDI_0: u64 = 0x820344;
+pub const NV_FUSE_OPT_PDI_1: u64 = 0x820348;
+// Registers visible to a virtual function, relative to the BAR0 of the VF.
+pub const NV_VIRTUAL_FUNCTION_PRIV: u64 = 0x30000;
+/// Mirror of the CC mode of the physical function.
+pub const NV_VF_CC_MODE: u64 = NV_VIRTUAL_FUNCTION_PRIV + 0xcc;
+/// Set to 0x1 by the host once the VF is ready to accept work.
+pub const NV_VF_READY: u64 = NV_VIRTUAL_FUNCTION_PRIV + 0xd0;
+/// The size of the protected (CPR) memory assigned to the VF, in MB.
+pub const NV_VF_PROTECTED_MEM_SIZE: u64 = NV_VIRTUAL_FUNCTION_PRIV + 0xd4;
 // Clocks.
 pub const NV_H100_CLOCK_LOW: u64 = 0xbb0080;
 pub const NV_H100_CLOCK_HIGH: u64 = 0xbb0084;
 
+// Falcon common registers, relative to the base of the falcon.
+pub const NV_FALCON_MAILBOX0: u64 = 0x40;
+pub const NV_FALCON_MAILBOX1: u64 = 0x44;
+/// The firmware version the falcon reports, e.g., that of GSP-RM.
+pub const NV_FALCON_OS: u64 = 0x80;
+pub const NV_FALCON_CPUCTL: u64 = 0x100;
+pub const NV_FALCON_CPUCTL_HALTED: u32 = 1 << 4;
+pub const NV_FALCON_CPUCTL_STOPPED: u32 = 1 << 5;
+/// The CPU control of the RISC-V core next to the falcon, which runs GSP-RM since Ampere.
+pub const NV_RISCV_CPUCTL: u64 = 0x1388;
+pub const NV_RISCV_CPUCTL_HALTED: u32 = 1 << 4;
+pub const NV_RISCV_CPUCTL_ACTIVE: u32 = 1 << 7;
+/// Hardware configuration of the falcon; bits 17:9 hold the DMEM size in 256-byte blocks.
+pub const NV_FALCON_HWCFG: u64 = 0x108;
+/// DMEM port control register, relative to the base of the falcon. Each port takes 8 bytes.
+pub const NV_FALCON_DMEMC: u64 = 0x1c0;
+/// DMEM port data register, relative to the base of the falcon. Each port takes 8 bytes.
+pub const NV_FALCON_DMEMD: u64 = 0x1c4;
+/// EMEM port control register, relative to the EMEM base of the falcon. Each port takes 8 bytes.
+pub const NV_FALCON_EMEMC: u64 = 0xac0;
+/// EMEM port data register, relative to the EMEM base of the falcon. Each port takes 8 bytes.
+pub const NV_FALCON_EMEMD: u64 = 0xac4;
+/// Auto-increment the EMEM/DMEM offset after each write to the data register.
+pub const NV_FALCON_EMEMC_AINCW: u32 = 1 << 24;
+/// Auto-increment the EMEM/DMEM offset after each read from the data register.
+pub const NV_FALCON_EMEMC_AINCR: u32 = 1 << 25;
+
+// GSP and SEC2.
+pub const NV_GSP_BASE: u64 = 0x110000;
+pub const NV_SEC2_BASE: u64 = 0x840000;
+/// The LibOS region descriptors of GSP-RM are 32 bytes each.
+pub const LIBOS_REGION_DESC_SIZE: usize = 32;
+/// The maximum number of LibOS region descriptors in the init arguments page.
+pub const LIBOS_MAX_REGIONS: usize = 4096 / LIBOS_REGION_DESC_SIZE;
+pub const LIBOS_REGION_LOC_SYSMEM: u8 = 1;
+pub const LIBOS_REGION_LOC_FB: u8 = 2;
+pub const PAGE_SIZE: u64 = 4096;
+
+// FSP (Falcon Security Processor) which owns the CC knobs on Hopper.
+pub const NV_FSP_BASE: u64 = 0x8f0000;
+pub const NV_FSP_EMEM_BASE: u64 = 0x8f2000;
+/// Command queue head of channel `i` is at `NV_FSP_QUEUE_HEAD + i * 8`.
+pub const NV_FSP_QUEUE_HEAD: u64 = 0x8f2c00;
+/// Command queue tail of channel `i` is at `NV_FSP_QUEUE_TAIL + i * 8`.
+pub const NV_FSP_QUEUE_TAIL: u64 = 0x8f2c04;
+/// Message queue head of channel `i` is at `NV_FSP_MSGQ_HEAD + i * 8`.
+pub const NV_FSP_MSGQ_HEAD: u64 = 0x8f2c80;
+/// Message queue tail of channel `i` is at `NV_FSP_MSGQ_TAIL + i * 8`.
+pub const NV_FSP_MSGQ_TAIL: u64 = 0x8f2c84;
+/// Scratch registers the FSP uses to report its boot and error status.
+pub const NV_FSP_SCRATCH_GROUP_2: u64 = 0x8f0320;
+pub const NV_FSP_SCRATCH_GROUP_2_LEN: u64 = 4;
+/// The channel we use to talk to the FSP.
+pub const NV_FSP_CHANNEL: u64 = 2;
+/// Each channel owns 1KB of EMEM, starting at `channel * NV_FSP_EMEM_CHANNEL_SIZE`.
+pub const NV_FSP_EMEM_CHANNEL_SIZE: u64 = 1024;
+/// How long we wait for the FSP to consume a command or to produce a response, in seconds.
+pub const NV_FSP_RPC_TIMEOUT: u64 = 5;
+/// How long the firmware may take to boot the GPU after a reset, in seconds.
+pub const NV_BOOT_TIMEOUT: u64 = 5;
+/// How often a register is read while polling it, in milliseconds.
+pub const NV_POLL_INTERVAL: u64 = 10;
+/// The seconds a device may take to come back after a reset.
+pub const PCI_RESET_TIMEOUT: u64 = 10;
+/// How long a DOE mailbox may take to respond, in seconds, as per the PCIe spec.
+pub const PCI_DOE_TIMEOUT: u64 = 1;
+
+// MCTP transport header.
+pub const MCTP_HEADER_SOM: u32 = 1 << 31;
+pub const MCTP_HEADER_EOM: u32 = 1 << 30;
+/// Bits 29:28 of the transport header count the packets of a message, modulo 4.
+pub const MCTP_HEADER_SEQ_SHIFT: u32 = 28;
+pub const MCTP_HEADER_SEQ_MASK: u32 = 0x3;
+// MCTP message header.
+pub const MCTP_MSG_HEADER_TYPE_VENDOR_PCI: u32 = 0x7e;
+pub const MCTP_MSG_HEADER_VENDOR_ID_SHIFT: u32 = 8;
+pub const MCTP_MSG_HEADER_NVDM_TYPE_SHIFT: u32 = 24;
+// NVDM message types.
+pub const NVDM_TYPE_PRC: u8 = 0x13;
+pub const NVDM_TYPE_FSP_RESPONSE: u8 = 0x15;
+// PRC sub-messages.
+pub const PRC_SUBMSG_ID_KNOB_READ: u32 = 0x0c;
+pub const PRC_SUBMSG_ID_KNOB_WRITE: u32 = 0x0d;
+
+pub const PCI_COMMAND: u64 = 0x04;
+pub const PCI_COMMAND_IO: u16 = 0x1;
+pub const PCI_COMMAND_MEMORY: u16 = 0x2;
+pub const PCI_COMMAND_MASTER: u16 = 0x4;
+pub const PCI_COMMAND_INTX_DISABLE: u16 = 0x400;
+pub const PCI_STATUS_CAP_LIST: u16 = 0x10;
+pub const PCI_STATUS_DETECTED_PARITY: u16 = 0x8000;
 pub const PCI_CFG_SPACE_SIZE: u64 = 256;
+/// The size of the standard type 0 header, which is what gets restored after a reset.
+pub const PCI_STD_HEADER_SIZEOF: u64 = 64;
 pub const PCI_CFG_SPACE_EXP_SIZE: u64 = 4096;
 pub const PCI_CAPABILITY_LIST: u64 = 0x34;
+pub const PCI_BRIDGE_CONTROL: u64 = 0x3e;
+pub const PCI_BRIDGE_CTL_BUS_RESET: u16 = 0x40;
+/// How long a secondary bus reset is held, in milliseconds; the spec asks for at least 1ms.
+pub const PCI_SBR_ASSERT_DELAY: u64 = 2;
+/// How long a device may take to answer config requests after a reset, in milliseconds.
+pub const PCI_RESET_READY_DELAY: u64 = 100;
 pub const PCI_CAP_ID_EXP: u64 = 0x10;
+pub const PCI_EXP_FLAGS: u64 = 0x02;
+pub const PCI_EXP_FLAGS_TYPE: u16 = 0xf0;
+pub const PCI_EXP_DEVCAP: u64 = 0x04;
+pub const PCI_EXP_DEVCAP_FLR: u32 = 1 << 28;
+pub const PCI_EXP_DEVCTL: u64 = 0x08;
+pub const PCI_EXP_DEVCTL_BCR_FLR: u16 = 0x8000;
+pub const PCI_EXP_DEVSTA: u64 = 0x0a;
+pub const PCI_EXP_DEVSTA_TRPND: u16 = 0x20;
+pub const PCI_EXP_SLTCTL: u64 = 0x18;
+pub const PCI_EXP_RTCTL: u64 = 0x1c;
+pub const PCI_EXP_DEVCTL2: u64 = 0x28;
+pub const PCI_EXP_LNKCTL2: u64 = 0x30;
+/// How long the transactions of a device may stay pending before an FLR, in milliseconds.
+pub const PCI_FLR_PENDING_TIMEOUT: u64 = 1000;
+pub const PCI_EXP_LNKCAP: u64 = 0x0c;
+pub const PCI_EXP_LNKCAP_SLS: u32 = 0xf;
+pub const PCI_EXP_LNKCAP_MLW: u32 = 0x3f0;
+pub const PCI_EXP_LNKCAP_ASPMS: u32 = 0xc00;
+pub const PCI_EXP_LNKCTL: u64 = 0x10;
+pub const PCI_EXP_LNKCTL_ASPM_L0S: u16 = 0x1;
+pub const PCI_EXP_LNKCTL_ASPM_L1: u16 = 0x2;
+/// Retrain the link; only for downstream ports.
+pub const PCI_EXP_LNKCTL_RL: u16 = 0x20;
+pub const PCI_EXP_LNKSTA: u64 = 0x12;
+pub const PCI_EXP_LNKSTA_CLS: u16 = 0xf;
+pub const PCI_EXP_LNKSTA_NLW: u16 = 0x3f0;
+/// Link training is in progress.
+pub const PCI_EXP_LNKSTA_LT: u16 = 0x800;
+/// The data link layer is up.
+pub const PCI_EXP_LNKSTA_DLLLA: u16 = 0x2000;
+/// How long link training may take, in seconds.
+pub const PCI_LINK_TRAIN_TIMEOUT: u64 = 1;
 pub const PCI_CAP_ID_PM: u64 = 0x01;
+pub const PCI_PM_CTRL: u64 = 0x4;
+pub const PCI_PM_CTRL_STATE_MASK: u16 = 0x3;
+/// The device keeps its state going from D3hot to D0, rather than being reset.
+pub const PCI_PM_CTRL_NO_SOFT_RESET: u16 = 0x8;
+/// The delay after a transition to or from D3hot, in milliseconds.
+pub const PCI_PM_D3HOT_WAIT: u64 = 10;
+pub const PCI_CAP_ID_MSI: u64 = 0x05;
+pub const PCI_MSI_FLAGS: u64 = 0x2;
+pub const PCI_MSI_FLAGS_ENABLE: u16 = 0x1;
+pub const PCI_MSI_FLAGS_64BIT: u16 = 0x80;
+pub const PCI_MSI_FLAGS_MASKBIT: u16 = 0x100;
+pub const PCI_MSI_ADDRESS_LO: u64 = 0x4;
+/// The message data, with a 32-bit and a 64-bit message address.
+pub const PCI_MSI_DATA_32: u64 = 0x8;
+pub const PCI_MSI_DATA_64: u64 = 0xc;
+pub const PCI_CAP_ID_VNDR: u64 = 0x09;
+pub const PCI_CAP_ID_MSIX: u64 = 0x11;
+pub const PCI_MSIX_FLAGS: u64 = 0x2;
+pub const PCI_MSIX_FLAGS_QSIZE: u16 = 0x7ff;
+pub const PCI_MSIX_FLAGS_MASKALL: u16 = 1 << 14;
+pub const PCI_MSIX_FLAGS_ENABLE: u16 = 1 << 15;
+pub const PCI_MSIX_TABLE: u64 = 0x4;
+pub const PCI_MSIX_PBA: u64 = 0x8;
+/// The BAR of the MSI-X table or PBA; the rest of the register is the offset in it.
+pub const PCI_MSIX_BIR: u32 = 0x7;
+/// An MSI-X table entry: the message address (low, high), data and vector control dwords.
+pub const PCI_MSIX_ENTRY_SIZE: u64 = 16;
+pub const PCI_MSIX_ENTRY_VECTOR_CTRL: u64 = 0xc;
+pub const PCI_MSIX_ENTRY_CTRL_MASKBIT: u32 = 0x1;
 pub const PCI_EXT_CAP_ID_ERR: u64 = 0x01;
+pub const PCI_ERR_UNCOR_STATUS: u64 = 0x04;
+pub const PCI_ERR_UNCOR_MASK: u64 = 0x08;
+pub const PCI_ERR_UNCOR_SEVER: u64 = 0x0c;
+pub const PCI_ERR_COR_STATUS: u64 = 0x10;
+pub const PCI_ERR_COR_MASK: u64 = 0x14;
+/// Advanced Error Capabilities and Control, e.g., whether ECRC is generated and checked.
+pub const PCI_ERR_CAP: u64 = 0x18;
+pub const PCI_EXT_CAP_ID_DSN: u64 = 0x03;
 pub const PCI_EXP_CAP_ID_SRIOV: u64 = 0x10;
+pub const PCI_EXT_CAP_ID_REBAR: u64 = 0x15;
+/// The capability and control registers of the first resizable BAR; the others follow, 8 bytes
+/// apart.
+pub const PCI_REBAR_CAP: u64 = 0x4;
+pub const PCI_REBAR_CTRL: u64 = 0x8;
+pub const PCI_REBAR_CTRL_BAR_IDX: u32 = 0x7;
+pub const PCI_REBAR_CTRL_NBAR_MASK: u32 = 0xe0;
+pub const PCI_REBAR_CTRL_BAR_SIZE: u32 = 0x3f00;
+pub const PCI_EXT_CAP_ID_DVSEC: u64 = 0x23;
+pub const PCI_EXT_CAP_ID_DOE: u64 = 0x2e;
+pub const PCI_EXT_CAP_ID_IDE: u64 = 0x30;
+pub const PCI_EXT_CAP_ID_ACS: u64 = 0x0d;
+pub const PCI_ACS_CTRL: u64 = 0x6;
+pub const PCI_ACS_SV: u16 = 0x01;
+pub const PCI_ACS_RR: u16 = 0x04;
+pub const PCI_ACS_CR: u16 = 0x08;
+pub const PCI_ACS_UF: u16 = 0x10;
+/// Source validation, P2P request and completion redirect, and upstream forwarding.
+pub const ACS_CTRL_ISOLATION: u16 = 0x1d;
 pub const CAP_ID_MASK: u64 = 0xff;
 
 bitflags! {
@@ -59,7 +383,7 @@ bitflags! {
 
 bitflags! {
   /// The Confidential Computing (CC) mode of the GPU.
-  #[derive(Debug)]
+  #[derive(Debug, Clone, Copy, PartialEq, Eq)]
   pub struct CcMode : u8 {
       /// The CC mode is off.
       const CC_MODE_OFF = 0x0;
@@ -70,8 +394,50 @@ bitflags! {
   }
 }
 
+impl fmt::Display for CcMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            CcMode::CC_MODE_OFF => write!(f, "off"),
+            CcMode::CC_MODE_ON => write!(f, "on"),
+            CcMode::CC_MODE_DEV_TOOLS => write!(f, "devtools"),
+            _ => write!(f, "unknown(0x{:x})", self.bits()),
+        }
+    }
+}
+
+impl FromStr for CcMode {
+    type Err = crate::error::NvTrustError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(CcMode::CC_MODE_OFF),
+            "on" => Ok(CcMode::CC_MODE_ON),
+            "devtools" => Ok(CcMode::CC_MODE_DEV_TOOLS),
+            _ => Err(crate::error::NvTrustError::InvalidArgument(format!(
+                "unknown CC mode {s}"
+            ))),
+        }
+    }
+}
+
+/// CC modes are (de)serialized by name, as `off`, `on` and `devtools`.
+impl Serialize for CcMode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for CcMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
 bitflags! {
-    /// Pci Uncorrectable Errors
+    /// The bits of the AER Uncorrectable Error Status, Mask and Severity registers.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct PciUncorrectableErrors: u32 {
         /// Undefined error.
         const UND = 0x00000001;
@@ -84,8 +450,158 @@ bitflags! {
         /// Flow control protocol.
         const FCP = 0x00002000;
         /// Completion timeout.
-        const COMP_TIME = 0x0004000;
+        const COMP_TIME = 0x00004000;
+        /// Completer abort.
+        const COMP_ABORT = 0x00008000;
+        /// Unexpected completion.
+        const UNX_COMP = 0x00010000;
+        /// Receiver overflow.
+        const RX_OVER = 0x00020000;
+        /// Malformed TLP.
+        const MALF_TLP = 0x00040000;
+        /// ECRC error.
+        const ECRC = 0x00080000;
+        /// Unsupported request.
+        const UNSUP = 0x00100000;
+        /// ACS violation.
+        const ACSV = 0x00200000;
+        /// Uncorrectable internal error.
+        const INTN = 0x00400000;
+        /// MC blocked TLP.
+        const MCBTLP = 0x00800000;
+        /// AtomicOp egress blocked.
+        const ATOMEG = 0x01000000;
+        /// TLP prefix blocked.
+        const TLPPRE = 0x02000000;
+    }
+}
 
-        // todo.
+bitflags! {
+    /// The bits of the AER Correctable Error Status and Mask registers.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PciCorrectableErrors: u32 {
+        /// Receiver error.
+        const RCVR = 0x00000001;
+        /// Bad TLP.
+        const BAD_TLP = 0x00000040;
+        /// Bad DLLP.
+        const BAD_DLLP = 0x00000080;
+        /// REPLAY_NUM rollover.
+        const REP_ROLL = 0x00000100;
+        /// Replay timer timeout.
+        const REP_TIMER = 0x00001000;
+        /// Advisory non-fatal error.
+        const ADV_NFAT = 0x00002000;
+        /// Corrected internal error.
+        const INTERNAL = 0x00004000;
+        /// Header log overflow.
+        const LOG_OVER = 0x00008000;
     }
 }
+
+// PCI expansion ROM layout, see the PCI Firmware Specification 3.0.
+pub const PCI_ROM_SIGNATURE: u16 = 0xaa55;
+/// The offset of the pointer to the PCI data structure in a ROM image.
+pub const PCI_ROM_PCIR_OFFSET: usize = 0x18;
+pub const PCI_ROM_PCIR_SIGNATURE: &[u8; 4] = b"PCIR";
+/// The offset of the image length, in 512-byte units, in the PCI data structure.
+pub const PCI_ROM_PCIR_IMAGE_LEN: usize = 0x10;
+/// The offset of the indicator byte, whose bit 7 marks the last image, in the PCI data structure.
+pub const PCI_ROM_PCIR_INDICATOR: usize = 0x15;
+pub const PCI_ROM_PCIR_LAST_IMAGE: u8 = 0x80;
+/// The offset of the code type in the PCI data structure.
+pub const PCI_ROM_PCIR_CODE_TYPE: usize = 0x14;
+/// The code type seen on the images that seem to hold the VBIOS certificate chain and signatures;
+/// it is not documented.
+pub const PCI_ROM_CODE_TYPE_NV_CERT: u8 = 0xe0;
+
+// The BIOS Information Table (BIT) of the VBIOS, which points to all the other tables.
+pub const BIT_SIGNATURE: &[u8; 6] = b"\xff\xb8BIT\0";
+/// The offsets of the header size, token size and token count in the BIT header.
+pub const BIT_HEADER_SIZE: usize = 8;
+pub const BIT_HEADER_TOKEN_SIZE: usize = 9;
+pub const BIT_HEADER_TOKEN_COUNT: usize = 10;
+/// The BIOS data token, whose data starts with the VBIOS version dword and the OEM version byte.
+pub const BIT_TOKEN_BIOSDATA: u8 = b'B';
+/// The internal-use token, whose data holds the board ID at [`BIT_INTERNAL_USE_BOARD_ID`].
+pub const BIT_TOKEN_INTERNAL_USE: u8 = b'i';
+pub const BIT_INTERNAL_USE_BOARD_ID: usize = 0xc;
+
+// The Data Object Exchange (DOE) mailbox, relative to its extended capability, see PCIe 6.0
+// section 7.9.24.
+pub const PCI_DOE_CAP: u64 = 0x04;
+pub const PCI_DOE_CTRL: u64 = 0x08;
+pub const PCI_DOE_CTRL_ABORT: u32 = 1 << 0;
+pub const PCI_DOE_CTRL_GO: u32 = 1 << 31;
+pub const PCI_DOE_STATUS: u64 = 0x0c;
+pub const PCI_DOE_STATUS_BUSY: u32 = 1 << 0;
+pub const PCI_DOE_STATUS_ERROR: u32 = 1 << 2;
+pub const PCI_DOE_STATUS_DATA_OBJECT_READY: u32 = 1 << 31;
+pub const PCI_DOE_WRITE: u64 = 0x10;
+pub const PCI_DOE_READ: u64 = 0x14;
+/// The length field of the second data object header, in dwords including the headers.
+pub const PCI_DOE_DATA_OBJECT_LENGTH_MASK: u32 = 0x3ffff;
+/// The largest data object, 2^18 dwords, which is encoded as a length of 0.
+pub const PCI_DOE_MAX_LENGTH: usize = 1 << 18;
+pub const PCI_VENDOR_ID_PCI_SIG: u16 = 0x0001;
+pub const PCI_DOE_PROTOCOL_DISCOVERY: u8 = 0x00;
+pub const PCI_DOE_PROTOCOL_CMA_SPDM: u8 = 0x01;
+pub const PCI_DOE_PROTOCOL_SECURED_CMA_SPDM: u8 = 0x02;
+
+// SPDM (DSP0274) request and response codes.
+pub const SPDM_GET_MEASUREMENTS: u8 = 0xe0;
+pub const SPDM_GET_DIGESTS: u8 = 0x81;
+pub const SPDM_GET_CERTIFICATE: u8 = 0x82;
+pub const SPDM_CHALLENGE: u8 = 0x83;
+pub const SPDM_GET_VERSION: u8 = 0x84;
+pub const SPDM_GET_CAPABILITIES: u8 = 0xe1;
+pub const SPDM_NEGOTIATE_ALGORITHMS: u8 = 0xe3;
+pub const SPDM_MEASUREMENTS: u8 = 0x60;
+pub const SPDM_DIGESTS: u8 = 0x01;
+pub const SPDM_CERTIFICATE: u8 = 0x02;
+pub const SPDM_CHALLENGE_AUTH: u8 = 0x03;
+pub const SPDM_VERSION: u8 = 0x04;
+pub const SPDM_CAPABILITIES: u8 = 0x61;
+pub const SPDM_ALGORITHMS: u8 = 0x63;
+pub const SPDM_ERROR: u8 = 0x7f;
+/// GET_VERSION is always sent as version 1.0.
+pub const SPDM_VERSION_10: u8 = 0x10;
+pub const SPDM_VERSION_11: u8 = 0x11;
+pub const SPDM_VERSION_12: u8 = 0x12;
+pub const SPDM_NONCE_SIZE: usize = 32;
+/// Bit 0 of param1 of GET_MEASUREMENTS asks for a signed response.
+pub const SPDM_MEASUREMENTS_SIGNED: u8 = 0x1;
+/// The measurement operation asking for all the measurement blocks.
+pub const SPDM_MEASUREMENTS_ALL: u8 = 0xff;
+/// The size of the signing context of SPDM 1.2 signatures, which is zero-padded at the front.
+pub const SPDM_SIGNING_CONTEXT_SIZE: usize = 36;
+pub const SPDM_MEASUREMENTS_SIGNING_CONTEXT: &str = "responder-measurements signing";
+/// The bytes of the certificate chain requested per GET_CERTIFICATE.
+pub const SPDM_CERT_PORTION: u16 = 0x400;
+// The requester capabilities: CERT_CAP, CHAL_CAP, ENCRYPT_CAP, MAC_CAP, KEY_EX_CAP.
+pub const SPDM_REQUESTER_CAPS: u32 = (1 << 1) | (1 << 2) | (1 << 6) | (1 << 7) | (1 << 9);
+pub const SPDM_MEASUREMENT_SPEC_DMTF: u8 = 0x01;
+pub const SPDM_ASYM_ECDSA_P256: u32 = 1 << 4;
+pub const SPDM_ASYM_ECDSA_P384: u32 = 1 << 7;
+pub const SPDM_HASH_SHA_256: u32 = 1 << 0;
+pub const SPDM_HASH_SHA_384: u32 = 1 << 1;
+pub const SPDM_HASH_SHA_512: u32 = 1 << 2;
+
+// The fields of the opaque data of the MEASUREMENTS of NVIDIA GPUs.
+pub const NV_OPAQUE_FIELD_DRIVER_VERSION: u16 = 3;
+pub const NV_OPAQUE_FIELD_VBIOS_VERSION: u16 = 6;
+pub const NV_OPAQUE_FIELD_CHIP_SKU: u16 = 15;
+pub const NV_OPAQUE_FIELD_PROJECT: u16 = 17;
+pub const NV_OPAQUE_FIELD_PROJECT_SKU: u16 = 18;
+
+/// The VFIO API of `linux/vfio.h`.
+pub const VFIO_TYPE: u8 = b';';
+pub const VFIO_BASE: u8 = 100;
+pub const VFIO_API_VERSION: i32 = 0;
+pub const VFIO_TYPE1_IOMMU: i32 = 1;
+pub const VFIO_TYPE1V2_IOMMU: i32 = 3;
+pub const VFIO_GROUP_FLAGS_VIABLE: u32 = 1 << 0;
+pub const VFIO_REGION_INFO_FLAG_READ: u32 = 1 << 0;
+pub const VFIO_REGION_INFO_FLAG_WRITE: u32 = 1 << 1;
+pub const VFIO_REGION_INFO_FLAG_MMAP: u32 = 1 << 2;
+pub const VFIO_PCI_CONFIG_REGION_INDEX: u32 = 7;
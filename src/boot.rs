@@ -0,0 +1,65 @@
+use serde::Serialize;
+
+use crate::{
+    bits::*,
+    dev::{is_mmio_error, GpuObject},
+    error::Result,
+};
+
+/// How far the firmware got in booting the GPU.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BootStatus {
+    /// The GFW boot progress, which reads 0xff once devinit has finished.
+    pub gfw_progress: u32,
+    /// The FSP boot status, which reads 0xff once secure boot has finished, if the GPU has an
+    /// FSP.
+    pub fsp_status: Option<u32>,
+    /// The FSP error and status scratch registers, which are not all zero if the FSP failed.
+    pub fsp_errors: Vec<u32>,
+    /// Whether the GPU has finished booting, as [`GpuObject::wait_for_boot`] waits for.
+    pub complete: bool,
+}
+
+impl BootStatus {
+    /// Whether devinit has finished.
+    pub fn devinit_done(&self) -> bool {
+        self.gfw_progress & NV_GFW_BOOT_PROGRESS_MASK == NV_GFW_BOOT_PROGRESS_COMPLETED
+    }
+
+    /// Whether the FSP has finished secure boot, or `None` if the GPU has no FSP.
+    pub fn secure_boot_done(&self) -> Option<bool> {
+        self.fsp_status
+            .map(|status| status == NV_THERM_I2CS_SCRATCH_FSP_BOOT_COMPLETE)
+    }
+
+    /// Whether the FSP reported an error, e.g., a failed secure boot.
+    pub fn fsp_failed(&self) -> bool {
+        self.fsp_errors
+            .iter()
+            .any(|error| *error != 0 && !is_mmio_error(*error))
+    }
+}
+
+impl GpuObject {
+    /// Read the boot progress and status registers without waiting for them, e.g., to tell
+    /// where a GPU stopped whose CC mode switch hangs.
+    pub fn query_boot_status(&self) -> Result<BootStatus> {
+        self.ensure_pf("The boot status")?;
+
+        let (register, value, mask) = self.regs().boot_done();
+        let (fsp_status, fsp_errors) = match self.regs().fsp() {
+            Some(_) => (
+                Some(self.checked_read32(NV_THERM_I2CS_SCRATCH)?),
+                self.read_array(NV_FSP_SCRATCH_GROUP_2, NV_FSP_SCRATCH_GROUP_2_LEN as _)?,
+            ),
+            None => (None, vec![]),
+        };
+
+        Ok(BootStatus {
+            gfw_progress: self.checked_read32(NV_PGC6_AON_SECURE_SCRATCH_GROUP_05)?,
+            fsp_status,
+            fsp_errors,
+            complete: self.checked_read32(register)? & mask == value,
+        })
+    }
+}
@@ -0,0 +1,148 @@
+use x509_parser::{
+    certificate::X509Certificate, oid_registry::OID_X509_SERIALNUMBER, parse_x509_certificate,
+};
+
+use crate::error::{NvTrustError, Result};
+
+/// The outcome of verifying a device certificate chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The chain leads up to the pinned root; the PDI is taken from the subject of the leaf.
+    Trusted { pdi: Option<String> },
+    /// The chain does not verify, for the given reason.
+    Untrusted(String),
+}
+
+/// Split concatenated DER certificates, e.g., the certificates of an SPDM certificate chain.
+pub fn split_der(mut der: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut certs = vec![];
+
+    while !der.is_empty() {
+        let (rest, _) = parse_x509_certificate(der)
+            .map_err(|e| NvTrustError::Certificate(format!("certificate {}: {e}", certs.len())))?;
+        certs.push(der[..der.len() - rest.len()].to_vec());
+        der = rest;
+    }
+
+    Ok(certs)
+}
+
+/// Split an SPDM certificate chain, i.e., its length, the hash of the root and the DER
+/// certificates, into the certificates.
+pub fn split_spdm_chain(chain: &[u8], hash_size: usize) -> Result<Vec<Vec<u8>>> {
+    let der = chain
+        .get(4 + hash_size..)
+        .ok_or(NvTrustError::Certificate(format!(
+            "SPDM certificate chain of {} bytes is too short",
+            chain.len()
+        )))?;
+
+    split_der(der)
+}
+
+/// Verify the chain from the root to the leaf against the pinned root certificate.
+///
+/// The chain either starts with the pinned root itself or with a certificate issued by it. Every
+/// certificate must be within its validity period and signed by its predecessor, whose subject is
+/// its issuer, and every issuer must be a CA allowed to sign certificates at that depth.
+pub fn verify_chain(certs: &[Vec<u8>], root: &[u8]) -> Result<Verdict> {
+    let root = parse(root)?;
+    let chain = certs
+        .iter()
+        .map(|der| parse(der))
+        .collect::<Result<Vec<_>>>()?;
+    let Some(leaf) = chain.last() else {
+        return Ok(Verdict::Untrusted("the chain is empty".to_string()));
+    };
+
+    let issuers = std::iter::once(&root).chain(chain.iter());
+    for (i, (issuer, cert)) in issuers.zip(chain.iter()).enumerate() {
+        if i == 0 && cert.tbs_certificate.as_ref() == root.tbs_certificate.as_ref() {
+            continue;
+        }
+
+        // Only a CA may issue certificates, and only as many levels below it as it allows.
+        let below = chain.len() - 1 - i;
+        if let Some(reason) = issuer_error(issuer, below) {
+            return Ok(Verdict::Untrusted(reason));
+        }
+        if cert.issuer().as_raw() != issuer.subject().as_raw() {
+            return Ok(Verdict::Untrusted(format!(
+                "{} is issued by {}, not by {}",
+                cert.subject(),
+                cert.issuer(),
+                issuer.subject()
+            )));
+        }
+
+        if !cert.validity().is_valid() {
+            return Ok(Verdict::Untrusted(format!(
+                "{} is not valid now ({} - {})",
+                cert.subject(),
+                cert.validity().not_before,
+                cert.validity().not_after
+            )));
+        }
+
+        if let Err(e) = cert.verify_signature(Some(issuer.public_key())) {
+            return Ok(Verdict::Untrusted(format!(
+                "{} is not signed by {}: {e}",
+                cert.subject(),
+                issuer.subject()
+            )));
+        }
+    }
+
+    Ok(Verdict::Trusted {
+        pdi: leaf_pdi(leaf),
+    })
+}
+
+/// Why the certificate may not issue one with `below` CAs between the two of them and the leaf,
+/// if it may not.
+fn issuer_error(issuer: &X509Certificate, below: usize) -> Option<String> {
+    let constraints = match issuer.basic_constraints() {
+        Ok(Some(constraints)) if constraints.value.ca => constraints.value,
+        Ok(_) => return Some(format!("{} is not a CA", issuer.subject())),
+        Err(e) => {
+            return Some(format!(
+                "{} has invalid basic constraints: {e}",
+                issuer.subject()
+            ))
+        }
+    };
+    if let Some(len) = constraints.path_len_constraint {
+        if below > len as usize {
+            return Some(format!(
+                "{} allows {len} CAs below it, not {below}",
+                issuer.subject()
+            ));
+        }
+    }
+
+    match issuer.key_usage() {
+        Ok(Some(usage)) if !usage.value.key_cert_sign() => {
+            Some(format!("{} may not sign certificates", issuer.subject()))
+        }
+        Err(e) => Some(format!(
+            "{} has an invalid key usage: {e}",
+            issuer.subject()
+        )),
+        _ => None,
+    }
+}
+
+/// NVIDIA device certificates carry the PDI as hex in the serial number of their subject.
+fn leaf_pdi(leaf: &X509Certificate) -> Option<String> {
+    leaf.subject()
+        .iter_by_oid(&OID_X509_SERIALNUMBER)
+        .filter_map(|attr| attr.as_str().ok())
+        .map(|serial| serial.to_lowercase())
+        .next()
+}
+
+fn parse(der: &[u8]) -> Result<X509Certificate<'_>> {
+    parse_x509_certificate(der)
+        .map(|(_, cert)| cert)
+        .map_err(|e| NvTrustError::Certificate(e.to_string()))
+}
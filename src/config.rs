@@ -0,0 +1,48 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use nvtrust::bits;
+
+/// The defaults of the CLI, from [`bits::CONFIG_FILE`] or `--config`, e.g.,
+///
+/// ```toml
+/// gpu_bdf = "01:00"
+/// backend = "vfio"
+/// log = "warn"
+/// nras_url = "https://nras.example.com/v3/attest/gpu"
+/// rim_cache = "/srv/nvtrust/rim"
+/// ```
+///
+/// The keys are named after the flags they stand for, and the flags on the command line win.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub gpu: Option<i64>,
+    pub gpu_bdf: Option<String>,
+    pub gpu_name: Option<String>,
+    /// `auto`, `devmem`, `resource` or `vfio`.
+    pub backend: Option<String>,
+    /// `os`, `flr` or `sbr`.
+    pub reset_method: Option<String>,
+    pub log: Option<String>,
+    /// The NRAS endpoint of `nras`.
+    pub nras_url: Option<String>,
+    /// The RIM cache of `fetch-rim` and `verify-local`.
+    pub rim_cache: Option<String>,
+}
+
+impl Config {
+    /// Load the given file, or else the system-wide one if there is one.
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(bits::CONFIG_FILE).exists() => bits::CONFIG_FILE,
+            None => return Ok(Self::default()),
+        };
+
+        let text = std::fs::read_to_string(path).map_err(|e| anyhow!("Cannot read {path}: {e}"))?;
+        toml::from_str(&text).map_err(|e| anyhow!("Invalid config {path}: {e}"))
+    }
+}
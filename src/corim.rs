@@ -0,0 +1,556 @@
+use std::collections::BTreeMap;
+
+use ring::signature;
+use x509_parser::parse_x509_certificate;
+
+use crate::{
+    certs::{self, Verdict},
+    error::{NvTrustError, Result},
+    spdm::to_hex,
+    verifier::ReferenceValues,
+};
+
+/// COSE_Sign1, which wraps a signed CoRIM.
+const CBOR_TAG_COSE_SIGN1: u64 = 18;
+const CBOR_TAG_CORIM: u64 = 501;
+const CBOR_TAG_COSWID: u64 = 505;
+const CBOR_TAG_COMID: u64 = 506;
+
+// The keys of the CoRIM and CoMID maps we look at.
+const CORIM_ID: u64 = 0;
+const CORIM_TAGS: u64 = 1;
+const COMID_TAG_IDENTITY: u64 = 1;
+const COMID_TRIPLES: u64 = 4;
+const COMID_REFERENCE_TRIPLES: u64 = 0;
+const MEASUREMENT_KEY: u64 = 0;
+const MEASUREMENT_VALUES: u64 = 1;
+const MEASUREMENT_DIGESTS: u64 = 2;
+
+// The keys of the CoSWID maps we look at.
+const COSWID_TAG_ID: u64 = 0;
+const COSWID_SOFTWARE_NAME: u64 = 1;
+const COSWID_PAYLOAD: u64 = 6;
+const COSWID_SOFTWARE_VERSION: u64 = 13;
+const COSWID_RESOURCE: u64 = 19;
+const COSWID_TYPE: u64 = 29;
+
+// The COSE header labels and algorithms we look at, see RFC 9052 and RFC 9360.
+const COSE_HEADER_ALG: u64 = 1;
+const COSE_HEADER_X5CHAIN: u64 = 33;
+const COSE_ALG_ES256: i128 = -7;
+const COSE_ALG_ES384: i128 = -35;
+
+/// The nesting limit of the decoder, which is far beyond what a RIM needs.
+const MAX_DEPTH: usize = 32;
+
+/// A decoded CBOR data item.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cbor {
+    Int(i128),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Tag(u64, Box<Cbor>),
+    Bool(bool),
+    Null,
+    Undefined,
+    Float(f64),
+}
+
+impl Cbor {
+    /// Decode a single data item that spans the whole input.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut decoder = Decoder { data, offset: 0 };
+        let item = decoder.item(0)?;
+        if decoder.offset != data.len() {
+            return Err(NvTrustError::Cbor(format!(
+                "{} trailing bytes after the CBOR item",
+                data.len() - decoder.offset
+            )));
+        }
+
+        Ok(item)
+    }
+
+    /// The value of an integer key of a map.
+    pub fn get(&self, key: u64) -> Option<&Cbor> {
+        self.get_by(&Cbor::Int(key as i128))
+    }
+
+    /// The value of a text key of a map.
+    pub fn get_text(&self, key: &str) -> Option<&Cbor> {
+        self.get_by(&Cbor::Text(key.to_string()))
+    }
+
+    fn get_by(&self, key: &Cbor) -> Option<&Cbor> {
+        match self {
+            Cbor::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Cbor]> {
+        match self {
+            Cbor::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Cbor::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i128> {
+        match self {
+            Cbor::Int(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// The item with the given tag removed, if it has it.
+    pub fn untag(&self, tag: u64) -> &Cbor {
+        match self {
+            Cbor::Tag(t, inner) if *t == tag => inner,
+            item => item,
+        }
+    }
+
+    /// The item a byte string wraps, as `bstr .cbor`.
+    fn unwrap_bytes(&self) -> Result<Cbor> {
+        match self {
+            Cbor::Bytes(bytes) => Cbor::decode(bytes),
+            item => Ok(item.clone()),
+        }
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        let bytes = self
+            .offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.offset..end))
+            .ok_or_else(|| NvTrustError::Cbor(format!("truncated CBOR at byte {}", self.offset)))?;
+        self.offset += len;
+
+        Ok(bytes)
+    }
+
+    /// The argument of the head, or `None` for an indefinite length.
+    fn argument(&mut self, info: u8) -> Result<Option<u64>> {
+        let len = match info {
+            0..=23 => return Ok(Some(info as u64)),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            31 => return Ok(None),
+            _ => {
+                return Err(NvTrustError::Cbor(format!(
+                    "reserved CBOR additional info {info}"
+                )))
+            }
+        };
+
+        Ok(Some(
+            self.take(len)?
+                .iter()
+                .fold(0u64, |acc, &b| (acc << 8) | b as u64),
+        ))
+    }
+
+    fn length(&mut self, info: u8) -> Result<Option<usize>> {
+        match self.argument(info)? {
+            Some(len) if len > self.data.len() as u64 => Err(NvTrustError::Cbor(format!(
+                "CBOR length {len} beyond the input"
+            ))),
+            len => Ok(len.map(|len| len as usize)),
+        }
+    }
+
+    fn is_break(&self) -> bool {
+        self.data.get(self.offset) == Some(&0xff)
+    }
+
+    fn string(&mut self, major: u8, info: u8) -> Result<Vec<u8>> {
+        match self.length(info)? {
+            Some(len) => Ok(self.take(len)?.to_vec()),
+            None => {
+                let mut bytes = vec![];
+                while !self.is_break() {
+                    let head = self.take(1)?[0];
+                    if head >> 5 != major || head & 0x1f == 31 {
+                        return Err(NvTrustError::Cbor(
+                            "invalid chunk in an indefinite CBOR string".to_string(),
+                        ));
+                    }
+                    bytes.extend(self.string(major, head & 0x1f)?);
+                }
+                self.offset += 1;
+                Ok(bytes)
+            }
+        }
+    }
+
+    fn item(&mut self, depth: usize) -> Result<Cbor> {
+        if depth > MAX_DEPTH {
+            return Err(NvTrustError::Cbor("CBOR nested too deeply".to_string()));
+        }
+
+        let head = self.take(1)?[0];
+        let (major, info) = (head >> 5, head & 0x1f);
+
+        Ok(match major {
+            0 => Cbor::Int(self.argument(info)?.ok_or_else(indefinite)? as i128),
+            1 => Cbor::Int(-1 - self.argument(info)?.ok_or_else(indefinite)? as i128),
+            2 => Cbor::Bytes(self.string(major, info)?),
+            3 => Cbor::Text(String::from_utf8(self.string(major, info)?).map_err(|_| {
+                NvTrustError::Cbor("invalid UTF-8 in a CBOR text string".to_string())
+            })?),
+            4 => {
+                let mut items = vec![];
+                match self.length(info)? {
+                    Some(len) => {
+                        for _ in 0..len {
+                            items.push(self.item(depth + 1)?);
+                        }
+                    }
+                    None => {
+                        while !self.is_break() {
+                            items.push(self.item(depth + 1)?);
+                        }
+                        self.offset += 1;
+                    }
+                }
+                Cbor::Array(items)
+            }
+            5 => {
+                let mut entries = vec![];
+                match self.length(info)? {
+                    Some(len) => {
+                        for _ in 0..len {
+                            entries.push((self.item(depth + 1)?, self.item(depth + 1)?));
+                        }
+                    }
+                    None => {
+                        while !self.is_break() {
+                            entries.push((self.item(depth + 1)?, self.item(depth + 1)?));
+                        }
+                        self.offset += 1;
+                    }
+                }
+                Cbor::Map(entries)
+            }
+            6 => {
+                let tag = self.argument(info)?.ok_or_else(indefinite)?;
+                Cbor::Tag(tag, Box::new(self.item(depth + 1)?))
+            }
+            _ => match info {
+                20 => Cbor::Bool(false),
+                21 => Cbor::Bool(true),
+                22 => Cbor::Null,
+                23 => Cbor::Undefined,
+                25 => Cbor::Float(half_to_f64(u16::from_be_bytes(
+                    self.take(2)?.try_into().unwrap(),
+                ))),
+                26 => Cbor::Float(f32::from_be_bytes(self.take(4)?.try_into().unwrap()) as f64),
+                27 => Cbor::Float(f64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+                _ => {
+                    return Err(NvTrustError::Cbor(format!(
+                        "unsupported CBOR simple value {info}"
+                    )))
+                }
+            },
+        })
+    }
+}
+
+fn indefinite() -> NvTrustError {
+    NvTrustError::Cbor("indefinite length on a CBOR integer or tag".to_string())
+}
+
+fn half_to_f64(half: u16) -> f64 {
+    let exp = (half >> 10) & 0x1f;
+    let mant = (half & 0x3ff) as f64;
+    let val = match exp {
+        0 => mant * 2f64.powi(-24),
+        31 if mant == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mant + 1024.0) * 2f64.powi(exp as i32 - 25),
+    };
+
+    if half & 0x8000 != 0 {
+        -val
+    } else {
+        val
+    }
+}
+
+impl ReferenceValues {
+    /// Read the reference values from a CoRIM, signed or not, as NVIDIA publishes its RIMs in
+    /// CBOR.
+    ///
+    /// Both kinds of tags are read: the reference triples of CoMIDs, keyed by the measurement
+    /// index, and the measurement resources of CoSWIDs, which carry the same fields as the SWID
+    /// form of the RIMs.
+    pub fn from_corim(data: &[u8]) -> Result<Self> {
+        let mut corim = Cbor::decode(data)?;
+
+        // The payload of a signed CoRIM is the tagged CoRIM, as a byte string.
+        if let Cbor::Tag(CBOR_TAG_COSE_SIGN1, sign1) = &corim {
+            let payload = sign1
+                .as_array()
+                .and_then(|sign1| sign1.get(2))
+                .ok_or_else(|| NvTrustError::Rim("COSE_Sign1 without a payload".to_string()))?;
+            corim = payload.unwrap_bytes()?;
+        }
+        let corim = corim.untag(CBOR_TAG_CORIM);
+
+        let mut values = Self {
+            name: corim
+                .get(CORIM_ID)
+                .and_then(Cbor::as_text)
+                .unwrap_or_default()
+                .to_string(),
+            measurements: BTreeMap::new(),
+            signature_verified: false,
+        };
+
+        let tags = corim
+            .get(CORIM_TAGS)
+            .and_then(Cbor::as_array)
+            .ok_or_else(|| NvTrustError::Rim("CoRIM without tags".to_string()))?;
+        for tag in tags {
+            match tag {
+                Cbor::Tag(CBOR_TAG_COMID, comid) => values.add_comid(&comid.unwrap_bytes()?)?,
+                Cbor::Tag(CBOR_TAG_COSWID, coswid) => values.add_coswid(&coswid.unwrap_bytes()?)?,
+                Cbor::Tag(tag, _) => log::debug!("Skipping CoRIM tag {tag}"),
+                _ => return Err(NvTrustError::Rim("untagged CoRIM tag".to_string())),
+            }
+        }
+
+        Ok(values)
+    }
+
+    fn add_comid(&mut self, comid: &Cbor) -> Result<()> {
+        if self.name.is_empty() {
+            if let Some(id) = comid
+                .get(COMID_TAG_IDENTITY)
+                .and_then(|identity| identity.get(0))
+                .and_then(Cbor::as_text)
+            {
+                self.name = id.to_string();
+            }
+        }
+
+        let triples = comid
+            .get(COMID_TRIPLES)
+            .and_then(|triples| triples.get(COMID_REFERENCE_TRIPLES))
+            .and_then(Cbor::as_array)
+            .unwrap_or_default();
+        for triple in triples {
+            let measurements = triple
+                .as_array()
+                .and_then(|triple| triple.get(1))
+                .and_then(Cbor::as_array)
+                .ok_or_else(|| NvTrustError::Rim("malformed reference triple".to_string()))?;
+
+            for measurement in measurements {
+                let index = measurement
+                    .get(MEASUREMENT_KEY)
+                    .and_then(Cbor::as_int)
+                    .and_then(|index| u32::try_from(index).ok())
+                    .ok_or_else(|| {
+                        NvTrustError::Rim(format!(
+                            "a measurement without an index in {}",
+                            self.name
+                        ))
+                    })?;
+                let digests = measurement
+                    .get(MEASUREMENT_VALUES)
+                    .and_then(|values| values.get(MEASUREMENT_DIGESTS))
+                    .and_then(Cbor::as_array)
+                    .ok_or_else(|| {
+                        NvTrustError::Rim(format!(
+                            "measurement {index} of {} has no digest",
+                            self.name
+                        ))
+                    })?;
+
+                let alternatives = self.measurements.entry(index).or_default();
+                for digest in digests {
+                    match digest.as_array().and_then(|digest| digest.get(1)) {
+                        Some(Cbor::Bytes(value)) => alternatives.push(to_hex(value)),
+                        _ => {
+                            return Err(NvTrustError::Rim(format!(
+                                "malformed digest of measurement {index}"
+                            )))
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_coswid(&mut self, coswid: &Cbor) -> Result<()> {
+        if self.name.is_empty() {
+            let name = coswid
+                .get(COSWID_SOFTWARE_NAME)
+                .or_else(|| coswid.get(COSWID_TAG_ID))
+                .and_then(Cbor::as_text)
+                .unwrap_or_default();
+            let version = coswid
+                .get(COSWID_SOFTWARE_VERSION)
+                .and_then(Cbor::as_text)
+                .unwrap_or_default();
+            self.name = format!("{name} {version}").trim().to_string();
+        }
+
+        let resources = match coswid
+            .get(COSWID_PAYLOAD)
+            .and_then(|payload| payload.get(COSWID_RESOURCE))
+        {
+            Some(Cbor::Array(resources)) => resources.clone(),
+            Some(resource) => vec![resource.clone()],
+            None => vec![],
+        };
+        for resource in resources.iter() {
+            if resource.get(COSWID_TYPE).and_then(Cbor::as_text) != Some("Measurement") {
+                continue;
+            }
+            if resource.get_text("active") == Some(&Cbor::Bool(false)) {
+                continue;
+            }
+
+            let index = resource
+                .get_text("index")
+                .and_then(Cbor::as_int)
+                .and_then(|index| u32::try_from(index).ok())
+                .ok_or_else(|| {
+                    NvTrustError::Rim(format!("a measurement without an index in {}", self.name))
+                })?;
+            let alternatives = (0..)
+                .map_while(|i| resource.get_text(&format!("Hash{i}")))
+                .map(|hash| match hash {
+                    Cbor::Bytes(value) => Ok(to_hex(value)),
+                    Cbor::Text(value) => Ok(value.to_lowercase()),
+                    _ => Err(NvTrustError::Rim(format!(
+                        "malformed hash of measurement {index}"
+                    ))),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            if alternatives.is_empty() {
+                return Err(NvTrustError::Rim(format!(
+                    "measurement {index} of {} has no hash",
+                    self.name
+                )));
+            }
+
+            self.measurements
+                .entry(index)
+                .or_default()
+                .extend(alternatives);
+        }
+
+        Ok(())
+    }
+}
+
+/// Verify the COSE_Sign1 signature of a signed CoRIM against the pinned root of its signers (DER),
+/// and return the CoRIM it signs.
+///
+/// The signing certificate and its chain are taken from the x5chain header, leaf first.
+pub fn verify(data: &[u8], root_ca: &[u8]) -> Result<Vec<u8>> {
+    let invalid = |reason: &str| NvTrustError::Rim(format!("invalid COSE_Sign1: {reason}"));
+    let sign1 = match Cbor::decode(data)? {
+        Cbor::Tag(CBOR_TAG_COSE_SIGN1, sign1) => *sign1,
+        _ => return Err(NvTrustError::Rim("the CoRIM is not signed".to_string())),
+    };
+    let [Cbor::Bytes(protected), unprotected, payload, Cbor::Bytes(value)] =
+        sign1.as_array().unwrap_or_default()
+    else {
+        return Err(invalid("not an array of four items"));
+    };
+    let Cbor::Bytes(payload) = payload else {
+        return Err(invalid("a detached payload"));
+    };
+    let headers = match protected.is_empty() {
+        true => Cbor::Map(vec![]),
+        false => Cbor::decode(protected)?,
+    };
+
+    let chain = match headers
+        .get(COSE_HEADER_X5CHAIN)
+        .or_else(|| unprotected.get(COSE_HEADER_X5CHAIN))
+    {
+        Some(Cbor::Bytes(cert)) => vec![cert.clone()],
+        Some(Cbor::Array(certs)) => certs
+            .iter()
+            .map(|cert| match cert {
+                Cbor::Bytes(cert) => Ok(cert.clone()),
+                _ => Err(invalid("a certificate that is not a byte string")),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        _ => return Err(invalid("no x5chain")),
+    };
+    let leaf = chain
+        .first()
+        .cloned()
+        .ok_or_else(|| invalid("an empty x5chain"))?;
+    let chain = chain.into_iter().rev().collect::<Vec<_>>();
+    if let Verdict::Untrusted(reason) = certs::verify_chain(&chain, root_ca)? {
+        return Err(NvTrustError::Rim(format!(
+            "the RIM signer is untrusted: {reason}"
+        )));
+    }
+
+    // COSE encodes ECDSA signatures as r || s.
+    let algorithm: &dyn signature::VerificationAlgorithm =
+        match headers.get(COSE_HEADER_ALG).and_then(Cbor::as_int) {
+            Some(COSE_ALG_ES256) => &signature::ECDSA_P256_SHA256_FIXED,
+            Some(COSE_ALG_ES384) => &signature::ECDSA_P384_SHA384_FIXED,
+            Some(alg) => return Err(invalid(&format!("unsupported algorithm {alg}"))),
+            None => return Err(invalid("no protected algorithm")),
+        };
+
+    // The Sig_structure ["Signature1", protected, external_aad, payload], without external data.
+    let mut message = encode_head(4, 4);
+    message.extend(encode_head(3, "Signature1".len()));
+    message.extend(b"Signature1");
+    message.extend(encode_head(2, protected.len()));
+    message.extend(protected);
+    message.extend(encode_head(2, 0));
+    message.extend(encode_head(2, payload.len()));
+    message.extend(payload);
+
+    let (_, leaf) = parse_x509_certificate(&leaf)
+        .map_err(|e| NvTrustError::Certificate(format!("the RIM signer: {e}")))?;
+    signature::UnparsedPublicKey::new(algorithm, &leaf.public_key().subject_public_key.data)
+        .verify(&message, value)
+        .map_err(|_| NvTrustError::Rim("the RIM signature does not verify".to_string()))?;
+
+    Ok(payload.clone())
+}
+
+/// The head of a data item of the major type with the argument, in its shortest form.
+fn encode_head(major: u8, arg: usize) -> Vec<u8> {
+    let major = major << 5;
+    match arg as u64 {
+        arg @ 0..=23 => vec![major | arg as u8],
+        arg @ 24..=0xff => vec![major | 24, arg as u8],
+        arg @ 0x100..=0xffff => [&[major | 25][..], &(arg as u16).to_be_bytes()].concat(),
+        arg @ 0x1_0000..=0xffff_ffff => [&[major | 26][..], &(arg as u32).to_be_bytes()].concat(),
+        arg => [&[major | 27][..], &arg.to_be_bytes()].concat(),
+    }
+}
@@ -1,12 +1,24 @@
-use anyhow::{anyhow, Result};
+#[cfg(any(
+    all(any(feature = "snp", feature = "tdx"), target_arch = "x86_64"),
+    target_arch = "aarch64"
+))]
 use log::info;
+#[cfg(all(any(feature = "snp", feature = "tdx"), target_arch = "x86_64"))]
 use x86::cpuid;
 
+#[cfg(target_arch = "aarch64")]
+use crate::bits::*;
+#[cfg(any(
+    all(any(feature = "snp", feature = "tdx"), target_arch = "x86_64"),
+    target_arch = "aarch64"
+))]
+use crate::error::{NvTrustError, Result};
+
 #[cfg(all(feature = "snp", target_arch = "x86_64"))]
 pub fn check_sev_snp() -> Result<()> {
     let cpuid = cpuid::CpuId::new();
-    let svm = cpuid.get_svm_info().ok_or(anyhow!(
-        "no svm information detected: is this a SNP-supported CPU?"
+    let svm = cpuid.get_svm_info().ok_or(NvTrustError::Tee(
+        "no svm information detected: is this a SNP-supported CPU?".to_string(),
     ))?;
 
     if svm.has_nested_paging() {
@@ -28,6 +40,68 @@ pub fn check_sev_snp() -> Result<()> {
 
         Ok(())
     } else {
-        Err(anyhow!("nested paging is not supported"))
+        Err(NvTrustError::Tee(
+            "nested paging is not supported".to_string(),
+        ))
+    }
+}
+
+/// Tell whether we run in an Intel TDX trust domain, from the vendor of CPUID leaf 0x21.
+#[cfg(all(feature = "tdx", target_arch = "x86_64"))]
+pub fn check_tdx() -> Result<()> {
+    let raw_info = cpuid::cpuid!(0x0, 0x0);
+    if raw_info.eax < 0x21 {
+        return Err(NvTrustError::Tee(
+            "CPUID leaf 0x21 is not available: is this a TD?".to_string(),
+        ));
+    }
+
+    // The vendor is "IntelTDX    " in EBX, EDX and ECX.
+    let raw_info = cpuid::cpuid!(0x21, 0x0);
+    let vendor = [raw_info.ebx, raw_info.edx, raw_info.ecx]
+        .iter()
+        .flat_map(|reg| reg.to_le_bytes())
+        .collect::<Vec<_>>();
+
+    if vendor == b"IntelTDX    " {
+        info!("detected Intel TDX trust domain!");
+        Ok(())
+    } else {
+        Err(NvTrustError::Tee(
+            "not running in a TDX trust domain".to_string(),
+        ))
+    }
+}
+
+/// Tell whether we run in any of the TEEs this build supports.
+#[cfg(all(any(feature = "snp", feature = "tdx"), target_arch = "x86_64"))]
+pub fn check_tee() -> Result<()> {
+    let mut errors = vec![];
+
+    #[cfg(feature = "snp")]
+    match check_sev_snp() {
+        Ok(()) => return Ok(()),
+        Err(e) => errors.push(format!("SEV-SNP: {e}")),
+    }
+    #[cfg(feature = "tdx")]
+    match check_tdx() {
+        Ok(()) => return Ok(()),
+        Err(e) => errors.push(format!("TDX: {e}")),
+    }
+
+    Err(NvTrustError::Tee(errors.join("; ")))
+}
+
+/// Tell whether we run in an Arm CCA realm, e.g., a confidential VM on a Grace Hopper superchip,
+/// from the realm guest driver the kernel binds when the RSI is present.
+#[cfg(target_arch = "aarch64")]
+pub fn check_arm_cca() -> Result<()> {
+    if std::path::Path::new(ARM_CCA_GUEST_DRIVER).exists() {
+        info!("detected Arm CCA realm!");
+        Ok(())
+    } else {
+        Err(NvTrustError::Tee(format!(
+            "{ARM_CCA_GUEST_DRIVER} is missing: not a realm, or the kernel has no arm-cca-guest driver"
+        )))
     }
 }
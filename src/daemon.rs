@@ -0,0 +1,142 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+    policy::Claims,
+    spdm,
+    verifier::{self, Verification},
+};
+
+/// The attestation status of a single GPU.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GpuStatus {
+    /// Whether the last attestation succeeded, i.e., the evidence verified and the claims passed
+    /// the policy.
+    pub attested: bool,
+    /// The claims collected by the last attestation.
+    pub claims: Claims,
+    /// Why the last attestation failed.
+    pub error: Option<String>,
+    /// When the GPU was last attested, successfully or not, in seconds since the epoch.
+    pub checked_at: u64,
+    /// When the GPU was last attested successfully, in seconds since the epoch.
+    pub attested_at: Option<u64>,
+}
+
+/// The status file read by node agents, keyed by BDF.
+///
+/// A consumer decides whether a status is fresh enough from `attested_at` and `interval`, e.g., by
+/// rejecting a GPU whose last success is older than twice the interval.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StatusFile {
+    /// The re-attestation interval in seconds.
+    pub interval: u64,
+    /// When the file was written, in seconds since the epoch.
+    pub updated_at: u64,
+    pub gpus: BTreeMap<String, GpuStatus>,
+}
+
+impl StatusFile {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    /// Save the status file, replacing the old one atomically so readers never see a partial file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.to_json()?)?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+}
+
+/// Attest the GPU: verify its evidence over a fresh nonce as verify-local does, see
+/// [`verifier::verify_local`], and, if a policy is given, appraise the claims.
+pub fn attest(gpu: &GpuObject, verification: &Verification) -> Result<Claims> {
+    let (_, claims) = verifier::verify_local(gpu, spdm::random_nonce()?, verification)?;
+    if claims["x-nvidia-overall-att-result"] != "true" {
+        let failed = claims
+            .iter()
+            .filter(|(_, value)| *value == "false")
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        return Err(NvTrustError::Attestation(format!(
+            "the evidence of {} did not verify: {}",
+            gpu.get_label(),
+            failed.join(", ")
+        )));
+    }
+
+    if let Some(policy) = &verification.policy {
+        policy.check(&claims)?;
+    }
+
+    Ok(claims)
+}
+
+/// Re-attest the GPUs every `interval` and write their status to `path`, forever.
+pub fn run(
+    gpus: &[GpuObject],
+    verification: &Verification,
+    interval: Duration,
+    path: &str,
+) -> Result<()> {
+    let mut status = StatusFile {
+        interval: interval.as_secs(),
+        ..Default::default()
+    };
+
+    loop {
+        for gpu in gpus {
+            let entry = status.gpus.entry(gpu.get_bdf().to_string()).or_default();
+            entry.checked_at = now();
+
+            // Skip the GPU this round if another nvtrust is operating on it.
+            let attested = gpu.lock().and_then(|_lock| attest(gpu, verification));
+            match attested {
+                Ok(claims) => {
+                    log::debug!("{} attested", gpu.get_label());
+                    entry.attested = true;
+                    entry.claims = claims;
+                    entry.error = None;
+                    entry.attested_at = Some(entry.checked_at);
+                }
+                Err(e) => {
+                    log::error!("{} failed to attest: {e}", gpu.get_label());
+                    // Keep the time of the last success so that consumers can tell how stale it is.
+                    entry.attested = false;
+                    entry.claims.clear();
+                    entry.error = Some(e.to_string());
+                }
+            }
+        }
+
+        status.updated_at = now();
+        if let Err(e) = status.save(path) {
+            log::error!("Cannot write the status file {path}: {e}");
+        }
+
+        std::thread::sleep(interval);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
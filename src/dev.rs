@@ -1,36 +1,352 @@
-use std::{collections::HashMap, path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, Ordering},
+        Arc, OnceLock,
+    },
+};
 
-use anyhow::{anyhow, Result};
 use rustix::{fd::OwnedFd, fs, io, mm};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    arch::{Arch, ArchRegs},
+    backend::{self, BackendKind, DeviceBackend},
+    bits::*,
+    error::{NvTrustError, Result},
+    lock::DeviceLock,
+    pcicfg,
+};
+
+/// How [`GpuObject::reset`] resets a GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResetMethod {
+    /// Whatever the kernel picks through `/sys/.../reset`.
+    #[default]
+    Os,
+    /// A function level reset through the Express capability.
+    Flr,
+    /// A secondary bus reset of the upstream port.
+    SecondaryBus,
+}
+
+impl std::fmt::Display for ResetMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResetMethod::Os => write!(f, "os"),
+            ResetMethod::Flr => write!(f, "FLR"),
+            ResetMethod::SecondaryBus => write!(f, "secondary bus reset"),
+        }
+    }
+}
+
+/// The reset method of all the GPUs, if not the default.
+static RESET_METHOD: OnceLock<ResetMethod> = OnceLock::new();
+
+/// Reset all the GPUs with the given method from now on, e.g., as configured by the user.
+pub fn set_reset_method(method: ResetMethod) -> Result<()> {
+    RESET_METHOD
+        .set(method)
+        .map_err(|_| NvTrustError::InvalidArgument("the reset method is already set".to_string()))
+}
+
+/// An NVIDIA GPU or NVSwitch as sysfs describes it, without opening the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SysfsDevice {
+    pub path: String,
+    pub device_id: u16,
+    pub class: u32,
+    pub driver: Option<String>,
+}
+
+impl SysfsDevice {
+    #[inline]
+    pub fn bdf(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    #[inline]
+    pub fn is_nvswitch(&self) -> bool {
+        self.class == PCI_CLASS_BRIDGE_OTHER
+    }
 
-use crate::bits::*;
+    /// Whether the device is a SR-IOV virtual function seen from the host.
+    pub fn is_vf(&self) -> bool {
+        Path::new(&self.path).join("physfn").exists()
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        device_name(self.device_id)
+    }
+
+    /// Get the GPU driver bound to the device, see [`PciDevice::gpu_driver`].
+    pub fn gpu_driver(&self) -> Option<&str> {
+        self.driver
+            .as_deref()
+            .filter(|driver| GPU_DRIVERS.contains(driver))
+    }
+}
+
+/// List all the NVIDIA GPUs (and NVSwitches) from sysfs, sorted by BDF.
+pub fn list_sysfs_devices() -> Result<Vec<SysfsDevice>> {
+    let mut devices = vec![];
+    let read_hex = |path: &str, name: &str| -> Result<u32> {
+        let value = std::fs::read_to_string(format!("{path}/{name}"))?;
+        Ok(u32::from_str_radix(
+            value.trim().trim_start_matches("0x"),
+            16,
+        )?)
+    };
+
+    for device in std::fs::read_dir(PCI_DEVICES)? {
+        let path = device?.path().to_string_lossy().to_string();
+
+        // Check if is a nvidia GPU.
+        if read_hex(&path, "vendor")? != NVIDIA_VENDOR_ID as u32 {
+            continue;
+        }
+        let class = read_hex(&path, "class")?;
+        if [
+            PCI_CLASS_DISPLAY_VGA,
+            PCI_CLASS_DISPLAY_3D,
+            PCI_CLASS_BRIDGE_OTHER,
+        ]
+        .contains(&class)
+        {
+            devices.push(SysfsDevice {
+                device_id: read_hex(&path, "device")? as u16,
+                class,
+                driver: driver_of(&path),
+                path,
+            });
+        }
+    }
+
+    devices.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(devices)
+}
+
+/// List the sysfs paths of all the NVIDIA GPUs (and NVSwitches), sorted by BDF.
+pub fn list_nvidia_devices() -> Result<Vec<String>> {
+    Ok(list_sysfs_devices()?
+        .into_iter()
+        .map(|device| device.path)
+        .collect())
+}
+
+/// Get the name of the kernel driver bound to the device at the given sysfs path, if any.
+pub fn driver_of(path: &str) -> Option<String> {
+    std::fs::read_link(format!("{path}/driver"))
+        .ok()
+        .and_then(|link| link.file_name().map(|s| s.to_string_lossy().to_string()))
+}
+
+/// Whether the devices bound to a GPU driver are opened, see [`set_open_driver_bound`].
+static OPEN_DRIVER_BOUND: AtomicBool = AtomicBool::new(true);
+
+/// Open the devices bound to nvidia or nouveau from now on, as by default, or refuse to.
+///
+/// Opening a GPU reads its BAR0, so a tool that goes on to program the registers refuses them
+/// unless told otherwise, and the searches below skip them.
+pub fn set_open_driver_bound(open: bool) {
+    OPEN_DRIVER_BOUND.store(open, Ordering::Relaxed);
+}
+
+/// Check that the device at the given sysfs path may be opened, by its driver alone.
+pub fn check_driver(path: &str) -> Result<()> {
+    if OPEN_DRIVER_BOUND.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    match driver_of(path).filter(|driver| GPU_DRIVERS.contains(&driver.as_str())) {
+        Some(driver) => Err(NvTrustError::DriverBound {
+            device: path.rsplit('/').next().unwrap_or(path).to_string(),
+            driver,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Open the PCI device at the given sysfs path.
+pub fn open_device(path: &str) -> Result<PciDevice> {
+    let mut dev = PciDevice::new(path)?;
+    dev.init_caps()?;
+    dev.init_bars()?;
+
+    Ok(dev)
+}
+
+/// Open the GPU at the given sysfs path, unless its driver forbids, see [`check_driver`].
+pub fn open_gpu(path: &str) -> Result<GpuObject> {
+    check_driver(path)?;
+    let lock = crate::lock::lock_on_open(path.rsplit('/').next().unwrap_or(path))?;
+
+    let mut gpu = GpuObject::new(open_device(path)?.into())?;
+    gpu.lock = lock;
+    Ok(gpu)
+}
+
+/// Open the PCI device at the given path through the given backend, e.g., a
+/// [`crate::backend::MockBackend`] in tests.
+pub fn open_device_with(path: &str, backend: Arc<dyn DeviceBackend>) -> Result<PciDevice> {
+    let mut dev = PciDevice::with_backend(path, backend)?;
+    dev.init_caps()?;
+    dev.init_bars()?;
+
+    Ok(dev)
+}
+
+/// Open a PCI device of any vendor for its config space only, e.g., a PCIe port.
+pub fn open_port(path: &str) -> Result<PciDevice> {
+    open_port_with(path, Arc::new(backend::ConfigBackend::open(path)?))
+}
+
+/// Open a PCI device of any vendor through the given backend.
+pub fn open_port_with(path: &str, backend: Arc<dyn DeviceBackend>) -> Result<PciDevice> {
+    let mut dev = PciDevice::any_with_backend(path, backend)?;
+    dev.init_caps()?;
+    dev.init_bars()?;
+
+    Ok(dev)
+}
 
 /// Find the GPUs by the given BDF.
 pub fn find_gpus_by_bdf(bdf: &str) -> Result<Vec<GpuObject>> {
-    let mut gpus = vec![];
-    let devices = std::fs::read_dir(PCI_DEVICES)?;
-
-    for device in devices {
-        let device = device?;
-        let path = device.path();
-        let path = path.to_string_lossy().to_string();
-
-        if path.contains(&bdf) {
-            // Check if is a nvidia GPU.
-            let vendor = std::fs::read_to_string(format!("{}/vendor", path))?;
-            if vendor.trim() == "0x10de" {
-                let class = std::fs::read_to_string(format!("{}/class", path))?;
-                if class.trim() == "0x030000"
-                    || class.trim() == "0x030200"
-                    || class.trim() == "0x068000"
-                {
-                    let mut dev = PciDevice::new(path.clone())?;
-                    dev.init_caps()?;
-                    dev.init_bars()?;
+    Ok(find_devices_by_bdf(bdf)?
+        .into_iter()
+        .filter(|dev| !dev.is_nvswitch())
+        .collect())
+}
 
-                    let gpu = GpuObject::new(dev.into())?;
-                    gpus.push(gpu);
-                }
+/// Find all the NVSwitches of the board.
+pub fn find_nvswitches() -> Result<Vec<GpuObject>> {
+    Ok(find_devices_by_bdf("")?
+        .into_iter()
+        .filter(|dev| dev.is_nvswitch())
+        .collect())
+}
+
+/// Run `f` on every item on its own thread and return the results in the order of the items,
+/// e.g., to reset all the GPUs of a board at once rather than waiting for each in turn.
+pub fn par_map<'a, T: Sync, R: Send>(items: &'a [T], f: impl Fn(&'a T) -> R + Sync) -> Vec<R> {
+    std::thread::scope(|scope| {
+        let handles = items
+            .iter()
+            .map(|item| scope.spawn(|| f(item)))
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+            .collect()
+    })
+}
+
+/// Find the GPUs and NVSwitches by the given BDF, skipping those that cannot be opened with a
+/// warning.
+pub fn find_devices_by_bdf(bdf: &str) -> Result<Vec<GpuObject>> {
+    let (gpus, failed) = open_devices_by_bdf(bdf)?;
+    for (bdf, e) in failed {
+        log::warn!("Skipping {bdf}: {e}");
+    }
+
+    Ok(gpus)
+}
+
+/// The devices that cannot be opened, by BDF, with the reason.
+pub type OpenFailures = Vec<(String, NvTrustError)>;
+
+/// Open the GPUs and NVSwitches matching the given BDF, returning those that cannot be opened,
+/// e.g., because they fell off the bus, apart.
+pub fn open_devices_by_bdf(bdf: &str) -> Result<(Vec<GpuObject>, OpenFailures)> {
+    let paths = list_nvidia_devices()?
+        .into_iter()
+        .filter(|path| path.contains(bdf))
+        .collect::<Vec<_>>();
+
+    // Mapping the BARs of a device takes a while, so all of them are opened at once.
+    let (mut gpus, mut failed) = (vec![], vec![]);
+    for (path, gpu) in paths.iter().zip(par_map(&paths, |path| open_gpu(path))) {
+        match gpu {
+            Ok(gpu) => gpus.push(gpu),
+            Err(e) => failed.push((path.rsplit('/').next().unwrap_or(path).to_string(), e)),
+        }
+    }
+
+    Ok((gpus, failed))
+}
+
+/// Find the GPU at the given index among all the GPUs, sorted by BDF, as `list-gpus` numbers
+/// them.
+pub fn find_gpu_by_index(index: usize) -> Result<GpuObject> {
+    let gpus = list_sysfs_devices()?
+        .into_iter()
+        .filter(|device| !device.is_nvswitch())
+        .collect::<Vec<_>>();
+
+    if index >= gpus.len() {
+        let valid = gpus
+            .iter()
+            .enumerate()
+            .map(|(i, gpu)| format!("{i} ({})", gpu.bdf()))
+            .collect::<Vec<_>>();
+
+        return Err(match valid.is_empty() {
+            true => NvTrustError::DeviceNotFound(format!(
+                "GPU index {index} is out of range: no GPU found"
+            )),
+            false => NvTrustError::DeviceNotFound(format!(
+                "GPU index {index} is out of range; valid indices are {}",
+                valid.join(", ")
+            )),
+        });
+    }
+
+    open_gpu(&gpus[index].path)
+}
+
+/// Look up the marketing name of an NVIDIA device ID.
+pub fn device_name(device_id: u16) -> Option<&'static str> {
+    crate::arch::DEVICES
+        .iter()
+        .find(|(id, _, _)| *id == device_id)
+        .map(|(_, name, _)| *name)
+}
+
+/// Find the GPUs whose marketing name contains the given substring, ignoring case.
+pub fn find_gpus_by_name(name: &str) -> Result<Vec<GpuObject>> {
+    let name = name.to_lowercase();
+
+    Ok(find_gpus_by_bdf("")?
+        .into_iter()
+        .filter(|gpu| {
+            gpu.get_device_handle()
+                .get_product_name()
+                .is_some_and(|product| product.to_lowercase().contains(&name))
+        })
+        .collect())
+}
+
+/// Find the devices matching any of the BDF substrings with the given search, e.g.,
+/// [`find_gpus_by_bdf`], or all of them if there are none.
+pub fn find_by_bdfs(
+    bdfs: &[String],
+    find: fn(&str) -> Result<Vec<GpuObject>>,
+) -> Result<Vec<GpuObject>> {
+    if bdfs.is_empty() {
+        return find("");
+    }
+
+    let mut gpus: Vec<GpuObject> = vec![];
+    for bdf in bdfs {
+        for gpu in find(bdf)? {
+            if !gpus.iter().any(|other| other.get_bdf() == gpu.get_bdf()) {
+                gpus.push(gpu);
             }
         }
     }
@@ -38,25 +354,331 @@ pub fn find_gpus_by_bdf(bdf: &str) -> Result<Vec<GpuObject>> {
     Ok(gpus)
 }
 
-pub fn find_gpus_by_name(name: String) -> Result<Vec<String>> {
-    let mut gpus = vec![];
-    let devices = std::fs::read_dir(PCI_DEVICES)?;
+/// Open every device of the board for board-wide configuration, which must not leave any out,
+/// e.g., one bound to a GPU driver.
+pub fn open_board() -> Result<Vec<GpuObject>> {
+    let (devices, failed) = open_devices_by_bdf("")?;
+    for (bdf, e) in failed.iter() {
+        log::error!("Cannot open {bdf}: {e}");
+    }
+    if !failed.is_empty() {
+        return Err(NvTrustError::DeviceNotFound(format!(
+            "{} devices of the board cannot be opened, and board-wide configuration cannot leave them out",
+            failed.len()
+        )));
+    }
+
+    Ok(devices)
+}
+
+/// How the GPUs to run on are selected, in the order of precedence of the fields.
+#[derive(Debug, Clone, Default)]
+pub struct Selector {
+    /// Every GPU and NVSwitch.
+    pub all: bool,
+    /// Substrings of the BDFs: all the devices matching any of them if there are several, else
+    /// the first device matching the one.
+    pub bdfs: Vec<String>,
+    /// A substring of the marketing name; the first GPU matching it.
+    pub name: Option<String>,
+    /// The index of the GPU, in the order of [`find_gpu_by_index`].
+    pub index: Option<usize>,
+}
+
+/// The devices selected, and the BDFs of those that cannot be opened or the selectors that
+/// match nothing.
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub gpus: Vec<GpuObject>,
+    pub failed: Vec<String>,
+}
 
-    for device in devices {
-        let device = device?;
-        let path = device.path();
-        let path = path.to_string_lossy().to_string();
+/// Select and open the GPUs. Only selecting several devices tolerates some of them failing, which
+/// [`Selection::failed`] lists; a single one fails the selection.
+pub fn select(selector: &Selector) -> Result<Selection> {
+    let mut selection = Selection::default();
 
-        if path.contains(&name) {
-            gpus.push(path);
+    if selector.all {
+        let (gpus, failed) = open_devices_by_bdf("")?;
+        for (bdf, e) in failed {
+            log::error!("{bdf}: {e}");
+            selection.failed.push(bdf);
+        }
+        selection.gpus = gpus;
+    } else if selector.bdfs.len() > 1 {
+        for bdf in selector.bdfs.iter() {
+            let (found, failed) = open_devices_by_bdf(bdf)?;
+            if found.is_empty() && failed.is_empty() {
+                log::error!("Matching for {bdf} found nothing");
+                selection.failed.push(bdf.clone());
+            }
+            for (bdf, e) in failed {
+                if !selection.failed.contains(&bdf) {
+                    log::error!("{bdf}: {e}");
+                    selection.failed.push(bdf);
+                }
+            }
+            for gpu in found {
+                if !selection
+                    .gpus
+                    .iter()
+                    .any(|other| other.get_bdf() == gpu.get_bdf())
+                {
+                    selection.gpus.push(gpu);
+                }
+            }
+        }
+    } else if let Some(bdf) = selector.bdfs.first() {
+        let (gpus, mut failed) = open_devices_by_bdf(bdf)?;
+        if gpus.is_empty() {
+            return Err(match failed.pop() {
+                Some((_, e)) => e,
+                None => NvTrustError::DeviceNotFound(format!("Matching for {bdf} found nothing")),
+            });
+        }
+        for (bdf, e) in failed {
+            log::warn!("Skipping {bdf}: {e}");
+        }
+        if gpus.len() > 1 {
+            log::warn!(
+                "Matching for {bdf} found multiple GPUs: {:?}. Use the first one.",
+                gpus.iter().map(|gpu| gpu.get_bdf()).collect::<Vec<_>>(),
+            );
+        }
+        selection.gpus = gpus.into_iter().take(1).collect();
+    } else if let Some(name) = &selector.name {
+        let gpus = find_gpus_by_name(name)?;
+        if gpus.is_empty() {
+            return Err(NvTrustError::DeviceNotFound(format!(
+                "Matching for {name} found nothing"
+            )));
         }
+        if gpus.len() > 1 {
+            log::warn!(
+                "Matching for {name} found multiple GPUs: {:?}. Use the first one.",
+                gpus.iter().map(|gpu| gpu.get_bdf()).collect::<Vec<_>>(),
+            );
+        }
+        selection.gpus = gpus.into_iter().take(1).collect();
+    } else if let Some(index) = selector.index {
+        selection.gpus = vec![find_gpu_by_index(index)?];
+    } else {
+        return Err(NvTrustError::InvalidArgument(
+            "no GPU selected, by index, BDF or name".to_string(),
+        ));
     }
 
-    Ok(gpus)
+    Ok(selection)
+}
+
+/// Read the system memory at the given physical address through `/dev/mem`.
+pub fn read_sysmem(addr: u64, len: usize) -> Result<Vec<u8>> {
+    let base = addr & !(PAGE_SIZE - 1);
+    let delta = (addr - base) as usize;
+    let mapping = Mapping::new(
+        &format!("{MEM_FILE} at 0x{addr:x}"),
+        base,
+        delta + len,
+        false,
+    )?;
+
+    let mut buf = vec![0u8; len];
+    unsafe {
+        std::ptr::copy_nonoverlapping(mapping.as_ptr().add(delta), buf.as_mut_ptr(), len);
+    }
+
+    Ok(buf)
+}
+
+/// A mapping of physical memory through `/dev/mem`, unmapped when dropped.
+#[derive(Debug)]
+pub struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    /// Map `len` bytes at the page-aligned physical address `addr`; `what` names the mapping in
+    /// the error.
+    ///
+    /// The file descriptor is closed right away, as the mapping outlives it.
+    pub fn new(what: &str, addr: u64, len: usize, writable: bool) -> Result<Self> {
+        let flags = match writable {
+            true => fs::OFlags::RDWR,
+            false => fs::OFlags::RDONLY,
+        };
+        let fd = fs::open(MEM_FILE, flags, fs::Mode::empty()).map_err(|source| match source {
+            io::Errno::PERM | io::Errno::ACCESS => NvTrustError::DevMemDenied {
+                source,
+                reason: crate::preflight::devmem_blocker(),
+            },
+            source => source.into(),
+        })?;
+
+        Self::from_fd(what, fd, addr, len, writable)
+    }
+
+    /// Map `len` bytes at the page-aligned `offset` of a sysfs `resource<N>` file of a PCI
+    /// device, which keeps working when `/dev/mem` is locked down.
+    pub fn from_resource<P: AsRef<Path>>(
+        what: &str,
+        path: P,
+        offset: u64,
+        len: usize,
+        writable: bool,
+    ) -> Result<Self> {
+        let flags = match writable {
+            true => fs::OFlags::RDWR,
+            false => fs::OFlags::RDONLY,
+        };
+        let fd = fs::open(path.as_ref(), flags, fs::Mode::empty())?;
+
+        Self::from_fd(what, fd, offset, len, writable)
+    }
+
+    /// Map `len` bytes at the page-aligned `offset` of an open file.
+    pub(crate) fn from_fd(
+        what: &str,
+        fd: OwnedFd,
+        offset: u64,
+        len: usize,
+        writable: bool,
+    ) -> Result<Self> {
+        let prot = match writable {
+            true => mm::ProtFlags::READ | mm::ProtFlags::WRITE,
+            false => mm::ProtFlags::READ,
+        };
+
+        let ptr = unsafe {
+            mm::mmap(
+                std::ptr::null_mut(),
+                len,
+                prot,
+                mm::MapFlags::SHARED,
+                fd,
+                offset,
+            )
+        }
+        .map_err(|source| NvTrustError::MmapFailed {
+            what: what.to_string(),
+            source,
+        })?;
+
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    #[inline]
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Read a value at the given offset with a single volatile access, so that the compiler
+    /// neither elides, merges nor reorders it with respect to other MMIO accesses.
+    #[inline]
+    pub fn read_volatile<T: Copy>(&self, offset: u64) -> T {
+        unsafe { std::ptr::read_volatile(self.ptr.add(offset as _) as *const T) }
+    }
+
+    /// Write a value at the given offset with a single volatile access.
+    #[inline]
+    pub fn write_volatile<T: Copy>(&self, offset: u64, val: T) {
+        unsafe { std::ptr::write_volatile(self.ptr.add(offset as _) as *mut T, val) }
+    }
+}
+
+// The mapping is device or system memory that is only accessed through raw pointers, and it stays
+// valid until dropped, so it can be shared between threads like the memory it maps.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { mm::munmap(self.ptr as *mut _, self.len) } {
+            log::warn!("Cannot unmap {:p}: {e}", self.ptr);
+        }
+    }
+}
+
+/// Check if the value read from BAR0 is an error code rather than a value: one of the
+/// 0xbadfXXXX codes of the PRI ring or the 0xbad00XXX codes of the host interface.
+#[inline]
+pub fn is_mmio_error(val: u32) -> bool {
+    NvidiaMmioErrorCode::decode(val).is_some()
+}
+
+impl NvidiaMmioErrorCode {
+    /// The error codes, each with the mask of the bits that identify it and its probable cause.
+    const DECODE: [(Self, u32, &'static str); 7] = [
+        (
+            Self::NONEXISTENT_REG,
+            0xffffff00,
+            "no register at this offset",
+        ),
+        (Self::VM_FAULT, 0xffffff00, "a VM fault on the access"),
+        (
+            Self::TARGET_REFUSE_TX,
+            0xffffff00,
+            "the target unit refused the access",
+        ),
+        (Self::NO_TARGET, 0xffffff00, "no unit decodes this offset"),
+        (
+            Self::TARGET_DISABLED_PMCE,
+            0xffffff00,
+            "the target unit is disabled in NV_PMC_ENABLE",
+        ),
+        (
+            Self::TARGET_DISABLED_PRING,
+            0xffffff00,
+            "the target unit is disabled or floorswept on the PRI ring",
+        ),
+        (
+            Self::OTHER_ERROR,
+            0xfffff000,
+            "a PRI ring error, e.g., a timeout or the firewall",
+        ),
+    ];
+
+    /// Decode a value read from BAR0 into the error it reports, or `None` if it is a value.
+    pub fn decode(val: u32) -> Option<Self> {
+        match Self::DECODE
+            .iter()
+            .find(|(code, mask, _)| val & mask == code.bits())
+        {
+            Some((code, _, _)) => Some(Self::from_bits_retain(code.bits())),
+            // The PRI ring and the host interface report more errors than we know of.
+            None if (val >> 16) as u64 == NV_MMIO_ERROR_PREFIX
+                || (val >> 12) as u64 == NV_MMIO_ERROR_PREFIX_HOST =>
+            {
+                Some(Self::OTHER_ERROR)
+            }
+            None => None,
+        }
+    }
+
+    /// What probably caused the error.
+    pub fn cause(&self) -> &'static str {
+        Self::DECODE
+            .iter()
+            .find(|(code, _, _)| code.bits() == self.bits())
+            .map_or("an unknown error", |(_, _, cause)| cause)
+    }
 }
 
 /// A structure representing a base address register (BAR).
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
 pub struct Bar {
     /// The address of the BAR.
     pub addr: u64,
@@ -64,48 +686,49 @@ pub struct Bar {
     pub size: u64,
     /// The type of the BAR.
     pub is_64: bool,
+    /// The index of the sysfs `resource<N>` file of the BAR; a 64-bit BAR takes two indices.
+    pub resource: usize,
 }
 
 /// A structure representing the configuration of a PCI device.
 ///
 /// Refer to the PCI Local Bus Specification, Revision 3.0 for more information.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[repr(C, align(4))]
 pub struct RawConfig {
     pub vendor: u16,
     pub device: u16,
-    pub status: u16,
     pub command: u16,
+    pub status: u16,
     pub rev_id: u8,
     pub class_code: [u8; 3],
-    pub bist: u8,
-    pub header_type: u8,
-    pub latency_timer: u8,
     pub cache_line_size: u8,
+    pub latency_timer: u8,
+    pub header_type: u8,
+    pub bist: u8,
     pub bars: [u32; 6],
     pub cardbus_cis_pointer: u32,
     pub subsystem_vendor_id: u16,
     pub subsystem_id: u16,
     pub expansion_rom_base_address: u32,
     pub capabilities_pointer: u8,
+    #[serde(skip)]
     _pad0: [u8; 3],
+    #[serde(skip)]
     _pad1: u32,
-    pub max_latency: u8,
-    pub min_grant: u8,
-    pub interrupt_pin: u8,
     pub interrupt_line: u8,
-}
-
-#[derive(Debug)]
-pub struct Config {
-    pub config: RawConfig,
-    pub file_fd: OwnedFd,
+    pub interrupt_pin: u8,
+    pub min_grant: u8,
+    pub max_latency: u8,
 }
 
 impl RawConfig {
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
         if bytes.len() < std::mem::size_of::<Self>() {
-            return Err(anyhow!("Invalid length"));
+            return Err(NvTrustError::InvalidArgument(format!(
+                "config space of {} bytes is too short",
+                bytes.len()
+            )));
         }
 
         let mut config = unsafe { std::mem::zeroed::<Self>() };
@@ -117,133 +740,571 @@ impl RawConfig {
 
         Ok(config)
     }
-}
+}
+
+/// A process that holds a device open.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Holder {
+    pub pid: u32,
+    pub command: String,
+    /// The file through which it holds the device, e.g., `/dev/nvidia0`.
+    pub file: String,
+}
+
+/// A structure representing a PCI device.
+#[derive(Debug)]
+pub struct PciDevice {
+    /// The path
+    path: String,
+    /// The standard header of the config space.
+    config: RawConfig,
+    /// How the config space, the BARs and the reset are accessed.
+    backend: Arc<dyn DeviceBackend>,
+    /// The capabilities of the PCI device.
+    caps: HashMap<u8, u64>,
+    /// The PCIe extended capabilities of the PCI device.
+    ext_caps: HashMap<u16, u64>,
+    /// Whether the device is a SR-IOV virtual function.
+    is_vf: bool,
+    /// The base address registers, we only need the first 6 ones.
+    ///
+    /// From the (incomplete) documentation provided by NVIDIA, we know that
+    ///
+    /// - BAR0: MMIO registers. This is the main control space of the card - all engines are controlled
+    ///   through it, and it contains alternate means to access most of the other spaces.
+    /// - BAR1: VRAM aperture. This is an area of prefetchable memory that maps to the card’s VRAM.
+    bars: [Bar; 6],
+    /// The command register bits we enabled, to disable again when done.
+    enabled: AtomicU16,
+}
+
+impl Drop for PciDevice {
+    fn drop(&mut self) {
+        let bits = self.enabled.load(Ordering::Relaxed);
+        // A driver bound since owns the command register now.
+        if bits == 0 || self.get_driver().is_some() {
+            return;
+        }
+
+        let restored = self
+            .read_config16(PCI_COMMAND)
+            .and_then(|command| self.write_config16(PCI_COMMAND, command & !bits));
+        match restored {
+            Ok(()) => log::info!(
+                "Disabled command bits 0x{bits:x} of {} again",
+                self.get_bdf()
+            ),
+            Err(e) => log::warn!(
+                "Cannot restore the command register of {}: {e}",
+                self.get_bdf()
+            ),
+        }
+    }
+}
+
+/// A structure representing a GPU object.
+#[derive(Debug, Clone)]
+pub struct GpuObject {
+    /// The PCI device.
+    device: Arc<PciDevice>,
+    /// The first base address register.
+    bar0: Bar,
+    /// Whether the GPU is a SR-IOV virtual function, either seen from the host or from a guest.
+    is_vf: bool,
+    /// The architecture of the GPU, which decides the registers to use.
+    arch: Arch,
+    /// The lock taken before the GPU was opened, if any, see [`crate::lock::set_lock_on_open`].
+    pub(crate) lock: Option<Arc<DeviceLock>>,
+}
+
+impl PciDevice {
+    /// Create a new instance of `GpuObject`.
+    ///
+    /// This function will open the device at the given sysfs path with the backend that suits it,
+    /// see [`crate::backend::open`], and read the config.
+    pub fn new<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let backend = backend::open(path.as_ref())?;
+        Self::with_backend(path, backend)
+    }
+
+    /// Create a PCI device accessed through the given backend.
+    pub fn with_backend<P>(path: P, backend: Arc<dyn DeviceBackend>) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut buf = [0; std::mem::size_of::<RawConfig>()];
+        backend.read_config(0, &mut buf)?;
+
+        let mut config = RawConfig::from_bytes(buf.as_ref())?;
+
+        // The vendor and device IDs of a VF read as 0xffff from its config space, so we take them
+        // from the kernel instead.
+        let is_vf = path.as_ref().join("physfn").exists();
+        if is_vf {
+            let read_id = |name: &str| -> Result<u16> {
+                let id = std::fs::read_to_string(path.as_ref().join(name))?;
+                Ok(u16::from_str_radix(id.trim().trim_start_matches("0x"), 16)?)
+            };
+
+            config.vendor = read_id("vendor")?;
+            config.device = read_id("device")?;
+        }
+
+        if config.vendor != NVIDIA_VENDOR_ID
+            || (Arch::from_device_id(config.device).is_none() && !is_vf)
+        {
+            return Err(NvTrustError::UnsupportedDevice(format!(
+                "{:04x}:{:04x}",
+                config.vendor, config.device
+            )));
+        }
+
+        Ok(Self::from_parts(path, config, backend, is_vf))
+    }
+
+    /// Create a PCI device of any vendor, e.g., the PCIe port above a GPU.
+    pub fn any_with_backend<P>(path: P, backend: Arc<dyn DeviceBackend>) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut buf = [0; std::mem::size_of::<RawConfig>()];
+        backend.read_config(0, &mut buf)?;
+
+        Ok(Self::from_parts(
+            path,
+            RawConfig::from_bytes(buf.as_ref())?,
+            backend,
+            false,
+        ))
+    }
+
+    fn from_parts<P: AsRef<Path>>(
+        path: P,
+        config: RawConfig,
+        backend: Arc<dyn DeviceBackend>,
+        is_vf: bool,
+    ) -> Self {
+        Self {
+            path: path.as_ref().to_string_lossy().to_string(),
+            config,
+            backend,
+            caps: HashMap::new(),
+            ext_caps: HashMap::new(),
+            is_vf,
+            bars: Default::default(),
+            enabled: AtomicU16::new(0),
+        }
+    }
+
+    /// Set the given command register bits, e.g., [`PCI_COMMAND_MEMORY`] without which BAR reads
+    /// return all ones, if they are not set yet. The bits are cleared again when the device is
+    /// dropped, unless a driver is bound by then.
+    pub fn enable_command(&self, bits: u16) -> Result<()> {
+        let command = self.read_config16(PCI_COMMAND)?;
+        let missing = bits & !command;
+        if missing == 0 {
+            return Ok(());
+        }
+
+        log::info!(
+            "Enabling command bits 0x{missing:x} of {}: 0x{command:x} to 0x{:x}",
+            self.get_bdf(),
+            command | missing
+        );
+        self.write_config16(PCI_COMMAND, command | missing)?;
+        self.enabled.fetch_or(missing, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Initialize the capabilities of the PCI device, both the legacy ones and the PCIe extended
+    /// ones.
+    pub fn init_caps(&mut self) -> Result<()> {
+        if self.config.capabilities_pointer == CAP_ID_MASK as u8 {
+            return Err(NvTrustError::UnsupportedDevice(format!(
+                "{} has no capabilities",
+                self.get_bdf()
+            )));
+        }
+
+        let config = self.read_config_space()?;
+        for (extended, id, offset) in pcicfg::capabilities(&config) {
+            match extended {
+                false => {
+                    self.caps.insert(id as u8, offset);
+                }
+                true => {
+                    self.ext_caps.entry(id).or_insert(offset);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the whole config space.
+    ///
+    /// Only root can read past the first 256 bytes; others get the legacy config space only.
+    pub fn read_config_space(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![0; PCI_CFG_SPACE_EXP_SIZE as usize];
+        let len = self.backend.read_config(0, &mut buf)?;
+        buf.truncate(len);
+
+        Ok(buf)
+    }
+
+    /// Read a dword of the config space.
+    pub fn read_config32(&self, offset: u64) -> Result<u32> {
+        let mut data = [0; 4];
+        match self.backend.read_config(offset, &mut data)? {
+            4 => Ok(u32::from_le_bytes(data)),
+            _ => Err(self.unreadable_config(offset)),
+        }
+    }
+
+    /// Read a word of the config space.
+    pub fn read_config16(&self, offset: u64) -> Result<u16> {
+        let mut data = [0; 2];
+        match self.backend.read_config(offset, &mut data)? {
+            2 => Ok(u16::from_le_bytes(data)),
+            _ => Err(self.unreadable_config(offset)),
+        }
+    }
+
+    fn unreadable_config(&self, offset: u64) -> NvTrustError {
+        NvTrustError::NotSupported {
+            what: format!("config offset 0x{offset:x}"),
+            device: self.get_bdf().to_string(),
+            reason: "it is past the readable config space, which is 256 bytes unless root"
+                .to_string(),
+        }
+    }
+
+    pub fn write_config32(&self, offset: u64, value: u32) -> Result<()> {
+        self.backend.write_config(offset, &value.to_le_bytes())
+    }
+
+    pub fn write_config16(&self, offset: u64, value: u16) -> Result<()> {
+        self.backend.write_config(offset, &value.to_le_bytes())
+    }
+
+    /// Read and decode the whole config space: the header and every capability.
+    pub fn dump_config(&self) -> Result<pcicfg::ConfigDump> {
+        pcicfg::decode(self.get_bdf(), &self.read_config_space()?)
+    }
+
+    /// Get the legacy capabilities, by ID, with their offsets in the config space.
+    #[inline]
+    pub fn caps(&self) -> &HashMap<u8, u64> {
+        &self.caps
+    }
+
+    /// Get the PCIe extended capabilities, e.g., [`PCI_EXT_CAP_ID_DOE`], by ID with their offsets
+    /// in the config space. Only the first instance of a capability is kept.
+    #[inline]
+    pub fn ext_caps(&self) -> &HashMap<u16, u64> {
+        &self.ext_caps
+    }
+
+    /// Find the offset of the given PCIe extended capability.
+    pub fn find_ext_cap(&self, id: u64) -> Result<Option<u64>> {
+        Ok(self.ext_caps.get(&(id as u16)).copied())
+    }
+
+    /// Initialize the base address registers of the PCI device.
+    pub fn init_bars(&mut self) -> Result<()> {
+        self.bars = self.backend.bars()?;
+        Ok(())
+    }
+
+    /// Get the BARs in use; the unused ones and the upper halves of 64-bit BARs are skipped, so
+    /// the index of a BAR is not its BAR register, which is [`Bar::resource`].
+    #[inline]
+    pub fn bars(&self) -> &[Bar; 6] {
+        &self.bars
+    }
+
+    #[inline]
+    pub fn get_name(&self) -> &str {
+        &self.path
+    }
+
+    #[inline]
+    pub fn backend(&self) -> &dyn DeviceBackend {
+        self.backend.as_ref()
+    }
+
+    /// Get the name of the kernel driver bound to the device, if any.
+    pub fn get_driver(&self) -> Option<String> {
+        driver_of(&self.path)
+    }
+
+    /// Get the GPU driver bound to the device, nvidia or nouveau, which drives the registers
+    /// itself and races with anything else that does.
+    pub fn gpu_driver(&self) -> Option<String> {
+        self.get_driver()
+            .filter(|driver| GPU_DRIVERS.contains(&driver.as_str()))
+    }
+
+    /// Get the BDF of the device, e.g., `0000:01:00.0`.
+    #[inline]
+    pub fn get_bdf(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// Find the processes that hold the device open: through its nvidia device node, e.g.,
+    /// CUDA applications and nvidia-persistenced, or through its VFIO group, e.g., a VM.
+    pub fn holders(&self) -> Vec<Holder> {
+        let mut files = vec![];
+        let info =
+            std::fs::read_to_string(format!("{NVIDIA_PROC_GPUS}/{}/information", self.get_bdf()));
+        if let Some(minor) = info.ok().and_then(|info| {
+            info.lines()
+                .find_map(|line| line.strip_prefix("Device Minor:"))
+                .map(|minor| minor.trim().to_string())
+        }) {
+            files.push(format!("/dev/nvidia{minor}"));
+        }
+        if let Some(group) = std::fs::read_link(format!("{}/iommu_group", self.path))
+            .ok()
+            .and_then(|link| link.file_name().map(|s| s.to_string_lossy().to_string()))
+        {
+            files.push(format!("{VFIO_DIR}/{group}"));
+        }
+
+        let Ok(procs) = std::fs::read_dir("/proc") else {
+            return vec![];
+        };
+        let mut holders = vec![];
+        for pid in procs
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_string_lossy().parse::<u32>().ok())
+        {
+            let Ok(fds) = std::fs::read_dir(format!("/proc/{pid}/fd")) else {
+                continue;
+            };
+            let Some(file) = fds
+                .filter_map(|fd| fd.ok())
+                .filter_map(|fd| std::fs::read_link(fd.path()).ok())
+                .map(|target| target.to_string_lossy().to_string())
+                .find(|target| files.contains(target))
+            else {
+                continue;
+            };
+
+            let command = std::fs::read_to_string(format!("/proc/{pid}/comm")).unwrap_or_default();
+            holders.push(Holder {
+                pid,
+                command: command.trim().to_string(),
+                file,
+            });
+        }
+
+        holders
+    }
+
+    #[inline]
+    pub fn is_vf(&self) -> bool {
+        self.is_vf
+    }
+
+    /// Get the marketing name of the device, e.g., `H100 PCIe`.
+    #[inline]
+    pub fn get_product_name(&self) -> Option<&'static str> {
+        device_name(self.config.device)
+    }
 
-/// A structure representing a PCI device.
-#[derive(Debug)]
-pub struct PciDevice {
-    /// The path
-    path: String,
-    /// The configuration file.
-    config: Config,
-    /// The capabilities of the PCI device.
-    caps: HashMap<u8, u64>,
-    /// The base address registers, we only need the first 6 ones.
-    ///
-    /// From the (incomplete) documentation provided by NVIDIA, we know that
-    ///
-    /// - BAR0: MMIO registers. This is the main control space of the card - all engines are controlled
-    ///         through it, and it contains alternate means to access most of the other spaces.
-    /// - BAR1: VRAM aperture. This is an area of prefetchable memory that maps to the card’s VRAM.
-    bars: [Bar; 6],
-}
+    /// Unbind the kernel driver from the device, returning its name if one was bound.
+    pub fn unbind_driver(&self) -> Result<Option<String>> {
+        let driver = match self.get_driver() {
+            Some(driver) => driver,
+            None => return Ok(None),
+        };
 
-/// A structure representing a GPU object.
-#[derive(Debug, Clone)]
-pub struct GpuObject {
-    /// The PCI device.
-    device: Arc<PciDevice>,
-    /// The first base address register.
-    bar0: Bar,
-    /// base address register mappined into the memory.
-    bar0_mapped: *mut u8,
-}
+        std::fs::write(format!("{}/driver/unbind", self.path), self.get_bdf())?;
+        Ok(Some(driver))
+    }
 
-impl PciDevice {
-    /// Create a new instance of `GpuObject`.
+    /// Bind the given kernel driver to the device, e.g., the one [`Self::unbind_driver`]
+    /// unbound.
     ///
-    /// This function will open the file at the given path and read the config.
-    pub fn new<P>(path: P) -> Result<Self>
-    where
-        P: AsRef<Path>,
-    {
-        let config_path = path.as_ref().join("config");
-        let file_fd = fs::open(config_path, fs::OFlags::RDONLY, fs::Mode::all())?;
-
-        let mut buf = [0; std::mem::size_of::<RawConfig>()];
-        io::read(&file_fd, &mut buf)?;
+    /// The driver is forced through `driver_override`, since drivers like vfio-pci do not match
+    /// the device by its ID.
+    pub fn bind_driver(&self, driver: &str) -> Result<()> {
+        if !Path::new(PCI_DRIVERS).join(driver).exists() {
+            return Err(NvTrustError::NotSupported {
+                what: format!("binding {driver}"),
+                device: self.get_bdf().to_string(),
+                reason: format!("{driver} is not loaded; modprobe it first"),
+            });
+        }
 
-        let config = RawConfig::from_bytes(buf.as_ref())?;
+        std::fs::write(format!("{}/driver_override", self.path), driver)?;
+        let bound = std::fs::write(format!("{PCI_DRIVERS}/{driver}/bind"), self.get_bdf());
+        std::fs::write(format!("{}/driver_override", self.path), "\n")?;
 
-        if config.device != NVIDIA_HOPPER_H100 || config.vendor != NVIDIA_VENDOR_ID {
-            return Err(anyhow!(
-                "Invalid device found: {}:{}",
-                config.vendor,
-                config.device
-            ));
-        }
+        Ok(bound?)
+    }
 
-        Ok(Self {
-            path: path.as_ref().to_string_lossy().to_string(),
-            config: Config { config, file_fd },
-            caps: HashMap::new(),
-            bars: Default::default(),
-        })
+    /// Snapshot the config space, all 4KB of it if readable, to restore after a reset.
+    pub fn save_config_space(&self) -> Result<Vec<u8>> {
+        self.read_config_space()
     }
 
-    /// Initialize the capabilities of the PCI device.
-    pub fn init_caps(&mut self) -> Result<()> {
-        if self.config.config.capabilities_pointer == CAP_ID_MASK as _ {
-            return Err(anyhow!("No capabilities found"));
+    /// Restore the config space saved by [`Self::save_config_space`].
+    ///
+    /// Like the kernel, only the registers that software sets up are restored, and only those
+    /// that changed: the PCIe, Resizable BAR and AER controls first, then the standard header
+    /// from the last dword to the first so that the command register is written after the BARs,
+    /// and MSI and MSI-X last. The MSI-X table lives in a BAR and is left alone.
+    pub fn restore_config_space(&self, saved: &[u8]) -> Result<()> {
+        let current = self.read_config_space()?;
+        let restore = |offset: u64, len: u64| -> Result<()> {
+            let range = offset as usize..(offset + len) as usize;
+            match (saved.get(range.clone()), current.get(range)) {
+                (Some(old), Some(new)) if old != new => {
+                    log::debug!("Restoring config 0x{offset:x}: {old:x?}");
+                    self.backend.write_config(offset, old)
+                }
+                _ => Ok(()),
+            }
+        };
+        let caps = pcicfg::capabilities(saved);
+        let find = |extended: bool, id: u64| {
+            caps.iter()
+                .find(|(ext, cap, _)| *ext == extended && *cap as u64 == id)
+                .map(|(_, _, offset)| *offset)
+        };
+        let word = |offset: u64| {
+            saved
+                .get(offset as usize..offset as usize + 2)
+                .map_or(0, |w| u16::from_le_bytes([w[0], w[1]]))
+        };
+
+        if let Some(exp) = find(false, PCI_CAP_ID_EXP) {
+            for reg in [
+                PCI_EXP_DEVCTL,
+                PCI_EXP_LNKCTL,
+                PCI_EXP_SLTCTL,
+                PCI_EXP_RTCTL,
+                PCI_EXP_DEVCTL2,
+                PCI_EXP_LNKCTL2,
+            ] {
+                restore(exp + reg, 2)?;
+            }
         }
 
-        let mut ptr = self.config.config.capabilities_pointer;
+        // The BAR sizes go before the BARs, which the kernel assigned for them.
+        if let Some(rebar) = find(true, PCI_EXT_CAP_ID_REBAR) {
+            let count = ((word(rebar + PCI_REBAR_CTRL) as u32 & PCI_REBAR_CTRL_NBAR_MASK) >> 5)
+                .clamp(1, 6) as u64;
+            for i in 0..count {
+                restore(rebar + PCI_REBAR_CTRL + i * 8, 4)?;
+            }
+        }
 
-        while ptr != 0 {
-            let mut data = [0u8; 4];
-            fs::seek(&self.config.file_fd, fs::SeekFrom::Start(ptr as _))?;
-            io::read(&self.config.file_fd, &mut data)?;
+        if let Some(aer) = find(true, PCI_EXT_CAP_ID_ERR) {
+            for reg in [
+                PCI_ERR_UNCOR_MASK,
+                PCI_ERR_UNCOR_SEVER,
+                PCI_ERR_COR_MASK,
+                PCI_ERR_CAP,
+            ] {
+                restore(aer + reg, 4)?;
+            }
+        }
 
-            let cap_id = data[0];
-            let cap_next = data[1];
+        for i in (0..PCI_STD_HEADER_SIZEOF / 4).rev() {
+            restore(i * 4, 4)?;
+        }
 
-            self.caps.insert(cap_id, ptr as u64);
+        if let Some(msi) = find(false, PCI_CAP_ID_MSI) {
+            let flags = word(msi + PCI_MSI_FLAGS);
+            let data = match flags & PCI_MSI_FLAGS_64BIT != 0 {
+                true => PCI_MSI_DATA_64,
+                false => PCI_MSI_DATA_32,
+            };
+            // The address and data, and the mask bits after them, before the enable.
+            restore(msi + PCI_MSI_ADDRESS_LO, data - PCI_MSI_ADDRESS_LO + 2)?;
+            if flags & PCI_MSI_FLAGS_MASKBIT != 0 {
+                restore(msi + data + 4, 4)?;
+            }
+            restore(msi + PCI_MSI_FLAGS, 2)?;
+        }
 
-            ptr = cap_next;
+        if let Some(msix) = find(false, PCI_CAP_ID_MSIX) {
+            restore(msix + PCI_MSIX_FLAGS, 2)?;
         }
 
         Ok(())
     }
 
-    /// Initialize the base address registers of the PCI device.
-    pub fn init_bars(&mut self) -> Result<()> {
-        let rsrc_path = format!("{}/{}", self.path, "resource");
-        let raw_bars = std::fs::read_to_string(rsrc_path)?
-            .split("\n")
-            .map(|s| s.to_string())
-            .collect::<Vec<_>>();
+    /// Wait until the device answers config reads again after a reset.
+    pub fn wait_for_config_space(&self) -> Result<()> {
+        let now = std::time::Instant::now();
+        loop {
+            let mut vendor = [0; 2];
+            self.backend.read_config(0, &mut vendor)?;
+            // A device that is still initializing may answer with the Request Retry Status
+            // vendor ID instead.
+            if !matches!(u16::from_le_bytes(vendor), 0xffff | 0x0001) {
+                return Ok(());
+            }
 
-        let mut i = 0;
-        for bar in raw_bars.iter().take(6) {
-            log::debug!("BAR {}: {}", i, bar);
-            let bar = bar
-                .split(" ")
-                .map(|s| s.replace("0x", "").to_string())
-                .collect::<Vec<_>>();
-            let addr = u64::from_str_radix(&bar[0], 16)?;
-            let end = u64::from_str_radix(&bar[1], 16)?;
-            let flags = u64::from_str_radix(&bar[2], 16)?;
+            if now.elapsed().as_secs() > PCI_RESET_TIMEOUT {
+                return Err(NvTrustError::Timeout(format!(
+                    "{} to come back",
+                    self.get_bdf()
+                )));
+            }
 
-            // If the flag's bit 0 is set, then the BAR is not a MMIO BAR.
-            if flags & 0x1 == 0 {
-                // If the address is not 0, then the BAR is valid.
-                if addr != 0 {
-                    let size = end - addr + 1;
-                    let is_64 = (flags >> 1) & 0x3 == 0x2;
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
 
-                    self.bars[i] = Bar { addr, size, is_64 };
+    /// Reset the function with an FLR through the Device Control register, the way the kernel
+    /// does when it picks FLR for `/sys/.../reset`, and wait until the device is back.
+    ///
+    /// Nothing is saved or restored; see [`GpuObject::function_level_reset`].
+    pub fn flr(&self) -> Result<()> {
+        let offset = self.express_offset()?;
+        if self.read_config32(offset + PCI_EXP_DEVCAP)? & PCI_EXP_DEVCAP_FLR == 0 {
+            return Err(NvTrustError::NotSupported {
+                what: "FLR".to_string(),
+                device: self.get_bdf().to_string(),
+                reason: "it does not advertise it".to_string(),
+            });
+        }
 
-                    i += 1;
-                }
+        // Stop issuing requests and let the outstanding ones complete, or they may be lost.
+        self.write_config16(PCI_COMMAND, PCI_COMMAND_INTX_DISABLE)?;
+        let now = std::time::Instant::now();
+        while self.read_config16(offset + PCI_EXP_DEVSTA)? & PCI_EXP_DEVSTA_TRPND != 0 {
+            if now.elapsed().as_millis() >= PCI_FLR_PENDING_TIMEOUT as u128 {
+                log::warn!(
+                    "{} still has pending transactions, resetting anyway",
+                    self.get_bdf()
+                );
+                break;
             }
+
+            std::thread::sleep(std::time::Duration::from_millis(10));
         }
 
-        Ok(())
+        log::info!("Resetting {} with an FLR", self.get_bdf());
+        let ctrl = self.read_config16(offset + PCI_EXP_DEVCTL)?;
+        self.write_config16(offset + PCI_EXP_DEVCTL, ctrl | PCI_EXP_DEVCTL_BCR_FLR)?;
+        std::thread::sleep(std::time::Duration::from_millis(PCI_RESET_READY_DELAY));
+
+        self.wait_for_config_space()
     }
 
     #[inline]
-    pub fn get_name(&self) -> &str {
-        &self.path
+    pub fn get_config(&self) -> &RawConfig {
+        &self.config
     }
 }
 
@@ -252,46 +1313,46 @@ impl GpuObject {
     ///
     /// This function will read the value at the given address and compare it with the value at the given
     /// address in the iomem file. If the values are not the same, then this function will return an error.
-    fn sanity_check(fd: OwnedFd, addr: *const u8, target: &str) -> Result<()> {
-        let boot = unsafe { std::ptr::read_volatile((addr.add(NV_PMC_BOOT_0 as _)) as *const u32) };
-        if boot == 0xffffffff {
-            return Err(anyhow!("sanity check of mmio failed"));
-        }
-
+    fn sanity_check(boot: u32, target: &str) -> Result<()> {
         let iomem = std::fs::read_to_string(IOMEM_FILE)?
             .split("\n")
             .map(|s| s.to_string())
             .collect::<Vec<String>>();
 
         if let Some(line) = iomem.iter().find(|line| line.contains(target)) {
-            let res = line
-                .trim()
-                .split(" ")
-                .collect::<Vec<_>>()
-                .first()
-                .ok_or(anyhow!("No match found for {target}"))?
-                .split("-")
-                .map(|s| u64::from_str_radix(s, 16).unwrap())
-                .collect::<Vec<_>>();
-
-            let (start, end) = (res[0], res[1]);
-            let size = end - start + 1;
-
-            let mapped = unsafe {
-                mm::mmap(
-                    std::ptr::null_mut(),
-                    size as _,
-                    mm::ProtFlags::READ | mm::ProtFlags::WRITE,
-                    mm::MapFlags::SHARED,
-                    fd,
-                    start as _,
-                )? as *mut u8
+            let malformed = || {
+                NvTrustError::Malformed(format!(
+                    "malformed {target} range in {IOMEM_FILE}: {}",
+                    line.trim()
+                ))
             };
-
-            let boot_val = unsafe { std::ptr::read_volatile(mapped as *const u32) };
+            let (start, end) = line
+                .split_whitespace()
+                .next()
+                .and_then(|range| range.split_once('-'))
+                .ok_or_else(malformed)?;
+            let start = u64::from_str_radix(start, 16).map_err(|_| malformed())?;
+            let end = u64::from_str_radix(end, 16).map_err(|_| malformed())?;
+            // The end is inclusive; the range must hold at least the boot register.
+            let size = end
+                .checked_sub(start)
+                .and_then(|size| size.checked_add(1))
+                .filter(|size| *size >= size_of::<u32>() as u64)
+                .ok_or_else(malformed)?;
+
+            let mapping = Mapping::new(
+                &format!("the {target} range of {IOMEM_FILE}"),
+                start,
+                size as _,
+                false,
+            )?;
+
+            let boot_val = mapping.read_volatile::<u32>(0);
 
             if boot_val != boot {
-                return Err(anyhow!("sanity check of iomem failed"));
+                return Err(NvTrustError::UnsupportedDevice(format!(
+                    "BAR0 reads 0x{boot:x} but the {target} range of {IOMEM_FILE} reads 0x{boot_val:x}"
+                )));
             }
         }
 
@@ -300,38 +1361,338 @@ impl GpuObject {
 
     /// Reset the GPU with the OS.
     pub fn sysfs_reset(&self) -> Result<()> {
-        let reset_path = format!("{}/{}", self.device.path, "reset");
-        let reset_fd = fs::open(reset_path, fs::OFlags::WRONLY, fs::Mode::all())?;
-        io::write(&reset_fd, b"1")?;
+        self.ensure_pf("Reset")?;
 
-        Ok(())
+        self.device.backend.reset()
+    }
+
+    /// Reset the GPU with an FLR of our own, restoring its config space afterwards.
+    pub fn function_level_reset(&self) -> Result<()> {
+        let saved = self.device.save_config_space()?;
+        self.device.flr()?;
+        self.device.restore_config_space(&saved)
+    }
+
+    /// Reset the GPU with the method set by [`set_reset_method`], the OS one by default.
+    pub fn reset(&self) -> Result<()> {
+        match RESET_METHOD.get().copied().unwrap_or_default() {
+            ResetMethod::Os => self.sysfs_reset(),
+            ResetMethod::Flr => self.function_level_reset(),
+            ResetMethod::SecondaryBus => self.secondary_bus_reset(),
+        }
+    }
+
+    /// Reset the GPU so that the CC mode programmed by `set-cc-mode` becomes active.
+    ///
+    /// Any bound driver is unbound first. The config space is saved before and restored after the
+    /// reset, since the BAR0 mapping relies on the BARs staying where they were.
+    pub fn reset_after_cc_mode_switch(&self) -> Result<CcMode> {
+        self.ensure_pf("Reset")?;
+
+        let pending = self.pending_cc_mode()?;
+        log::info!("{}: pending CC mode {pending}", self.get_bdf());
+
+        // vfio-pci stays bound, as it would block the unbind until we close the device.
+        if self.device.backend.kind() == BackendKind::Vfio {
+            log::info!("Resetting {} through VFIO", self.get_bdf());
+        } else if let Some(driver) = self.device.unbind_driver()? {
+            log::info!("Unbound {driver} from {}", self.get_bdf());
+        }
+        self.quiesce()?;
+
+        let config = self.device.save_config_space()?;
+        self.reset()?;
+        self.device.wait_for_config_space()?;
+        self.device.restore_config_space(&config)?;
+
+        self.wait_for_boot()?;
+        let current = self.query_cc_mode()?;
+        if current != pending {
+            return Err(NvTrustError::CcSwitchFailed {
+                device: self.get_label(),
+                reason: format!("CC mode {current} after the reset instead of {pending}"),
+            });
+        }
+
+        Ok(current)
+    }
+
+    /// Let go of the GPU before its driver is unbound, which blocks while anyone holds it, us
+    /// included through VFIO, and open it again for its config space only.
+    pub fn release(self) -> Result<PciDevice> {
+        let path = self.get_name().to_string();
+        drop(self);
+
+        let device = open_port(&path)?;
+        if let Some(holder) = device.holders().first() {
+            return Err(NvTrustError::NotSupported {
+                what: "Unbinding the driver".to_string(),
+                device: device.get_bdf().to_string(),
+                reason: format!(
+                    "it is in use by {} (pid {}) through {}; stop it first",
+                    holder.command, holder.pid, holder.file
+                ),
+            });
+        }
+
+        Ok(device)
+    }
+
+    /// Reset the GPU from whatever state it is in and hand it back to its driver: unbind the
+    /// driver, reset the GPU with the method set by [`set_reset_method`], wait for it to boot,
+    /// restore its config space and bind the driver again. Returns the driver, if one was bound.
+    ///
+    /// Fails before touching anything if a process holds the GPU open, since the unbind would
+    /// block until it lets go. The driver is bound again even if the reset fails.
+    pub fn full_reset(&self) -> Result<Option<String>> {
+        self.ensure_pf("Reset")?;
+
+        if let Some(holder) = self.device.holders().first() {
+            return Err(NvTrustError::NotSupported {
+                what: "Reset".to_string(),
+                device: self.get_label(),
+                reason: format!(
+                    "it is in use by {} (pid {}) through {}",
+                    holder.command, holder.pid, holder.file
+                ),
+            });
+        }
+
+        // vfio-pci stays bound, as it would block the unbind until we close the device.
+        let driver = match self.device.backend.kind() {
+            BackendKind::Vfio => None,
+            _ => self.device.unbind_driver()?,
+        };
+        if let Some(driver) = &driver {
+            log::info!("Unbound {driver} from {}", self.get_bdf());
+        }
+
+        let reset = self.reset_unbound();
+        if let Some(driver) = &driver {
+            log::info!("Binding {driver} to {} again", self.get_bdf());
+            match (self.device.bind_driver(driver), &reset) {
+                (Ok(()), _) => {}
+                // The failed reset is the cause, so it is the error to report.
+                (Err(e), Err(_)) => log::error!("Cannot bind {driver} to {}: {e}", self.get_bdf()),
+                (Err(e), Ok(())) => return Err(e),
+            }
+        }
+
+        reset.map(|_| driver)
+    }
+
+    fn reset_unbound(&self) -> Result<()> {
+        self.quiesce()?;
+        let boot = self.read32(NV_PMC_BOOT_0)?;
+
+        let config = self.device.save_config_space()?;
+        self.reset()?;
+        self.device.wait_for_config_space()?;
+        self.device.restore_config_space(&config)?;
+
+        // BAR0 reads all ones until the GPU is out of reset.
+        self.poll_with_timeout(
+            "NV_PMC_BOOT_0",
+            NV_PMC_BOOT_0,
+            0xffffffff,
+            boot,
+            std::time::Duration::from_secs(NV_BOOT_TIMEOUT),
+        )?;
+        self.wait_for_boot()
     }
 
+    /// Make sure nothing else is driving the GPU before it gets reset.
+    ///
+    /// A GPU driver bound to the device would lose the GPU under its feet, and a reset in the
+    /// middle of an FSP transaction leaves the FSP in an undefined state.
+    pub fn quiesce(&self) -> Result<()> {
+        if let Some(driver) = self.device.gpu_driver() {
+            return Err(NvTrustError::NotSupported {
+                what: "Reset".to_string(),
+                device: self.get_label(),
+                reason: format!("it is bound to {driver}; unbind it first"),
+            });
+        }
+
+        if !self.arch.has_cc() {
+            return Ok(());
+        }
+
+        crate::fsp::FspRpc::new(self, NV_FSP_CHANNEL)?.poll_for_queue_empty()
+    }
+
+    /// Query the active CC mode; GPUs that do not support CC are reported as off.
     pub fn query_cc_mode(&self) -> Result<CcMode> {
         self.wait_for_boot()?;
 
-        let mode = self.read8(NV_CC_MODE)?;
-        Ok(CcMode::from_bits_truncate(mode & 0b11))
+        let register = match self.regs().cc_mode(self.is_vf) {
+            Some(register) => register,
+            None => return Ok(CcMode::CC_MODE_OFF),
+        };
+
+        // An error code would read as a mode.
+        let mode = self.checked_read32(register & !0x3)? >> ((register & 0x3) * 8);
+        Ok(CcMode::from_bits_truncate(mode as u8 & 0b11))
     }
 
+    /// Read `len` bytes at the given host physical address, which must fall in BAR0 or BAR1.
+    ///
+    /// BAR0 addresses are read as registers. BAR1 addresses are read from VRAM through the PRAMIN
+    /// window, assuming BAR1 maps VRAM linearly as it does without a driver, since the BAR1 page
+    /// tables cannot be trusted to be set up.
     pub fn read_phys(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
-        let mut data = vec![0u8; len];
+        match self.locate_phys(addr, len)? {
+            (0, offset) => self.read_dwords(offset, len),
+            (_, offset) => self.read_vram(offset, len),
+        }
+    }
+
+    /// Write `data` at the given host physical address, the counterpart of [`Self::read_phys`].
+    ///
+    /// BAR0 writes must be dword aligned and dword sized, as they go to registers.
+    pub fn write_phys(&self, addr: u64, data: &[u8]) -> Result<()> {
+        match self.locate_phys(addr, data.len())? {
+            (0, offset) => self.write(offset, data),
+            (_, offset) => self.write_vram(offset, data),
+        }
+    }
+
+    /// Find the BAR that `len` bytes at the host physical address fall in, returning its index and
+    /// the offset into it.
+    fn locate_phys(&self, addr: u64, len: usize) -> Result<(usize, u64)> {
+        let bar0 = &self.bar0;
+        let bar1 = &self.device.bars[1];
+        let end = addr
+            .checked_add(len as u64)
+            .ok_or(NvTrustError::InvalidArgument(format!(
+                "0x{:x} + 0x{:x} overflows",
+                addr, len
+            )))?;
+        let contains = |bar: &Bar| bar.size != 0 && addr >= bar.addr && end <= bar.addr + bar.size;
+
+        if contains(bar0) {
+            log::debug!("0x{:x} is in BAR0", addr);
+            Ok((0, addr - bar0.addr))
+        } else if contains(bar1) {
+            log::debug!("0x{:x} is in BAR1", addr);
+            Ok((1, addr - bar1.addr))
+        } else {
+            let ranges = [("BAR0", bar0), ("BAR1", bar1)]
+                .iter()
+                .filter(|(_, bar)| bar.size != 0)
+                .map(|(name, bar)| format!("{name} 0x{:x}-0x{:x}", bar.addr, bar.addr + bar.size))
+                .collect::<Vec<_>>();
+
+            Err(NvTrustError::InvalidArgument(format!(
+                "0x{:x}-0x{:x} is not within an aperture of {}; valid ranges are {}",
+                addr,
+                end,
+                self.get_bdf(),
+                ranges.join(", ")
+            )))
+        }
+    }
+
+    /// Read `len` bytes at the given offset into the BAR1 aperture.
+    ///
+    /// Only the pages covering the range are mapped, as BAR1 may span all of VRAM. Without a
+    /// driver BAR1 maps VRAM linearly, so the offset is the framebuffer address; once a driver has
+    /// set up the BAR1 page tables, use [`Self::read_vram`] instead.
+    pub fn read_bar1(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let bar1 = self.device.bars[1];
+        if offset
+            .checked_add(len as u64)
+            .is_none_or(|end| end > bar1.size)
+        {
+            return Err(NvTrustError::OutOfRange {
+                bar: "BAR1",
+                offset,
+                size: len as _,
+                limit: bar1.size,
+            });
+        }
 
-        println!("addr: 0x{:x}", self.read32(NV_HOST_MEM)?);
-        self.write32(NV_HOST_MEM, addr as u32)?;
+        let start = offset & !0x3;
+        let skip = (offset - start) as usize;
+        let mut data = vec![0; (skip + len).next_multiple_of(4)];
+        self.device.backend.read_bar(1, start, &mut data)?;
 
-        for i in 0..len {
-            let b = self.read8(NV_PMC_PRAMIN_START + i as u64)?;
-            data[i] = b;
+        Ok(data[skip..skip + len].to_vec())
+    }
+
+    /// Read `len` bytes of registers at the BAR0 offset with dword accesses only, as byte
+    /// accesses to registers are not always honored, failing on an error code.
+    pub(crate) fn read_dwords(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        self.read_dwords_with(offset, len, Self::checked_read32)
+    }
+
+    /// Read `len` bytes of a data window of BAR0, e.g., the PROM or PRAMIN, like
+    /// [`Self::read_dwords`] but as is, since any dword, error codes included, may be data there.
+    pub(crate) fn read_window(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        self.read_dwords_with(offset, len, Self::read32)
+    }
+
+    fn read_dwords_with(
+        &self,
+        offset: u64,
+        len: usize,
+        read32: fn(&Self, u64) -> Result<u32>,
+    ) -> Result<Vec<u8>> {
+        let start = offset & !0x3;
+        let skip = (offset - start) as usize;
+
+        let mut data = Vec::with_capacity(skip + len + 4);
+        for i in (0..skip + len).step_by(4) {
+            data.extend(read32(self, start + i as u64)?.to_le_bytes());
         }
 
-        Ok(data)
+        Ok(data[skip..skip + len].to_vec())
     }
 
+    /// Wait for the firmware to finish booting the GPU, e.g., after a reset, before anything
+    /// talks to the FSP.
     pub fn wait_for_boot(&self) -> Result<()> {
-        self.poll_register("boot_complete", 0x200bc, 0xff, 5, 0.01, 0xffffffff)
+        let timeout = std::time::Duration::from_secs(NV_BOOT_TIMEOUT);
+
+        // The FSP is not visible to a VF; the host tells us when the VF is ready instead.
+        if self.is_vf {
+            let name = format!("{} to be ready", self.get_label());
+            return self
+                .poll_with_timeout(&name, NV_VF_READY, 0x1, 0x1, timeout)
+                .map(|_| ());
+        }
+
+        let (register, value, mask) = self.regs().boot_done();
+        let name = format!("{} to boot", self.get_label());
+        self.poll_with_timeout(&name, register, mask, value, timeout)
+            .map(|_| ())
+    }
+
+    /// Read a register until its bits under `mask` read `expected`, and return what it read
+    /// last, or time out with the value it is stuck at.
+    ///
+    /// Error codes count as not ready yet, since the PRI ring returns them while the GPU comes out
+    /// of reset; the last one is returned if the register never reads a value in time.
+    pub fn poll_with_timeout(
+        &self,
+        name: &str,
+        offset: u64,
+        mask: u32,
+        expected: u32,
+        timeout: std::time::Duration,
+    ) -> Result<u32> {
+        let interval = std::time::Duration::from_millis(NV_POLL_INTERVAL);
+        self.poll_until(offset, mask, expected, timeout, interval)?
+            .map_err(|reg| {
+                NvTrustError::Timeout(format!(
+                    "{name}: {} reads 0x{reg:08x}, not 0x{expected:x} under mask 0x{mask:x}",
+                    crate::regs::describe(offset)
+                ))
+            })
     }
 
+    /// Like [`Self::poll_with_timeout`], with the timeout in seconds and the interval between
+    /// reads in seconds.
     pub fn poll_register(
         &self,
         name: &str,
@@ -341,19 +1702,41 @@ impl GpuObject {
         sleep_interval: f64,
         mask: u32,
     ) -> Result<()> {
+        self.poll_until(
+            offset,
+            mask,
+            value,
+            std::time::Duration::from_secs(timeout),
+            std::time::Duration::from_secs_f64(sleep_interval),
+        )?
+        .map(|_| ())
+        .map_err(|_| NvTrustError::Timeout(name.to_string()))
+    }
+
+    /// Poll the register, giving either the value that matched or the last value read once the
+    /// timeout passes, or the last error code if it never read a value.
+    fn poll_until(
+        &self,
+        offset: u64,
+        mask: u32,
+        expected: u32,
+        timeout: std::time::Duration,
+        interval: std::time::Duration,
+    ) -> Result<std::result::Result<u32, u32>> {
         let now = std::time::Instant::now();
         loop {
-            if now.elapsed().as_secs() > timeout {
-                return Err(anyhow!("Timeout waiting for {}", name));
-            }
-
-            let reg = self.read32(offset)?;
+            let last = match self.checked_read32(offset) {
+                Ok(reg) if reg & mask == expected => return Ok(Ok(reg)),
+                Ok(reg) => Ok(Err(reg)),
+                Err(e @ NvTrustError::MmioError { .. }) => Err(e),
+                Err(e) => return Err(e),
+            };
 
-            if reg & mask == value {
-                return Ok(());
+            if now.elapsed() >= timeout {
+                return last;
             }
 
-            std::thread::sleep(std::time::Duration::from_secs_f64(sleep_interval));
+            std::thread::sleep(interval);
         }
     }
 
@@ -362,95 +1745,310 @@ impl GpuObject {
         self.device.get_name()
     }
 
+    #[inline]
+    pub fn get_bdf(&self) -> &str {
+        self.device.get_bdf()
+    }
+
     /// Create a new instance of `GpuObject`.
     pub fn new(device: Arc<PciDevice>) -> Result<Self> {
-        let fd = fs::open(MEM_FILE, fs::OFlags::RDWR, fs::Mode::all())?;
-        let fd_cloned = fd.try_clone()?;
         let bar0 = device.bars[0];
+        if bar0.size < NV_PMC_BOOT_1 + 4 {
+            return Err(NvTrustError::UnsupportedDevice(format!(
+                "{}: BAR0 of 0x{:x} bytes",
+                device.get_bdf(),
+                bar0.size
+            )));
+        }
 
-        let bar0_mapped = unsafe {
-            mm::mmap(
-                std::ptr::null_mut(),
-                bar0.size as _,
-                mm::ProtFlags::READ | mm::ProtFlags::WRITE,
-                mm::MapFlags::SHARED,
-                fd,
-                bar0.addr as _,
-            )?
-        } as *mut u8;
+        device.enable_command(PCI_COMMAND_MEMORY)?;
 
         // Do a simple sanity check to check if this register is valid.
-        let boot = unsafe { std::ptr::read_volatile(bar0_mapped as *const u32) };
+        let boot = device.backend.read32(0, NV_PMC_BOOT_0)?;
         if boot == 0xffffffff {
-            return Err(anyhow!("sanity check of mmio failed"));
+            return Err(NvTrustError::MmioError {
+                offset: NV_PMC_BOOT_0,
+                code: boot,
+                cause: "BAR0 is not decoded; the GPU may have fallen off the bus",
+            });
+        }
+        if let Some(error) = NvidiaMmioErrorCode::decode(boot) {
+            return Err(NvTrustError::MmioError {
+                offset: NV_PMC_BOOT_0,
+                code: boot,
+                cause: error.cause(),
+            });
+        }
+
+        // Within a guest the VF looks like a regular device; only the GPU itself knows.
+        let boot_1 = device.backend.read32(0, NV_PMC_BOOT_1)?;
+        let is_vf = device.is_vf()
+            || (boot_1 >> NV_PMC_BOOT_1_VGPU_SHIFT) & NV_PMC_BOOT_1_VGPU_MASK
+                == NV_PMC_BOOT_1_VGPU_VF;
+
+        let arch = Arch::from_boot0(boot)
+            .or(Arch::from_device_id(device.get_config().device))
+            .ok_or(NvTrustError::UnsupportedDevice(format!(
+                "{}: NV_PMC_BOOT_0 = 0x{:x}",
+                device.get_bdf(),
+                boot
+            )))?;
+
+        // Only the physical addresses of /dev/mem can be cross-checked with the iomem ranges.
+        if device.backend.kind() == BackendKind::DevMem {
+            GpuObject::sanity_check(boot, "nvidia")?;
         }
 
-        let res = Self {
+        Ok(Self {
             device,
             bar0,
-            bar0_mapped,
-        };
-
-        GpuObject::sanity_check(fd_cloned, bar0_mapped, "nvidia")?;
-        Ok(res)
+            is_vf,
+            arch,
+            lock: None,
+        })
     }
 
     pub fn get_device_handle(&self) -> Arc<PciDevice> {
         self.device.clone()
     }
 
-    /// Read the value at the given offset.
+    #[inline]
+    pub fn get_bar0(&self) -> &Bar {
+        &self.bar0
+    }
+
+    #[inline]
+    pub fn get_bar1(&self) -> &Bar {
+        &self.device.bars[1]
+    }
+
+    #[inline]
+    pub fn is_vf(&self) -> bool {
+        self.is_vf
+    }
+
+    #[inline]
+    pub fn get_arch(&self) -> Arch {
+        self.arch
+    }
+
+    /// Get the registers of the architecture of the GPU.
+    #[inline]
+    pub fn regs(&self) -> &'static dyn ArchRegs {
+        self.arch.regs()
+    }
+
+    /// Read the nanosecond timer of the GPU.
+    pub fn read_timer(&self) -> Result<u64> {
+        let (lo, hi) = self.regs().timer();
+
+        loop {
+            let high = self.read32(hi)?;
+            let low = self.read32(lo)?;
+            if self.read32(hi)? == high {
+                return Ok(((high as u64) << 32) | low as u64);
+            }
+        }
+    }
+
+    /// Whether the device is an NVSwitch rather than a GPU.
+    #[inline]
+    pub fn is_nvswitch(&self) -> bool {
+        self.arch == Arch::NvSwitch
+    }
+
+    /// Get a label of the GPU for the output, e.g., `0000:01:00.0 (VF)`.
+    pub fn get_label(&self) -> String {
+        if self.is_vf {
+            format!("{} (VF)", self.get_bdf())
+        } else if self.is_nvswitch() {
+            format!("{} (NVSwitch)", self.get_bdf())
+        } else {
+            self.get_bdf().to_string()
+        }
+    }
+
+    /// Fail if the GPU is a VF, for operations that only the host can do on the physical function.
+    pub fn ensure_pf(&self, what: &str) -> Result<()> {
+        if self.is_vf {
+            return Err(NvTrustError::NotSupported {
+                what: what.to_string(),
+                device: self.get_label(),
+                reason: "it is a virtual function".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Fail if the GPU does not support CC, for operations that need the FSP or the CC registers.
+    pub fn ensure_cc(&self, what: &str) -> Result<()> {
+        if !self.arch.has_cc() {
+            return Err(NvTrustError::NotSupported {
+                what: what.to_string(),
+                device: self.get_label(),
+                reason: format!("{} GPUs do not support CC", self.arch),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Read `size` bytes at the given BAR0 offset.
+    ///
+    /// BAR0 only decodes naturally aligned dword accesses reliably, so the bytes are extracted from
+    /// aligned dword reads. An access within a single dword may start anywhere in it, but a longer
+    /// one must start on a dword boundary.
     pub fn read(&self, offset: u64, size: u64) -> Result<Vec<u8>> {
-        let mut buf = vec![0; size as _];
-        let addr = self.bar0_mapped as u64 + offset;
+        self.check_range(offset, size)?;
 
-        unsafe {
-            std::ptr::copy_nonoverlapping(addr as *const u8, buf.as_mut_ptr(), size as _);
+        let skip = (offset & 0x3) as usize;
+        if skip != 0 && skip as u64 + size > 4 {
+            return Err(NvTrustError::Misaligned { offset, size });
         }
 
-        Ok(buf)
+        let start = offset - skip as u64;
+        let mut buf = Vec::with_capacity(skip + size as usize + 4);
+        while buf.len() < skip + size as usize {
+            buf.extend(self.read32(start + buf.len() as u64)?.to_le_bytes());
+        }
+
+        Ok(buf[skip..skip + size as usize].to_vec())
     }
 
-    /// Write the value at the given offset.
+    /// Write `data` at the given BAR0 offset, which must be dword aligned and dword sized.
     pub fn write(&self, offset: u64, data: &[u8]) -> Result<()> {
-        let addr = self.bar0_mapped as u64 + offset;
+        if offset & 0x3 != 0 || data.len() & 0x3 != 0 {
+            return Err(NvTrustError::Misaligned {
+                offset,
+                size: data.len() as _,
+            });
+        }
+        self.check_range(offset, data.len() as _)?;
 
-        unsafe {
-            std::ptr::copy_nonoverlapping(data.as_ptr(), addr as *mut u8, data.len());
+        for (i, dword) in data.chunks_exact(4).enumerate() {
+            self.write32(
+                offset + i as u64 * 4,
+                u32::from_le_bytes(dword.try_into().unwrap()),
+            )?;
         }
 
         Ok(())
     }
 
+    /// Read the 8-bit register at the given BAR0 offset, through a read of its dword.
     pub fn read8(&self, offset: u64) -> Result<u8> {
-        self.read(offset, 1).map(|mut buf| buf.pop().unwrap())
+        let dword = self.read32(offset & !0x3)?;
+        Ok((dword >> ((offset & 0x3) * 8)) as u8)
     }
 
+    /// Read the 16-bit register at the given BAR0 offset, through a read of its dword.
     pub fn read16(&self, offset: u64) -> Result<u16> {
-        self.read(offset, 2).map(|buf| {
-            let mut data = [0; 2];
-            data.copy_from_slice(&buf);
-            u16::from_le_bytes(data)
-        })
+        if offset & 0x1 != 0 {
+            return Err(NvTrustError::Misaligned { offset, size: 2 });
+        }
+
+        let dword = self.read32(offset & !0x3)?;
+        Ok((dword >> ((offset & 0x3) * 8)) as u16)
     }
 
+    /// Read the 32-bit register at the given BAR0 offset.
     pub fn read32(&self, offset: u64) -> Result<u32> {
-        self.read(offset, 4).map(|buf| {
-            let mut data = [0; 4];
-            data.copy_from_slice(&buf);
-            u32::from_le_bytes(data)
-        })
+        if offset & 0x3 != 0 {
+            return Err(NvTrustError::Misaligned { offset, size: 4 });
+        }
+        self.check_range(offset, 4)?;
+
+        self.device.backend.read32(0, offset)
+    }
+
+    /// Read the 32-bit register at the given BAR0 offset, failing with the decoded error if the
+    /// GPU answered with one of the error codes of [`NvidiaMmioErrorCode`] instead of a value.
+    pub fn checked_read32(&self, offset: u64) -> Result<u32> {
+        let val = self.read32(offset)?;
+        match NvidiaMmioErrorCode::decode(val) {
+            Some(error) => Err(NvTrustError::MmioError {
+                offset,
+                code: val,
+                cause: error.cause(),
+            }),
+            None => Ok(val),
+        }
     }
 
+    /// Write the 8-bit register at the given BAR0 offset by a read-modify-write of its dword.
     pub fn write8(&self, offset: u64, data: u8) -> Result<()> {
-        self.write(offset, &[data])
+        let shift = (offset & 0x3) * 8;
+        let dword = self.read32(offset & !0x3)?;
+        self.write32(
+            offset & !0x3,
+            (dword & !(0xff << shift)) | ((data as u32) << shift),
+        )
     }
 
+    /// Write the 16-bit register at the given BAR0 offset by a read-modify-write of its dword.
     pub fn write16(&self, offset: u64, data: u16) -> Result<()> {
-        self.write(offset, &data.to_le_bytes())
+        if offset & 0x1 != 0 {
+            return Err(NvTrustError::Misaligned { offset, size: 2 });
+        }
+
+        let shift = (offset & 0x3) * 8;
+        let dword = self.read32(offset & !0x3)?;
+        self.write32(
+            offset & !0x3,
+            (dword & !(0xffff << shift)) | ((data as u32) << shift),
+        )
     }
 
+    /// Write the 32-bit register at the given BAR0 offset.
     pub fn write32(&self, offset: u64, data: u32) -> Result<()> {
-        self.write(offset, &data.to_le_bytes())
+        if offset & 0x3 != 0 {
+            return Err(NvTrustError::Misaligned { offset, size: 4 });
+        }
+        self.check_range(offset, 4)?;
+
+        self.device.backend.write32(0, offset, data)
+    }
+
+    /// Read the 64-bit register pair at the given BAR0 offset, low dword first.
+    ///
+    /// The two halves are not read atomically; for counters that may carry between the reads, see
+    /// [`Self::read_timer`].
+    pub fn read64(&self, offset: u64) -> Result<u64> {
+        let low = self.read32(offset)?;
+        let high = self.read32(offset + 4)?;
+
+        Ok(((high as u64) << 32) | low as u64)
+    }
+
+    /// Write the 64-bit register pair at the given BAR0 offset, low dword first, as the write of
+    /// the high dword is what latches the pair.
+    pub fn write64(&self, offset: u64, data: u64) -> Result<()> {
+        self.write32(offset, data as u32)?;
+        self.write32(offset + 4, (data >> 32) as u32)
+    }
+
+    /// Read `count` consecutive 32-bit registers starting at the given BAR0 offset, in order.
+    pub fn read_array(&self, offset: u64, count: usize) -> Result<Vec<u32>> {
+        self.check_range(offset, count as u64 * 4)?;
+
+        (0..count as u64)
+            .map(|i| self.checked_read32(offset + i * 4))
+            .collect()
+    }
+
+    /// Fail unless `size` bytes at `offset` are within BAR0, so that a bad offset cannot fault.
+    fn check_range(&self, offset: u64, size: u64) -> Result<()> {
+        let limit = self.bar0.size;
+        if offset.checked_add(size).is_none_or(|end| end > limit) {
+            return Err(NvTrustError::OutOfRange {
+                bar: "BAR0",
+                offset,
+                size,
+                limit,
+            });
+        }
+
+        Ok(())
     }
 }
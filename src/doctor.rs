@@ -0,0 +1,564 @@
+use std::{fmt, path::Path};
+
+use nix::unistd::Uid;
+use rustix::fs;
+use serde::Serialize;
+
+use crate::{
+    bits::*,
+    dev::{self, is_mmio_error, GpuObject, PciDevice},
+    fabric, preflight,
+};
+
+/// How urgent a problem is; problems are reported from the most to the least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Nothing will work until this is fixed.
+    Critical,
+    /// CC will not work until this is fixed.
+    Error,
+    /// Things may work, but are fragile.
+    Warning,
+}
+
+/// A problem found by the doctor, with a concrete fix.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    /// What was checked: host, pci, driver or gpu.
+    pub area: &'static str,
+    pub summary: String,
+    pub fix: String,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Critical => write!(f, "critical"),
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+impl Problem {
+    fn new(severity: Severity, area: &'static str, summary: String, fix: &str) -> Self {
+        Self {
+            severity,
+            area,
+            summary,
+            fix: fix.to_string(),
+        }
+    }
+}
+
+/// Run all the checks and return the problems found, the most severe first.
+pub fn diagnose() -> Vec<Problem> {
+    let mut problems = check_host();
+
+    if Uid::effective().is_root() {
+        match dev::list_nvidia_devices() {
+            Ok(paths) if paths.is_empty() => problems.push(Problem::new(
+                Severity::Critical,
+                "pci",
+                "No NVIDIA GPU found".to_string(),
+                "Make sure the GPU is seated and visible in lspci; in a VM, check that it is passed through.",
+            )),
+            Ok(paths) => {
+                for path in paths {
+                    problems.extend(check_device(&path));
+                }
+            }
+            Err(e) => problems.push(Problem::new(
+                Severity::Critical,
+                "pci",
+                format!("Cannot enumerate PCI devices: {e}"),
+                "Make sure sysfs is mounted at /sys.",
+            )),
+        }
+    }
+
+    problems.sort_by_key(|problem| problem.severity);
+    problems
+}
+
+fn check_host() -> Vec<Problem> {
+    let mut problems = vec![];
+
+    if !Uid::effective().is_root() {
+        problems.push(Problem::new(
+            Severity::Critical,
+            "host",
+            "Not running as root; the PCI, driver and GPU checks were skipped".to_string(),
+            "Run `sudo nvtrust doctor`.",
+        ));
+        return problems;
+    }
+
+    let iommu_enabled = std::fs::read_dir(IOMMU_CLASS)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+    if !iommu_enabled {
+        problems.push(Problem::new(
+            Severity::Error,
+            "host",
+            "The IOMMU is disabled".to_string(),
+            "Enable VT-d/AMD-Vi in the BIOS and add `intel_iommu=on iommu=pt` or `amd_iommu=on iommu=pt` to the kernel command line.",
+        ));
+    }
+
+    #[cfg(all(feature = "snp", target_arch = "x86_64"))]
+    if let Err(e) = crate::cpuid::check_sev_snp() {
+        problems.push(Problem::new(
+            Severity::Error,
+            "host",
+            format!("SEV-SNP is not available: {e}"),
+            "Enable SEV-SNP in the BIOS and boot a kernel with `mem_encrypt=on kvm_amd.sev=1 kvm_amd.sev_snp=1`.",
+        ));
+    }
+
+    if let Err(e) = fs::open(MEM_FILE, fs::OFlags::RDWR, fs::Mode::empty()) {
+        // The BARs are mapped through their sysfs resource files then; only the reads of system
+        // memory need /dev/mem.
+        problems.push(Problem::new(
+            Severity::Warning,
+            "host",
+            format!(
+                "Cannot open {MEM_FILE}: {e}: {}",
+                preflight::devmem_blocker()
+            ),
+            "The BARs are mapped through sysfs instead; boot with `iomem=relaxed` to read system memory.",
+        ));
+    }
+
+    if fabric::FabricManagerStatus::probe().is_running() {
+        problems.push(Problem::new(
+            Severity::Warning,
+            "host",
+            "The Fabric Manager is running".to_string(),
+            "Stop it with `systemctl stop nvidia-fabricmanager` before switching modes.",
+        ));
+    }
+
+    problems
+}
+
+fn check_device(path: &str) -> Vec<Problem> {
+    let bdf = path.rsplit('/').next().unwrap_or(path).to_string();
+    let mut problems = vec![];
+
+    let device = match dev::open_device(path) {
+        Ok(device) => device,
+        // Not a device we support, e.g., an NVSwitch or another GPU; nothing to diagnose.
+        Err(e) => {
+            log::debug!("Skipping {bdf}: {e}");
+            return problems;
+        }
+    };
+
+    problems.extend(check_pci(&bdf, &device));
+    problems.extend(check_driver(&bdf, &device));
+
+    match GpuObject::new(device.into()) {
+        Ok(gpu) => problems.extend(check_gpu(&bdf, &gpu)),
+        Err(e) => problems.push(Problem::new(
+            Severity::Critical,
+            "gpu",
+            format!("{bdf}: cannot map BAR0: {e}"),
+            "Reset the GPU with `nvtrust --gpu-bdf <bdf> reset-with-os`; if it persists, power cycle the host.",
+        )),
+    }
+
+    problems
+}
+
+fn check_pci(bdf: &str, device: &PciDevice) -> Vec<Problem> {
+    let mut problems = vec![];
+    let config = device.get_config();
+
+    if config.command & PCI_COMMAND_MEMORY == 0 {
+        problems.push(Problem::new(
+            Severity::Warning,
+            "pci",
+            format!("{bdf}: memory space decoding is disabled, nvtrust enables it while it runs"),
+            "Run `setpci -s <bdf> COMMAND=0x6:0x6`, or bind a driver that enables the device.",
+        ));
+    }
+
+    problems
+}
+
+fn check_driver(bdf: &str, device: &PciDevice) -> Vec<Problem> {
+    let mut problems = vec![];
+
+    match device.get_driver().as_deref() {
+        Some("nouveau") => problems.push(Problem::new(
+            Severity::Error,
+            "driver",
+            format!("{bdf}: nouveau is bound, which does not support CC"),
+            "Blacklist nouveau (`modprobe.blacklist=nouveau`) and reboot.",
+        )),
+        Some("nvidia") => problems.push(Problem::new(
+            Severity::Warning,
+            "driver",
+            format!("{bdf}: nvidia is bound; register access races with the driver"),
+            "Stop the GPU workloads and `rmmod nvidia` (or bind vfio-pci) before switching modes.",
+        )),
+        _ => (),
+    }
+
+    if !Path::new(&format!("{}/reset", device.get_name())).exists() {
+        problems.push(Problem::new(
+            Severity::Warning,
+            "driver",
+            format!("{bdf}: the kernel cannot reset the device"),
+            "Use a kernel that supports FLR or secondary bus reset for this slot.",
+        ));
+    }
+
+    problems
+}
+
+fn check_gpu(bdf: &str, gpu: &GpuObject) -> Vec<Problem> {
+    let mut problems = vec![];
+
+    match gpu.read32(NV_PMC_BOOT_0) {
+        Ok(boot) if is_mmio_error(boot) => problems.push(Problem::new(
+            Severity::Error,
+            "gpu",
+            format!(
+                "{bdf}: BAR0 returns error 0x{boot:x}: {}",
+                NvidiaMmioErrorCode::decode(boot).map_or("unknown", |error| error.cause())
+            ),
+            "Reset the GPU with `nvtrust --gpu-bdf <bdf> reset-with-os`.",
+        )),
+        Err(e) => problems.push(Problem::new(
+            Severity::Critical,
+            "gpu",
+            format!("{bdf}: cannot read BAR0: {e}"),
+            "Reset the GPU with `nvtrust --gpu-bdf <bdf> reset-with-os`.",
+        )),
+        _ => (),
+    }
+
+    if !gpu.is_vf() {
+        if let Err(e) = gpu.wait_for_boot() {
+            problems.push(Problem::new(
+                Severity::Error,
+                "gpu",
+                format!("{bdf}: the GPU firmware did not finish booting: {e}"),
+                "Reset the GPU; if it keeps failing, update the VBIOS and dump the FSP state with `nvtrust dump-fsp-emem`.",
+            ));
+        }
+    }
+
+    problems
+}
+
+/// The outcome of a host check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    /// Things may work, e.g., the check could not be done.
+    Warn,
+    Fail,
+}
+
+/// An entry of the CC readiness checklist of the host.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    /// What was found.
+    pub detail: String,
+    /// How to fix it, if it did not pass.
+    pub fix: &'static str,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Pass => write!(f, "pass"),
+            Status::Warn => write!(f, "warn"),
+            Status::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+impl Check {
+    pub(crate) fn new(
+        name: &'static str,
+        status: Status,
+        detail: String,
+        fix: &'static str,
+    ) -> Self {
+        Self {
+            name,
+            status,
+            detail,
+            fix,
+        }
+    }
+}
+
+/// Check the whole host stack for CC: the CPU TEE in firmware and kernel, the IOMMU, the kernel
+/// and its configuration, the NVIDIA driver and the GPUs.
+pub fn check_host_readiness() -> Vec<Check> {
+    let mut checks = vec![];
+
+    checks.push(if Uid::effective().is_root() {
+        Check::new("root", Status::Pass, "running as root".to_string(), "")
+    } else {
+        Check::new(
+            "root",
+            Status::Fail,
+            "not running as root; the GPU checks are skipped".to_string(),
+            "Run `sudo nvtrust host-check`.",
+        )
+    });
+
+    checks.push(check_cpu_tee());
+
+    let iommus = std::fs::read_dir(IOMMU_CLASS)
+        .map(|entries| entries.count())
+        .unwrap_or(0);
+    checks.push(if iommus > 0 {
+        Check::new("iommu", Status::Pass, format!("{iommus} IOMMUs"), "")
+    } else {
+        Check::new(
+            "iommu",
+            Status::Fail,
+            "no IOMMU".to_string(),
+            "Enable VT-d/AMD-Vi in the BIOS and add `intel_iommu=on iommu=pt` or `amd_iommu=on iommu=pt` to the kernel command line.",
+        )
+    });
+
+    let release = std::fs::read_to_string(KERNEL_RELEASE)
+        .map(|release| release.trim().to_string())
+        .unwrap_or_default();
+    checks.push(check_kernel_version(&release));
+    checks.push(check_kernel_config(&release));
+    checks.push(check_nvidia_module());
+
+    checks.extend(preflight::check_devmem());
+
+    if Uid::effective().is_root() {
+        checks.push(check_cc_gpus());
+        let gpus = dev::find_gpus_by_bdf("").unwrap_or_default();
+        checks.push(check_swiotlb(gpus.len()));
+        for gpu in &gpus {
+            checks.push(preflight::check_acs(gpu.get_bdf()));
+        }
+    }
+
+    checks
+}
+
+fn check_cpu_tee() -> Check {
+    let enabled = |param: &str| {
+        std::fs::read_to_string(param).is_ok_and(|v| matches!(v.trim(), "Y" | "y" | "1"))
+    };
+
+    if enabled(KVM_AMD_SEV_SNP) {
+        return Check::new(
+            "cpu tee",
+            Status::Pass,
+            "SEV-SNP is enabled in kvm_amd".to_string(),
+            "",
+        );
+    }
+    if enabled(KVM_INTEL_TDX) {
+        return Check::new(
+            "cpu tee",
+            Status::Pass,
+            "TDX is enabled in kvm_intel".to_string(),
+            "",
+        );
+    }
+
+    #[cfg(all(feature = "snp", target_arch = "x86_64"))]
+    if let Err(e) = crate::cpuid::check_sev_snp() {
+        return Check::new(
+            "cpu tee",
+            Status::Fail,
+            format!("SEV-SNP is not available in the CPU: {e}"),
+            "Enable SEV-SNP (or TDX) in the BIOS.",
+        );
+    }
+
+    Check::new(
+        "cpu tee",
+        Status::Fail,
+        "neither SEV-SNP nor TDX is enabled in KVM".to_string(),
+        "Enable SEV-SNP or TDX in the BIOS and boot with `kvm_amd.sev_snp=1` or `kvm_intel.tdx=1`.",
+    )
+}
+
+fn check_kernel_version(release: &str) -> Check {
+    let mut version = release
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|v| v.parse::<u32>().ok());
+    let (major, minor) = (version.next(), version.next());
+
+    match (major, minor) {
+        (Some(major), Some(minor)) if (major, minor) >= HOST_MIN_KERNEL => {
+            Check::new("kernel", Status::Pass, release.to_string(), "")
+        }
+        (Some(_), Some(_)) => Check::new(
+            "kernel",
+            Status::Fail,
+            format!(
+                "{release} is older than {}.{}",
+                HOST_MIN_KERNEL.0, HOST_MIN_KERNEL.1
+            ),
+            "Upgrade to a kernel with SEV-SNP or TDX host support.",
+        ),
+        _ => Check::new(
+            "kernel",
+            Status::Warn,
+            format!("cannot tell the version of {release:?}"),
+            "",
+        ),
+    }
+}
+
+fn check_kernel_config(release: &str) -> Check {
+    const REQUIRED: &[&str] = &["CONFIG_KVM", "CONFIG_VFIO_PCI", "CONFIG_IOMMU_SUPPORT"];
+    const TEE: &[&str] = &["CONFIG_KVM_AMD_SEV", "CONFIG_INTEL_TDX_HOST"];
+
+    let Some(config) = preflight::KernelConfig::load() else {
+        return Check::new(
+            "kernel config",
+            Status::Warn,
+            format!("cannot read /boot/config-{release}"),
+            "Install the kernel config, or check CONFIG_KVM_AMD_SEV/CONFIG_INTEL_TDX_HOST and CONFIG_VFIO_PCI by hand.",
+        );
+    };
+    let enabled = |option: &str| config.is_enabled(option);
+
+    let mut missing = REQUIRED
+        .iter()
+        .filter(|option| !enabled(option))
+        .copied()
+        .collect::<Vec<_>>();
+    if !TEE.iter().any(|option| enabled(option)) {
+        missing.push("CONFIG_KVM_AMD_SEV or CONFIG_INTEL_TDX_HOST");
+    }
+
+    if missing.is_empty() {
+        Check::new("kernel config", Status::Pass, config.path.clone(), "")
+    } else {
+        Check::new(
+            "kernel config",
+            Status::Fail,
+            format!("{} not set in {}", missing.join(", "), config.path),
+            "Rebuild the kernel with the missing options.",
+        )
+    }
+}
+
+fn check_nvidia_module() -> Check {
+    match std::fs::read_to_string(NVIDIA_DRIVER_VERSION) {
+        Ok(version) if version.contains("Open Kernel Module") => Check::new(
+            "nvidia module",
+            Status::Pass,
+            version.lines().next().unwrap_or_default().to_string(),
+            "",
+        ),
+        Ok(version) => Check::new(
+            "nvidia module",
+            Status::Fail,
+            format!(
+                "the proprietary module is loaded: {}",
+                version.lines().next().unwrap_or_default()
+            ),
+            "CC needs the open kernel module; install the `-open` driver flavor.",
+        ),
+        Err(_) => Check::new(
+            "nvidia module",
+            Status::Warn,
+            "not loaded".to_string(),
+            "Fine on a host that passes the GPUs through with vfio-pci; otherwise install the open kernel module.",
+        ),
+    }
+}
+
+fn check_cc_gpus() -> Check {
+    match dev::find_gpus_by_bdf("") {
+        Ok(gpus) => {
+            let cc = gpus.iter().filter(|gpu| gpu.get_arch().has_cc()).count();
+            if cc > 0 {
+                Check::new(
+                    "cc gpu",
+                    Status::Pass,
+                    format!("{cc} of {} GPUs support CC", gpus.len()),
+                    "",
+                )
+            } else {
+                Check::new(
+                    "cc gpu",
+                    Status::Fail,
+                    format!("none of {} GPUs supports CC", gpus.len()),
+                    "CC needs a Hopper or Blackwell GPU.",
+                )
+            }
+        }
+        Err(e) => Check::new(
+            "cc gpu",
+            Status::Fail,
+            format!("cannot enumerate the GPUs: {e}"),
+            "Make sure sysfs is mounted at /sys and the GPUs are visible in lspci.",
+        ),
+    }
+}
+
+/// Check that the SWIOTLB of a CC guest is large enough for its GPUs: a CC GPU cannot DMA to the
+/// private memory of the guest, so every transfer is bounced through the shared SWIOTLB, and
+/// workloads stall or fail with DMA mapping errors once it runs out.
+pub fn check_swiotlb(gpus: usize) -> Check {
+    let want = SWIOTLB_MIN_PER_GPU * gpus.max(1) as u64;
+    let nslabs = swiotlb_nslabs();
+    let fix = "Boot the guest with `swiotlb=<slabs>`, in 2 KiB slabs, e.g., `swiotlb=262144` for 512 MiB per GPU.";
+
+    match nslabs {
+        Some((nslabs, source)) => {
+            let size = nslabs * SWIOTLB_SLAB_SIZE;
+            let detail = format!(
+                "{} MiB from {source}, {} MiB wanted for {gpus} GPUs",
+                size >> 20,
+                want >> 20
+            );
+            if size >= want {
+                Check::new("swiotlb", Status::Pass, detail, "")
+            } else {
+                Check::new("swiotlb", Status::Warn, detail, fix)
+            }
+        }
+        None => Check::new(
+            "swiotlb",
+            Status::Warn,
+            format!("cannot read {SWIOTLB_NSLABS} and no swiotlb= on the command line"),
+            fix,
+        ),
+    }
+}
+
+/// The number of SWIOTLB slabs, from debugfs if mounted, or else from the kernel command line.
+fn swiotlb_nslabs() -> Option<(u64, &'static str)> {
+    if let Some(nslabs) = std::fs::read_to_string(SWIOTLB_NSLABS)
+        .ok()
+        .and_then(|nslabs| nslabs.trim().parse().ok())
+    {
+        return Some((nslabs, SWIOTLB_NSLABS));
+    }
+
+    // `swiotlb=<nslabs>[,<nslabs for the area>][,force|noforce]`; the last one wins.
+    let cmdline = std::fs::read_to_string(KERNEL_CMDLINE).ok()?;
+    cmdline
+        .split_whitespace()
+        .rev()
+        .filter_map(|arg| arg.strip_prefix("swiotlb="))
+        .find_map(|value| value.split(',').next()?.parse().ok())
+        .map(|nslabs| (nslabs, KERNEL_CMDLINE))
+}
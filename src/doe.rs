@@ -0,0 +1,237 @@
+use serde::{Deserialize, Serialize};
+
+use std::time::{Duration, Instant};
+
+use rustix::{fd::OwnedFd, fs, io};
+
+use crate::{
+    bits::*,
+    dev::PciDevice,
+    error::{NvTrustError, Result},
+};
+
+/// A data object protocol, identified by its vendor ID and type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DoeProtocol {
+    pub vendor: u16,
+    pub kind: u8,
+}
+
+impl DoeProtocol {
+    pub const DISCOVERY: Self = Self::pci_sig(PCI_DOE_PROTOCOL_DISCOVERY);
+    pub const CMA_SPDM: Self = Self::pci_sig(PCI_DOE_PROTOCOL_CMA_SPDM);
+    pub const SECURED_CMA_SPDM: Self = Self::pci_sig(PCI_DOE_PROTOCOL_SECURED_CMA_SPDM);
+
+    const fn pci_sig(kind: u8) -> Self {
+        Self {
+            vendor: PCI_VENDOR_ID_PCI_SIG,
+            kind,
+        }
+    }
+}
+
+/// A Data Object Exchange mailbox in the extended config space of a device.
+///
+/// A request is written a dword at a time into the write mailbox and kicked off with the GO bit.
+/// The response is read a dword at a time from the read mailbox, where writing any value pops the
+/// current dword. Each data object starts with two header dwords: the vendor ID and type of the
+/// protocol, and the length of the object.
+pub struct DoeMailbox<'a> {
+    device: &'a PciDevice,
+    /// The offset of the DOE extended capability.
+    offset: u64,
+    /// The config space opened for writing, as the one of the device is read-only.
+    fd: OwnedFd,
+}
+
+impl<'a> DoeMailbox<'a> {
+    pub fn new(device: &'a PciDevice, offset: u64) -> Result<Self> {
+        let fd = fs::open(
+            format!("{}/config", device.get_name()),
+            fs::OFlags::RDWR,
+            fs::Mode::empty(),
+        )?;
+
+        Ok(Self { device, offset, fd })
+    }
+
+    fn read32(&self, reg: u64) -> Result<u32> {
+        let mut data = [0; 4];
+        io::pread(&self.fd, &mut data, self.offset + reg)?;
+
+        Ok(u32::from_le_bytes(data))
+    }
+
+    fn write32(&self, reg: u64, val: u32) -> Result<()> {
+        io::pwrite(&self.fd, &val.to_le_bytes(), self.offset + reg)?;
+
+        Ok(())
+    }
+
+    /// Abort the exchange in flight, if any, and wait for the mailbox to become idle.
+    pub fn abort(&self) -> Result<()> {
+        self.write32(PCI_DOE_CTRL, PCI_DOE_CTRL_ABORT)?;
+        self.poll_status("the DOE abort", |status| {
+            status & (PCI_DOE_STATUS_BUSY | PCI_DOE_STATUS_ERROR) == 0
+        })
+    }
+
+    /// Wait until the given condition holds on the status register, failing on a mailbox error.
+    fn poll_status<F>(&self, what: &str, done: F) -> Result<()>
+    where
+        F: Fn(u32) -> bool,
+    {
+        let now = Instant::now();
+        loop {
+            let status = self.read32(PCI_DOE_STATUS)?;
+            if done(status) {
+                return Ok(());
+            }
+
+            if status & PCI_DOE_STATUS_ERROR != 0 {
+                return Err(NvTrustError::Doe(format!(
+                    "{} reports an error: status 0x{:x}",
+                    self.device.get_bdf(),
+                    status
+                )));
+            }
+
+            if now.elapsed().as_secs() > PCI_DOE_TIMEOUT {
+                return Err(NvTrustError::Timeout(format!(
+                    "{what} on {}: status 0x{:x}",
+                    self.device.get_bdf(),
+                    status
+                )));
+            }
+
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Write a data object of the given protocol and kick it off.
+    pub fn write_object(&self, protocol: DoeProtocol, payload: &[u32]) -> Result<()> {
+        let len = payload.len() + 2;
+        if len > PCI_DOE_MAX_LENGTH {
+            return Err(NvTrustError::InvalidArgument(format!(
+                "DOE object of {len} dwords is too large"
+            )));
+        }
+
+        self.poll_status("the DOE mailbox to become idle", |status| {
+            status & PCI_DOE_STATUS_BUSY == 0
+        })?;
+
+        self.write32(
+            PCI_DOE_WRITE,
+            protocol.vendor as u32 | ((protocol.kind as u32) << 16),
+        )?;
+        self.write32(PCI_DOE_WRITE, len as u32 & PCI_DOE_DATA_OBJECT_LENGTH_MASK)?;
+        for dword in payload {
+            self.write32(PCI_DOE_WRITE, *dword)?;
+        }
+
+        self.write32(PCI_DOE_CTRL, PCI_DOE_CTRL_GO)
+    }
+
+    /// Wait for the response to be ready.
+    pub fn poll_ready(&self) -> Result<()> {
+        self.poll_status("a DOE response", |status| {
+            status & PCI_DOE_STATUS_DATA_OBJECT_READY != 0
+        })
+    }
+
+    /// Read the response data object, returning its protocol and payload.
+    pub fn read_object(&self) -> Result<(DoeProtocol, Vec<u32>)> {
+        let pop = || -> Result<u32> {
+            let dword = self.read32(PCI_DOE_READ)?;
+            self.write32(PCI_DOE_READ, 0)?;
+            Ok(dword)
+        };
+
+        let header1 = pop()?;
+        let protocol = DoeProtocol {
+            vendor: header1 as u16,
+            kind: (header1 >> 16) as u8,
+        };
+        let len = match pop()? & PCI_DOE_DATA_OBJECT_LENGTH_MASK {
+            0 => PCI_DOE_MAX_LENGTH,
+            len => len as usize,
+        };
+        if len < 2 {
+            return Err(NvTrustError::Doe(format!(
+                "data object of {len} dwords is too short"
+            )));
+        }
+
+        let payload = (2..len).map(|_| pop()).collect::<Result<Vec<_>>>()?;
+        Ok((protocol, payload))
+    }
+
+    /// Send a request and receive its response, aborting the exchange if it fails midway.
+    pub fn exchange(&self, protocol: DoeProtocol, payload: &[u32]) -> Result<Vec<u32>> {
+        let exchange = || {
+            self.write_object(protocol, payload)?;
+            self.poll_ready()?;
+
+            let (response, data) = self.read_object()?;
+            if response != protocol {
+                return Err(NvTrustError::Doe(format!(
+                    "response of protocol {response:?} to a request of {protocol:?}"
+                )));
+            }
+
+            Ok(data)
+        };
+
+        exchange().inspect_err(|_| {
+            if let Err(e) = self.abort() {
+                log::warn!("Cannot abort the DOE exchange: {e}");
+            }
+        })
+    }
+
+    /// List the protocols the mailbox supports, walking the discovery protocol.
+    pub fn discover(&self) -> Result<Vec<DoeProtocol>> {
+        let mut protocols = vec![];
+        let mut index = 0;
+
+        loop {
+            let response = self.exchange(DoeProtocol::DISCOVERY, &[index])?;
+            let dword = response
+                .first()
+                .ok_or(NvTrustError::Doe("empty discovery response".to_string()))?;
+
+            protocols.push(DoeProtocol {
+                vendor: *dword as u16,
+                kind: (dword >> 16) as u8,
+            });
+
+            index = dword >> 24;
+            if index == 0 || protocols.len() > 0xff {
+                break;
+            }
+        }
+
+        Ok(protocols)
+    }
+
+    /// The capabilities register of the mailbox.
+    pub fn capabilities(&self) -> Result<u32> {
+        self.read32(PCI_DOE_CAP)
+    }
+}
+
+impl PciDevice {
+    /// Open the first DOE mailbox of the device.
+    pub fn doe(&self) -> Result<DoeMailbox<'_>> {
+        let offset = self
+            .find_ext_cap(PCI_EXT_CAP_ID_DOE)?
+            .ok_or(NvTrustError::NotSupported {
+                what: "DOE".to_string(),
+                device: self.get_bdf().to_string(),
+                reason: "it has no DOE mailbox".to_string(),
+            })?;
+
+        DoeMailbox::new(self, offset)
+    }
+}
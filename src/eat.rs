@@ -0,0 +1,86 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::Engine;
+use ring::{rand::SystemRandom, signature};
+use serde_json::json;
+
+use crate::{
+    bits::EAT_PROFILE,
+    error::{NvTrustError, Result},
+    identity,
+    policy::Claims,
+    spdm::to_hex,
+};
+
+/// A signing key for Entity Attestation Tokens: an ECDSA P-256 or P-384 key as PKCS#8.
+pub struct EatSigner {
+    key: signature::EcdsaKeyPair,
+    alg: &'static str,
+    rng: SystemRandom,
+}
+
+impl EatSigner {
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Self> {
+        let rng = SystemRandom::new();
+
+        for (alg, name) in [
+            (&signature::ECDSA_P384_SHA384_FIXED_SIGNING, "ES384"),
+            (&signature::ECDSA_P256_SHA256_FIXED_SIGNING, "ES256"),
+        ] {
+            if let Ok(key) = signature::EcdsaKeyPair::from_pkcs8(alg, pkcs8, &rng) {
+                return Ok(Self {
+                    key,
+                    alg: name,
+                    rng,
+                });
+            }
+        }
+
+        Err(NvTrustError::Eat(
+            "the signing key is not a PKCS#8 ECDSA P-256 or P-384 key".to_string(),
+        ))
+    }
+
+    /// Issue an EAT as a signed JWT, with the claims of each GPU as a submodule keyed by its BDF.
+    ///
+    /// The claims are carried as strings, the way the appraisal produced them; `eat_nonce` is the
+    /// nonce the evidence was collected over, so that the relying party can tie the token to its
+    /// request.
+    pub fn issue(&self, nonce: &[u8], gpus: &BTreeMap<String, Claims>) -> Result<String> {
+        let b64 = |data: &[u8]| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(data);
+        let iat = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let header = json!({"alg": self.alg, "typ": "JWT"});
+        let payload = json!({
+            "eat_profile": EAT_PROFILE,
+            "iat": iat,
+            "eat_nonce": to_hex(nonce),
+            "submods": gpus,
+        });
+
+        let signing_input = format!(
+            "{}.{}",
+            b64(&serde_json::to_vec(&header)?),
+            b64(&serde_json::to_vec(&payload)?)
+        );
+        let sig = self
+            .key
+            .sign(&self.rng, signing_input.as_bytes())
+            .map_err(|_| NvTrustError::Eat("cannot sign the token".to_string()))?;
+
+        Ok(format!("{signing_input}.{}", b64(sig.as_ref())))
+    }
+}
+
+/// Issue an EAT over the claims of the GPUs, see [`EatSigner::issue`], with the PKCS#8 signing
+/// key in the file, PEM or DER.
+pub fn issue(key: &str, nonce: &[u8], gpus: &BTreeMap<String, Claims>) -> Result<String> {
+    EatSigner::from_pkcs8(&identity::decode_pem(&fs::read(key)?)?)?.issue(nonce, gpus)
+}
@@ -0,0 +1,135 @@
+use thiserror::Error;
+
+/// The errors returned by the device APIs, so that library consumers can match on them.
+#[derive(Debug, Error)]
+pub enum NvTrustError {
+    /// No device matched the selection.
+    #[error("{0}")]
+    DeviceNotFound(String),
+    #[error("you need to be root to run this program")]
+    NotRoot,
+    /// `/dev/mem` refused us; the reason names what blocks it and what to use instead.
+    #[error("cannot open /dev/mem: {source}: {reason}")]
+    DevMemDenied {
+        source: rustix::io::Errno,
+        reason: String,
+    },
+    #[error("cannot map {what}: {source}")]
+    MmapFailed {
+        what: String,
+        source: rustix::io::Errno,
+    },
+    /// A register read returned an error code instead of a value, e.g., because BAR0 is
+    /// firewalled or the device fell off the bus.
+    #[error("reading 0x{offset:x} returned error 0x{code:08x}: {cause}")]
+    MmioError {
+        offset: u64,
+        code: u32,
+        /// What probably made the read fail, see [`crate::bits::NvidiaMmioErrorCode`].
+        cause: &'static str,
+    },
+    /// A register access that the CC firewall blocks: with the BAR0 decoupler engaged, the host
+    /// only reaches the few windows it needs, e.g., the FSP queues.
+    #[error("0x{offset:x} is blocked by the CC firewall; only devtools mode opens it to the host")]
+    Firewalled { offset: u64 },
+    /// A register access that is not naturally aligned or crosses a dword boundary.
+    #[error("misaligned register access of {size} bytes at 0x{offset:x}")]
+    Misaligned { offset: u64, size: u64 },
+    /// An access beyond the end of a BAR.
+    #[error("access of {size} bytes at 0x{offset:x} is outside {bar} of 0x{limit:x} bytes")]
+    OutOfRange {
+        bar: &'static str,
+        offset: u64,
+        size: u64,
+        limit: u64,
+    },
+    /// The device is not one we know how to drive.
+    #[error("unsupported device: {0}")]
+    UnsupportedDevice(String),
+    /// The operation does not apply to this device, e.g., a reset of a VF.
+    #[error("{what} is not available on {device}: {reason}")]
+    NotSupported {
+        what: String,
+        device: String,
+        reason: String,
+    },
+    /// The VBIOS image read from the PROM is malformed.
+    #[error("invalid VBIOS image: {0}")]
+    InvalidVbios(String),
+    #[error("timeout waiting for {0}")]
+    Timeout(String),
+    /// The FSP rejected a command or answered with a malformed message.
+    #[error("FSP error: {0}")]
+    Fsp(String),
+    /// An FSP transaction failed; the FSP state is attached for the diagnosis.
+    #[error("{source}\nFSP state:\n{dump}")]
+    FspTransaction {
+        source: Box<NvTrustError>,
+        dump: String,
+    },
+    /// The DOE mailbox reported an error or returned a malformed data object.
+    #[error("DOE error: {0}")]
+    Doe(String),
+    /// The SPDM responder returned an error or a malformed message.
+    #[error("SPDM error: {0}")]
+    Spdm(String),
+    /// A certificate could not be parsed.
+    #[error("invalid certificate: {0}")]
+    Certificate(String),
+    #[error("failed to switch the CC mode of {device}: {reason}")]
+    CcSwitchFailed { device: String, reason: String },
+    /// A VFIO ioctl failed, or the device cannot be used through vfio-pci.
+    #[error("VFIO error: {0}")]
+    Vfio(String),
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    /// A policy or a claims file is malformed, or the claims fail the policy.
+    #[error("policy error: {0}")]
+    Policy(String),
+    /// A RIM is malformed, or its signature or its signer cannot be trusted.
+    #[error("attestation failed: {0}")]
+    Attestation(String),
+
+    #[error("RIM error: {0}")]
+    Rim(String),
+    /// A CBOR item, e.g., of a CoRIM, is malformed.
+    #[error("invalid CBOR: {0}")]
+    Cbor(String),
+    /// A request to NRAS or to the RIM service failed.
+    #[error("HTTP error: {0}")]
+    Http(String),
+    /// The CPU TEE, e.g., SEV-SNP or TDX, is missing or refused a report.
+    #[error("TEE error: {0}")]
+    Tee(String),
+    /// An EAT cannot be signed, e.g., because the key is malformed.
+    #[error("EAT error: {0}")]
+    Eat(String),
+    /// A register script is malformed or one of its steps failed.
+    #[error("script error: {0}")]
+    Script(String),
+    /// The firmware logs cannot be located or decoded.
+    #[error("firmware log error: {0}")]
+    FwLog(String),
+    /// The Fabric Manager cannot be used as required, e.g., together with PPCIe mode.
+    #[error("Fabric Manager error: {0}")]
+    FabricManager(String),
+    /// A file we read is malformed, e.g., a knob file or a measurement history.
+    #[error("{0}")]
+    Malformed(String),
+    /// A GPU driver owns the registers of the device, which we leave alone unless forced.
+    #[error("{device} is bound to {driver}, and accessing its registers behind the back of RM can hang the GPU; unbind {driver} first (unbind-driver), or pass --force")]
+    DriverBound { device: String, driver: String },
+    /// Another process, e.g., the attestation daemon, holds the lock of the device.
+    #[error("{device} is in use by another nvtrust (pid {})", pid.map_or("unknown".to_string(), |pid| pid.to_string()))]
+    Locked { device: String, pid: Option<u32> },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Os(#[from] rustix::io::Errno),
+    #[error(transparent)]
+    Parse(#[from] std::num::ParseIntError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, NvTrustError>;
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bits::*,
+    certs,
+    dev::GpuObject,
+    error::Result,
+    policy::Claims,
+    spdm::{Algorithms, SpdmRequester},
+};
+
+/// The attestation evidence of a GPU: a signed measurement report bound to the caller's nonce and
+/// the certificate chain of the signing key, which is all a verifier needs.
+///
+/// In the evidence files the binary fields are base64, the nonce is hex and the SPDM version reads
+/// like `1.1`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Evidence {
+    pub bdf: String,
+    pub arch: String,
+    #[serde(with = "hex_nonce")]
+    pub nonce: [u8; SPDM_NONCE_SIZE],
+    #[serde(with = "spdm_version")]
+    pub spdm_version: u8,
+    pub algorithms: Algorithms,
+    /// The GET_MEASUREMENTS request and the signed MEASUREMENTS response.
+    #[serde(with = "b64")]
+    pub report: Vec<u8>,
+    #[serde(with = "b64")]
+    pub signature: Vec<u8>,
+    /// The opaque data of the report, in which NVIDIA GPUs carry, e.g., their firmware versions.
+    #[serde(with = "b64")]
+    pub opaque: Vec<u8>,
+    /// The messages covered by the signature, which also include the version and algorithm
+    /// negotiation since SPDM 1.2.
+    #[serde(with = "b64")]
+    pub transcript: Vec<u8>,
+    /// The DER certificates from the root down to the attestation key.
+    #[serde(with = "b64_list")]
+    pub certificates: Vec<Vec<u8>>,
+    pub measurements: BTreeMap<u32, String>,
+}
+
+impl GpuObject {
+    /// Collect the evidence of the GPU, signed with the key of the given slot over `nonce`.
+    pub fn collect_evidence(&self, slot: u8, nonce: [u8; SPDM_NONCE_SIZE]) -> Result<Evidence> {
+        let device = self.get_device_handle();
+        let mut requester = SpdmRequester::new(device.doe()?);
+        requester.init()?;
+
+        let chain = requester.get_certificate(slot)?;
+        let certificates = certs::split_spdm_chain(&chain, requester.algorithms().hash_size())?;
+        let measurements = requester.get_measurements(slot, Some(nonce))?;
+
+        Ok(Evidence {
+            bdf: self.get_bdf().to_string(),
+            arch: self.get_arch().to_string(),
+            nonce,
+            spdm_version: requester.version(),
+            algorithms: requester.algorithms(),
+            signature: measurements.signature.clone().unwrap_or_default(),
+            transcript: requester.measurement_transcript().to_vec(),
+            measurements: measurements.to_set(),
+            opaque: measurements.opaque.clone(),
+            report: measurements.report,
+            certificates,
+        })
+    }
+}
+
+impl Evidence {
+    /// The fields of the opaque data, which NVIDIA GPUs encode as a list of type, length and
+    /// value, with a 16-bit type and length.
+    pub fn opaque_field(&self, id: u16) -> Option<&[u8]> {
+        let mut data = &self.opaque[..];
+
+        while data.len() >= 4 {
+            let kind = u16::from_le_bytes([data[0], data[1]]);
+            let len = u16::from_le_bytes([data[2], data[3]]) as usize;
+            let value = data.get(4..4 + len)?;
+            if kind == id {
+                return Some(value);
+            }
+            data = &data[4 + len..];
+        }
+
+        None
+    }
+
+    /// An opaque field holding a NUL-terminated string.
+    pub fn opaque_string(&self, id: u16) -> Option<String> {
+        let value = self.opaque_field(id)?;
+        let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+
+        Some(String::from_utf8_lossy(&value[..end]).into_owned())
+    }
+
+    /// The claims the evidence carries, for policies: the firmware versions and each measurement
+    /// as `measurement.<index>`.
+    pub fn claims(&self) -> Claims {
+        let mut claims = Claims::new();
+
+        if let Some(version) = self.opaque_string(NV_OPAQUE_FIELD_DRIVER_VERSION) {
+            claims.insert("driver_version".to_string(), version);
+        }
+        if let Some(v) = self
+            .opaque_field(NV_OPAQUE_FIELD_VBIOS_VERSION)
+            .filter(|v| v.len() >= 5)
+        {
+            // The same layout as the version in the BIT of the VBIOS.
+            claims.insert(
+                "vbios_version".to_string(),
+                format!(
+                    "{:02X}.{:02X}.{:02X}.{:02X}.{:02X}",
+                    v[3], v[2], v[1], v[0], v[4]
+                ),
+            );
+        }
+        for (index, digest) in self.measurements.iter() {
+            claims.insert(format!("measurement.{index}"), digest.clone());
+        }
+
+        claims
+    }
+
+    /// The certificate chain as concatenated PEM blocks.
+    pub fn certificates_pem(&self) -> String {
+        self.certificates
+            .iter()
+            .map(|der| {
+                let body = base64::engine::general_purpose::STANDARD.encode(der);
+                let lines = body
+                    .as_bytes()
+                    .chunks(64)
+                    .map(|line| String::from_utf8_lossy(line).into_owned())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!("-----BEGIN CERTIFICATE-----\n{lines}\n-----END CERTIFICATE-----\n")
+            })
+            .collect()
+    }
+
+    /// The evidence file.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)? + "\n")
+    }
+}
+
+/// Binary fields as base64.
+pub mod b64 {
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        base64::engine::general_purpose::STANDARD
+            .decode(String::deserialize(deserializer)?)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Lists of binary fields as lists of base64.
+pub mod b64_list {
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(list: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            list.iter()
+                .map(|data| base64::engine::general_purpose::STANDARD.encode(data)),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|data| base64::engine::general_purpose::STANDARD.decode(data))
+            .collect::<Result<_, _>>()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Nonces as hex.
+pub mod hex_nonce {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::{
+        bits::SPDM_NONCE_SIZE,
+        spdm::{from_hex, to_hex},
+    };
+
+    pub fn serialize<S: Serializer>(
+        nonce: &[u8; SPDM_NONCE_SIZE],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_hex(nonce))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u8; SPDM_NONCE_SIZE], D::Error> {
+        from_hex(&String::deserialize(deserializer)?)
+            .map_err(serde::de::Error::custom)?
+            .try_into()
+            .map_err(|_| serde::de::Error::custom("the nonce must be 32 bytes"))
+    }
+}
+
+/// SPDM versions as `major.minor`.
+mod spdm_version {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(version: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{}.{}", version >> 4, version & 0xf))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+        let version = String::deserialize(deserializer)?;
+        version
+            .split_once('.')
+            .and_then(|(major, minor)| {
+                Some(major.parse::<u8>().ok()? << 4 | minor.parse::<u8>().ok()?)
+            })
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid SPDM version {version}")))
+    }
+}
@@ -0,0 +1,148 @@
+use std::{fs, path::PathBuf, process::Command};
+
+use crate::{
+    bits::*,
+    error::{NvTrustError, Result},
+};
+
+/// What we know about the NVIDIA Fabric Manager (FM) on this host.
+#[derive(Debug, Clone, Default)]
+pub struct FabricManagerStatus {
+    /// The path of the FM binary, if installed.
+    pub path: Option<PathBuf>,
+    /// The version reported by FM.
+    pub version: Option<String>,
+    /// The version of the loaded NVIDIA kernel module.
+    pub driver_version: Option<String>,
+    /// The `FABRIC_MODE` in the FM configuration.
+    pub fabric_mode: Option<u32>,
+    /// The PIDs of the running FM processes.
+    pub pids: Vec<u32>,
+}
+
+impl FabricManagerStatus {
+    /// Collect the status of FM.
+    pub fn probe() -> Self {
+        let path = find_in_path(FABRIC_MANAGER_BIN);
+        let version = path.as_ref().and_then(|path| {
+            let output = Command::new(path).arg("--version").output().ok()?;
+            parse_version(&String::from_utf8_lossy(&output.stdout))
+        });
+        let driver_version = fs::read_to_string(NVIDIA_MODULE_VERSION)
+            .ok()
+            .map(|v| v.trim().to_string());
+        let fabric_mode = fs::read_to_string(FABRIC_MANAGER_CONFIG)
+            .ok()
+            .and_then(|config| {
+                config
+                    .lines()
+                    .filter_map(|line| line.trim().strip_prefix("FABRIC_MODE="))
+                    .find_map(|mode| mode.trim().parse().ok())
+            });
+
+        Self {
+            path,
+            version,
+            driver_version,
+            fabric_mode,
+            pids: find_processes(FABRIC_MANAGER_BIN),
+        }
+    }
+
+    /// Check whether FM is usable together with PPCIe mode. Returns the list of problems found.
+    ///
+    /// FM refuses to start when its version does not match the driver exactly, and PPCIe mode
+    /// does not support the shared NVSwitch multitenancy mode (`FABRIC_MODE=1`).
+    pub fn check_ppcie(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        if self.path.is_none() {
+            problems.push(format!("{FABRIC_MANAGER_BIN} is not installed"));
+            return problems;
+        }
+
+        match (&self.version, &self.driver_version) {
+            (Some(fm), Some(driver)) if fm != driver => problems.push(format!(
+                "Fabric Manager version {fm} does not match the driver version {driver}"
+            )),
+            (None, _) => problems.push("cannot determine the Fabric Manager version".to_string()),
+            _ => (),
+        }
+
+        if self.fabric_mode == Some(1) {
+            problems.push(format!(
+                "FABRIC_MODE=1 (shared NVSwitch) in {FABRIC_MANAGER_CONFIG} is not supported in PPCIe mode"
+            ));
+        }
+
+        problems
+    }
+
+    /// Fail with the problems of [`Self::check_ppcie`], if any.
+    pub fn ensure_ppcie(&self) -> Result<()> {
+        let problems = self.check_ppcie();
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(NvTrustError::FabricManager(format!(
+                "not ready for PPCIe mode: {}",
+                problems.join("; ")
+            ))),
+        }
+    }
+
+    #[inline]
+    pub fn is_running(&self) -> bool {
+        !self.pids.is_empty()
+    }
+}
+
+/// Warn if FM is running, as switching modes under FM makes it fail at its next start.
+pub fn warn_if_running() {
+    let pids = find_processes(FABRIC_MANAGER_BIN);
+
+    if !pids.is_empty() {
+        log::warn!(
+            "{FABRIC_MANAGER_BIN} is running (PID {:?}); stop it before switching modes and restart it after the reset.",
+            pids
+        );
+    }
+}
+
+fn find_in_path(bin: &str) -> Option<PathBuf> {
+    let paths = std::env::var("PATH").unwrap_or_default();
+
+    let found = paths
+        .split(':')
+        .chain(["/usr/bin", "/usr/sbin"])
+        .map(|dir| PathBuf::from(dir).join(bin))
+        .find(|path| path.is_file());
+
+    found
+}
+
+/// Extract the version from, e.g., `Fabric Manager version is : 535.86.10`.
+fn parse_version(output: &str) -> Option<String> {
+    output
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .find(|s| s.contains('.') && s.starts_with(|c: char| c.is_ascii_digit()))
+        .map(|s| s.to_string())
+}
+
+/// Find the PIDs of the processes with the given name.
+pub fn find_processes(name: &str) -> Vec<u32> {
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_string_lossy().parse::<u32>().ok())
+        .filter(|pid| {
+            fs::read_to_string(format!("/proc/{pid}/comm"))
+                // The kernel truncates the command name to 15 characters.
+                .map(|comm| comm.trim() == &name[..name.len().min(15)])
+                .unwrap_or(false)
+        })
+        .collect()
+}
@@ -0,0 +1,220 @@
+use serde::Serialize;
+
+use crate::{
+    bits::*,
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+};
+
+/// A structure representing one of the falcon microcontrollers embedded in the GPU.
+#[derive(Debug, Clone, Copy)]
+pub struct Falcon {
+    /// The name of the falcon.
+    pub name: &'static str,
+    /// The base address of the falcon in BAR0.
+    pub base: u64,
+    /// The base address of the EMEM ports in BAR0, if the falcon has EMEM.
+    pub emem_base: Option<u64>,
+}
+
+/// The FSP falcon.
+pub const FSP: Falcon = Falcon {
+    name: "fsp",
+    base: NV_FSP_BASE,
+    emem_base: Some(NV_FSP_EMEM_BASE),
+};
+
+/// The GSP falcon which runs GSP-RM.
+pub const GSP: Falcon = Falcon {
+    name: "gsp",
+    base: NV_GSP_BASE,
+    emem_base: None,
+};
+
+/// The SEC2 falcon.
+pub const SEC2: Falcon = Falcon {
+    name: "sec2",
+    base: NV_SEC2_BASE,
+    emem_base: None,
+};
+
+/// What the cores of a falcon are doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FalconStatus {
+    pub cpuctl: u32,
+    pub riscv_cpuctl: u32,
+    /// The firmware version reported by the falcon, or 0.
+    pub os: u32,
+}
+
+impl FalconStatus {
+    /// Whether the RISC-V core is running firmware.
+    pub fn is_running(&self) -> bool {
+        self.riscv_cpuctl & NV_RISCV_CPUCTL_ACTIVE != 0
+            && self.riscv_cpuctl & NV_RISCV_CPUCTL_HALTED == 0
+    }
+
+    /// Whether a core ran firmware that has since halted, e.g., after a crash or an unload.
+    pub fn is_halted(&self) -> bool {
+        self.riscv_cpuctl & NV_RISCV_CPUCTL_HALTED != 0
+            || self.cpuctl & NV_FALCON_CPUCTL_HALTED != 0
+    }
+}
+
+/// The state of GSP-RM, the firmware of the GSP the driver boots to run the resource manager.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct GspStatus {
+    pub falcon: FalconStatus,
+    /// The address of the LibOS init arguments a driver left in the mailboxes, or 0 if no
+    /// driver booted GSP-RM since the reset.
+    pub init_args: u64,
+}
+
+impl GspStatus {
+    /// Whether a driver loaded GSP-RM since the reset.
+    pub fn is_loaded(&self) -> bool {
+        self.init_args != 0
+    }
+}
+
+impl GpuObject {
+    /// Read the state of the GSP and GSP-RM.
+    pub fn query_gsp(&self) -> Result<GspStatus> {
+        self.ensure_pf("The GSP state")?;
+
+        let lo = GSP.read_mailbox0(self)?;
+        let hi = GSP.read_mailbox1(self)?;
+        for (offset, value) in [(NV_FALCON_MAILBOX0, lo), (NV_FALCON_MAILBOX1, hi)] {
+            if let Some(error) = NvidiaMmioErrorCode::decode(value) {
+                return Err(NvTrustError::MmioError {
+                    offset: GSP.base + offset,
+                    code: value,
+                    cause: error.cause(),
+                });
+            }
+        }
+
+        Ok(GspStatus {
+            falcon: GSP.status(self)?,
+            init_args: ((hi as u64) << 32) | lo as u64,
+        })
+    }
+}
+
+impl Falcon {
+    /// Read what the cores of the falcon are doing.
+    pub fn status(&self, gpu: &GpuObject) -> Result<FalconStatus> {
+        Ok(FalconStatus {
+            cpuctl: gpu.checked_read32(self.base + NV_FALCON_CPUCTL)?,
+            riscv_cpuctl: gpu.checked_read32(self.base + NV_RISCV_CPUCTL)?,
+            os: gpu.read32(self.base + NV_FALCON_OS)?,
+        })
+    }
+
+    pub fn read_mailbox0(&self, gpu: &GpuObject) -> Result<u32> {
+        gpu.read32(self.base + NV_FALCON_MAILBOX0)
+    }
+
+    pub fn read_mailbox1(&self, gpu: &GpuObject) -> Result<u32> {
+        gpu.read32(self.base + NV_FALCON_MAILBOX1)
+    }
+
+    /// Get the size of the DMEM in bytes.
+    pub fn dmem_size(&self, gpu: &GpuObject) -> Result<usize> {
+        let hwcfg = gpu.read32(self.base + NV_FALCON_HWCFG)?;
+
+        Ok((((hwcfg >> 9) & 0x1ff) as usize) * 256)
+    }
+
+    /// Read `len` bytes of DMEM starting at `offset` through the given DMEM port.
+    ///
+    /// The offset must be dword aligned and the length is rounded up to whole dwords.
+    pub fn read_dmem(
+        &self,
+        gpu: &GpuObject,
+        port: u64,
+        offset: u32,
+        len: usize,
+    ) -> Result<Vec<u32>> {
+        if offset & 0x3 != 0 {
+            return Err(NvTrustError::InvalidArgument(format!(
+                "DMEM offset 0x{:x} is not dword aligned",
+                offset
+            )));
+        }
+
+        let dmemc = self.base + NV_FALCON_DMEMC + port * 8;
+        let dmemd = self.base + NV_FALCON_DMEMD + port * 8;
+        gpu.write32(dmemc, offset | NV_FALCON_EMEMC_AINCR)?;
+
+        let mut data = Vec::with_capacity(len.div_ceil(4));
+        for _ in 0..len.div_ceil(4) {
+            data.push(gpu.read32(dmemd)?);
+        }
+
+        Ok(data)
+    }
+
+    /// Read `len` bytes of EMEM starting at `offset` through the given EMEM port.
+    ///
+    /// The offset must be dword aligned and the length is rounded up to whole dwords.
+    pub fn read_emem(
+        &self,
+        gpu: &GpuObject,
+        port: u64,
+        offset: u32,
+        len: usize,
+    ) -> Result<Vec<u32>> {
+        let (ememc, ememd) = self.emem_port(port)?;
+
+        if offset & 0x3 != 0 {
+            return Err(NvTrustError::InvalidArgument(format!(
+                "EMEM offset 0x{:x} is not dword aligned",
+                offset
+            )));
+        }
+
+        gpu.write32(ememc, offset | NV_FALCON_EMEMC_AINCR)?;
+
+        let mut data = Vec::with_capacity(len.div_ceil(4));
+        for _ in 0..len.div_ceil(4) {
+            data.push(gpu.read32(ememd)?);
+        }
+
+        Ok(data)
+    }
+
+    /// Write `data` to EMEM starting at `offset` through the given EMEM port.
+    pub fn write_emem(&self, gpu: &GpuObject, port: u64, offset: u32, data: &[u32]) -> Result<()> {
+        let (ememc, ememd) = self.emem_port(port)?;
+
+        if offset & 0x3 != 0 {
+            return Err(NvTrustError::InvalidArgument(format!(
+                "EMEM offset 0x{:x} is not dword aligned",
+                offset
+            )));
+        }
+
+        gpu.write32(ememc, offset | NV_FALCON_EMEMC_AINCW)?;
+
+        for d in data {
+            gpu.write32(ememd, *d)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the control and data registers of the given EMEM port.
+    fn emem_port(&self, port: u64) -> Result<(u64, u64)> {
+        let emem_base = self.emem_base.ok_or(NvTrustError::NotSupported {
+            what: "EMEM access".to_string(),
+            device: format!("falcon {}", self.name),
+            reason: "it has no EMEM".to_string(),
+        })?;
+
+        Ok((
+            emem_base + NV_FALCON_EMEMC + port * 8,
+            emem_base + NV_FALCON_EMEMD + port * 8,
+        ))
+    }
+}
@@ -0,0 +1,124 @@
+use serde::Serialize;
+
+use crate::{
+    bits::*,
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+    fsp::{FspRpc, PrcKnob},
+};
+
+/// The BAR0 windows probed for the firewall, by a register in each that reads without side
+/// effects.
+const WINDOWS: &[(&str, u64)] = &[
+    ("PMC", NV_PMC_BOOT_0),
+    ("PBUS", NV_PBUS_SW_SCRATCH),
+    ("PTIMER", NV_PTIMER_TIME_0),
+    ("THERM", NV_THERM_I2CS_SCRATCH),
+    ("VIRTUAL_FUNCTION", NV_VF_CC_MODE),
+    ("PCFG", NV_PBUS_PCI_NV_20),
+    ("GSP", NV_GSP_BASE + NV_FALCON_HWCFG),
+    ("PGC6_AON", NV_CC_MODE),
+    ("PROM", NV_PROM_DATA),
+    ("PRAMIN", NV_PMC_PRAMIN_START),
+    ("FUSE", NV_FUSE_OPT_PDI_0),
+    ("SEC2", NV_SEC2_BASE + NV_FALCON_HWCFG),
+    ("FSP", NV_FSP_SCRATCH_GROUP_2),
+    ("FSP_EMEM", NV_FSP_QUEUE_HEAD),
+];
+
+/// A BAR0 window and whether the host can reach it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Window {
+    pub name: &'static str,
+    pub begin: u64,
+    pub end: u64,
+    pub accessible: bool,
+    /// What the probe read returned instead of a value, see
+    /// [`crate::bits::NvidiaMmioErrorCode`].
+    pub error: Option<&'static str>,
+}
+
+impl Window {
+    /// Whether the window holds the given BAR0 offset.
+    pub fn contains(&self, offset: u64) -> bool {
+        (self.begin..self.end).contains(&offset)
+    }
+}
+
+/// The state of the BAR0 decoupler, which firewalls BAR0 from the host in CC mode.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FirewallStatus {
+    /// Whether the decoupler is engaged, as it is in CC mode but not in devtools mode.
+    pub engaged: bool,
+    /// The decoupler knob, which takes effect upon the next reset, if the FSP can tell.
+    pub pending: Option<bool>,
+    pub windows: Vec<Window>,
+}
+
+impl FirewallStatus {
+    /// The windows the host cannot reach.
+    pub fn blocked(&self) -> impl Iterator<Item = &Window> {
+        self.windows.iter().filter(|window| !window.accessible)
+    }
+}
+
+impl GpuObject {
+    /// Whether the BAR0 decoupler is engaged, i.e., whether register accesses may be blocked.
+    pub fn is_firewalled(&self) -> Result<bool> {
+        Ok(self.query_cc_mode()? == CcMode::CC_MODE_ON)
+    }
+
+    /// Report the BAR0 decoupler and which of the known BAR0 windows the host can still reach.
+    pub fn query_firewall(&self) -> Result<FirewallStatus> {
+        let engaged = self.is_firewalled()?;
+        let pending = match self.regs().fsp() {
+            Some(_) if !self.is_vf() => FspRpc::new(self, NV_FSP_CHANNEL)
+                .and_then(|rpc| rpc.prc_knob_read(PrcKnob::Bar0Decoupler))
+                .map(|value| value != 0)
+                .ok(),
+            _ => None,
+        };
+
+        let windows = WINDOWS
+            .iter()
+            .map(|(name, probe)| {
+                let begin = probe & !(NV_FIREWALL_WINDOW_SIZE - 1);
+                let error = self.read32(*probe).map(NvidiaMmioErrorCode::decode)?;
+                Ok(Window {
+                    name,
+                    begin,
+                    end: begin + NV_FIREWALL_WINDOW_SIZE,
+                    accessible: error.is_none(),
+                    error: error.map(|error| error.cause()),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(FirewallStatus {
+            engaged,
+            pending,
+            windows,
+        })
+    }
+
+    /// Read a register like [`Self::checked_read32`], but blame the firewall for an error code
+    /// while the decoupler is engaged.
+    pub fn firewalled_read32(&self, offset: u64) -> Result<u32> {
+        match self.checked_read32(offset) {
+            Err(NvTrustError::MmioError { .. }) if self.is_firewalled()? => {
+                Err(NvTrustError::Firewalled { offset })
+            }
+            result => result,
+        }
+    }
+
+    /// Write a register, or fail without writing if the firewall blocks it, which would drop the
+    /// write silently.
+    pub fn firewalled_write32(&self, offset: u64, value: u32) -> Result<()> {
+        if self.is_firewalled()? {
+            self.firewalled_read32(offset)?;
+        }
+
+        self.write32(offset, value)
+    }
+}
@@ -0,0 +1,761 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    arch::{Arch, FspRegs},
+    bits::*,
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+};
+
+/// A snapshot of the FSP's EMEM window and status registers.
+///
+/// This is what we look at when an FSP transaction gets stuck: the queue pointers tell whether the
+/// FSP consumed our command and the EMEM window still holds the last command or response.
+#[derive(Debug, Clone)]
+pub struct FspEmemDump {
+    /// The EMEM channel that was dumped.
+    pub channel: u64,
+    /// The labelled registers: (name, offset, value).
+    pub registers: Vec<(String, u64, u32)>,
+    /// The EMEM window of the channel, in dwords.
+    pub emem: Vec<u32>,
+}
+
+impl fmt::Display for FspEmemDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "FSP registers (channel {}):", self.channel)?;
+        for (name, offset, val) in self.registers.iter() {
+            writeln!(f, "  {:<28} [0x{:06x}] = 0x{:08x}", name, offset, val)?;
+        }
+
+        let base = self.channel * NV_FSP_EMEM_CHANNEL_SIZE;
+        writeln!(
+            f,
+            "FSP EMEM window (0x{:x} - 0x{:x}):",
+            base,
+            base + NV_FSP_EMEM_CHANNEL_SIZE
+        )?;
+        for (i, row) in self.emem.chunks(4).enumerate() {
+            write!(f, "  0x{:04x}:", base + i as u64 * 16)?;
+            for dword in row {
+                write!(f, " {:08x}", dword)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl GpuObject {
+    /// Capture the EMEM window of the given FSP channel together with the FSP status registers.
+    pub fn dump_fsp_emem(&self, channel: u64) -> Result<FspEmemDump> {
+        self.ensure_pf("FSP access")?;
+        self.ensure_cc("FSP access")?;
+
+        let fsp = self.fsp_regs()?;
+        let (boot_done, _, _) = self.regs().boot_done();
+        let mut regs = vec![
+            ("FSP_BOOT_COMPLETE".to_string(), boot_done),
+            (
+                "FSP_MAILBOX0".to_string(),
+                fsp.falcon.base + NV_FALCON_MAILBOX0,
+            ),
+            (
+                "FSP_MAILBOX1".to_string(),
+                fsp.falcon.base + NV_FALCON_MAILBOX1,
+            ),
+            ("FSP_QUEUE_HEAD".to_string(), fsp.queue_head + channel * 8),
+            ("FSP_QUEUE_TAIL".to_string(), fsp.queue_tail + channel * 8),
+            ("FSP_MSGQ_HEAD".to_string(), fsp.msgq_head + channel * 8),
+            ("FSP_MSGQ_TAIL".to_string(), fsp.msgq_tail + channel * 8),
+        ];
+        if let Some(cc_mode) = self.regs().cc_mode(false) {
+            regs.push(("CC_MODE".to_string(), cc_mode));
+        }
+        for i in 0..NV_FSP_SCRATCH_GROUP_2_LEN {
+            regs.push((
+                format!("FSP_SCRATCH_GROUP_2({i})"),
+                fsp.scratch_group_2 + i * 4,
+            ));
+        }
+
+        let registers = regs
+            .into_iter()
+            .map(|(name, offset)| Ok((name, offset, self.read32(offset)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let emem = fsp.falcon.read_emem(
+            self,
+            channel,
+            (channel * NV_FSP_EMEM_CHANNEL_SIZE) as u32,
+            NV_FSP_EMEM_CHANNEL_SIZE as usize,
+        )?;
+
+        Ok(FspEmemDump {
+            channel,
+            registers,
+            emem,
+        })
+    }
+
+    /// Get the FSP registers of the GPU, failing if it has no FSP.
+    pub fn fsp_regs(&self) -> Result<FspRegs> {
+        self.regs().fsp().ok_or(NvTrustError::NotSupported {
+            what: "FSP access".to_string(),
+            device: self.get_label(),
+            reason: format!("{} GPUs have no FSP", self.get_arch()),
+        })
+    }
+
+    /// Attach the FSP state to the error of a failed FSP transaction so that it can be diagnosed
+    /// after the fact.
+    pub fn attach_fsp_dump(&self, err: NvTrustError) -> NvTrustError {
+        let dump = match self.dump_fsp_emem(NV_FSP_CHANNEL) {
+            Ok(dump) => dump.to_string(),
+            Err(e) => format!("unavailable: {e}"),
+        };
+
+        NvTrustError::FspTransaction {
+            source: Box::new(err),
+            dump,
+        }
+    }
+}
+
+/// The persistent reset-controlled (PRC) knobs owned by the FSP.
+///
+/// Writes to these knobs only take effect after the next GPU reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u16)]
+pub enum PrcKnob {
+    /// Whether the CC dev-tools mode may be set in-band, i.e., from the host.
+    CcDevModeAllowInband = 5,
+    /// CC dev-tools mode.
+    CcDevMode = 6,
+    /// Whether the CC mode may be set in-band.
+    CcModeAllowInband = 7,
+    /// CC mode.
+    CcMode = 8,
+    /// Whether the BAR0 decoupler may be set in-band.
+    Bar0DecouplerAllowInband = 9,
+    /// BAR0 decoupler, which firewalls BAR0 from the host in CC mode.
+    Bar0Decoupler = 10,
+    /// Whether the PPCIe mode may be set in-band.
+    PpcieAllowInband = 33,
+    /// Protected PCIe mode, which protects the traffic of several GPUs and NVSwitches of a
+    /// board together rather than each GPU in CC mode.
+    Ppcie = 34,
+}
+
+impl PrcKnob {
+    /// All the knobs, in the order NVIDIA's tool lists them.
+    pub const ALL: [PrcKnob; 8] = [
+        PrcKnob::CcDevModeAllowInband,
+        PrcKnob::CcDevMode,
+        PrcKnob::CcModeAllowInband,
+        PrcKnob::CcMode,
+        PrcKnob::Bar0DecouplerAllowInband,
+        PrcKnob::Bar0Decoupler,
+        PrcKnob::PpcieAllowInband,
+        PrcKnob::Ppcie,
+    ];
+
+    /// The name of the knob in machine-readable output, e.g., `cc_dev_mode`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PrcKnob::CcDevModeAllowInband => "cc_dev_mode_allow_inband",
+            PrcKnob::CcDevMode => "cc_dev_mode",
+            PrcKnob::CcModeAllowInband => "cc_mode_allow_inband",
+            PrcKnob::CcMode => "cc_mode",
+            PrcKnob::Bar0DecouplerAllowInband => "bar0_decoupler_allow_inband",
+            PrcKnob::Bar0Decoupler => "bar0_decoupler",
+            PrcKnob::PpcieAllowInband => "ppcie_allow_inband",
+            PrcKnob::Ppcie => "ppcie",
+        }
+    }
+
+    /// Whether the knob can be written in-band; the knobs that allow in-band writes can only be
+    /// changed out of band, e.g., through the BMC.
+    pub fn is_writable(&self) -> bool {
+        !matches!(
+            self,
+            PrcKnob::CcDevModeAllowInband
+                | PrcKnob::CcModeAllowInband
+                | PrcKnob::Bar0DecouplerAllowInband
+                | PrcKnob::PpcieAllowInband
+        )
+    }
+
+    /// Whether devices of the architecture have the knob: the CC knobs are on the GPUs, and PPCIe
+    /// is on the Hopper GPUs and the NVSwitches.
+    pub fn supported_on(&self, arch: Arch) -> bool {
+        match self {
+            PrcKnob::Ppcie | PrcKnob::PpcieAllowInband => {
+                matches!(arch, Arch::Hopper | Arch::NvSwitch)
+            }
+            _ => matches!(arch, Arch::Hopper | Arch::Blackwell),
+        }
+    }
+}
+
+impl FromStr for PrcKnob {
+    type Err = NvTrustError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        PrcKnob::ALL
+            .into_iter()
+            .find(|knob| knob.name() == name)
+            .ok_or_else(|| {
+                let names = PrcKnob::ALL.map(|knob| knob.name());
+                NvTrustError::InvalidArgument(format!(
+                    "unknown knob {name}; the knobs are {}",
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+/// A PRC knob with the value in effect and the value that takes effect upon the next reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct KnobState {
+    pub knob: PrcKnob,
+    /// The value in effect, if the GPU reports it outside the knob, as it does for the CC mode.
+    pub current: Option<u16>,
+    /// The value of the knob, or `None` if the firmware does not know the knob.
+    pub pending: Option<u16>,
+}
+
+impl KnobState {
+    /// Whether the knob only takes effect upon the next reset.
+    pub fn needs_reset(&self) -> bool {
+        matches!((self.current, self.pending), (Some(current), Some(pending)) if current != pending)
+    }
+}
+
+/// Split a message, the MCTP message header first, into the MCTP packets of at most one EMEM
+/// channel each that carry it.
+pub fn mctp_packets(message: &[u32]) -> Vec<Vec<u32>> {
+    let chunks = message.chunks(NV_FSP_EMEM_CHANNEL_SIZE as usize / 4 - 1);
+    let count = chunks.len();
+
+    chunks
+        .enumerate()
+        .map(|(seq, chunk)| {
+            let mut header = (seq as u32 & MCTP_HEADER_SEQ_MASK) << MCTP_HEADER_SEQ_SHIFT;
+            if seq == 0 {
+                header |= MCTP_HEADER_SOM;
+            }
+            if seq == count - 1 {
+                header |= MCTP_HEADER_EOM;
+            }
+
+            let mut packet = vec![header];
+            packet.extend_from_slice(chunk);
+            packet
+        })
+        .collect()
+}
+
+/// A client of the FSP's RPC interface over one EMEM channel.
+///
+/// Each message is an MCTP message header carrying the NVDM type and the payload, sent as MCTP
+/// packets that each fill at most the EMEM window of the channel. The command queue pointers tell
+/// the FSP where a packet is, and the FSP replies through the message queue pointers in the same
+/// window.
+pub struct FspRpc<'a> {
+    gpu: &'a GpuObject,
+    regs: FspRegs,
+    channel: u64,
+    timeout: std::time::Duration,
+}
+
+impl<'a> FspRpc<'a> {
+    pub fn new(gpu: &'a GpuObject, channel: u64) -> Result<Self> {
+        Ok(Self {
+            gpu,
+            regs: gpu.fsp_regs()?,
+            channel,
+            timeout: std::time::Duration::from_secs(NV_FSP_RPC_TIMEOUT),
+        })
+    }
+
+    /// Wait for the FSP for the given time rather than [`NV_FSP_RPC_TIMEOUT`].
+    pub fn with_timeout(self, timeout: std::time::Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    #[inline]
+    fn emem_base(&self) -> u32 {
+        (self.channel * NV_FSP_EMEM_CHANNEL_SIZE) as u32
+    }
+
+    fn queue_head_tail(&self) -> Result<(u32, u32)> {
+        Ok((
+            self.gpu
+                .checked_read32(self.regs.queue_head + self.channel * 8)?,
+            self.gpu
+                .checked_read32(self.regs.queue_tail + self.channel * 8)?,
+        ))
+    }
+
+    fn msg_queue_head_tail(&self) -> Result<(u32, u32)> {
+        Ok((
+            self.gpu
+                .checked_read32(self.regs.msgq_head + self.channel * 8)?,
+            self.gpu
+                .checked_read32(self.regs.msgq_tail + self.channel * 8)?,
+        ))
+    }
+
+    /// Wait until the FSP has consumed all the commands in the command queue.
+    pub fn poll_for_queue_empty(&self) -> Result<()> {
+        let now = std::time::Instant::now();
+        loop {
+            let (head, tail) = self.queue_head_tail()?;
+            if head == tail {
+                return Ok(());
+            }
+
+            if now.elapsed() > self.timeout {
+                return Err(NvTrustError::Timeout(format!(
+                    "the FSP command queue to drain: head 0x{:x} tail 0x{:x}",
+                    head, tail
+                )));
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    /// Wait until the FSP has put a message into the message queue.
+    fn poll_for_msg_queue(&self) -> Result<()> {
+        let now = std::time::Instant::now();
+        loop {
+            let (head, tail) = self.msg_queue_head_tail()?;
+            if head != tail {
+                return Ok(());
+            }
+
+            if now.elapsed() > self.timeout {
+                return Err(NvTrustError::Timeout("a response from the FSP".to_string()));
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    /// Send a message of the given NVDM type.
+    pub fn send(&self, nvdm_type: u8, payload: &[u32]) -> Result<()> {
+        let msg_header = MCTP_MSG_HEADER_TYPE_VENDOR_PCI
+            | ((NVIDIA_VENDOR_ID as u32) << MCTP_MSG_HEADER_VENDOR_ID_SHIFT)
+            | ((nvdm_type as u32) << MCTP_MSG_HEADER_NVDM_TYPE_SHIFT);
+
+        let mut message = vec![msg_header];
+        message.extend_from_slice(payload);
+
+        for packet in mctp_packets(&message) {
+            self.send_packet(&packet)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_packet(&self, packet: &[u32]) -> Result<()> {
+        self.poll_for_queue_empty()?;
+        self.regs
+            .falcon
+            .write_emem(self.gpu, self.channel, self.emem_base(), packet)?;
+
+        // The tail points at the last dword of the packet; the head is written last as it kicks
+        // off the FSP.
+        let tail = self.emem_base() + (packet.len() as u32 - 1) * 4;
+        self.gpu
+            .write32(self.regs.queue_tail + self.channel * 8, tail)?;
+        self.gpu
+            .write32(self.regs.queue_head + self.channel * 8, self.emem_base())?;
+
+        Ok(())
+    }
+
+    /// Receive a message, returning its NVDM type and payload.
+    pub fn receive(&self) -> Result<(u8, Vec<u32>)> {
+        let mut message = vec![];
+        let mut seq = 0;
+        loop {
+            let packet = self.receive_packet()?;
+            let header = packet[0];
+
+            if (seq == 0) != (header & MCTP_HEADER_SOM != 0) {
+                return Err(NvTrustError::Fsp(format!(
+                    "packet {seq} of the message has header 0x{header:x}"
+                )));
+            }
+            if (header >> MCTP_HEADER_SEQ_SHIFT) & MCTP_HEADER_SEQ_MASK
+                != seq & MCTP_HEADER_SEQ_MASK
+            {
+                return Err(NvTrustError::Fsp(format!(
+                    "packet {seq} of the message is out of sequence: 0x{header:x}"
+                )));
+            }
+
+            message.extend_from_slice(&packet[1..]);
+            if header & MCTP_HEADER_EOM != 0 {
+                break;
+            }
+            seq += 1;
+        }
+
+        let nvdm_type = (message[0] >> MCTP_MSG_HEADER_NVDM_TYPE_SHIFT) as u8;
+
+        Ok((nvdm_type, message[1..].to_vec()))
+    }
+
+    fn receive_packet(&self) -> Result<Vec<u32>> {
+        self.poll_for_msg_queue()?;
+
+        let (head, tail) = self.msg_queue_head_tail()?;
+        if tail < head {
+            return Err(NvTrustError::Fsp(format!(
+                "malformed message queue: head 0x{:x} tail 0x{:x}",
+                head, tail
+            )));
+        }
+
+        let packet =
+            self.regs
+                .falcon
+                .read_emem(self.gpu, self.channel, head, (tail - head + 4) as usize)?;
+
+        // Mark the packet as consumed.
+        self.gpu
+            .write32(self.regs.msgq_tail + self.channel * 8, head)?;
+
+        if packet.len() < 2 {
+            return Err(NvTrustError::Fsp(format!(
+                "packet of {} dwords is too short",
+                packet.len()
+            )));
+        }
+
+        Ok(packet)
+    }
+
+    /// Send a command and wait for the FSP to acknowledge it.
+    ///
+    /// The FSP acknowledges each command with an `NVDM_TYPE_FSP_RESPONSE` message whose payload is
+    /// the task id, the NVDM type of the command and an error code, followed by the response data.
+    pub fn command(&self, nvdm_type: u8, payload: &[u32]) -> Result<Vec<u32>> {
+        let transact = || -> Result<Vec<u32>> {
+            self.send(nvdm_type, payload)?;
+            let (response_type, response) = self.receive()?;
+
+            if response_type != NVDM_TYPE_FSP_RESPONSE || response.len() < 3 {
+                return Err(NvTrustError::Fsp(format!(
+                    "unexpected response of type 0x{:x}: {:x?}",
+                    response_type, response
+                )));
+            }
+
+            if response[1] != nvdm_type as u32 {
+                return Err(NvTrustError::Fsp(format!(
+                    "responded to command 0x{:x} instead of 0x{:x}",
+                    response[1], nvdm_type
+                )));
+            }
+
+            if response[2] != 0 {
+                return Err(NvTrustError::Fsp(format!(
+                    "command 0x{:x} failed with error 0x{:x}",
+                    nvdm_type, response[2]
+                )));
+            }
+
+            Ok(response[3..].to_vec())
+        };
+
+        transact().map_err(|e| self.gpu.attach_fsp_dump(e))
+    }
+
+    /// Read the current value of a PRC knob.
+    ///
+    /// Both the request and the response carry the knob id in bits 15:0 and the value in bits
+    /// 31:16 of the second dword.
+    pub fn prc_knob_read(&self, knob: PrcKnob) -> Result<u16> {
+        let response = self.command(NVDM_TYPE_PRC, &[PRC_SUBMSG_ID_KNOB_READ, knob as u32])?;
+        let data = response.first().ok_or(NvTrustError::Fsp(format!(
+            "no data returned for knob {:?}",
+            knob
+        )))?;
+
+        Ok((data >> 16) as u16)
+    }
+
+    /// Write a PRC knob; the value takes effect after the next reset.
+    pub fn prc_knob_write(&self, knob: PrcKnob, value: u16) -> Result<()> {
+        self.command(
+            NVDM_TYPE_PRC,
+            &[
+                PRC_SUBMSG_ID_KNOB_WRITE,
+                knob as u32 | ((value as u32) << 16),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Write a PRC knob only if its value differs, as each write costs an FSP flash update.
+    pub fn prc_knob_check_and_write(&self, knob: PrcKnob, value: u16) -> Result<()> {
+        let current = self.prc_knob_read(knob)?;
+        if current == value {
+            log::debug!("Knob {:?} is already 0x{:x}", knob, value);
+            return Ok(());
+        }
+
+        log::debug!("Knob {:?}: 0x{:x} -> 0x{:x}", knob, current, value);
+        self.prc_knob_write(knob, value)
+    }
+}
+
+impl GpuObject {
+    /// Read the CC knobs that take effect upon the next reset.
+    pub fn query_cc_settings(&self) -> Result<Vec<(PrcKnob, u16)>> {
+        self.ensure_pf("Querying the CC settings")?;
+        self.ensure_cc("Querying the CC settings")?;
+        self.wait_for_boot()?;
+
+        let rpc = FspRpc::new(self, NV_FSP_CHANNEL)?;
+        [PrcKnob::CcMode, PrcKnob::CcDevMode, PrcKnob::Bar0Decoupler]
+            .into_iter()
+            .map(|knob| Ok((knob, rpc.prc_knob_read(knob)?)))
+            .collect()
+    }
+
+    /// Read all the PRC knobs, with the value in effect of those the GPU reports.
+    pub fn query_prc_knobs(&self) -> Result<Vec<KnobState>> {
+        self.ensure_pf("Querying the PRC knobs")?;
+        self.ensure_cc("Querying the PRC knobs")?;
+
+        let active = self.query_cc_mode()?;
+        let rpc = FspRpc::new(self, NV_FSP_CHANNEL)?;
+        PrcKnob::ALL
+            .into_iter()
+            .map(|knob| {
+                let pending = match rpc.prc_knob_read(knob) {
+                    Ok(value) => Some(value),
+                    // Older firmware rejects the knobs it does not have, e.g., PPCIe.
+                    Err(NvTrustError::FspTransaction { source, .. })
+                        if matches!(*source, NvTrustError::Fsp(_)) =>
+                    {
+                        log::debug!("{} has no knob {}: {source}", self.get_bdf(), knob.name());
+                        None
+                    }
+                    Err(e) => return Err(e),
+                };
+                let current = match knob {
+                    PrcKnob::CcMode => Some(active.contains(CcMode::CC_MODE_ON) as u16),
+                    PrcKnob::CcDevMode => Some((active == CcMode::CC_MODE_DEV_TOOLS) as u16),
+                    _ => None,
+                };
+
+                Ok(KnobState {
+                    knob,
+                    current,
+                    pending,
+                })
+            })
+            .collect()
+    }
+
+    /// Write a single PRC knob, which takes effect upon the next reset, and read it back.
+    ///
+    /// [`Self::set_cc_mode`] keeps the CC knobs consistent with each other; this does not, so a
+    /// combination the firmware does not expect may leave the GPU unusable until it is fixed.
+    pub fn set_prc_knob(&self, knob: PrcKnob, value: u16) -> Result<()> {
+        self.ensure_pf("Setting a PRC knob")?;
+        self.ensure_cc("Setting a PRC knob")?;
+
+        let invalid = |reason: String| NvTrustError::InvalidArgument(reason);
+        if !knob.supported_on(self.get_arch()) {
+            return Err(invalid(format!(
+                "{} GPUs have no knob {}",
+                self.get_arch(),
+                knob.name()
+            )));
+        }
+        if !knob.is_writable() {
+            return Err(invalid(format!(
+                "knob {} can only be set out of band",
+                knob.name()
+            )));
+        }
+        // All the knobs are switches.
+        if value > 1 {
+            return Err(invalid(format!(
+                "knob {} cannot be {value}; it is 0 or 1",
+                knob.name()
+            )));
+        }
+
+        self.wait_for_boot()?;
+        let rpc = FspRpc::new(self, NV_FSP_CHANNEL)?;
+        rpc.prc_knob_check_and_write(knob, value)?;
+
+        let readback = rpc.prc_knob_read(knob)?;
+        if readback != value {
+            return Err(NvTrustError::Fsp(format!(
+                "knob {} reads back 0x{readback:x} instead of 0x{value:x}",
+                knob.name()
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn ensure_ppcie(&self, what: &str) -> Result<()> {
+        self.ensure_pf(what)?;
+        self.ensure_cc(what)?;
+
+        if !PrcKnob::Ppcie.supported_on(self.get_arch()) {
+            return Err(NvTrustError::NotSupported {
+                what: what.to_string(),
+                device: self.get_label(),
+                reason: format!("{} GPUs do not support PPCIe", self.get_arch()),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Whether the GPU or NVSwitch is in PPCIe mode.
+    pub fn query_ppcie_mode(&self) -> Result<bool> {
+        self.ensure_ppcie("Querying the PPCIe mode")?;
+        self.wait_for_boot()?;
+
+        let register = self.regs().cc_mode(false).unwrap_or(NV_CC_MODE);
+        Ok(self.checked_read32(register)? & NV_CC_MODE_PPCIE != 0)
+    }
+
+    /// Whether the PPCIe knob selects PPCIe mode, i.e., whether it is on after the next reset.
+    pub fn pending_ppcie_mode(&self) -> Result<bool> {
+        self.ensure_ppcie("Querying the PPCIe mode")?;
+        self.wait_for_boot()?;
+
+        Ok(FspRpc::new(self, NV_FSP_CHANNEL)?.prc_knob_read(PrcKnob::Ppcie)? != 0)
+    }
+
+    /// Program the PPCIe knob, which takes effect after the next reset. PPCIe replaces the CC mode
+    /// of each GPU, so turning it on turns the CC knobs of a GPU off.
+    ///
+    /// All the GPUs and NVSwitches of a board must agree on the mode, see
+    /// [`crate::txn::set_ppcie_mode_all`].
+    pub fn set_ppcie_mode(&self, on: bool) -> Result<()> {
+        self.ensure_ppcie("Setting the PPCIe mode")?;
+        self.wait_for_boot()?;
+
+        let mut targets = vec![];
+        if on && !self.is_nvswitch() {
+            targets.extend([
+                (PrcKnob::CcDevMode, 0),
+                (PrcKnob::CcMode, 0),
+                (PrcKnob::Bar0Decoupler, 0),
+            ]);
+        }
+        targets.push((PrcKnob::Ppcie, on as u16));
+
+        let rpc = FspRpc::new(self, NV_FSP_CHANNEL)?;
+        for (knob, value) in targets.iter() {
+            rpc.prc_knob_check_and_write(*knob, *value)?;
+        }
+        for (knob, value) in targets {
+            let readback = rpc.prc_knob_read(knob)?;
+            if readback != value {
+                return Err(NvTrustError::CcSwitchFailed {
+                    device: self.get_label(),
+                    reason: format!(
+                        "knob {:?} reads back 0x{:x} instead of 0x{:x}",
+                        knob, readback, value
+                    ),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the CC mode that the knobs select, i.e., the mode that becomes active upon the next reset.
+    pub fn pending_cc_mode(&self) -> Result<CcMode> {
+        let settings = self.query_cc_settings()?;
+        let knob = |knob| {
+            settings
+                .iter()
+                .find(|(k, _)| *k == knob)
+                .map(|(_, v)| *v)
+                .unwrap_or_default()
+        };
+
+        Ok(match (knob(PrcKnob::CcMode), knob(PrcKnob::CcDevMode)) {
+            (0, _) => CcMode::CC_MODE_OFF,
+            (_, 0) => CcMode::CC_MODE_ON,
+            _ => CcMode::CC_MODE_DEV_TOOLS,
+        })
+    }
+
+    /// Program the CC knobs for the given mode. The mode takes effect after the next reset.
+    pub fn set_cc_mode(&self, mode: CcMode) -> Result<()> {
+        self.ensure_pf("Setting the CC mode")?;
+        self.ensure_cc("Setting the CC mode")?;
+        if !PrcKnob::CcMode.supported_on(self.get_arch()) {
+            return Err(NvTrustError::NotSupported {
+                what: "Setting the CC mode".to_string(),
+                device: self.get_label(),
+                reason: "NVSwitches take the PPCIe mode only".to_string(),
+            });
+        }
+
+        let (cc_mode, cc_dev_mode, bar0_decoupler) = match mode {
+            CcMode::CC_MODE_OFF => (0, 0, 0),
+            CcMode::CC_MODE_ON => (1, 0, 1),
+            CcMode::CC_MODE_DEV_TOOLS => (1, 1, 0),
+            _ => {
+                return Err(NvTrustError::InvalidArgument(format!(
+                    "invalid CC mode {mode}"
+                )))
+            }
+        };
+
+        self.wait_for_boot()?;
+
+        let targets = [
+            (PrcKnob::CcDevMode, cc_dev_mode),
+            (PrcKnob::CcMode, cc_mode),
+            (PrcKnob::Bar0Decoupler, bar0_decoupler),
+        ];
+
+        let rpc = FspRpc::new(self, NV_FSP_CHANNEL)?;
+        for (knob, value) in targets {
+            rpc.prc_knob_check_and_write(knob, value)?;
+        }
+
+        // The FSP acknowledges a write before it commits it, so read the knobs back to make sure
+        // they will take effect upon the reset.
+        for (knob, value) in self.query_cc_settings()? {
+            if let Some((_, target)) = targets.iter().find(|(k, _)| *k == knob) {
+                if *target != value {
+                    return Err(NvTrustError::CcSwitchFailed {
+                        device: self.get_label(),
+                        reason: format!(
+                            "knob {:?} reads back 0x{:x} instead of 0x{:x}",
+                            knob, value, target
+                        ),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,417 @@
+use crate::{
+    bits::*,
+    dev::{is_mmio_error, read_sysmem, GpuObject},
+    error::{NvTrustError, Result},
+    falcon::{GSP, SEC2},
+};
+
+/// A memory region described in the LibOS init arguments that the driver hands to GSP-RM.
+#[derive(Debug, Clone)]
+pub struct LibosRegion {
+    /// The name of the region, e.g., `LOGINIT`.
+    pub id: String,
+    /// The physical address of the region.
+    pub pa: u64,
+    /// The size of the region.
+    pub size: u64,
+    /// The kind of the region (contiguous, radix3, ...).
+    pub kind: u8,
+    /// Where the region lives, see [`LIBOS_REGION_LOC_SYSMEM`] and [`LIBOS_REGION_LOC_FB`].
+    pub loc: u8,
+}
+
+/// A log buffer extracted from the firmware.
+#[derive(Debug, Clone)]
+pub struct FwLogBuffer {
+    /// The name of the log buffer.
+    pub name: String,
+    /// The put pointer of the ring in qwords, i.e., how many qwords the firmware has written.
+    pub put: u64,
+    /// The records of the ring.
+    pub data: Vec<u8>,
+}
+
+impl LibosRegion {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let qword = |i: usize| u64::from_le_bytes(bytes[i * 8..(i + 1) * 8].try_into().unwrap());
+
+        // The id is packed with the first character in the most significant used byte.
+        let id = qword(0)
+            .to_be_bytes()
+            .iter()
+            .filter(|c| **c != 0)
+            .map(|c| *c as char)
+            .collect();
+
+        Self {
+            id,
+            pa: qword(1),
+            size: qword(2),
+            kind: bytes[24],
+            loc: bytes[25],
+        }
+    }
+
+    fn read(&self, gpu: &GpuObject, addr: u64, len: usize) -> Result<Vec<u8>> {
+        match self.loc {
+            LIBOS_REGION_LOC_SYSMEM => Ok(read_sysmem(addr, len)?),
+            LIBOS_REGION_LOC_FB => Ok(gpu.read_vram(addr, len)?),
+            loc => Err(NvTrustError::FwLog(format!(
+                "region {} has unknown location {}",
+                self.id, loc
+            ))),
+        }
+    }
+}
+
+impl GpuObject {
+    /// Locate the LibOS memory regions of GSP-RM.
+    ///
+    /// The driver passes the physical address of the init arguments to GSP-RM through the GSP
+    /// mailboxes, which are still readable after the driver has been unloaded.
+    pub fn gsp_libos_regions(&self) -> Result<Vec<LibosRegion>> {
+        self.ensure_pf("GSP log extraction")?;
+
+        let lo = GSP.read_mailbox0(self)?;
+        let hi = GSP.read_mailbox1(self)?;
+
+        if is_mmio_error(lo) || is_mmio_error(hi) {
+            return Err(NvTrustError::FwLog(format!(
+                "GSP mailboxes are not accessible: 0x{:x} 0x{:x}",
+                hi, lo
+            )));
+        }
+
+        let args = ((hi as u64) << 32) | lo as u64;
+        if args == 0 {
+            return Err(NvTrustError::FwLog(
+                "GSP-RM has not been booted by a driver".to_string(),
+            ));
+        }
+
+        log::debug!("LibOS init arguments at 0x{:x}", args);
+
+        let raw = read_sysmem(args, PAGE_SIZE as _)?;
+        let regions = raw
+            .chunks(LIBOS_REGION_DESC_SIZE)
+            .take(LIBOS_MAX_REGIONS)
+            .map(LibosRegion::from_bytes)
+            .take_while(|region| !region.id.is_empty())
+            .collect();
+
+        Ok(regions)
+    }
+
+    /// Extract the GSP-RM log ring buffers (`LOGINIT`, `LOGINTR`, `LOGRM`, ...).
+    ///
+    /// Each log buffer starts with the put pointer, followed by the PTEs of the pages backing the
+    /// buffer itself; the records follow the PTEs.
+    pub fn extract_gsp_logs(&self) -> Result<Vec<FwLogBuffer>> {
+        let mut logs = vec![];
+
+        for region in self
+            .gsp_libos_regions()?
+            .into_iter()
+            .filter(|region| region.id.starts_with("LOG"))
+        {
+            let pages = region.size.div_ceil(PAGE_SIZE) as usize;
+            let head = region.read(self, region.pa, PAGE_SIZE as _)?;
+            let qword = |i: usize| u64::from_le_bytes(head[i * 8..(i + 1) * 8].try_into().unwrap());
+
+            if 1 + pages > PAGE_SIZE as usize / 8 {
+                log::warn!("Log buffer {} is too large, skipped.", region.id);
+                continue;
+            }
+
+            let put = qword(0);
+            let mut buf = head.clone();
+            for i in 1..pages {
+                buf.extend(region.read(self, qword(1 + i), PAGE_SIZE as _)?);
+            }
+
+            logs.push(FwLogBuffer {
+                name: region.id.clone(),
+                put,
+                data: buf.split_off((1 + pages) * 8),
+            });
+        }
+
+        Ok(logs)
+    }
+
+    /// Extract the SEC2 print buffer which SEC2 keeps in its DMEM.
+    pub fn extract_sec2_log(&self) -> Result<FwLogBuffer> {
+        self.ensure_pf("SEC2 log extraction")?;
+
+        let size = SEC2.dmem_size(self)?;
+        if size == 0 {
+            return Err(NvTrustError::FwLog(
+                "SEC2 DMEM is not accessible".to_string(),
+            ));
+        }
+
+        let dmem = SEC2.read_dmem(self, 0, 0, size)?;
+        if dmem.iter().all(|dword| is_mmio_error(*dword)) {
+            return Err(NvTrustError::FwLog(format!(
+                "SEC2 DMEM is blocked: 0x{:x}",
+                dmem[0]
+            )));
+        }
+
+        Ok(FwLogBuffer {
+            name: "SEC2DMEM".to_string(),
+            put: 0,
+            data: dmem.iter().flat_map(|dword| dword.to_le_bytes()).collect(),
+        })
+    }
+}
+
+/// The logging sections of a LibOS firmware ELF, which hold the metadata of each log statement.
+#[derive(Debug, Clone)]
+pub struct LogElf {
+    /// The logging sections: (name, virtual address, data).
+    sections: Vec<(String, u64, Vec<u8>)>,
+}
+
+/// A decoded firmware log record.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    /// The timestamp in nanoseconds.
+    pub timestamp: u64,
+    /// The source file of the log statement.
+    pub file: String,
+    /// The line number of the log statement.
+    pub line: u32,
+    /// The print level of the log statement.
+    pub level: u8,
+    /// The formatted message.
+    pub message: String,
+}
+
+impl LogElf {
+    /// Parse the `.fwlogging*` sections out of a 64-bit little-endian ELF.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 0x40 || &bytes[..4] != b"\x7fELF" || bytes[4] != 2 {
+            return Err(NvTrustError::FwLog("not a 64-bit ELF file".to_string()));
+        }
+
+        let u16_at = |off: usize| -> Result<u16> {
+            Ok(u16::from_le_bytes(
+                bytes
+                    .get(off..off + 2)
+                    .ok_or(NvTrustError::FwLog("truncated ELF".to_string()))?
+                    .try_into()
+                    .unwrap(),
+            ))
+        };
+        let u32_at = |off: usize| -> Result<u32> {
+            Ok(u32::from_le_bytes(
+                bytes
+                    .get(off..off + 4)
+                    .ok_or(NvTrustError::FwLog("truncated ELF".to_string()))?
+                    .try_into()
+                    .unwrap(),
+            ))
+        };
+        let u64_at = |off: usize| -> Result<u64> {
+            Ok(u64::from_le_bytes(
+                bytes
+                    .get(off..off + 8)
+                    .ok_or(NvTrustError::FwLog("truncated ELF".to_string()))?
+                    .try_into()
+                    .unwrap(),
+            ))
+        };
+
+        let shoff = u64_at(0x28)? as usize;
+        let shentsize = u16_at(0x3a)? as usize;
+        let shnum = u16_at(0x3c)? as usize;
+        let shstrndx = u16_at(0x3e)? as usize;
+
+        let section = |i: usize| -> Result<(u32, u64, usize, usize)> {
+            let hdr = shoff + i * shentsize;
+            Ok((
+                u32_at(hdr)?,
+                u64_at(hdr + 16)?,
+                u64_at(hdr + 24)? as usize,
+                u64_at(hdr + 32)? as usize,
+            ))
+        };
+
+        let (_, _, strtab, _) = section(shstrndx)?;
+        let mut sections = vec![];
+        for i in 0..shnum {
+            let (name, addr, offset, size) = section(i)?;
+            let name = read_cstr(bytes.get(strtab + name as usize..).unwrap_or_default());
+
+            if name.starts_with(".fwlogging") {
+                let data = bytes
+                    .get(offset..offset + size)
+                    .ok_or(NvTrustError::FwLog(format!("truncated section {name}")))?;
+                sections.push((name, addr, data.to_vec()));
+            }
+        }
+
+        if sections.is_empty() {
+            return Err(NvTrustError::FwLog(
+                "no .fwlogging sections found".to_string(),
+            ));
+        }
+
+        Ok(Self { sections })
+    }
+
+    /// Get the bytes at the given virtual address, if it lies in one of the logging sections.
+    fn bytes_at(&self, addr: u64) -> Option<&[u8]> {
+        self.sections
+            .iter()
+            .find(|(_, base, data)| addr >= *base && addr < *base + data.len() as u64)
+            .map(|(_, base, data)| &data[(addr - base) as usize..])
+    }
+
+    fn read_str(&self, addr: u64) -> Option<String> {
+        self.bytes_at(addr).map(read_cstr)
+    }
+}
+
+impl LogRecord {
+    /// The timestamp formatted as seconds.
+    pub fn time(&self) -> String {
+        format!(
+            "{}.{:09}",
+            self.timestamp / 1_000_000_000,
+            self.timestamp % 1_000_000_000
+        )
+    }
+}
+
+/// Decode the records of a LibOS log ring.
+///
+/// Each record is laid out as qwords: the address of its metadata in the logging ELF, the
+/// timestamp, then the arguments. The metadata holds the file name, the format string, the line
+/// number, the argument count (including the format string) and the print level.
+pub fn decode_log(buf: &FwLogBuffer, elf: &LogElf) -> Vec<LogRecord> {
+    let words = buf
+        .data
+        .chunks_exact(8)
+        .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+        .collect::<Vec<_>>();
+
+    if words.is_empty() {
+        return vec![];
+    }
+
+    // If the ring has wrapped, the oldest records start right after the put pointer; the record
+    // there may have been partially overwritten, so we resynchronize on the first valid metadata.
+    let n = words.len();
+    let (start, count) = if buf.put == 0 || buf.put as usize > n {
+        ((buf.put as usize) % n, n)
+    } else {
+        (0, buf.put as usize)
+    };
+    let word = |i: usize| words[(start + i) % n];
+
+    let mut records = vec![];
+    let mut i = 0;
+    while i + 2 <= count {
+        let meta_addr = word(i);
+        if buf.put == 0 && meta_addr == 0 {
+            break;
+        }
+
+        let meta = match elf.bytes_at(meta_addr).filter(|meta| meta.len() >= 22) {
+            Some(meta) => meta,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let file = u64::from_le_bytes(meta[0..8].try_into().unwrap());
+        let format = u64::from_le_bytes(meta[8..16].try_into().unwrap());
+        let line = u32::from_le_bytes(meta[16..20].try_into().unwrap());
+        let argc = (meta[20] as usize).saturating_sub(1);
+        let level = meta[21];
+
+        if i + 2 + argc > count {
+            break;
+        }
+
+        let args = (0..argc).map(|j| word(i + 2 + j)).collect::<Vec<_>>();
+        let format = elf.read_str(format).unwrap_or_default();
+
+        records.push(LogRecord {
+            timestamp: word(i + 1),
+            file: elf.read_str(file).unwrap_or_default(),
+            line,
+            level,
+            message: format_printf(&format, &args, elf),
+        });
+
+        i += 2 + argc;
+    }
+
+    records
+}
+
+/// A minimal printf implementation for the format strings in the firmware.
+fn format_printf(format: &str, args: &[u64], elf: &LogElf) -> String {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        let mut zero_pad = false;
+        let mut width = 0;
+        if chars.peek() == Some(&'0') {
+            zero_pad = true;
+            chars.next();
+        }
+        while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+            width = width * 10 + d as usize;
+            chars.next();
+        }
+        while matches!(chars.peek(), Some('l') | Some('h') | Some('z')) {
+            chars.next();
+        }
+
+        let spec = match chars.next() {
+            Some(spec) => spec,
+            None => break,
+        };
+        if spec == '%' {
+            out.push('%');
+            continue;
+        }
+
+        let arg = args.next().copied().unwrap_or_default();
+        let s = match spec {
+            'd' | 'i' => (arg as i64).to_string(),
+            'u' => arg.to_string(),
+            'x' => format!("{:x}", arg),
+            'X' => format!("{:X}", arg),
+            'p' => format!("0x{:x}", arg),
+            'c' => ((arg as u8) as char).to_string(),
+            's' => elf
+                .read_str(arg)
+                .unwrap_or_else(|| format!("<0x{:x}>", arg)),
+            other => format!("%{other}"),
+        };
+
+        let pad = width.saturating_sub(s.len());
+        out.extend(std::iter::repeat_n(if zero_pad { '0' } else { ' ' }, pad));
+        out.push_str(&s);
+    }
+
+    out.trim_end().to_string()
+}
+
+fn read_cstr(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
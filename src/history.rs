@@ -0,0 +1,138 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::error::{NvTrustError, Result};
+
+/// A set of measurements, keyed by measurement index, with the digests as hex.
+pub type MeasurementSet = BTreeMap<u32, String>;
+
+/// The local history database.
+///
+/// Each GPU gets its own directory named after its identity, holding the trusted baseline and an
+/// append-only log of every measurement set observed for that GPU:
+///
+/// ```text
+/// <dir>/<identity>/baseline       # "<index> <digest>" per line
+/// <dir>/<identity>/history.log    # "<unix time> <event> <index>=<digest> ..." per line
+/// ```
+#[derive(Debug, Clone)]
+pub struct HistoryDb {
+    dir: PathBuf,
+}
+
+/// A measurement that differs from the baseline.
+#[derive(Debug, Clone)]
+pub struct MeasurementChange {
+    pub index: u32,
+    pub baseline: Option<String>,
+    pub observed: Option<String>,
+}
+
+/// Parse a measurement set from "<index> <digest>" lines.
+pub fn parse_measurements(content: &str) -> Result<MeasurementSet> {
+    let mut set = MeasurementSet::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (index, digest) =
+            line.split_once(char::is_whitespace)
+                .ok_or(NvTrustError::Malformed(format!(
+                    "line {}: expected '<index> <digest>'",
+                    i + 1
+                )))?;
+        let index = index
+            .parse()
+            .map_err(|e| NvTrustError::Malformed(format!("line {}: invalid index: {e}", i + 1)))?;
+
+        set.insert(index, digest.trim().to_lowercase());
+    }
+
+    Ok(set)
+}
+
+/// Compare the observed measurements against the baseline.
+pub fn compare(baseline: &MeasurementSet, observed: &MeasurementSet) -> Vec<MeasurementChange> {
+    let mut indices = baseline.keys().chain(observed.keys()).collect::<Vec<_>>();
+    indices.sort();
+    indices.dedup();
+
+    indices
+        .into_iter()
+        .filter(|index| baseline.get(index) != observed.get(index))
+        .map(|index| MeasurementChange {
+            index: *index,
+            baseline: baseline.get(index).cloned(),
+            observed: observed.get(index).cloned(),
+        })
+        .collect()
+}
+
+impl HistoryDb {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn gpu_dir(&self, identity: &str) -> PathBuf {
+        self.dir.join(identity)
+    }
+
+    /// The trusted baseline of the GPU, if one has been recorded.
+    pub fn baseline(&self, identity: &str) -> Result<Option<MeasurementSet>> {
+        match fs::read_to_string(self.gpu_dir(identity).join("baseline")) {
+            Ok(content) => Ok(Some(parse_measurements(&content)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Record the trusted baseline of the GPU.
+    pub fn set_baseline(&self, identity: &str, measurements: &MeasurementSet) -> Result<()> {
+        let dir = self.gpu_dir(identity);
+        fs::create_dir_all(&dir)?;
+
+        let content = measurements
+            .iter()
+            .map(|(index, digest)| format!("{index} {digest}\n"))
+            .collect::<String>();
+
+        let tmp = dir.join("baseline.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, dir.join("baseline"))?;
+
+        Ok(())
+    }
+
+    /// Append an event with the observed measurements to the history log of the GPU.
+    pub fn append(&self, identity: &str, event: &str, measurements: &MeasurementSet) -> Result<()> {
+        let dir = self.gpu_dir(identity);
+        fs::create_dir_all(&dir)?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        let mut line = format!("{now} {event}");
+        for (index, digest) in measurements.iter() {
+            line.push_str(&format!(" {index}={digest}"));
+        }
+        line.push('\n');
+
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join("history.log"))?
+            .write_all(line.as_bytes())?;
+
+        Ok(())
+    }
+}
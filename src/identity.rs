@@ -0,0 +1,72 @@
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bits::*,
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+};
+
+/// The per-device identity (PDI) burnt into the fuses of the GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceIdentity(pub u64);
+
+impl DeviceIdentity {
+    /// The PDI as hex, the way it shows up in the subject of the device certificate.
+    pub fn to_hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+
+    /// Check whether the given certificate (PEM or DER) is issued to this device.
+    ///
+    /// NVIDIA device certificates carry the PDI as hex in their subject, so we look for it in the
+    /// encoded certificate rather than parsing the whole X.509 structure.
+    pub fn matches_cert(&self, cert: &[u8]) -> Result<bool> {
+        let der = decode_pem(cert)?;
+        let hex = self.to_hex();
+
+        Ok([hex.to_lowercase(), hex.to_uppercase()]
+            .iter()
+            .any(|needle| der.windows(needle.len()).any(|w| w == needle.as_bytes())))
+    }
+}
+
+/// Decode the first PEM block of the input; input that is not PEM is taken as DER.
+pub fn decode_pem(input: &[u8]) -> Result<Vec<u8>> {
+    let text = match std::str::from_utf8(input) {
+        Ok(text) if text.contains("-----BEGIN") => text,
+        _ => return Ok(input.to_vec()),
+    };
+
+    let body = text
+        .lines()
+        .skip_while(|line| !line.starts_with("-----BEGIN"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----END"))
+        .collect::<String>();
+
+    base64::engine::general_purpose::STANDARD
+        .decode(body.trim())
+        .map_err(|e| NvTrustError::Certificate(format!("invalid PEM: {e}")))
+}
+
+impl GpuObject {
+    /// Read the per-device identity from the fuses.
+    pub fn query_device_identity(&self) -> Result<DeviceIdentity> {
+        self.ensure_pf("Fuse access")?;
+
+        let inaccessible = |e: NvTrustError| NvTrustError::NotSupported {
+            what: "Fuse access".to_string(),
+            device: self.get_label(),
+            reason: format!("the PDI fuses are not accessible: {e}"),
+        };
+        let lo = self
+            .checked_read32(NV_FUSE_OPT_PDI_0)
+            .map_err(inaccessible)?;
+        let hi = self
+            .checked_read32(NV_FUSE_OPT_PDI_1)
+            .map_err(inaccessible)?;
+
+        Ok(DeviceIdentity(((hi as u64) << 32) | lo as u64))
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{
+    dev::PciDevice,
+    error::{NvTrustError, Result},
+};
+
+/// A device in the IOMMU group of a GPU.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GroupMember {
+    pub bdf: String,
+    pub vendor: u16,
+    pub device: u16,
+    /// The class code, e.g., 0x030200 for a 3D controller.
+    pub class: u32,
+    pub driver: Option<String>,
+    /// Whether vfio can take the device along with the group: it is bound to a vfio driver or
+    /// pci-stub, bound to nothing, or a bridge.
+    pub viable: bool,
+}
+
+/// The IOMMU group of a GPU, which vfio can only take as a whole.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IommuGroup {
+    pub group: u32,
+    /// The kind of the default domain, e.g., `DMA-FQ`, or `identity` with `iommu=pt`.
+    pub kind: Option<String>,
+    pub devices: Vec<GroupMember>,
+}
+
+impl IommuGroup {
+    /// Whether the group can be passed to a guest through vfio.
+    pub fn is_viable(&self) -> bool {
+        self.devices.iter().all(|device| device.viable)
+    }
+}
+
+fn read_hex(path: &Path) -> u32 {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|value| u32::from_str_radix(value.trim().trim_start_matches("0x"), 16).ok())
+        .unwrap_or(0)
+}
+
+fn member(path: &Path) -> GroupMember {
+    let class = read_hex(&path.join("class"));
+    let driver = std::fs::read_link(path.join("driver"))
+        .ok()
+        .and_then(|link| link.file_name().map(|s| s.to_string_lossy().to_string()));
+    let viable = match &driver {
+        Some(driver) => driver.starts_with("vfio") || driver == "pci-stub",
+        None => true,
+    } || class >> 8 == 0x0604;
+
+    GroupMember {
+        bdf: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        vendor: read_hex(&path.join("vendor")) as u16,
+        device: read_hex(&path.join("device")) as u16,
+        class,
+        driver,
+        viable,
+    }
+}
+
+impl PciDevice {
+    /// Read the IOMMU group of the device, with all the devices in it.
+    pub fn iommu_group(&self) -> Result<IommuGroup> {
+        let group = Path::new(self.get_name())
+            .join("iommu_group")
+            .canonicalize()
+            .map_err(|_| NvTrustError::NotSupported {
+                what: "the IOMMU group".to_string(),
+                device: self.get_bdf().to_string(),
+                reason: "it has none; the IOMMU is off".to_string(),
+            })?;
+
+        let mut devices = std::fs::read_dir(group.join("devices"))?
+            .flatten()
+            .map(|entry| member(&entry.path()))
+            .collect::<Vec<_>>();
+        devices.sort_by(|a, b| a.bdf.cmp(&b.bdf));
+
+        Ok(IommuGroup {
+            group: group
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.parse().ok())
+                .unwrap_or_default(),
+            kind: std::fs::read_to_string(group.join("type"))
+                .ok()
+                .map(|kind| kind.trim().to_string()),
+            devices,
+        })
+    }
+}
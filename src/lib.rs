@@ -0,0 +1,84 @@
+//! Confidential Computing (CC) control of NVIDIA GPUs.
+//!
+//! This is the library behind the `nvtrust` tool, for services that want to drive the GPUs
+//! themselves. The entry points are:
+//!
+//! - GPU enumeration: [`dev::find_gpus_by_bdf`], [`dev::find_gpus_by_name`],
+//!   [`dev::find_gpu_by_index`] and [`dev::find_nvswitches`], which return [`dev::GpuObject`]s.
+//! - CC query and configuration: [`dev::GpuObject::query_cc_mode`],
+//!   [`dev::GpuObject::query_cc_settings`] and [`dev::GpuObject::set_cc_mode`], and
+//!   [`txn::set_cc_mode_all`] and [`txn::reset_all`] to switch a whole board.
+//! - Register access: [`dev::GpuObject::read32`], [`dev::GpuObject::write32`] and friends, which
+//!   take offsets into BAR0, and [`dev::GpuObject::read_phys`] for physical addresses.
+//!
+//! All of these need root, as the GPU is accessed through `/dev/mem` and sysfs.
+//! They fail with an [`error::NvTrustError`], so that callers can tell, e.g., a missing device
+//! from a failed CC mode switch.
+//!
+//! ```no_run
+//! use nvtrust::{bits::CcMode, dev};
+//!
+//! # fn main() -> nvtrust::error::Result<()> {
+//! for gpu in dev::find_gpus_by_bdf("")? {
+//!     if gpu.query_cc_mode()? != CcMode::CC_MODE_ON {
+//!         gpu.set_cc_mode(CcMode::CC_MODE_ON)?;
+//!         gpu.reset_after_cc_mode_switch()?;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod aer;
+pub mod arch;
+pub mod backend;
+pub mod bits;
+pub mod boot;
+pub mod certs;
+pub mod corim;
+pub mod cpuid;
+pub mod daemon;
+pub mod dev;
+pub mod doctor;
+pub mod doe;
+pub mod eat;
+pub mod error;
+pub mod evidence;
+pub mod fabric;
+pub mod falcon;
+pub mod firewall;
+pub mod fsp;
+pub mod fwlog;
+pub mod history;
+pub mod identity;
+pub mod iommu;
+pub mod link;
+pub mod lock;
+pub mod msi;
+pub mod nras;
+pub mod pcicfg;
+pub mod persist;
+pub mod platform;
+pub mod pm;
+pub mod policy;
+pub mod pramin;
+pub mod preflight;
+pub mod rebar;
+pub mod regs;
+pub mod rim;
+pub mod scratch;
+pub mod script;
+#[cfg(all(feature = "snp", target_arch = "x86_64"))]
+pub mod snp;
+pub mod spdm;
+#[cfg(all(feature = "tdx", target_arch = "x86_64"))]
+pub mod tdx;
+pub mod tofu;
+pub mod topology;
+pub mod trace;
+pub mod txn;
+pub mod vbios;
+pub mod verifier;
+pub mod vfio;
+pub mod vgpu;
+pub mod xmldsig;
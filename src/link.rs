@@ -0,0 +1,139 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{
+    bits::*,
+    dev::PciDevice,
+    error::{NvTrustError, Result},
+};
+
+/// A PCIe link speed, by its Supported/Current Link Speed encoding: 1 is 2.5GT/s (Gen1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct LinkSpeed(pub u8);
+
+impl LinkSpeed {
+    /// The PCIe generation of the speed.
+    pub fn generation(&self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::Display for LinkSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            1 => write!(f, "2.5GT/s"),
+            2 => write!(f, "5GT/s"),
+            3 => write!(f, "8GT/s"),
+            4 => write!(f, "16GT/s"),
+            5 => write!(f, "32GT/s"),
+            6 => write!(f, "64GT/s"),
+            speed => write!(f, "unknown ({speed})"),
+        }
+    }
+}
+
+/// The state of a PCIe link, from the Express capability of the device at one end of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LinkStatus {
+    pub speed: LinkSpeed,
+    pub max_speed: LinkSpeed,
+    pub width: u8,
+    pub max_width: u8,
+    /// Whether ASPM L0s and L1 are supported, and whether they are enabled.
+    pub l0s_supported: bool,
+    pub l1_supported: bool,
+    pub l0s_enabled: bool,
+    pub l1_enabled: bool,
+    pub training: bool,
+    /// Whether the data link layer is up; only ports that report it set it.
+    pub dll_active: bool,
+}
+
+impl LinkStatus {
+    /// Whether the link runs slower or narrower than it can.
+    pub fn is_degraded(&self) -> bool {
+        self.speed < self.max_speed || self.width < self.max_width
+    }
+
+    /// Cap the maximum speed and width at what the other end of the link supports; each end only
+    /// reports its own.
+    pub fn limited_by(self, other: &LinkStatus) -> Self {
+        Self {
+            max_speed: self.max_speed.min(other.max_speed),
+            max_width: self.max_width.min(other.max_width),
+            ..self
+        }
+    }
+}
+
+impl PciDevice {
+    /// Get the offset of the PCI Express capability.
+    pub fn express_offset(&self) -> Result<u64> {
+        self.caps()
+            .get(&(PCI_CAP_ID_EXP as u8))
+            .copied()
+            .ok_or(NvTrustError::NotSupported {
+                what: "the PCIe link".to_string(),
+                device: self.get_bdf().to_string(),
+                reason: "it has no PCI Express capability".to_string(),
+            })
+    }
+
+    /// Read the status of the link of the device to its upstream port.
+    pub fn link_status(&self) -> Result<LinkStatus> {
+        let offset = self.express_offset()?;
+        let cap = self.read_config32(offset + PCI_EXP_LNKCAP)?;
+        let ctrl = self.read_config16(offset + PCI_EXP_LNKCTL)?;
+        let status = self.read_config16(offset + PCI_EXP_LNKSTA)?;
+        let aspm = (cap & PCI_EXP_LNKCAP_ASPMS) >> 10;
+
+        Ok(LinkStatus {
+            speed: LinkSpeed((status & PCI_EXP_LNKSTA_CLS) as u8),
+            max_speed: LinkSpeed((cap & PCI_EXP_LNKCAP_SLS) as u8),
+            width: ((status & PCI_EXP_LNKSTA_NLW) >> 4) as u8,
+            max_width: ((cap & PCI_EXP_LNKCAP_MLW) >> 4) as u8,
+            l0s_supported: aspm & 0x1 != 0,
+            l1_supported: aspm & 0x2 != 0,
+            l0s_enabled: ctrl & PCI_EXP_LNKCTL_ASPM_L0S != 0,
+            l1_enabled: ctrl & PCI_EXP_LNKCTL_ASPM_L1 != 0,
+            training: status & PCI_EXP_LNKSTA_LT != 0,
+            dll_active: status & PCI_EXP_LNKSTA_DLLLA != 0,
+        })
+    }
+
+    pub(crate) fn wait_for_link_training(&self, offset: u64) -> Result<()> {
+        let now = Instant::now();
+        while self.read_config16(offset + PCI_EXP_LNKSTA)? & PCI_EXP_LNKSTA_LT != 0 {
+            if now.elapsed().as_secs() >= PCI_LINK_TRAIN_TIMEOUT {
+                return Err(NvTrustError::Timeout(format!(
+                    "the link of {} to train",
+                    self.get_bdf()
+                )));
+            }
+
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        Ok(())
+    }
+
+    /// Retrain the link below this port, e.g., the one to a GPU that a reset trained down to
+    /// Gen1, and return the status of the link before and after.
+    pub fn retrain_link(&self) -> Result<(LinkStatus, LinkStatus)> {
+        let offset = self.express_offset()?;
+        let before = self.link_status()?;
+
+        // Let a training in progress finish, or the retrain request may be lost in it.
+        self.wait_for_link_training(offset)?;
+        let ctrl = self.read_config16(offset + PCI_EXP_LNKCTL)?;
+        log::info!("Retraining the link below {}", self.get_bdf());
+        self.write_config16(offset + PCI_EXP_LNKCTL, ctrl | PCI_EXP_LNKCTL_RL)?;
+        self.wait_for_link_training(offset)?;
+
+        Ok((before, self.link_status()?))
+    }
+}
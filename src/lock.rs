@@ -0,0 +1,94 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use rustix::fs::{flock, FlockOperation};
+
+use crate::{
+    bits::*,
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+};
+
+/// An advisory lock on a device, held until dropped, so that two nvtrust instances, e.g., a
+/// command and the attestation daemon, do not interleave their FSP transactions.
+#[derive(Debug)]
+pub struct DeviceLock {
+    _file: File,
+}
+
+impl DeviceLock {
+    /// Lock the device with the given BDF, failing at once if another process holds it.
+    pub fn acquire(bdf: &str) -> Result<Self> {
+        Self::acquire_in(LOCK_DIR, bdf)
+    }
+
+    /// Lock the device with the given BDF through a lock file in the given directory.
+    pub fn acquire_in<P: AsRef<Path>>(dir: P, bdf: &str) -> Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.as_ref().join(format!("{bdf}.lock")))?;
+
+        if let Err(e) = flock(&file, FlockOperation::NonBlockingLockExclusive) {
+            if e != rustix::io::Errno::WOULDBLOCK {
+                return Err(e.into());
+            }
+
+            // The holder writes its PID once it has the lock.
+            let mut pid = String::new();
+            file.read_to_string(&mut pid)?;
+            return Err(NvTrustError::Locked {
+                device: bdf.to_string(),
+                pid: pid.trim().parse().ok(),
+            });
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+/// Whether opening a device locks it, see [`set_lock_on_open`].
+static LOCK_ON_OPEN: AtomicBool = AtomicBool::new(false);
+
+/// Lock every device before it is opened from now on, for as long as its [`GpuObject`] lives, so
+/// that nothing even reads a device while another nvtrust is in the middle of programming it.
+pub fn set_lock_on_open(lock: bool) {
+    LOCK_ON_OPEN.store(lock, Ordering::Relaxed);
+}
+
+/// Lock the device with the given BDF if it is to be locked when opened.
+pub(crate) fn lock_on_open(bdf: &str) -> Result<Option<Arc<DeviceLock>>> {
+    match LOCK_ON_OPEN.load(Ordering::Relaxed) {
+        true => Ok(Some(Arc::new(DeviceLock::acquire(bdf)?))),
+        false => Ok(None),
+    }
+}
+
+impl GpuObject {
+    /// Lock the GPU for exclusive access until the lock is dropped; see [`DeviceLock`].
+    ///
+    /// A GPU opened with [`set_lock_on_open`] is locked already, see [`Self::held_lock`].
+    pub fn lock(&self) -> Result<DeviceLock> {
+        DeviceLock::acquire(self.get_bdf())
+    }
+
+    /// Get the lock taken when the GPU was opened, e.g., to keep holding it once the GPU is
+    /// dropped.
+    pub fn held_lock(&self) -> Option<Arc<DeviceLock>> {
+        self.lock.clone()
+    }
+}
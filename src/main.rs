@@ -1,14 +1,21 @@
 use std::{env, fs, io::Write};
 
-use anyhow::Result;
-use clap::{Parser, Subcommand, ValueEnum};
+use anyhow::{anyhow, Result};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use env_logger::TimestampPrecision;
 use log::LevelFilter;
 use nix::unistd::Uid;
+use serde_json::json;
 
-pub mod bits;
-pub mod cpuid;
-pub mod dev;
+use nvtrust::{
+    backend, bits, certs, daemon, dev, doctor, eat, error::NvTrustError, fabric, fsp, fwlog,
+    history, identity, lock, nras, persist, platform, pm, policy, rebar, regs, rim, script, spdm,
+    tofu, trace, txn, verifier,
+};
+
+mod config;
+mod report;
+mod table;
 
 const VERSION: &str = "535.86.06";
 
@@ -17,51 +24,215 @@ const VERSION: &str = "535.86.06";
 #[command(author = "Haobin Hiroki Chen. <haobchen@iu.edu>")]
 #[command(version = "1.0")]
 struct Cmd {
-    #[clap(long, help = "Select the index of the GPU.", default_value = "-1")]
+    #[clap(long, help = "Select the index of the GPU, in the order of list-gpus.")]
     gpu: Option<i64>,
     #[clap(
         long,
-        help = "Select a single GPU by providing a substring of the BDF, e.g. '01:00'."
+        help = "Select a single GPU by providing a substring of the BDF, e.g. '01:00'. Repeat it to run on several GPUs."
     )]
-    gpu_bdf: Option<String>,
+    gpu_bdf: Vec<String>,
     #[clap(
         long,
-        help = "Select a single GPU by providing a substring of the GPU name, e.g. 'T4'. If multiple GPUs match, the first one will be used."
+        help = "Select a single GPU by providing a substring of the GPU name, e.g. 'H100'. If multiple GPUs match, the first one will be used."
     )]
     gpu_name: Option<String>,
+    #[clap(
+        long,
+        help = "Run the command on every GPU and NVSwitch, and fail if it fails on any.",
+        default_value = "false"
+    )]
+    all_gpus: bool,
+    #[clap(
+        long,
+        help = "With several GPUs, run the command on all of them at once rather than one after another; their output may interleave, and commands that ask for confirmation need --yes.",
+        default_value = "false"
+    )]
+    parallel: bool,
     #[clap(
         long,
         help = "Do not use any of the GPUs; commands requiring one will not work.",
         default_value = "false"
     )]
     no_gpu: bool,
-    #[clap(long, default_value = "info")]
-    log: LevelFilter,
+    #[clap(long, help = "The log level. [default: info]")]
+    log: Option<LevelFilter>,
+    #[clap(long, help = "Do not color the output, as with NO_COLOR set.")]
+    no_color: bool,
+    #[clap(
+        long,
+        help = "Record every register and config space access to this trace file, e.g., for a bug report."
+    )]
+    record_mmio: Option<String>,
+    #[clap(
+        long,
+        help = "How to access the devices; auto picks one per device. [default: auto]"
+    )]
+    backend: Option<BackendChoice>,
+    #[clap(
+        long,
+        help = "Access the registers of a GPU even though nvidia or nouveau is bound to it.",
+        default_value = "false"
+    )]
+    force: bool,
+    #[clap(long, help = "How to reset the GPUs. [default: os]")]
+    reset_method: Option<ResetMethodChoice>,
+    #[clap(
+        long,
+        help = "The file with the defaults of these flags. [default: /etc/nvtrust/config.toml]"
+    )]
+    config: Option<String>,
+    #[clap(
+        long,
+        help = "A file of '<name> <offset>' lines naming more registers, e.g., of a newer chip."
+    )]
+    registers: Option<String>,
     // Some custom commands.
     #[clap(subcommand)]
     subcmd: SubCommand,
 }
 
-#[derive(Debug, Subcommand)]
+#[derive(Debug, Clone, Subcommand)]
 enum SubCommand {
-    #[clap(about = "Reset with OS through /sys/.../reset")]
-    ResetWithOs,
-    #[clap(about = "Query the current Confidential Computing (CC) mode of the GPU.")]
-    QueryCcMode,
+    #[clap(about = "List the GPUs with their function, driver and CC mode.")]
+    ListGpus {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(
+        about = "Show the PCIe tree from the root ports down to all the GPUs and NVSwitches, with the links and ACS and IDE of every port."
+    )]
+    Topology {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(about = "Reset the GPU through /sys/.../reset, or as --reset-method says.")]
+    ResetWithOs {
+        #[clap(long, help = "Do not ask for confirmation.")]
+        yes: bool,
+    },
+    #[clap(
+        about = "Unbind the driver, reset the GPU as --reset-method says, wait for it to boot, restore its config space and bind the driver again."
+    )]
+    FullReset {
+        #[clap(long, help = "Do not ask for confirmation.")]
+        yes: bool,
+    },
+    #[clap(about = "Unbind the kernel driver from the GPU.")]
+    UnbindDriver,
+    #[clap(about = "Bind a kernel driver to the GPU, unbinding the current one first.")]
+    BindDriver {
+        #[clap(help = "The driver, e.g., nvidia or vfio-pci.")]
+        driver: String,
+    },
+    #[clap(
+        about = "Query the active Confidential Computing (CC) mode of the GPU, and the pending one that becomes active upon the next reset."
+    )]
+    QueryCcMode {
+        #[clap(
+            long,
+            help = "Print nothing and exit with 0 if the GPU is in this mode, 1 if it is not, or 2 if the mode cannot be queried."
+        )]
+        check: Option<CcModeChoice>,
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
     #[clap(
         about = "Query the current Confidential Computing (CC) settings of the GPU.\nThis prints the lower level setting knobs that will take effect upon GPU reset."
     )]
-    QueryCcSettings,
+    QueryCcSettings {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(
+        about = "Query all the persistent reset-controlled (PRC) knobs of the FSP, with the value in effect and the one that takes effect upon the next reset."
+    )]
+    QueryPrcKnobs {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
     #[clap(
         about = "Configure Confidentail Computing (CC) mode. The choices are off (disabled), on (enabled) or devtools (enabled in DevTools mode).\n
         The GPU needs to be reset to make the selected mode active. See --reset-after-cc-mode-switch for one way of doing it."
     )]
-    SetCcMode { mode: CcModeChoice },
+    SetCcMode {
+        #[clap(long, help = "The CC mode to configure.")]
+        mode: CcModeChoice,
+        #[clap(
+            long,
+            help = "Reset the GPU afterwards so that the mode becomes active."
+        )]
+        reset: bool,
+        #[clap(
+            long,
+            help = "Fail unless the GPU reports the requested mode after the reset.",
+            requires = "reset"
+        )]
+        verify: bool,
+        #[clap(
+            long,
+            help = "Configure all the GPUs and NVSwitches of the board; either all of them are configured or none."
+        )]
+        all_gpus: bool,
+        #[clap(long, help = "Do not ask for confirmation before the reset.")]
+        yes: bool,
+    },
+    #[clap(
+        about = "Set a single PRC knob, e.g., cc_dev_mode, which takes effect upon the next reset. Unlike set-cc-mode, this does not keep the CC knobs consistent with each other."
+    )]
+    SetKnob {
+        #[clap(help = "The knob, by the name query-prc-knobs shows.")]
+        knob: fsp::PrcKnob,
+        #[clap(help = "The value, 0 or 1.")]
+        value: u16,
+    },
+    #[clap(
+        about = "Query the protected PCIe (PPCIe) mode of the GPU or NVSwitch, in effect and upon the next reset."
+    )]
+    QueryPpcieMode {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(
+        about = "Configure protected PCIe (PPCIe) mode on all the GPUs and NVSwitches of a Hopper board, which protects the NVLink traffic between the GPUs. Either all of them are configured or none.\n
+        PPCIe mode replaces CC mode, so turning it on turns CC mode off. The devices need to be reset to make the selected mode active."
+    )]
+    SetPpcieMode {
+        #[clap(long, help = "The PPCIe mode to configure.")]
+        mode: PpcieModeChoice,
+        #[clap(
+            long,
+            help = "Reset the devices afterwards so that the mode becomes active."
+        )]
+        reset: bool,
+        #[clap(
+            long,
+            help = "Fail unless every device reports the requested mode after the reset.",
+            requires = "reset"
+        )]
+        verify: bool,
+        #[clap(long, help = "Do not ask for confirmation before the reset.")]
+        yes: bool,
+    },
+    #[clap(
+        about = "Query the BAR0 decoupler, which firewalls BAR0 from the host in CC mode, and which BAR0 windows the host can still reach."
+    )]
+    QueryFirewall {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
     #[clap(about = "Reset the GPU after switching CC mode such that it is activated immediately.")]
-    ResetAfterCcModeSwitch,
-    #[clap(about = "Read the physical address in the GPU's MMIO space.")]
+    ResetAfterCcModeSwitch {
+        #[clap(long, help = "Do not ask for confirmation.")]
+        yes: bool,
+    },
+    #[clap(
+        about = "Read the physical address in the GPU's MMIO space. Addresses in BAR0 are read as registers and addresses in BAR1 from VRAM."
+    )]
     ReadPhys {
-        #[clap(long, help = "The physical address in the GPU's MMIO space.")]
+        #[clap(
+            long,
+            help = "The host physical address, which must fall in BAR0 or BAR1 of the GPU."
+        )]
         address: u64,
         #[clap(
             long,
@@ -76,10 +247,59 @@ enum SubCommand {
         )]
         len: usize,
     },
-    #[clap(about = "Read the given GPU's MMIO register.")]
-    ReadMmio {
-        #[clap(long, help = "The MMIO register to read.")]
-        register: u64,
+    #[clap(
+        about = "Write the physical address in the GPU's MMIO space, the counterpart of read-phys. Meant for debugging in dev-tools mode."
+    )]
+    WritePhys {
+        #[clap(
+            long,
+            help = "The host physical address, which must fall in BAR0 or BAR1 of the GPU."
+        )]
+        address: u64,
+        #[clap(
+            long,
+            help = "The file with the data to be written.",
+            conflicts_with = "value",
+            required_unless_present = "value"
+        )]
+        input: Option<String>,
+        #[clap(long, help = "A single dword to be written instead of a file.")]
+        value: Option<u32>,
+        #[clap(long, help = "Do not ask for confirmation.")]
+        yes: bool,
+    },
+    #[clap(about = "Dump VRAM through the BAR1 aperture.")]
+    VramRead {
+        #[clap(long, help = "The offset into BAR1, i.e., the framebuffer address.")]
+        offset: u64,
+        #[clap(long, help = "The length of the data to be read.")]
+        len: usize,
+        #[clap(
+            long,
+            help = "The output of the dumped file.",
+            default_value = "vram.bin"
+        )]
+        output: String,
+    },
+    #[clap(about = "Read the given GPU's MMIO register.", alias = "read-mmio")]
+    ReadReg {
+        #[clap(
+            long,
+            help = "The MMIO register to read, by name, e.g., NV_PMC_BOOT_0, or offset."
+        )]
+        register: String,
+    },
+    #[clap(about = "Write the given GPU's MMIO register. Meant for debugging in dev-tools mode.")]
+    WriteReg {
+        #[clap(
+            long,
+            help = "The MMIO register to write, by name, e.g., NV_PMC_BOOT_0, or offset."
+        )]
+        register: String,
+        #[clap(long, help = "The dword to write, decimal or 0x-prefixed hex.")]
+        value: String,
+        #[clap(long, help = "Do not ask for confirmation.")]
+        yes: bool,
     },
     #[clap(about = "Read all the MMIO ranges of the given GPU.")]
     ReadRange {
@@ -90,136 +310,1912 @@ enum SubCommand {
         #[clap(short, long, help = "The output of the dumped file.")]
         output: Option<String>,
     },
-    #[clap(about = "Watch the given GPU's MMIO register.")]
+    #[clap(about = "Watch the given GPU's MMIO register and print every change of it.")]
     Watch {
-        #[clap(long, help = "The MMIO register to watch.")]
-        register: u64,
+        #[clap(
+            long,
+            alias = "reg",
+            help = "The MMIO register to watch, by name or offset."
+        )]
+        register: String,
+        #[clap(
+            long,
+            help = "How often to read the register, e.g., 100ms or 2s.",
+            default_value = "1s",
+            value_parser = parse_interval
+        )]
+        interval: std::time::Duration,
+        #[clap(
+            long,
+            help = "Stop once the register masked with <MASK> reads <VALUE>, given as <MASK>=<VALUE>.",
+            value_parser = parse_condition
+        )]
+        until: Option<(u32, u32)>,
+    },
+    #[clap(about = "Dump the FSP's EMEM window and status registers for mailbox debugging.")]
+    DumpFspEmem {
+        #[clap(long, help = "The FSP EMEM channel to dump.", default_value = "2")]
+        channel: u64,
+        #[clap(short, long, help = "The output of the dumped file.")]
+        output: Option<String>,
+    },
+    #[clap(about = "Locate and dump the GSP-RM and SEC2 log buffers.")]
+    DumpFwLogs {
+        #[clap(
+            short,
+            long,
+            help = "The directory the log buffers are written to.",
+            default_value = "."
+        )]
+        output_dir: String,
+        #[clap(
+            long,
+            help = "The GSP firmware ELF used to decode the GSP-RM logs, e.g., gsp_ga10x.bin."
+        )]
+        firmware: Option<String>,
+    },
+    #[clap(about = "Decode a GSP-RM log buffer dumped by dump-fw-logs.")]
+    DecodeFwLog {
+        #[clap(short, long, help = "The dumped log buffer.")]
+        input: String,
+        #[clap(
+            long,
+            help = "The GSP firmware ELF that produced the log, e.g., gsp_ga10x.bin."
+        )]
+        firmware: String,
+    },
+    #[clap(
+        about = "Record the intended CC configuration, or verify that the GPU still reports the recorded one, e.g., after a reboot."
+    )]
+    VerifyPersistence {
+        #[clap(long, help = "Record the configuration instead of verifying it.")]
+        record: bool,
+        #[clap(
+            long,
+            help = "The intended CC mode to record. Defaults to the current mode of the GPU."
+        )]
+        mode: Option<CcModeChoice>,
+        #[clap(long, help = "The state file.", default_value = bits::PERSISTENCE_STATE_FILE)]
+        state: String,
+    },
+    #[clap(about = "Check that the Fabric Manager is installed and compatible with PPCIe mode.")]
+    CheckFabricManager,
+    #[clap(about = "Dump the labelled secure scratch registers used for CC state hand-off.")]
+    DumpScratch {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(about = "Query the SPDM responder of the GPU over its DOE mailbox.")]
+    QuerySpdm {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(
+        about = "Verify the certificate chain of the GPU, fetched over SPDM, against the NVIDIA device identity root CA."
+    )]
+    VerifyGpuCerts {
+        #[clap(
+            long,
+            help = "The pinned root CA (PEM or DER).",
+            default_value = bits::NVIDIA_DEVICE_ROOT_CA
+        )]
+        root_ca: String,
+        #[clap(long, help = "The certificate slot to verify.", default_value = "0")]
+        slot: u8,
+    },
+    #[clap(
+        about = "Fetch a signed attestation report bound to the given nonce and write the evidence, i.e., the report, its signature and the certificate chain, to a file."
+    )]
+    GpuAttest {
+        #[clap(long, help = "The 32-byte nonce as hex, chosen by the verifier.")]
+        nonce: String,
+        #[clap(long, help = "The certificate slot to sign with.", default_value = "0")]
+        slot: u8,
+        #[clap(
+            short,
+            long,
+            help = "The evidence file.",
+            default_value = "evidence.json"
+        )]
+        output: String,
+    },
+    #[clap(
+        about = "Attest the GPU with the NVIDIA Remote Attestation Service (NRAS) and print its verdict."
+    )]
+    Nras {
+        #[clap(
+            long,
+            help = "The 32-byte nonce as hex; a random one is used if not given."
+        )]
+        nonce: Option<String>,
+        #[clap(
+            long,
+            help = "The NRAS endpoint, e.g., a mirror; nras_url of the config or the NVIDIA service if not given."
+        )]
+        url: Option<String>,
+        #[clap(
+            long,
+            help = "The HTTP(S) proxy; the proxy in the environment is used if not given."
+        )]
+        proxy: Option<String>,
+        #[clap(
+            long,
+            help = "Save the response of the service, which carries the signed tokens."
+        )]
+        output: Option<String>,
+    },
+    #[clap(
+        about = "Attest the GPU offline: verify its evidence and appraise its measurements against RIMs, with the claims NRAS would return."
+    )]
+    VerifyLocal {
+        #[clap(
+            long,
+            help = "A reference integrity manifest (RIM) as a SWID tag or a CoRIM, whose signature is verified against --rim-root-ca, e.g., of the driver and of the VBIOS; may be repeated."
+        )]
+        rim: Vec<String>,
+        #[clap(
+            long,
+            help = "Also appraise against the RIMs of the running firmware from this cache, as filled by fetch-rim; rim_cache of the config if not given."
+        )]
+        rim_cache: Option<String>,
+        #[clap(
+            long,
+            help = "The pinned root CA of the RIM signers (PEM or DER).",
+            default_value = bits::NVIDIA_RIM_ROOT_CA
+        )]
+        rim_root_ca: String,
+        #[clap(
+            long,
+            help = "The pinned root CA (PEM or DER).",
+            default_value = bits::NVIDIA_DEVICE_ROOT_CA
+        )]
+        root_ca: String,
+        #[clap(
+            long,
+            help = "The 32-byte nonce as hex; a random one is used if not given."
+        )]
+        nonce: Option<String>,
+        #[clap(
+            long,
+            requires = "eat_key",
+            help = "Write the claims as an Entity Attestation Token (signed JWT) to this file, once the GPU has passed the attestation and the policy."
+        )]
+        eat: Option<String>,
+        #[clap(
+            long,
+            help = "The PKCS#8 ECDSA P-256 or P-384 key (PEM or DER) to sign the EAT with."
+        )]
+        eat_key: Option<String>,
+        #[clap(
+            long,
+            help = "Also appraise the claims against a policy, one '<claim> <op> <value>' rule per line. The claims read from the registers rather than the signed evidence are prefixed 'unverified.'."
+        )]
+        policy: Option<String>,
+        #[clap(long, help = "Print what each rule of the policy matched.")]
+        explain: bool,
+    },
+    #[clap(
+        about = "Download the driver and VBIOS RIMs of the running firmware from the NVIDIA RIM service into the local cache, for verify-local."
+    )]
+    FetchRim {
+        #[clap(long, help = "The RIM service, e.g., a mirror.", default_value = bits::RIM_SERVICE_URL)]
+        url: String,
+        #[clap(
+            long,
+            help = "The HTTP(S) proxy; the proxy in the environment is used if not given."
+        )]
+        proxy: Option<String>,
+        #[clap(
+            long,
+            help = "The RIM cache; rim_cache of the config or /var/lib/nvtrust/rim if not given."
+        )]
+        cache: Option<String>,
+        #[clap(
+            long,
+            help = "The pinned root CA of the RIM signers (PEM or DER).",
+            default_value = bits::NVIDIA_RIM_ROOT_CA
+        )]
+        rim_root_ca: String,
+        #[clap(long, help = "Download the RIMs even if they are cached.")]
+        refresh: bool,
+    },
+    #[clap(
+        about = "Collect the evidence of the confidential VM (SNP report or TDX quote) and of its GPUs over one nonce, with the VM report binding the GPU reports. Covers every GPU unless --gpu-bdf is given."
+    )]
+    AttestPlatform {
+        #[clap(
+            long,
+            help = "The 32-byte nonce as hex; a random one is used if not given."
+        )]
+        nonce: Option<String>,
+        #[clap(
+            short,
+            long,
+            help = "The evidence bundle.",
+            default_value = "platform-evidence.json"
+        )]
+        output: String,
     },
-}
-
-#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-enum CcModeChoice {
-    /// Disable CC mode.
-    Off,
-    /// Enable CC mode.
-    On,
-    /// Enable CC mode in DevTools mode.
-    DevTools,
-}
-
-fn init_logger(level: LevelFilter) {
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", "info");
-    }
-
-    env_logger::builder()
-        .filter_level(level)
-        .format_module_path(false)
-        .format_target(false)
-        .format_timestamp(Some(TimestampPrecision::Seconds))
-        .init();
-}
-
-fn main() -> Result<()> {
-    let args = Cmd::parse();
-    init_logger(args.log);
-
     #[cfg(all(feature = "snp", target_arch = "x86_64"))]
-    cpuid::check_sev_snp()?;
-
-    log::info!("NVIDIA GPU Tools version {VERSION}");
-
-    if Uid::effective().is_root() {
-        let gpu = {
-            if let Some(bdf) = args.gpu_bdf {
-                let gpus = dev::find_gpus_by_bdf(&bdf)?;
-
-                if gpus.is_empty() {
-                    log::error!("Matching for {bdf} found nothing");
-
-                    return Ok(());
-                } else if gpus.len() > 1 {
-                    log::warn!(
-                        "Matching for {bdf} found multiple GPUs: {:?}. Use the first one.",
-                        gpus,
-                    );
-
-                    gpus[0].clone()
-                } else {
-                    gpus[0].clone()
-                }
-            } else {
-                log::error!("No GPU specified, select GPU with --gpu, --gpu-bdf, or --gpu-name.");
-                return Ok(());
-            }
-        };
-
-        log::info!("Using GPU: {}", gpu.get_name());
-
-        match args.subcmd {
-            SubCommand::ResetWithOs => {
-                gpu.sysfs_reset()?;
-            }
-            SubCommand::QueryCcMode => {
-                let cc_mode = gpu.query_cc_mode()?;
-                log::info!("CC mode: {:?}", cc_mode);
-            }
-            SubCommand::ReadPhys {
+    #[clap(
+        about = "Get an attestation report of this SEV-SNP guest from the PSP through /dev/sev-guest."
+    )]
+    SnpReport {
+        #[clap(
+            long,
+            help = "Up to 64 bytes as hex to bind into the report, e.g., a nonce; zero-padded."
+        )]
+        report_data: String,
+        #[clap(
+            long,
+            help = "The VMPL to request the report for.",
+            default_value = "0"
+        )]
+        vmpl: u32,
+        #[clap(
+            short,
+            long,
+            help = "The report file.",
+            default_value = "snp-report.bin"
+        )]
+        output: String,
+    },
+    #[cfg(all(feature = "tdx", target_arch = "x86_64"))]
+    #[clap(
+        about = "Get the TD report of this Intel TDX trust domain through /dev/tdx_guest, or its quote through configfs-tsm."
+    )]
+    TdxReport {
+        #[clap(
+            long,
+            help = "Up to 64 bytes as hex to bind into the report, e.g., a nonce; zero-padded."
+        )]
+        report_data: String,
+        #[clap(
+            long,
+            help = "Convert the TD report into a quote for remote verifiers."
+        )]
+        quote: bool,
+        #[clap(
+            short,
+            long,
+            help = "The report file.",
+            default_value = "tdx-report.bin"
+        )]
+        output: String,
+    },
+    #[clap(
+        about = "Print the completions of the shell, e.g., `nvtrust completions bash > /etc/bash_completion.d/nvtrust`."
+    )]
+    Completions {
+        #[clap(help = "The shell to complete for.")]
+        shell: clap_complete::Shell,
+    },
+    #[clap(
+        about = "Run a register script of read, write, poll, sleep and assert steps, e.g., a bring-up sequence, and summarize what passed."
+    )]
+    RunScript {
+        #[clap(help = "The script, one step per line.")]
+        file: String,
+        #[clap(
+            long,
+            help = "Do not ask for confirmation if the script writes registers."
+        )]
+        yes: bool,
+    },
+    #[clap(about = "Get the measurements of the GPU over SPDM.")]
+    GpuMeasurements {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+        #[clap(
+            long,
+            help = "Also save them as '<index> <digest>' lines, e.g., for tofu."
+        )]
+        save: Option<String>,
+    },
+    #[clap(about = "Query whether the firmware finished devinit and secure boot of the GPU.")]
+    QueryBootStatus {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(about = "Query whether the GSP is running and whether a driver loaded GSP-RM.")]
+    QueryGsp {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(about = "Query the GPU and its VBIOS.")]
+    QueryGpuInfo {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(about = "Dump and decode the PCI config space: the header and every capability.")]
+    DumpConfig {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(about = "Query the errors logged by Advanced Error Reporting.")]
+    QueryAer {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(about = "Clear the sticky AER error status, e.g., after recovering from a fault.")]
+    ClearAer,
+    #[clap(about = "Query the MSI and MSI-X capabilities, with the MSI-X vector table and PBA.")]
+    QueryMsi {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(about = "Query the power state (D-state) of the GPU.")]
+    QueryPowerState {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(about = "Put the GPU in a power state; some reset flows need a D3hot to D0 cycle.")]
+    SetPowerState {
+        state: PowerStateChoice,
+        #[clap(long, help = "Do not ask for confirmation.")]
+        yes: bool,
+    },
+    #[clap(about = "Query the speed, width and ASPM state of the PCIe link of the GPU.")]
+    QueryLink {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(
+        about = "Retrain the PCIe link of the GPU from its upstream port, e.g., after a reset trained it down."
+    )]
+    RetrainLink,
+    #[clap(about = "Query the resizable BARs of the GPU and the sizes they support.")]
+    QueryRebar {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(about = "Resize a BAR of the GPU, which must not be bound to a driver.")]
+    SetRebar {
+        #[clap(long, help = "The BAR register, e.g., 1 for BAR1.")]
+        bar: u32,
+        #[clap(long, help = "The new size, e.g., 256M or 64G.", value_parser = parse_size)]
+        size: u64,
+    },
+    #[clap(about = "List the PCIe ports between the GPU and the host bridge, with their links.")]
+    QueryTopology {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(
+        about = "Check that every root and downstream port above the GPU isolates it with ACS, and fail if one does not."
+    )]
+    CheckAcs,
+    #[clap(about = "Query the IOMMU group of the GPU and whether vfio can take it.")]
+    QueryIommu {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(about = "Dump the VBIOS image from the flash.")]
+    DumpVbios {
+        #[clap(
+            short,
+            long,
+            help = "The output of the dumped file.",
+            default_value = "vbios.rom"
+        )]
+        output: String,
+    },
+    #[clap(about = "Query the CC state visible to a confidential vGPU virtual function.")]
+    QueryVgpu {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+    },
+    #[clap(about = "Query the per-device identity (PDI) of the GPU.")]
+    QueryDeviceIdentity {
+        #[clap(long, help = "The output format.", default_value = "table")]
+        format: Format,
+        #[clap(
+            long,
+            help = "The leaf attestation certificate (PEM or DER) to correlate the identity with."
+        )]
+        cert: Option<String>,
+    },
+    #[clap(
+        about = "Appraise measurements by trust on first use: the first set observed for a GPU becomes its baseline and any later change is reported."
+    )]
+    Tofu {
+        #[clap(
+            long,
+            help = "The observed measurements, one '<index> <digest>' per line."
+        )]
+        measurements: String,
+        #[clap(long, help = "The local history database.", default_value = bits::HISTORY_DIR)]
+        history: String,
+    },
+    #[clap(
+        about = "Re-attest the GPUs periodically, verifying their evidence locally as verify-local does, and write their status to a JSON file for node agents. Attests every GPU unless --gpu-bdf is given."
+    )]
+    AttestDaemon {
+        #[clap(
+            long,
+            help = "The re-attestation interval in seconds.",
+            default_value = "300"
+        )]
+        interval: u64,
+        #[clap(
+            long,
+            help = "A reference integrity manifest (RIM) as a SWID tag or a CoRIM, whose signature is verified against --rim-root-ca, e.g., of the driver and of the VBIOS; may be repeated."
+        )]
+        rim: Vec<String>,
+        #[clap(
+            long,
+            help = "Also appraise against the RIMs of the running firmware from this cache, as filled by fetch-rim; rim_cache of the config if not given."
+        )]
+        rim_cache: Option<String>,
+        #[clap(
+            long,
+            help = "The pinned root CA of the RIM signers (PEM or DER).",
+            default_value = bits::NVIDIA_RIM_ROOT_CA
+        )]
+        rim_root_ca: String,
+        #[clap(
+            long,
+            help = "The pinned root CA (PEM or DER).",
+            default_value = bits::NVIDIA_DEVICE_ROOT_CA
+        )]
+        root_ca: String,
+        #[clap(
+            long,
+            help = "The policy the claims must pass to be attested. The claims read from the registers rather than the signed evidence are prefixed 'unverified.'."
+        )]
+        policy: Option<String>,
+        #[clap(long, help = "The status file.", default_value = bits::ATTESTATION_STATUS_FILE)]
+        status: String,
+    },
+    #[clap(
+        about = "Check the host, PCI, driver and GPU, and print the problems found with how to fix them."
+    )]
+    Doctor,
+    #[clap(
+        about = "Check that the host is ready for CC: the CPU TEE, IOMMU, kernel, SWIOTLB, NVIDIA driver and GPUs."
+    )]
+    HostCheck,
+    #[clap(about = "Appraise the evidence of the GPU against a policy.")]
+    Appraise {
+        #[clap(
+            long,
+            help = "The policy file, one '<claim> <op> <value>' rule per line."
+        )]
+        policy: String,
+        #[clap(
+            long,
+            help = "Evaluate the claims saved in this file instead of collecting them from the GPU."
+        )]
+        evidence: Option<String>,
+        #[clap(
+            long,
+            help = "Do not touch the hardware; requires --evidence.",
+            requires = "evidence"
+        )]
+        dry_run: bool,
+        #[clap(long, help = "Print what each rule matched, failed and compared.")]
+        explain: bool,
+        #[clap(long, help = "Save the collected claims for later dry runs.")]
+        save_claims: Option<String>,
+    },
+}
+
+impl SubCommand {
+    /// The `--yes` of the commands that ask for confirmation.
+    fn yes(&self) -> Option<bool> {
+        match self {
+            SubCommand::ResetWithOs { yes }
+            | SubCommand::FullReset { yes }
+            | SubCommand::SetCcMode { yes, .. }
+            | SubCommand::SetPpcieMode { yes, .. }
+            | SubCommand::ResetAfterCcModeSwitch { yes }
+            | SubCommand::WritePhys { yes, .. }
+            | SubCommand::WriteReg { yes, .. }
+            | SubCommand::RunScript { yes, .. }
+            | SubCommand::SetPowerState { yes, .. } => Some(*yes),
+            _ => None,
+        }
+    }
+
+    /// Whether the command leaves the registers alone, so that it can run while a GPU driver
+    /// owns the GPU: it only reads the config space or changes the driver binding.
+    fn is_safe_with_driver(&self) -> bool {
+        matches!(
+            self,
+            SubCommand::FullReset { .. }
+                | SubCommand::UnbindDriver
+                | SubCommand::BindDriver { .. }
+                | SubCommand::DumpConfig { .. }
+                | SubCommand::QueryAer { .. }
+                | SubCommand::QueryMsi { .. }
+                | SubCommand::QueryPowerState { .. }
+                | SubCommand::QueryLink { .. }
+                | SubCommand::QueryRebar { .. }
+                | SubCommand::QueryTopology { .. }
+                | SubCommand::QueryIommu { .. }
+                | SubCommand::CheckAcs
+        )
+    }
+}
+
+/// The output of the query commands; the JSON is documented in the README.
+#[derive(Copy, Debug, Clone, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// A table for humans.
+    Table,
+    /// A JSON object for tools.
+    Json,
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, ValueEnum)]
+enum BackendChoice {
+    /// VFIO for devices bound to vfio-pci, or else /dev/mem, or the sysfs resource files.
+    Auto,
+    /// Map the BARs from /dev/mem.
+    #[value(name = "devmem")]
+    DevMem,
+    /// Map the BARs from the sysfs resource files.
+    Resource,
+    /// Go through vfio-pci.
+    Vfio,
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, ValueEnum)]
+enum ResetMethodChoice {
+    /// Whatever the kernel picks through /sys/.../reset.
+    Os,
+    /// A function level reset through the Express capability, without the kernel.
+    Flr,
+    /// A secondary bus reset of the upstream port, when the others do not recover the GPU.
+    Sbr,
+}
+
+impl From<ResetMethodChoice> for dev::ResetMethod {
+    fn from(choice: ResetMethodChoice) -> Self {
+        match choice {
+            ResetMethodChoice::Os => dev::ResetMethod::Os,
+            ResetMethodChoice::Flr => dev::ResetMethod::Flr,
+            ResetMethodChoice::Sbr => dev::ResetMethod::SecondaryBus,
+        }
+    }
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum CcModeChoice {
+    /// Disable CC mode.
+    Off,
+    /// Enable CC mode.
+    On,
+    /// Enable CC mode in DevTools mode.
+    DevTools,
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum PpcieModeChoice {
+    /// Disable PPCIe mode.
+    Off,
+    /// Enable PPCIe mode.
+    On,
+}
+
+impl From<CcModeChoice> for bits::CcMode {
+    fn from(choice: CcModeChoice) -> Self {
+        match choice {
+            CcModeChoice::Off => bits::CcMode::CC_MODE_OFF,
+            CcModeChoice::On => bits::CcMode::CC_MODE_ON,
+            CcModeChoice::DevTools => bits::CcMode::CC_MODE_DEV_TOOLS,
+        }
+    }
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, ValueEnum)]
+enum PowerStateChoice {
+    /// Fully on.
+    D0,
+    /// Off, but still answering config cycles.
+    #[value(name = "d3hot")]
+    D3Hot,
+}
+
+impl From<PowerStateChoice> for pm::PowerState {
+    fn from(choice: PowerStateChoice) -> Self {
+        match choice {
+            PowerStateChoice::D0 => pm::PowerState::D0,
+            PowerStateChoice::D3Hot => pm::PowerState::D3Hot,
+        }
+    }
+}
+
+/// Fill in the flags that were not given from the config.
+fn apply_config(args: &mut Cmd, config: config::Config) -> Result<()> {
+    // A GPU selected on the command line, in any way, replaces the whole selection of the config.
+    let selected =
+        args.gpu.is_some() || !args.gpu_bdf.is_empty() || args.gpu_name.is_some() || args.all_gpus;
+    if !selected {
+        args.gpu = config.gpu;
+        args.gpu_bdf = config.gpu_bdf.into_iter().collect();
+        args.gpu_name = config.gpu_name;
+    }
+
+    if args.log.is_none() {
+        args.log = match config.log {
+            Some(log) => Some(
+                log.parse()
+                    .map_err(|_| anyhow!("Invalid log level {log:?} in the config"))?,
+            ),
+            None => None,
+        };
+    }
+    if args.backend.is_none() {
+        args.backend = match config.backend {
+            Some(backend) => Some(
+                BackendChoice::from_str(&backend, true)
+                    .map_err(|_| anyhow!("Invalid backend {backend:?} in the config"))?,
+            ),
+            None => None,
+        };
+    }
+    if args.reset_method.is_none() {
+        args.reset_method = match config.reset_method {
+            Some(method) => Some(
+                ResetMethodChoice::from_str(&method, true)
+                    .map_err(|_| anyhow!("Invalid reset method {method:?} in the config"))?,
+            ),
+            None => None,
+        };
+    }
+
+    match &mut args.subcmd {
+        SubCommand::Nras { url, .. } => *url = url.take().or(config.nras_url),
+        SubCommand::FetchRim { cache, .. } => *cache = cache.take().or(config.rim_cache),
+        SubCommand::VerifyLocal { rim_cache, .. } | SubCommand::AttestDaemon { rim_cache, .. } => {
+            *rim_cache = rim_cache.take().or(config.rim_cache)
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn init_logger(level: LevelFilter) {
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "info");
+    }
+
+    env_logger::builder()
+        .filter_level(level)
+        .format_module_path(false)
+        .format_target(false)
+        .format_timestamp(Some(TimestampPrecision::Seconds))
+        .init();
+}
+
+/// Parse up to 64 bytes of hex report data for a CVM report, zero-padded.
+#[cfg(all(any(feature = "snp", feature = "tdx"), target_arch = "x86_64"))]
+fn pad_report_data(hex: &str) -> Result<[u8; 64]> {
+    let data = spdm::from_hex(hex)?;
+    if data.len() > 64 {
+        return Err(anyhow!("The report data must be at most 64 bytes."));
+    }
+
+    let mut report_data = [0; 64];
+    report_data[..data.len()].copy_from_slice(&data);
+    Ok(report_data)
+}
+
+/// Parse a size in bytes, or with a binary M, G or T suffix.
+fn parse_size(size: &str) -> Result<u64> {
+    let (number, shift) = match size.to_uppercase().chars().last() {
+        Some('M') => (&size[..size.len() - 1], 20),
+        Some('G') => (&size[..size.len() - 1], 30),
+        Some('T') => (&size[..size.len() - 1], 40),
+        _ => (size, 0),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or(anyhow!("Invalid size {size:?}"))
+}
+
+/// Parse an interval in milliseconds, or with an ms, s or m suffix.
+fn parse_interval(interval: &str) -> Result<std::time::Duration> {
+    let (number, scale) = match interval {
+        _ if interval.ends_with("ms") => (&interval[..interval.len() - 2], 1),
+        _ if interval.ends_with('s') => (&interval[..interval.len() - 1], 1000),
+        _ if interval.ends_with('m') => (&interval[..interval.len() - 1], 60_000),
+        _ => (interval, 1),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(scale))
+        .filter(|ms| *ms != 0)
+        .map(std::time::Duration::from_millis)
+        .ok_or(anyhow!("Invalid interval {interval:?}"))
+}
+
+/// Parse a `<mask>=<value>` condition on a register.
+fn parse_condition(condition: &str) -> Result<(u32, u32)> {
+    let parse = |number: &str| {
+        regs::parse_number(number.trim())
+            .and_then(|number| u32::try_from(number).ok())
+            .ok_or(anyhow!(
+                "Invalid condition {condition:?}; expected <mask>=<value>"
+            ))
+    };
+
+    match condition.split_once('=') {
+        Some((mask, value)) => Ok((parse(mask)?, parse(value)?)),
+        None => Err(anyhow!(
+            "Invalid condition {condition:?}; expected <mask>=<value>"
+        )),
+    }
+}
+
+fn parse_nonce(hex: &str) -> Result<[u8; bits::SPDM_NONCE_SIZE]> {
+    spdm::from_hex(hex)?
+        .try_into()
+        .map_err(|_| anyhow!("The nonce must be {} bytes.", bits::SPDM_NONCE_SIZE))
+}
+
+/// Ask the user to confirm a dangerous operation on the terminal.
+fn confirm(prompt: &str) -> Result<bool> {
+    print!("{prompt} [y/N] ");
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn warn_holders(gpus: &[dev::GpuObject]) {
+    for gpu in gpus {
+        for holder in gpu.get_device_handle().holders() {
+            log::warn!(
+                "{} is in use by {} (pid {}) through {}",
+                gpu.get_label(),
+                holder.command,
+                holder.pid,
+                holder.file
+            );
+        }
+    }
+}
+
+/// Ask before resetting GPUs, which kills whatever runs on them, and show what holds them.
+fn confirm_reset(gpus: &[dev::GpuObject], yes: bool) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+
+    warn_holders(gpus);
+    let labels = gpus.iter().map(|gpu| gpu.get_label()).collect::<Vec<_>>();
+    confirm(&format!(
+        "Reset {}? This kills the workloads running on it.",
+        labels.join(", ")
+    ))
+}
+
+/// The GPUs the `--gpu*` flags select.
+fn selector(args: &Cmd) -> dev::Selector {
+    dev::Selector {
+        all: args.all_gpus,
+        bdfs: args.gpu_bdf.clone(),
+        name: args.gpu_name.clone(),
+        index: args
+            .gpu
+            .filter(|index| *index >= 0)
+            .map(|index| index as usize),
+    }
+}
+
+/// Check whether the selected GPU is in the expected mode, for `--check`.
+fn check_cc_mode(args: &Cmd, expected: bits::CcMode) -> Result<bool> {
+    if !Uid::effective().is_root() {
+        return Err(NvTrustError::NotRoot.into());
+    }
+
+    // Only the first of several --gpu-bdf is checked.
+    let selector = dev::Selector {
+        all: false,
+        bdfs: args.gpu_bdf.iter().take(1).cloned().collect(),
+        ..selector(args)
+    };
+    let gpu = dev::select(&selector)?
+        .gpus
+        .into_iter()
+        .next()
+        .ok_or(NvTrustError::DeviceNotFound("No GPU found".to_string()))?;
+
+    Ok(gpu.query_cc_mode()? == expected)
+}
+
+/// Run a command that works on a single GPU.
+fn run_on_gpu(gpu: dev::GpuObject, subcmd: SubCommand, color: bool) -> Result<()> {
+    log::info!("Using GPU: {}", gpu.get_label());
+
+    // Without --force, the GPUs bound to a GPU driver are refused before they are opened.
+    if let Some(driver) = gpu.get_device_handle().gpu_driver() {
+        if !subcmd.is_safe_with_driver() {
+            log::warn!("{} is bound to {driver}; going on anyway", gpu.get_label());
+        }
+    }
+
+    match subcmd {
+        SubCommand::ResetWithOs { yes } => {
+            if !confirm_reset(std::slice::from_ref(&gpu), yes)? {
+                log::info!("Aborted.");
+                return Ok(());
+            }
+
+            gpu.reset()?;
+        }
+        SubCommand::FullReset { yes } => {
+            if !confirm_reset(std::slice::from_ref(&gpu), yes)? {
+                log::info!("Aborted.");
+                return Ok(());
+            }
+
+            match gpu.full_reset()? {
+                Some(driver) => {
+                    log::info!("{} is reset and bound to {driver}", gpu.get_label())
+                }
+                None => log::info!("{} is reset", gpu.get_label()),
+            }
+        }
+        SubCommand::UnbindDriver => {
+            let _lock = gpu.held_lock();
+            let device = gpu.release()?;
+            match device.unbind_driver()? {
+                Some(driver) => log::info!("Unbound {driver} from {}", device.get_bdf()),
+                None => log::info!("No driver is bound to {}", device.get_bdf()),
+            }
+        }
+        SubCommand::BindDriver { driver } => {
+            let _lock = gpu.held_lock();
+            let device = gpu.release()?;
+            match device.get_driver() {
+                Some(current) if current == driver => {
+                    log::info!("{} is already bound to {driver}", device.get_bdf());
+                    return Ok(());
+                }
+                Some(current) => {
+                    device.unbind_driver()?;
+                    log::info!("Unbound {current} from {}", device.get_bdf());
+                }
+                None => (),
+            }
+
+            device.bind_driver(&driver)?;
+            log::info!("Bound {driver} to {}", device.get_bdf());
+        }
+        SubCommand::QueryCcMode { format, .. } => report::print_cc_mode(&gpu, format)?,
+        SubCommand::QueryCcSettings { format } => report::print_cc_settings(&gpu, format, color)?,
+        SubCommand::QueryPrcKnobs { format } => report::print_prc_knobs(&gpu, format, color)?,
+        SubCommand::QueryPpcieMode { format } => report::print_ppcie_mode(&gpu, format)?,
+        SubCommand::QueryFirewall { format } => report::print_firewall(&gpu, format, color)?,
+        SubCommand::SetKnob { knob, value } => {
+            gpu.set_prc_knob(knob, value)?;
+            log::info!(
+                "Knob {} of {} set to {value}; it takes effect upon the next reset.",
+                knob.name(),
+                gpu.get_bdf()
+            );
+        }
+        SubCommand::SetCcMode {
+            mode,
+            reset,
+            verify,
+            yes,
+            ..
+        } => {
+            let mode = bits::CcMode::from(mode);
+
+            fabric::warn_if_running();
+            if reset && !confirm_reset(std::slice::from_ref(&gpu), yes)? {
+                log::info!("Aborted.");
+                return Ok(());
+            }
+            txn::set_cc_mode_all(std::slice::from_ref(&gpu), mode)?;
+            log::info!("CC mode set to {mode}; it takes effect after the next reset.");
+            report::print_cc_settings(&gpu, Format::Table, color)?;
+
+            if reset {
+                txn::reset_all(std::slice::from_ref(&gpu), mode, verify)?;
+            }
+        }
+        SubCommand::ResetAfterCcModeSwitch { yes } => {
+            fabric::warn_if_running();
+            if !confirm_reset(std::slice::from_ref(&gpu), yes)? {
+                log::info!("Aborted.");
+                return Ok(());
+            }
+
+            let mode = gpu.reset_after_cc_mode_switch()?;
+            log::info!("{} is now in CC mode {mode}.", gpu.get_label());
+        }
+        SubCommand::ReadPhys {
+            address,
+            output,
+            len,
+        } => {
+            log::info!("Reading {} bytes from 0x{:x} to {}", len, address, output);
+
+            let data = gpu.read_phys(address, len)?;
+
+            fs::write(&output, &data)?;
+            log::info!("Data written to {output}, {} bytes.", data.len());
+        }
+        SubCommand::WritePhys {
+            address,
+            input,
+            value,
+            yes,
+        } => {
+            let data = match (input, value) {
+                (Some(input), _) => fs::read(input)?,
+                (None, Some(value)) => value.to_le_bytes().to_vec(),
+                (None, None) => unreachable!("clap requires --input or --value"),
+            };
+
+            let prompt = format!(
+                "Write {} bytes to 0x{:x} of {}? This may crash the GPU.",
+                data.len(),
                 address,
-                output,
+                gpu.get_label()
+            );
+            if !yes && !confirm(&prompt)? {
+                log::info!("Aborted.");
+                return Ok(());
+            }
+
+            gpu.write_phys(address, &data)?;
+            log::info!("{} bytes written to 0x{:x}.", data.len(), address);
+        }
+        SubCommand::VramRead {
+            offset,
+            len,
+            output,
+        } => {
+            log::info!(
+                "Reading {} bytes from BAR1 offset 0x{:x} to {}",
                 len,
-            } => {
-                log::info!("Reading {} bytes from 0x{:x} to {}", len, address, output);
+                offset,
+                output
+            );
 
-                let data = gpu.read_phys(address, len)?;
+            let data = gpu.read_bar1(offset, len)?;
+
+            fs::write(&output, &data)?;
+            log::info!("Data written to {output}, {} bytes.", data.len());
+        }
+        SubCommand::ReadReg { register } => {
+            let register = regs::resolve(&register)?;
+            let val = gpu.firewalled_read32(register)?;
 
-                fs::write(&output, &data)?;
-                log::info!("Data written to {output}, {} bytes.", data.len());
+            log::info!("Register {} = 0x{:x}", regs::describe(register), val);
+        }
+        SubCommand::WriteReg {
+            register,
+            value,
+            yes,
+        } => {
+            let register = regs::resolve(&register)?;
+            let value = regs::parse_number(&value)
+                .and_then(|value| u32::try_from(value).ok())
+                .ok_or(anyhow!("Invalid register value {value}"))?;
+
+            let prompt = format!(
+                "Write 0x{value:x} to register {} of {}? This may crash the GPU.",
+                regs::describe(register),
+                gpu.get_label()
+            );
+            if !yes && !confirm(&prompt)? {
+                log::info!("Aborted.");
+                return Ok(());
             }
-            SubCommand::ReadMmio { register } => {
-                let val = gpu.read32(register)?;
 
-                log::info!("Register 0x{:x} = 0x{:x}", register, val);
+            gpu.firewalled_write32(register, value)?;
+            log::info!("Register {} = 0x{:x}", regs::describe(register), value);
+        }
+        SubCommand::ReadRange { begin, end, output } => {
+            let mut v = vec![];
+
+            for i in (begin..end).step_by(4) {
+                let val = gpu.read32(i)?;
+
+                if val != 0 {
+                    v.push((i, val));
+                }
             }
-            SubCommand::ReadRange { begin, end, output } => {
-                let mut v = vec![];
 
-                for i in (begin..end).step_by(4) {
-                    let val = gpu.read32(i)?;
+            match output {
+                Some(output) => {
+                    let mut f = fs::File::create(output)?;
 
-                    if val != 0 {
-                        v.push((i, val));
+                    for (i, val) in v {
+                        let s = format!("0x{:x} = 0x{:x}\n", i, val);
+                        f.write_all(s.as_bytes())?;
                     }
                 }
+                None => {
+                    for (i, val) in v {
+                        log::info!("0x{:x} = 0x{:x}", i, val);
+                    }
+                }
+            }
+        }
+        SubCommand::Watch {
+            register,
+            interval,
+            until,
+        } => report::watch_register(&gpu, regs::resolve(&register)?, interval, until)?,
+        SubCommand::DumpFspEmem { channel, output } => {
+            let dump = gpu.dump_fsp_emem(channel)?;
 
-                match output {
-                    Some(output) => {
-                        let mut f = fs::File::create(output)?;
+            match output {
+                Some(output) => {
+                    fs::write(&output, dump.to_string())?;
+                    log::info!("FSP EMEM dump written to {output}.");
+                }
+                None => log::info!("{dump}"),
+            }
+        }
+        SubCommand::VerifyPersistence {
+            record,
+            mode,
+            state,
+        } => {
+            let mut state_file = persist::StateFile::load(&state)?;
+            let current = gpu.snapshot_cc_config()?;
+
+            if record {
+                let knobs = match mode {
+                    Some(mode) => persist::Knobs::from([(
+                        "cc_mode".to_string(),
+                        bits::CcMode::from(mode).to_string(),
+                    )]),
+                    None => current,
+                };
 
-                        for (i, val) in v {
-                            let s = format!("0x{:x} = 0x{:x}\n", i, val);
-                            f.write_all(s.as_bytes())?;
-                        }
+                log::info!("Recording {:?} for {} in {state}", knobs, gpu.get_bdf());
+                state_file.gpus.insert(gpu.get_bdf().to_string(), knobs);
+                state_file.save(&state)?;
+            } else {
+                let expected = state_file.gpus.get(gpu.get_bdf()).ok_or(anyhow!(
+                    "No configuration recorded for {} in {state}; run with --record first.",
+                    gpu.get_bdf()
+                ))?;
+
+                let drifts = persist::diff(expected, &current);
+                if !drifts.is_empty() {
+                    for drift in drifts.iter() {
+                        log::error!(
+                            "{}: expected {} but the GPU reports {}",
+                            drift.knob,
+                            drift.expected,
+                            drift.actual.as_deref().unwrap_or("nothing")
+                        );
                     }
-                    None => {
-                        for (i, val) in v {
-                            log::info!("0x{:x} = 0x{:x}", i, val);
-                        }
+
+                    return Err(anyhow!(
+                        "CC configuration of {} drifted from {state}",
+                        gpu.get_bdf()
+                    ));
+                }
+
+                log::info!("CC configuration of {} matches {state}.", gpu.get_bdf());
+            }
+        }
+        SubCommand::RunScript { file, yes } => {
+            let script = script::Script::parse(&fs::read_to_string(&file)?)?;
+            let prompt = format!(
+                "{file} writes the registers of {}. Run it?",
+                gpu.get_label()
+            );
+            if script.writes() && !yes && !confirm(&prompt)? {
+                log::info!("Aborted.");
+                return Ok(());
+            }
+
+            let results = script.run(&gpu);
+            let mut table = table::Table::new(&["line", "step", "result", "detail"]);
+            for result in results.iter() {
+                table.push([
+                    table::Cell::new(result.command.line.to_string()),
+                    table::Cell::new(result.command.step.to_string()),
+                    match result.passed {
+                        true => table::Cell::colored("pass", table::Color::Green),
+                        false => table::Cell::colored("fail", table::Color::Red),
+                    },
+                    table::Cell::new(&result.detail),
+                ]);
+            }
+            table.print(color);
+
+            let failed = results.iter().filter(|result| !result.passed).count();
+            let skipped = script.commands.len() - results.len();
+            if failed != 0 || skipped != 0 {
+                return Err(anyhow!(
+                    "{failed} of {} steps failed, {skipped} not run",
+                    script.commands.len()
+                ));
+            }
+            log::info!("All {} steps passed.", results.len());
+        }
+        SubCommand::QueryGpuInfo { format } => report::print_gpu_info(&gpu, format, color)?,
+        SubCommand::QueryBootStatus { format } => report::print_boot_status(&gpu, format, color)?,
+        SubCommand::QueryGsp { format } => report::print_gsp(&gpu, format, color)?,
+        SubCommand::QuerySpdm { format } => report::print_spdm_info(&gpu, format, color)?,
+        SubCommand::QueryIommu { format } => report::print_iommu(&gpu, format, color)?,
+        SubCommand::CheckAcs => report::check_acs(&gpu, color)?,
+        SubCommand::QueryTopology { format } => report::print_topology(&gpu, format, color)?,
+        SubCommand::QueryRebar { format } => report::print_rebar(&gpu, format, color)?,
+        SubCommand::SetRebar { bar, size } => {
+            let resized = gpu.get_device_handle().resize_bar(bar, size)?;
+            log::info!(
+                "BAR{bar} is now {} at 0x{:x}",
+                rebar::format_size(resized.size),
+                resized.addr
+            );
+        }
+        SubCommand::RetrainLink => report::retrain_link(&gpu, color)?,
+        SubCommand::QueryLink { format } => report::print_link(&gpu, format, color)?,
+        SubCommand::QueryPowerState { format } => {
+            let state = gpu.get_device_handle().power_state()?;
+            match format {
+                Format::Table => log::info!("Power state: {state}"),
+                Format::Json => {
+                    report::print_json(&json!({"bdf": gpu.get_bdf(), "power_state": state}))?
+                }
+            }
+        }
+        SubCommand::SetPowerState { state, yes } => {
+            let state = pm::PowerState::from(state);
+            if state == pm::PowerState::D3Hot && !yes {
+                warn_holders(std::slice::from_ref(&gpu));
+                if !confirm(&format!(
+                    "Put {} in {state}? This stops the workloads running on it.",
+                    gpu.get_label()
+                ))? {
+                    log::info!("Aborted.");
+                    return Ok(());
+                }
+            }
+
+            gpu.get_device_handle().set_power_state(state)?;
+        }
+        SubCommand::QueryMsi { format } => report::print_msi(&gpu, format, color)?,
+        SubCommand::QueryAer { format } => {
+            let status = gpu.get_device_handle().aer_status()?;
+            match format {
+                Format::Table => report::print_aer(&status, color),
+                Format::Json => report::print_json(&status)?,
+            }
+        }
+        SubCommand::ClearAer => {
+            let status = gpu.get_device_handle().clear_aer()?;
+            if !status.has_errors() {
+                log::info!("No AER errors were logged");
+            }
+        }
+        SubCommand::DumpConfig { format } => report::print_config(&gpu, format, color)?,
+        SubCommand::VerifyGpuCerts { root_ca, slot } => {
+            let root = identity::decode_pem(&fs::read(&root_ca)?)?;
+
+            let device = gpu.get_device_handle();
+            let mut requester = spdm::SpdmRequester::new(device.doe()?);
+            requester.init()?;
+            let chain = requester.get_certificate(slot)?;
+            let certs = certs::split_spdm_chain(&chain, requester.algorithms().hash_size())?;
+
+            match certs::verify_chain(&certs, &root)? {
+                certs::Verdict::Trusted { pdi } => {
+                    log::info!(
+                        "Trusted: the {} certificates of slot {slot} chain up to {root_ca}.",
+                        certs.len()
+                    );
+                    log::info!("PDI: {}", pdi.as_deref().unwrap_or("unknown"));
+                }
+                certs::Verdict::Untrusted(reason) => {
+                    return Err(anyhow!("Untrusted: {reason}"));
+                }
+            }
+        }
+        SubCommand::GpuAttest {
+            nonce,
+            slot,
+            output,
+        } => {
+            let nonce = parse_nonce(&nonce)?;
+
+            let evidence = gpu.collect_evidence(slot, nonce)?;
+            fs::write(&output, evidence.to_json()?)?;
+            log::info!(
+                "Evidence of {} written to {output}: a {}-byte report with {} certificates.",
+                gpu.get_label(),
+                evidence.report.len(),
+                evidence.certificates.len()
+            );
+        }
+        SubCommand::Nras {
+            nonce,
+            url,
+            proxy,
+            output,
+        } => {
+            let nonce = match nonce {
+                Some(nonce) => parse_nonce(&nonce)?,
+                None => spdm::random_nonce()?,
+            };
+
+            let evidence = gpu.collect_evidence(0, nonce)?;
+            let url = url.as_deref().unwrap_or(bits::NRAS_GPU_URL);
+            let result = nras::NrasClient::new(url, proxy.as_deref())?.attest(&[evidence])?;
+
+            for claims in result.claims.iter() {
+                log::debug!("Claims: {claims}");
+            }
+            if let Some(output) = output {
+                fs::write(&output, &result.raw)?;
+                log::info!("Response written to {output}.");
+            }
+
+            match result.passed {
+                Some(true) => log::info!("{} passed the NRAS attestation.", gpu.get_label()),
+                Some(false) => {
+                    return Err(anyhow!("{} failed the NRAS attestation.", gpu.get_label()))
+                }
+                None => return Err(anyhow!("NRAS returned no overall result.")),
+            }
+        }
+        SubCommand::VerifyLocal {
+            rim,
+            rim_cache,
+            rim_root_ca,
+            root_ca,
+            nonce,
+            eat,
+            eat_key,
+            policy,
+            explain,
+        } => {
+            let verification = verifier::Verification::load(
+                &root_ca,
+                &rim,
+                rim_cache.as_deref(),
+                &rim_root_ca,
+                policy.as_deref(),
+            )?;
+            let nonce = match nonce {
+                Some(nonce) => parse_nonce(&nonce)?,
+                None => spdm::random_nonce()?,
+            };
+            let (evidence, claims) = verifier::verify_local(&gpu, nonce, &verification)?;
+
+            let mut table = table::Table::new(&["claim", "value"]);
+            for (name, value) in claims.iter() {
+                table.push([name.clone(), value.clone()]);
+            }
+            table.print(color);
+
+            if claims["x-nvidia-overall-att-result"] != "true" {
+                return Err(anyhow!("{} failed the attestation.", gpu.get_label()));
+            }
+            if let Some(policy) = &verification.policy {
+                report::appraise(policy, &claims, explain)?;
+            }
+
+            // Only a GPU that passed is vouched for.
+            if let (Some(eat), Some(key)) = (eat, eat_key) {
+                let gpus = [(gpu.get_bdf().to_string(), claims)].into();
+                fs::write(&eat, eat::issue(&key, &evidence.nonce, &gpus)?)?;
+                log::info!("EAT written to {eat}.");
+            }
+        }
+        SubCommand::FetchRim {
+            url,
+            proxy,
+            cache,
+            rim_root_ca,
+            refresh,
+        } => {
+            let cache = rim::RimCache::new(
+                cache.as_deref().unwrap_or(bits::RIM_CACHE_DIR),
+                &identity::decode_pem(&fs::read(&rim_root_ca)?)?,
+            );
+
+            // The firmware versions are only reported in the opaque data of the evidence.
+            let evidence = gpu.collect_evidence(0, spdm::random_nonce()?)?;
+            let ids = rim::RimIds::from_evidence(&evidence);
+            if ids.iter().next().is_none() {
+                return Err(anyhow!(
+                    "{} reports no firmware versions in its evidence.",
+                    gpu.get_label()
+                ));
+            }
+
+            for id in ids.iter() {
+                if !refresh && cache.contains(id) {
+                    log::info!("RIM {id} is cached.");
+                    continue;
+                }
+                cache.fetch(&url, proxy.as_deref(), id)?;
+            }
+        }
+        SubCommand::GpuMeasurements { format, save } => {
+            let device = gpu.get_device_handle();
+            let mut requester = spdm::SpdmRequester::new(device.doe()?);
+            requester.init()?;
+            let measurements = requester.get_measurements(0, None)?;
+
+            if format == Format::Json {
+                let blocks = measurements
+                    .blocks
+                    .iter()
+                    .map(|block| {
+                        json!({
+                            "index": block.index,
+                            "type": block.value_type,
+                            "value": spdm::to_hex(&block.value),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                report::print_json(&json!({"bdf": gpu.get_bdf(), "measurements": blocks}))?;
+            } else {
+                let mut table = table::Table::new(&["index", "type", "value"]);
+                for block in measurements.blocks.iter() {
+                    table.push([
+                        block.index.to_string(),
+                        format!("0x{:02x}", block.value_type),
+                        spdm::to_hex(&block.value),
+                    ]);
+                }
+                table.print(color);
+            }
+
+            if let Some(save) = save {
+                let lines = measurements
+                    .to_set()
+                    .iter()
+                    .map(|(index, digest)| format!("{index} {digest}\n"))
+                    .collect::<String>();
+                fs::write(&save, lines)?;
+                log::info!("Measurements written to {save}.");
+            }
+        }
+        SubCommand::DumpVbios { output } => {
+            let vbios = gpu.dump_vbios()?;
+
+            fs::write(&output, &vbios.image)?;
+            log::info!("VBIOS written to {output}, {} bytes.", vbios.image.len());
+        }
+        SubCommand::DumpScratch { format } => report::print_scratch(&gpu, format, color)?,
+        SubCommand::QueryVgpu { format } => report::print_vgpu(&gpu, format, color)?,
+        SubCommand::QueryDeviceIdentity { format, cert } => {
+            let pdi = gpu.query_device_identity()?;
+            let issued = match &cert {
+                Some(cert) => Some(pdi.matches_cert(&fs::read(cert)?)?),
+                None => None,
+            };
+
+            match format {
+                Format::Table => {
+                    let mut table = table::Table::new(&["field", "value"]);
+                    table.push(["pdi".to_string(), pdi.to_hex()]);
+                    if let (Some(cert), Some(issued)) = (&cert, issued) {
+                        let issued = match issued {
+                            true => "issued to this device",
+                            false => "not issued to this device",
+                        };
+                        table.push([cert.clone(), issued.to_string()]);
                     }
+                    table.print(color);
                 }
+                Format::Json => report::print_json(&json!({
+                    "bdf": gpu.get_bdf(),
+                    "pdi": pdi.to_hex(),
+                    "cert_issued": issued,
+                }))?,
             }
-            SubCommand::Watch { register } => loop {
-                let val = gpu.read32(register)?;
 
-                // Sleep for 1 sec.
-                std::thread::sleep(std::time::Duration::from_secs(1));
+            if let (Some(cert), Some(false)) = (cert, issued) {
+                return Err(anyhow!(
+                    "{cert} is not issued to {}: its subject does not carry PDI {}",
+                    gpu.get_label(),
+                    pdi.to_hex()
+                ));
+            }
+        }
+        SubCommand::Tofu {
+            measurements,
+            history,
+        } => {
+            let identity = gpu.query_device_identity()?.to_hex();
+            let observed = history::parse_measurements(&fs::read_to_string(&measurements)?)?;
+            let db = history::HistoryDb::open(&history);
 
-                log::info!("Register 0x{:x} = 0x{:x}", register, val);
+            match tofu::appraise(&db, &identity, &observed)? {
+                tofu::TofuVerdict::BaselineRecorded => log::info!(
+                    "No baseline for {identity}; recorded {} measurements as the baseline.",
+                    observed.len()
+                ),
+                tofu::TofuVerdict::Match => {
+                    log::info!("Measurements of {identity} match the baseline.")
+                }
+                tofu::TofuVerdict::Changed(changes) => {
+                    for change in changes.iter() {
+                        log::error!(
+                            "Measurement {}: baseline {} observed {}",
+                            change.index,
+                            change.baseline.as_deref().unwrap_or("none"),
+                            change.observed.as_deref().unwrap_or("none")
+                        );
+                    }
+
+                    return Err(anyhow!(
+                        "Measurements of {identity} changed since the baseline"
+                    ));
+                }
+            }
+        }
+        SubCommand::Appraise {
+            policy,
+            evidence,
+            explain,
+            save_claims,
+            ..
+        } => {
+            let policy = policy::Policy::parse(&fs::read_to_string(policy)?)?;
+            let claims = match evidence {
+                Some(evidence) => policy::parse_claims(&fs::read_to_string(evidence)?)?,
+                None => gpu.collect_claims()?,
+            };
+
+            if let Some(save_claims) = save_claims {
+                fs::write(&save_claims, policy::format_claims(&claims))?;
+                log::info!("Claims saved to {save_claims}.");
+            }
+
+            report::appraise(&policy, &claims, explain)?;
+        }
+        SubCommand::DumpFwLogs {
+            output_dir,
+            firmware,
+        } => {
+            let mut logs = vec![];
+            let elf = match firmware {
+                Some(firmware) => Some(fwlog::LogElf::parse(&fs::read(firmware)?)?),
+                None => None,
+            };
+
+            match gpu.extract_gsp_logs() {
+                Ok(gsp_logs) => logs.extend(gsp_logs),
+                Err(e) => log::warn!("GSP-RM logs are not available: {e}"),
+            }
+            match gpu.extract_sec2_log() {
+                Ok(sec2_log) => logs.push(sec2_log),
+                Err(e) => log::warn!("SEC2 log is not available: {e}"),
+            }
+
+            fs::create_dir_all(&output_dir)?;
+            for log in logs {
+                let path = format!("{}/{}.bin", output_dir, log.name.to_lowercase());
+                fs::write(&path, &log.data)?;
+
+                log::info!(
+                    "{}: put = 0x{:x}, {} bytes written to {path}.",
+                    log.name,
+                    log.put,
+                    log.data.len()
+                );
+
+                if let Some(elf) = elf.as_ref().filter(|_| log.name.starts_with("LOG")) {
+                    report::print_fw_log(&log, elf);
+                }
+            }
+        }
+        _ => log::error!("Not implemented yet."),
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let mut args = Cmd::parse();
+    let config = config::Config::load(args.config.as_deref())?;
+    apply_config(&mut args, config)?;
+
+    let backend = match args.backend {
+        Some(BackendChoice::DevMem) => Some(backend::BackendKind::DevMem),
+        Some(BackendChoice::Resource) => Some(backend::BackendKind::Resource),
+        Some(BackendChoice::Vfio) => Some(backend::BackendKind::Vfio),
+        Some(BackendChoice::Auto) | None => None,
+    };
+    if let Some(kind) = backend {
+        backend::set_preferred(kind)?;
+    }
+    if let Some(method) = args.reset_method {
+        dev::set_reset_method(method.into())?;
+    }
+    // Devices bound to a GPU driver are not even opened, which reads BAR0, unless forced.
+    dev::set_open_driver_bound(args.force || args.subcmd.is_safe_with_driver());
+    // Every device is locked before it is opened, and for as long as we run; the daemon locks
+    // each GPU while attesting it instead.
+    lock::set_lock_on_open(!matches!(args.subcmd, SubCommand::AttestDaemon { .. }));
+
+    // Check mode communicates only through the exit code.
+    if let SubCommand::QueryCcMode {
+        check: Some(expected),
+        ..
+    } = args.subcmd
+    {
+        let code = match check_cc_mode(&args, expected.into()) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(_) => 2,
+        };
+
+        std::process::exit(code);
+    }
+
+    init_logger(args.log.unwrap_or(LevelFilter::Info));
+    let color = table::use_color(args.no_color);
+
+    if let Some(path) = &args.registers {
+        regs::load(path)?;
+    }
+
+    if let Some(path) = &args.record_mmio {
+        trace::record_to(path)?;
+        log::info!("Recording the MMIO trace to {path}.");
+    }
+
+    if let SubCommand::Completions { shell } = args.subcmd {
+        clap_complete::generate(
+            shell,
+            &mut Cmd::command(),
+            "nvtrust",
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
+
+    // Commands that only work on files need neither root nor a GPU.
+    if let SubCommand::DecodeFwLog { input, firmware } = &args.subcmd {
+        let elf = fwlog::LogElf::parse(&fs::read(firmware)?)?;
+        let log = fwlog::FwLogBuffer {
+            name: input.clone(),
+            put: 0,
+            data: fs::read(input)?,
+        };
+        report::print_fw_log(&log, &elf);
+
+        return Ok(());
+    }
+
+    if let SubCommand::Appraise {
+        policy,
+        evidence: Some(evidence),
+        dry_run: true,
+        explain,
+        ..
+    } = &args.subcmd
+    {
+        let policy = policy::Policy::parse(&fs::read_to_string(policy)?)?;
+        let claims = policy::parse_claims(&fs::read_to_string(evidence)?)?;
+
+        return report::appraise(&policy, &claims, *explain);
+    }
+
+    if let SubCommand::Doctor = &args.subcmd {
+        let problems = doctor::diagnose();
+        if problems.is_empty() {
+            log::info!("No problems found.");
+            return Ok(());
+        }
+
+        for (i, problem) in problems.iter().enumerate() {
+            log::info!(
+                "{}. [{}] {}: {}",
+                i + 1,
+                problem.severity,
+                problem.area,
+                problem.summary
+            );
+            log::info!("   fix: {}", problem.fix);
+        }
+
+        if problems
+            .iter()
+            .any(|problem| problem.severity != doctor::Severity::Warning)
+        {
+            return Err(anyhow!("{} problems found", problems.len()));
+        }
+
+        return Ok(());
+    }
+
+    if let SubCommand::HostCheck = &args.subcmd {
+        return report::host_check(color);
+    }
+
+    if let SubCommand::CheckFabricManager = &args.subcmd {
+        let status = fabric::FabricManagerStatus::probe();
+
+        log::info!(
+            "Fabric Manager: {}",
+            status
+                .path
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or("not installed".to_string())
+        );
+        log::info!(
+            "Version: {}",
+            status.version.as_deref().unwrap_or("unknown")
+        );
+        log::info!(
+            "Driver version: {}",
+            status
+                .driver_version
+                .as_deref()
+                .unwrap_or("driver not loaded")
+        );
+        log::info!("Fabric mode: {:?}", status.fabric_mode);
+        log::info!("Running: {:?}", status.pids);
+
+        status.ensure_ppcie()?;
+
+        log::info!("Fabric Manager is ready for PPCIe mode.");
+        return Ok(());
+    }
+
+    // The reports need their own TEE; anything else runs in whichever this build supports.
+    #[cfg(all(any(feature = "snp", feature = "tdx"), target_arch = "x86_64"))]
+    match &args.subcmd {
+        #[cfg(feature = "snp")]
+        SubCommand::SnpReport { .. } => nvtrust::cpuid::check_sev_snp()?,
+        #[cfg(feature = "tdx")]
+        SubCommand::TdxReport { .. } => nvtrust::cpuid::check_tdx()?,
+        _ => nvtrust::cpuid::check_tee()?,
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    if let Err(e) = nvtrust::cpuid::check_arm_cca() {
+        log::debug!("Not in an Arm CCA realm: {e}");
+    }
+
+    log::info!("NVIDIA GPU Tools version {VERSION}");
+
+    if Uid::effective().is_root() {
+        if let SubCommand::ListGpus { format } = args.subcmd {
+            return report::list_gpus(format, color);
+        }
+
+        if let SubCommand::Topology { format } = args.subcmd {
+            return report::print_tree(format, color);
+        }
+
+        if let SubCommand::AttestDaemon {
+            interval,
+            rim,
+            rim_cache,
+            rim_root_ca,
+            root_ca,
+            policy,
+            status,
+        } = &args.subcmd
+        {
+            let gpus = dev::find_by_bdfs(&args.gpu_bdf, dev::find_gpus_by_bdf)?;
+            if gpus.is_empty() {
+                return Err(NvTrustError::DeviceNotFound("No GPU found".to_string()).into());
+            }
+
+            let verification = verifier::Verification::load(
+                root_ca,
+                rim,
+                rim_cache.as_deref(),
+                rim_root_ca,
+                policy.as_deref(),
+            )?;
+
+            log::info!(
+                "Re-attesting {} GPUs every {interval}s into {status}",
+                gpus.len()
+            );
+            return Ok(daemon::run(
+                &gpus,
+                &verification,
+                std::time::Duration::from_secs(*interval),
+                status,
+            )?);
+        }
+
+        if let SubCommand::AttestPlatform { nonce, output } = &args.subcmd {
+            let gpus = dev::find_by_bdfs(&args.gpu_bdf, dev::find_gpus_by_bdf)?;
+            if gpus.is_empty() {
+                return Err(NvTrustError::DeviceNotFound("No GPU found".to_string()).into());
+            }
+
+            let nonce = match nonce {
+                Some(nonce) => parse_nonce(nonce)?,
+                None => spdm::random_nonce()?,
+            };
+
+            let evidence = platform::PlatformEvidence::collect(&gpus, nonce)?;
+            fs::write(output, evidence.to_json()?)?;
+            log::info!(
+                "Evidence of the {} VM and {} GPUs written to {output}.",
+                evidence.host.provider,
+                evidence.gpus.len()
+            );
+            return Ok(());
+        }
+
+        #[cfg(all(feature = "snp", target_arch = "x86_64"))]
+        if let SubCommand::SnpReport {
+            report_data,
+            vmpl,
+            output,
+        } = &args.subcmd
+        {
+            let report = nvtrust::snp::get_report(&pad_report_data(report_data)?, *vmpl)?;
+            fs::write(output, &report)?;
+            log::info!("SNP report written to {output}, {} bytes.", report.len());
+            return Ok(());
+        }
+
+        #[cfg(all(feature = "tdx", target_arch = "x86_64"))]
+        if let SubCommand::TdxReport {
+            report_data,
+            quote,
+            output,
+        } = &args.subcmd
+        {
+            let report_data = pad_report_data(report_data)?;
+            let report = if *quote {
+                nvtrust::tdx::get_quote(&report_data)?.report
+            } else {
+                nvtrust::tdx::get_td_report(&report_data)?
+            };
+            fs::write(output, &report)?;
+            log::info!("TDX report written to {output}, {} bytes.", report.len());
+            return Ok(());
+        }
+
+        // Board-wide configuration operates on every GPU instead of the selected one, as one
+        // transaction.
+        let board_wide =
+            args.all_gpus || matches!(args.subcmd, SubCommand::SetCcMode { all_gpus: true, .. });
+        if let (
+            true,
+            SubCommand::SetCcMode {
+                mode,
+                reset,
+                verify,
+                yes,
+                ..
             },
-            _ => log::error!("Not implemented yet."),
+        ) = (board_wide, &args.subcmd)
+        {
+            let (mode, reset, verify, yes) = (*mode, *reset, *verify, *yes);
+            // The NVSwitches of the board take the PPCIe mode only.
+            let gpus = dev::open_board()?
+                .into_iter()
+                .filter(|gpu| !gpu.is_nvswitch())
+                .collect::<Vec<_>>();
+            if gpus.is_empty() {
+                return Err(NvTrustError::DeviceNotFound("No GPU found".to_string()).into());
+            }
+
+            fabric::warn_if_running();
+            if reset && !confirm_reset(&gpus, yes)? {
+                log::info!("Aborted.");
+                return Ok(());
+            }
+
+            let mode = bits::CcMode::from(mode);
+            txn::set_cc_mode_all(&gpus, mode)?;
+            if reset {
+                txn::reset_all(&gpus, mode, verify)?;
+            }
+
+            return Ok(());
+        }
+
+        // PPCIe mode is always board-wide: the NVLink traffic is only protected if every GPU and
+        // NVSwitch has it.
+        if let SubCommand::SetPpcieMode {
+            mode,
+            reset,
+            verify,
+            yes,
+        } = args.subcmd
+        {
+            let on = mode == PpcieModeChoice::On;
+            let devices = dev::open_board()?;
+            if devices.is_empty() {
+                return Err(NvTrustError::DeviceNotFound("No GPU found".to_string()).into());
+            }
+            if !devices.iter().any(|device| device.is_nvswitch()) {
+                log::warn!("No NVSwitch found; the NVLink traffic is not protected without one.");
+            }
+
+            if on {
+                for problem in fabric::FabricManagerStatus::probe().check_ppcie() {
+                    log::warn!("{problem}");
+                }
+            }
+            fabric::warn_if_running();
+            if reset && !confirm_reset(&devices, yes)? {
+                log::info!("Aborted.");
+                return Ok(());
+            }
+
+            txn::set_ppcie_mode_all(&devices, on)?;
+            if reset {
+                txn::reset_all_ppcie(&devices, on, verify)?;
+            } else {
+                log::info!("PPCIe mode set; it takes effect after the next reset.");
+            }
+
+            return Ok(());
+        }
+
+        // The GPUs of --all-gpus and of repeated --gpu-bdf that cannot be opened, or selectors that
+        // match nothing, count as failed.
+        let dev::Selection {
+            gpus,
+            failed: unopened,
+        } = dev::select(&selector(&args))?;
+
+        if gpus.is_empty() && unopened.is_empty() {
+            return Err(NvTrustError::DeviceNotFound("No GPU found".to_string()).into());
+        }
+        if gpus.len() == 1 && unopened.is_empty() {
+            return run_on_gpu(gpus.into_iter().next().unwrap(), args.subcmd, color);
+        }
+
+        // The workers would race for the answers on stdin.
+        if args.parallel && args.subcmd.yes() == Some(false) {
+            return Err(anyhow!(
+                "--parallel cannot ask for confirmation on each GPU; pass --yes to confirm for all of them"
+            ));
+        }
+
+        // Every GPU gets its turn even if some fail, so that one bad GPU does not hide the others.
+        let total = gpus.len() + unopened.len();
+        let run = |gpu: &dev::GpuObject| {
+            let label = gpu.get_label();
+            let result = run_on_gpu(gpu.clone(), args.subcmd.clone(), color);
+            if let Err(e) = &result {
+                log::error!("{label}: {e:#}");
+            }
+            result.err().map(|_| label)
+        };
+        let failed = match args.parallel {
+            true => dev::par_map(&gpus, run),
+            false => gpus.iter().map(run).collect(),
+        }
+        .into_iter()
+        .flatten()
+        .chain(unopened)
+        .collect::<Vec<_>>();
+
+        if !failed.is_empty() {
+            return Err(anyhow!(
+                "{} of {total} GPUs failed: {}",
+                failed.len(),
+                failed.join(", ")
+            ));
         }
+        log::info!("Done on all {total} GPUs.");
     } else {
-        log::error!("You need to be root to run this program.");
+        log::error!("{}", NvTrustError::NotRoot);
     }
 
     Ok(())
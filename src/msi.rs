@@ -0,0 +1,150 @@
+use serde::Serialize;
+
+use crate::{
+    bits::*,
+    dev::PciDevice,
+    error::{NvTrustError, Result},
+};
+
+/// The MSI capability of a device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Msi {
+    pub offset: u64,
+    pub enabled: bool,
+    /// The number of vectors the device asks for, and the number it was given.
+    pub vectors: u32,
+    pub vectors_enabled: u32,
+    pub is_64: bool,
+    pub address: u64,
+    pub data: u16,
+    /// The mask and pending bits, if the device supports per-vector masking.
+    pub mask: Option<u32>,
+    pub pending: Option<u32>,
+}
+
+/// An entry of the MSI-X table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MsixVector {
+    pub index: usize,
+    pub address: u64,
+    pub data: u32,
+    pub masked: bool,
+    pub pending: bool,
+}
+
+/// The MSI-X capability of a device, with its vector table and Pending Bit Array read from the
+/// BARs they live in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Msix {
+    pub offset: u64,
+    pub enabled: bool,
+    /// Whether all the vectors are masked, whatever their own mask bits say.
+    pub function_mask: bool,
+    pub table_bar: u32,
+    pub table_offset: u64,
+    pub pba_bar: u32,
+    pub pba_offset: u64,
+    pub vectors: Vec<MsixVector>,
+}
+
+impl PciDevice {
+    /// Read the MSI capability, if the device has one.
+    pub fn msi(&self) -> Result<Option<Msi>> {
+        let Some(&offset) = self.caps().get(&(PCI_CAP_ID_MSI as u8)) else {
+            return Ok(None);
+        };
+
+        let flags = self.read_config16(offset + PCI_MSI_FLAGS)?;
+        let is_64 = flags & PCI_MSI_FLAGS_64BIT != 0;
+        let low = self.read_config32(offset + PCI_MSI_ADDRESS_LO)? as u64;
+        // The message data, mask and pending registers move up a dword with a 64-bit address.
+        let (address, data) = match is_64 {
+            true => (
+                low | (self.read_config32(offset + PCI_MSI_ADDRESS_LO + 4)? as u64) << 32,
+                offset + PCI_MSI_DATA_64,
+            ),
+            false => (low, offset + PCI_MSI_DATA_32),
+        };
+        let (mask, pending) = match flags & PCI_MSI_FLAGS_MASKBIT != 0 {
+            true => (
+                Some(self.read_config32(data + 4)?),
+                Some(self.read_config32(data + 8)?),
+            ),
+            false => (None, None),
+        };
+
+        Ok(Some(Msi {
+            offset,
+            enabled: flags & PCI_MSI_FLAGS_ENABLE != 0,
+            vectors: 1 << ((flags >> 1) & 0x7),
+            vectors_enabled: 1 << ((flags >> 4) & 0x7),
+            is_64,
+            address,
+            data: self.read_config16(data)?,
+            mask,
+            pending,
+        }))
+    }
+
+    /// Read the MSI-X capability and its vector table and PBA, if the device has one.
+    pub fn msix(&self) -> Result<Option<Msix>> {
+        let Some(&offset) = self.caps().get(&(PCI_CAP_ID_MSIX as u8)) else {
+            return Ok(None);
+        };
+
+        let flags = self.read_config16(offset + PCI_MSIX_FLAGS)?;
+        let table = self.read_config32(offset + PCI_MSIX_TABLE)?;
+        let pba = self.read_config32(offset + PCI_MSIX_PBA)?;
+        let count = (flags & PCI_MSIX_FLAGS_QSIZE) as usize + 1;
+        let (table_bar, table_offset) = (table & PCI_MSIX_BIR, (table & !PCI_MSIX_BIR) as u64);
+        let (pba_bar, pba_offset) = (pba & PCI_MSIX_BIR, (pba & !PCI_MSIX_BIR) as u64);
+
+        let mut entries = vec![0; count * PCI_MSIX_ENTRY_SIZE as usize];
+        self.backend()
+            .read_bar(self.bar_index(table_bar)?, table_offset, &mut entries)?;
+        // One pending bit per vector, in qwords.
+        let mut bits = vec![0; count.div_ceil(64) * 8];
+        self.backend()
+            .read_bar(self.bar_index(pba_bar)?, pba_offset, &mut bits)?;
+
+        let vectors = entries
+            .chunks_exact(PCI_MSIX_ENTRY_SIZE as usize)
+            .enumerate()
+            .map(|(index, entry)| {
+                let dword = |i: usize| u32::from_le_bytes(entry[i..i + 4].try_into().unwrap());
+                MsixVector {
+                    index,
+                    address: dword(0) as u64 | (dword(4) as u64) << 32,
+                    data: dword(8),
+                    masked: dword(PCI_MSIX_ENTRY_VECTOR_CTRL as usize)
+                        & PCI_MSIX_ENTRY_CTRL_MASKBIT
+                        != 0,
+                    pending: bits[index / 8] & (1 << (index % 8)) != 0,
+                }
+            })
+            .collect();
+
+        Ok(Some(Msix {
+            offset,
+            enabled: flags & PCI_MSIX_FLAGS_ENABLE != 0,
+            function_mask: flags & PCI_MSIX_FLAGS_MASKALL != 0,
+            table_bar,
+            table_offset,
+            pba_bar,
+            pba_offset,
+            vectors,
+        }))
+    }
+
+    /// The index of the BAR with the given BAR Indicator Register value among [`Self::bars`].
+    fn bar_index(&self, bir: u32) -> Result<usize> {
+        self.bars()
+            .iter()
+            .position(|bar| bar.size != 0 && bar.resource == bir as usize)
+            .ok_or(NvTrustError::NotSupported {
+                what: format!("BAR {bir}"),
+                device: self.get_bdf().to_string(),
+                reason: "the BAR is not mapped".to_string(),
+            })
+    }
+}
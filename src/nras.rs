@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use base64::Engine;
+use serde_json::json;
+
+use crate::{
+    error::{NvTrustError, Result},
+    evidence::Evidence,
+    spdm::to_hex,
+};
+
+/// The result of an NRAS attestation.
+#[derive(Debug, Clone)]
+pub struct NrasResult {
+    /// The overall result claimed by the first token, if it carries one.
+    pub passed: Option<bool>,
+    /// The decoded claims of each token the service returned, the overall token first and then
+    /// one per GPU.
+    pub claims: Vec<String>,
+    /// The response as received, which is what should be kept as the proof of the verdict.
+    pub raw: String,
+}
+
+/// A client of the NVIDIA Remote Attestation Service.
+pub struct NrasClient {
+    agent: ureq::Agent,
+    url: String,
+}
+
+impl NrasClient {
+    /// A client of the service at `url`, through `proxy` if given and otherwise through the proxy
+    /// in the environment, if any.
+    pub fn new(url: &str, proxy: Option<&str>) -> Result<Self> {
+        Ok(Self {
+            agent: http_agent(proxy)?,
+            url: url.to_string(),
+        })
+    }
+
+    /// Submit the evidence of the GPUs, which must all have been collected over the same nonce.
+    pub fn attest(&self, evidence: &[Evidence]) -> Result<NrasResult> {
+        let first = evidence
+            .first()
+            .ok_or_else(|| NvTrustError::InvalidArgument("no evidence to submit".to_string()))?;
+        if evidence.iter().any(|e| e.nonce != first.nonce) {
+            return Err(NvTrustError::InvalidArgument(
+                "the evidence was collected over different nonces".to_string(),
+            ));
+        }
+
+        let b64 = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(data);
+        let list = evidence
+            .iter()
+            .map(|e| {
+                json!({
+                    "certificate": b64(e.certificates_pem().as_bytes()),
+                    "evidence": b64(&e.report),
+                })
+            })
+            .collect::<Vec<_>>();
+        let body = json!({
+            "nonce": to_hex(&first.nonce),
+            "arch": first.arch.to_uppercase(),
+            "claims_version": "3.0",
+            "evidence_list": list,
+        })
+        .to_string();
+
+        log::debug!("Submitting {} evidence to {}", evidence.len(), self.url);
+        let raw = match self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .set("Accept", "application/json")
+            .send_string(&body)
+        {
+            Ok(response) => response.into_string()?,
+            Err(ureq::Error::Status(code, response)) => {
+                let reason = response.into_string().unwrap_or_default();
+                return Err(NvTrustError::Http(format!(
+                    "{} answered {code}: {reason}",
+                    self.url
+                )));
+            }
+            Err(e) => {
+                return Err(NvTrustError::Http(format!(
+                    "cannot reach {}: {e}",
+                    self.url
+                )))
+            }
+        };
+
+        let claims = jwt_strings(&raw)
+            .iter()
+            .filter_map(|token| decode_jwt(token))
+            .collect::<Vec<_>>();
+        let passed = claims
+            .first()
+            .and_then(|claims| bool_claim(claims, "x-nvidia-overall-att-result"));
+
+        Ok(NrasResult {
+            passed,
+            claims,
+            raw,
+        })
+    }
+}
+
+/// An HTTP agent going through `proxy` if given and otherwise through the proxy in the
+/// environment, if any.
+pub fn http_agent(proxy: Option<&str>) -> Result<ureq::Agent> {
+    let mut builder = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .try_proxy_from_env(true);
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(
+            ureq::Proxy::new(proxy)
+                .map_err(|e| NvTrustError::Http(format!("invalid proxy {proxy}: {e}")))?,
+        );
+    }
+
+    Ok(builder.build())
+}
+
+/// The string literals of the response that look like a JWT.
+fn jwt_strings(json: &str) -> Vec<&str> {
+    json.split('"')
+        .skip(1)
+        .step_by(2)
+        .filter(|s| {
+            s.split('.').count() == 3
+                && s.len() > 32
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        })
+        .collect()
+}
+
+/// The payload of a JWT. The signature is not checked: the token came from the service over TLS,
+/// and relying parties that get the token second-hand must check it against the NRAS JWKS.
+fn decode_jwt(token: &str) -> Option<String> {
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .ok()?;
+
+    String::from_utf8(payload).ok()
+}
+
+fn bool_claim(claims: &str, name: &str) -> Option<bool> {
+    let key = format!("\"{name}\"");
+    let rest = claims[claims.find(&key)? + key.len()..]
+        .trim_start()
+        .strip_prefix(':')?
+        .trim_start();
+
+    if rest.starts_with("true") {
+        Some(true)
+    } else if rest.starts_with("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
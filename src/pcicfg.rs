@@ -0,0 +1,388 @@
+use serde::Serialize;
+
+use crate::{bits::*, dev::RawConfig, error::Result, link::LinkSpeed};
+
+/// A decoded field of the config space, e.g., the link speed of the PCIe capability.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Field {
+    pub name: &'static str,
+    pub value: String,
+}
+
+/// A capability in the config space, legacy or PCIe extended, with its decoded fields.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Capability {
+    pub id: u16,
+    pub extended: bool,
+    pub offset: u64,
+    pub name: &'static str,
+    /// The version of an extended capability.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u8>,
+    pub fields: Vec<Field>,
+}
+
+/// The decoded config space of a device.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigDump {
+    pub bdf: String,
+    /// How much of the config space could be read; only root can read past the first 256 bytes.
+    pub size: usize,
+    pub header: Vec<Field>,
+    pub capabilities: Vec<Capability>,
+}
+
+/// Walk the capability lists of the config space, legacy then extended, in list order, giving
+/// `(extended, id, offset)` for each.
+pub fn capabilities(config: &[u8]) -> Vec<(bool, u16, u64)> {
+    let dword = |ptr: usize| {
+        config
+            .get(ptr..ptr + 4)
+            .map(|d| u32::from_le_bytes([d[0], d[1], d[2], d[3]]))
+    };
+    let mut caps = vec![];
+
+    // Bound the walks so that a malformed list cannot loop forever.
+    let mut ptr = config
+        .get(PCI_CAPABILITY_LIST as usize)
+        .map_or(0, |ptr| *ptr as usize & 0xfc);
+    for _ in 0..PCI_CFG_SPACE_SIZE / 4 {
+        let Some(header) = dword(ptr).filter(|_| ptr != 0) else {
+            break;
+        };
+
+        caps.push((false, header as u8 as u16, ptr as u64));
+        ptr = (header >> 8) as usize & 0xfc;
+    }
+
+    let mut ptr = PCI_CFG_SPACE_SIZE as usize;
+    for _ in 0..PCI_CFG_SPACE_EXP_SIZE / 4 {
+        let Some(header) = dword(ptr).filter(|h| ptr != 0 && *h != 0 && *h != 0xffffffff) else {
+            break;
+        };
+
+        caps.push((true, header as u16, ptr as u64));
+        ptr = (header >> 20) as usize & 0xffc;
+    }
+
+    caps
+}
+
+/// Decode the config space, as read by [`crate::dev::PciDevice::read_config_space`].
+pub fn decode(bdf: &str, config: &[u8]) -> Result<ConfigDump> {
+    let header = RawConfig::from_bytes(config)?;
+    let capabilities = capabilities(config)
+        .into_iter()
+        .map(|(extended, id, offset)| {
+            let cfg = Cfg(config, offset as usize);
+            let (name, fields) = match extended {
+                false => legacy(id as u8, &cfg),
+                true => extended_cap(id, &cfg),
+            };
+
+            Capability {
+                id,
+                extended,
+                offset,
+                name,
+                version: extended.then(|| cfg.u8(2) & 0xf),
+                fields,
+            }
+        })
+        .collect();
+
+    Ok(ConfigDump {
+        bdf: bdf.to_string(),
+        size: config.len(),
+        header: decode_header(&header),
+        capabilities,
+    })
+}
+
+/// A view of the config space at a capability; what could not be read is 0.
+struct Cfg<'a>(&'a [u8], usize);
+
+impl Cfg<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let mut bytes = [0; N];
+        if let Some(data) = self.0.get(self.1 + offset..self.1 + offset + N) {
+            bytes.copy_from_slice(data);
+        }
+
+        bytes
+    }
+
+    fn u8(&self, offset: usize) -> u8 {
+        self.bytes::<1>(offset)[0]
+    }
+
+    fn u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes(self.bytes(offset))
+    }
+
+    fn u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.bytes(offset))
+    }
+
+    fn u64(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(self.bytes(offset))
+    }
+}
+
+fn field(name: &'static str, value: impl ToString) -> Field {
+    Field {
+        name,
+        value: value.to_string(),
+    }
+}
+
+fn flags(value: u32, names: &[(u32, &str)]) -> String {
+    let set = names
+        .iter()
+        .filter(|(bit, _)| value & bit != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>();
+
+    match set.is_empty() {
+        true => format!("0x{value:x}"),
+        false => format!("0x{value:x} ({})", set.join(" ")),
+    }
+}
+
+fn decode_header(config: &RawConfig) -> Vec<Field> {
+    let class = u32::from_le_bytes([
+        config.class_code[0],
+        config.class_code[1],
+        config.class_code[2],
+        0,
+    ]);
+    let mut fields = vec![
+        field("vendor", format!("0x{:04x}", config.vendor)),
+        field("device", format!("0x{:04x}", config.device)),
+        field(
+            "command",
+            flags(
+                config.command as u32,
+                &[
+                    (PCI_COMMAND_IO as u32, "io"),
+                    (PCI_COMMAND_MEMORY as u32, "memory"),
+                    (PCI_COMMAND_MASTER as u32, "bus-master"),
+                    (PCI_COMMAND_INTX_DISABLE as u32, "intx-disable"),
+                ],
+            ),
+        ),
+        field(
+            "status",
+            flags(
+                config.status as u32,
+                &[
+                    (PCI_STATUS_CAP_LIST as u32, "cap-list"),
+                    (PCI_STATUS_DETECTED_PARITY as u32, "parity-error"),
+                ],
+            ),
+        ),
+        field("revision", format!("0x{:02x}", config.rev_id)),
+        field("class", format!("0x{class:06x}")),
+        field("header type", format!("0x{:02x}", config.header_type)),
+    ];
+
+    for (i, bar) in config.bars.iter().enumerate() {
+        const NAMES: [&str; 6] = ["bar0", "bar1", "bar2", "bar3", "bar4", "bar5"];
+        fields.push(field(NAMES[i], format!("0x{bar:08x}")));
+    }
+
+    fields.extend([
+        field(
+            "subsystem vendor",
+            format!("0x{:04x}", config.subsystem_vendor_id),
+        ),
+        field("subsystem", format!("0x{:04x}", config.subsystem_id)),
+        field(
+            "expansion rom",
+            format!("0x{:08x}", config.expansion_rom_base_address),
+        ),
+        field(
+            "capabilities",
+            format!("0x{:02x}", config.capabilities_pointer),
+        ),
+        field("interrupt line", config.interrupt_line),
+        field("interrupt pin", config.interrupt_pin),
+    ]);
+
+    fields
+}
+
+fn legacy(id: u8, cap: &Cfg) -> (&'static str, Vec<Field>) {
+    match id as u64 {
+        PCI_CAP_ID_PM => {
+            let pmc = cap.u16(2);
+            let pmcsr = cap.u16(4);
+            (
+                "Power Management",
+                vec![
+                    field("version", pmc & 0x7),
+                    field("d1", pmc & (1 << 9) != 0),
+                    field("d2", pmc & (1 << 10) != 0),
+                    field("power state", format!("D{}", pmcsr & 0x3)),
+                    field("no soft reset", pmcsr & (1 << 3) != 0),
+                ],
+            )
+        }
+        PCI_CAP_ID_MSI => {
+            let ctrl = cap.u16(PCI_MSI_FLAGS as usize);
+            (
+                "MSI",
+                vec![
+                    field("enabled", ctrl & PCI_MSI_FLAGS_ENABLE != 0),
+                    field("vectors", 1 << ((ctrl >> 1) & 0x7)),
+                    field("vectors enabled", 1 << ((ctrl >> 4) & 0x7)),
+                    field("64-bit", ctrl & PCI_MSI_FLAGS_64BIT != 0),
+                    field("per-vector masking", ctrl & PCI_MSI_FLAGS_MASKBIT != 0),
+                ],
+            )
+        }
+        PCI_CAP_ID_VNDR => ("Vendor Specific", vec![field("length", cap.u8(2))]),
+        PCI_CAP_ID_EXP => {
+            let flags = cap.u16(2);
+            let devcap = cap.u32(4);
+            let devctl = cap.u16(8);
+            let lnkcap = cap.u32(PCI_EXP_LNKCAP as usize);
+            let lnksta = cap.u16(PCI_EXP_LNKSTA as usize);
+            let kind = match (flags >> 4) & 0xf {
+                0 => "endpoint".to_string(),
+                1 => "legacy endpoint".to_string(),
+                4 => "root port".to_string(),
+                5 => "upstream port".to_string(),
+                6 => "downstream port".to_string(),
+                9 => "root complex integrated endpoint".to_string(),
+                kind => format!("unknown ({kind})"),
+            };
+
+            (
+                "PCI Express",
+                vec![
+                    field("version", flags & 0xf),
+                    field("type", kind),
+                    field("max payload supported", 128 << (devcap & 0x7)),
+                    field("flr", devcap & PCI_EXP_DEVCAP_FLR != 0),
+                    field("max payload", 128 << ((devctl >> 5) & 0x7)),
+                    field("max read request", 128 << ((devctl >> 12) & 0x7)),
+                    field(
+                        "link speed supported",
+                        LinkSpeed((lnkcap & PCI_EXP_LNKCAP_SLS) as u8),
+                    ),
+                    field("link width supported", format!("x{}", (lnkcap >> 4) & 0x3f)),
+                    field("link speed", LinkSpeed((lnksta & PCI_EXP_LNKSTA_CLS) as u8)),
+                    field("link width", format!("x{}", (lnksta >> 4) & 0x3f)),
+                ],
+            )
+        }
+        PCI_CAP_ID_MSIX => {
+            let flags = cap.u16(PCI_MSIX_FLAGS as usize);
+            let table = cap.u32(PCI_MSIX_TABLE as usize);
+            let pba = cap.u32(PCI_MSIX_PBA as usize);
+            (
+                "MSI-X",
+                vec![
+                    field("enabled", flags & PCI_MSIX_FLAGS_ENABLE != 0),
+                    field("function mask", flags & PCI_MSIX_FLAGS_MASKALL != 0),
+                    field("vectors", (flags & PCI_MSIX_FLAGS_QSIZE) + 1),
+                    field("table bar", table & PCI_MSIX_BIR),
+                    field("table offset", format!("0x{:x}", table & !PCI_MSIX_BIR)),
+                    field("pba bar", pba & PCI_MSIX_BIR),
+                    field("pba offset", format!("0x{:x}", pba & !PCI_MSIX_BIR)),
+                ],
+            )
+        }
+        0x03 => ("VPD", vec![]),
+        0x0d => ("PCI Bridge Subsystem Vendor ID", vec![]),
+        0x13 => ("Advanced Features", vec![]),
+        _ => ("Unknown", vec![]),
+    }
+}
+
+fn extended_cap(id: u16, cap: &Cfg) -> (&'static str, Vec<Field>) {
+    let hex32 = |name, offset| field(name, format!("0x{:08x}", cap.u32(offset)));
+
+    match id as u64 {
+        PCI_EXT_CAP_ID_ERR => (
+            "Advanced Error Reporting",
+            vec![
+                hex32("uncorrectable status", PCI_ERR_UNCOR_STATUS as usize),
+                hex32("uncorrectable mask", PCI_ERR_UNCOR_MASK as usize),
+                hex32("uncorrectable severity", PCI_ERR_UNCOR_SEVER as usize),
+                hex32("correctable status", PCI_ERR_COR_STATUS as usize),
+                hex32("correctable mask", PCI_ERR_COR_MASK as usize),
+            ],
+        ),
+        PCI_EXT_CAP_ID_DSN => (
+            "Device Serial Number",
+            vec![field("serial", format!("{:016x}", cap.u64(4)))],
+        ),
+        PCI_EXT_CAP_ID_ACS => (
+            "Access Control Services",
+            vec![
+                field("capabilities", format!("0x{:04x}", cap.u16(4))),
+                field(
+                    "control",
+                    format!("0x{:04x}", cap.u16(PCI_ACS_CTRL as usize)),
+                ),
+            ],
+        ),
+        PCI_EXP_CAP_ID_SRIOV => (
+            "SR-IOV",
+            vec![
+                field("vf enable", cap.u16(8) & 0x1 != 0),
+                field("total vfs", cap.u16(0xe)),
+                field("vfs", cap.u16(0x10)),
+                field("first vf offset", cap.u16(0x14)),
+                field("vf stride", cap.u16(0x16)),
+                field("vf device", format!("0x{:04x}", cap.u16(0x1a))),
+            ],
+        ),
+        PCI_EXT_CAP_ID_REBAR => {
+            // One capability and control pair per resizable BAR.
+            let count = ((cap.u32(8) >> 5) & 0x7).clamp(1, 6) as usize;
+            let fields = (0..count)
+                .map(|i| {
+                    let ctrl = cap.u32(8 + i * 8);
+                    Field {
+                        name: "bar",
+                        value: format!("{} size {}MB", ctrl & 0x7, 1u64 << ((ctrl >> 8) & 0x3f)),
+                    }
+                })
+                .collect();
+
+            ("Resizable BAR", fields)
+        }
+        PCI_EXT_CAP_ID_DVSEC => (
+            "Designated Vendor-Specific",
+            vec![
+                field("vendor", format!("0x{:04x}", cap.u16(4))),
+                field("id", format!("0x{:04x}", cap.u16(8))),
+            ],
+        ),
+        PCI_EXT_CAP_ID_DOE => (
+            "Data Object Exchange",
+            vec![
+                hex32("capabilities", PCI_DOE_CAP as usize),
+                hex32("control", PCI_DOE_CTRL as usize),
+                hex32("status", PCI_DOE_STATUS as usize),
+            ],
+        ),
+        0x02 | 0x09 => ("Virtual Channel", vec![]),
+        0x04 => ("Power Budgeting", vec![]),
+        0x0b => ("Vendor Specific", vec![]),
+        0x0e => ("Alternative Routing-ID", vec![]),
+        0x12 => ("Multicast", vec![]),
+        0x18 => ("Latency Tolerance Reporting", vec![]),
+        0x19 => ("Secondary PCI Express", vec![]),
+        0x1e => ("L1 PM Substates", vec![]),
+        0x25 => ("Data Link Feature", vec![]),
+        0x26 => ("Physical Layer 16.0 GT/s", vec![]),
+        0x27 => ("Lane Margining", vec![]),
+        0x2a => ("Physical Layer 32.0 GT/s", vec![]),
+        PCI_EXT_CAP_ID_IDE => ("Integrity and Data Encryption", vec![]),
+        _ => ("Unknown", vec![]),
+    }
+}
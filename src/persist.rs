@@ -0,0 +1,120 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::{
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+};
+
+/// The CC configuration knobs of a single GPU, keyed by knob name.
+pub type Knobs = BTreeMap<String, String>;
+
+/// The state file recording the intended CC configuration of each GPU, keyed by BDF.
+///
+/// The file is a simple INI-like text file so that it can be inspected and edited by hand:
+///
+/// ```text
+/// [0000:01:00.0]
+/// cc_mode = on
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StateFile {
+    pub gpus: BTreeMap<String, Knobs>,
+}
+
+/// A knob whose value differs from the recorded one.
+#[derive(Debug, Clone)]
+pub struct Drift {
+    pub knob: String,
+    pub expected: String,
+    pub actual: Option<String>,
+}
+
+impl StateFile {
+    /// Load the state file; a missing file is treated as an empty state.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = match fs::read_to_string(path.as_ref()) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut state = Self::default();
+        let mut current = None;
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(bdf) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                state.gpus.entry(bdf.to_string()).or_default();
+                current = Some(bdf.to_string());
+            } else if let Some((key, val)) = line.split_once('=') {
+                let bdf = current.as_ref().ok_or(NvTrustError::Malformed(format!(
+                    "line {}: knob outside of a GPU section",
+                    i + 1
+                )))?;
+                state
+                    .gpus
+                    .entry(bdf.clone())
+                    .or_default()
+                    .insert(key.trim().to_string(), val.trim().to_string());
+            } else {
+                return Err(NvTrustError::Malformed(format!(
+                    "line {}: malformed line '{line}'",
+                    i + 1
+                )));
+            }
+        }
+
+        Ok(state)
+    }
+
+    /// Save the state file, replacing the old one atomically.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut content =
+            String::from("# CC configuration recorded by nvtrust verify-persistence.\n");
+        for (bdf, knobs) in self.gpus.iter() {
+            content.push_str(&format!("\n[{bdf}]\n"));
+            for (key, val) in knobs.iter() {
+                content.push_str(&format!("{key} = {val}\n"));
+            }
+        }
+
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+}
+
+/// Compare the recorded knobs with the current ones; only the recorded knobs are checked.
+pub fn diff(expected: &Knobs, actual: &Knobs) -> Vec<Drift> {
+    expected
+        .iter()
+        .filter(|(knob, val)| actual.get(*knob) != Some(val))
+        .map(|(knob, val)| Drift {
+            knob: knob.clone(),
+            expected: val.clone(),
+            actual: actual.get(knob).cloned(),
+        })
+        .collect()
+}
+
+impl GpuObject {
+    /// Take a snapshot of the CC configuration the GPU currently reports.
+    pub fn snapshot_cc_config(&self) -> Result<Knobs> {
+        let mut knobs = Knobs::new();
+        let mode = self.query_cc_mode()?;
+
+        knobs.insert("cc_mode".to_string(), mode.to_string());
+
+        Ok(knobs)
+    }
+}
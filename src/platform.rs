@@ -0,0 +1,126 @@
+use std::{fs, path::Path};
+
+use base64::Engine;
+use ring::digest;
+use serde_json::json;
+
+use crate::{
+    bits::*,
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+    evidence::Evidence,
+    spdm::to_hex,
+};
+
+/// The CC evidence of the confidential VM we run in: an SNP attestation report or a TDX quote,
+/// whose report data binds the GPU evidence.
+#[derive(Debug, Clone)]
+pub struct HostEvidence {
+    /// The TSM that produced the report, e.g., `sev_guest`, `tdx_guest` or `arm_cca_guest`.
+    pub provider: String,
+    pub report: Vec<u8>,
+    /// The certificates the provider appends to the report, e.g., the VCEK of SNP, if any.
+    pub auxblob: Option<Vec<u8>>,
+}
+
+/// Get a report of the confidential VM over the given report data through configfs-tsm, which
+/// covers SEV-SNP, TDX and Arm CCA guests, or through `/dev/sev-guest` on older SNP guests.
+pub fn host_report(report_data: &[u8; TSM_REPORT_DATA_SIZE]) -> Result<HostEvidence> {
+    let dir = Path::new(TSM_REPORT_DIR);
+
+    #[cfg(all(feature = "snp", target_arch = "x86_64"))]
+    if !dir.exists() && Path::new(SEV_GUEST_DEVICE).exists() {
+        return Ok(HostEvidence {
+            provider: "sev_guest".to_string(),
+            report: crate::snp::get_report(report_data, 0)?,
+            auxblob: None,
+        });
+    }
+
+    if !dir.exists() {
+        return Err(NvTrustError::Tee(format!(
+            "{TSM_REPORT_DIR} is missing: not a confidential VM, or configfs-tsm is not mounted"
+        )));
+    }
+
+    let entry = dir.join(format!("nvtrust-{}", std::process::id()));
+    fs::create_dir(&entry)?;
+
+    let report = (|| {
+        fs::write(entry.join("inblob"), report_data)?;
+        let report = fs::read(entry.join("outblob"))?;
+        let provider = fs::read_to_string(entry.join("provider"))?
+            .trim()
+            .to_string();
+        let auxblob = fs::read(entry.join("auxblob"))
+            .ok()
+            .filter(|aux| !aux.is_empty());
+
+        Ok(HostEvidence {
+            provider,
+            report,
+            auxblob,
+        })
+    })();
+
+    if let Err(e) = fs::remove_dir(&entry) {
+        log::warn!("Cannot remove {}: {e}", entry.display());
+    }
+    report
+}
+
+/// The evidence of the confidential VM and of its GPUs, collected over the same nonce.
+///
+/// The GPU reports are signed over the nonce, and the report data of the VM is the SHA-512 of the
+/// nonce followed by the GPU reports, so a verifier that checks both signatures knows that the
+/// GPUs answered to the same VM.
+#[derive(Debug, Clone)]
+pub struct PlatformEvidence {
+    pub nonce: [u8; SPDM_NONCE_SIZE],
+    pub host: HostEvidence,
+    pub gpus: Vec<Evidence>,
+}
+
+impl PlatformEvidence {
+    pub fn collect(gpus: &[GpuObject], nonce: [u8; SPDM_NONCE_SIZE]) -> Result<Self> {
+        let gpus = gpus
+            .iter()
+            .map(|gpu| {
+                gpu.collect_evidence(0, nonce).inspect_err(|e| {
+                    log::error!("Cannot collect the evidence of {}: {e}", gpu.get_label())
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let host = host_report(&report_data(&nonce, &gpus))?;
+
+        Ok(Self { nonce, host, gpus })
+    }
+
+    /// The evidence file: the GPU evidence as in their own files, and the VM report as base64.
+    pub fn to_json(&self) -> Result<String> {
+        let b64 = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(data);
+        let json = json!({
+            "nonce": to_hex(&self.nonce),
+            "report_data": to_hex(&report_data(&self.nonce, &self.gpus)),
+            "host": {
+                "provider": self.host.provider,
+                "auxblob": self.host.auxblob.as_deref().map(b64),
+                "report": b64(&self.host.report),
+            },
+            "gpus": self.gpus,
+        });
+
+        Ok(serde_json::to_string_pretty(&json)? + "\n")
+    }
+}
+
+/// The report data binding the GPU evidence to the VM report.
+pub fn report_data(nonce: &[u8], gpus: &[Evidence]) -> [u8; TSM_REPORT_DATA_SIZE] {
+    let mut ctx = digest::Context::new(&digest::SHA512);
+    ctx.update(nonce);
+    for gpu in gpus {
+        ctx.update(&gpu.report);
+    }
+
+    ctx.finish().as_ref().try_into().unwrap()
+}
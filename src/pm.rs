@@ -0,0 +1,95 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bits::*,
+    dev::PciDevice,
+    error::{NvTrustError, Result},
+};
+
+/// The power state of a PCI function, from the PM capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PowerState {
+    D0,
+    D1,
+    D2,
+    D3Hot,
+}
+
+impl fmt::Display for PowerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowerState::D0 => write!(f, "D0"),
+            PowerState::D1 => write!(f, "D1"),
+            PowerState::D2 => write!(f, "D2"),
+            PowerState::D3Hot => write!(f, "D3hot"),
+        }
+    }
+}
+
+impl PowerState {
+    fn from_bits(bits: u16) -> Self {
+        match bits & PCI_PM_CTRL_STATE_MASK {
+            0 => PowerState::D0,
+            1 => PowerState::D1,
+            2 => PowerState::D2,
+            _ => PowerState::D3Hot,
+        }
+    }
+
+    fn bits(&self) -> u16 {
+        *self as u16
+    }
+}
+
+impl PciDevice {
+    fn pm_offset(&self) -> Result<u64> {
+        self.caps()
+            .get(&(PCI_CAP_ID_PM as u8))
+            .copied()
+            .ok_or(NvTrustError::NotSupported {
+                what: "power management".to_string(),
+                device: self.get_bdf().to_string(),
+                reason: "it has no PM capability".to_string(),
+            })
+    }
+
+    /// Get the current power state.
+    pub fn power_state(&self) -> Result<PowerState> {
+        let ctrl = self.read_config16(self.pm_offset()? + PCI_PM_CTRL)?;
+        Ok(PowerState::from_bits(ctrl))
+    }
+
+    /// Put the device in the given power state, waiting the delay the PM spec requires after
+    /// D3hot.
+    ///
+    /// Devices without No_Soft_Reset are reset going from D3hot to D0, so the config space is
+    /// restored afterwards, as after any other reset.
+    pub fn set_power_state(&self, state: PowerState) -> Result<()> {
+        let offset = self.pm_offset()? + PCI_PM_CTRL;
+        let ctrl = self.read_config16(offset)?;
+        let current = PowerState::from_bits(ctrl);
+        if current == state {
+            return Ok(());
+        }
+
+        // The config space is still accessible in D3hot, BARs included.
+        let saved = self.save_config_space()?;
+        log::info!("Moving {} from {current} to {state}", self.get_bdf());
+        self.write_config16(offset, (ctrl & !PCI_PM_CTRL_STATE_MASK) | state.bits())?;
+        if current == PowerState::D3Hot || state == PowerState::D3Hot {
+            std::thread::sleep(Duration::from_millis(PCI_PM_D3HOT_WAIT));
+        }
+
+        if current == PowerState::D3Hot
+            && state == PowerState::D0
+            && ctrl & PCI_PM_CTRL_NO_SOFT_RESET == 0
+        {
+            self.wait_for_config_space()?;
+            self.restore_config_space(&saved)?;
+        }
+
+        Ok(())
+    }
+}
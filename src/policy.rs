@@ -0,0 +1,298 @@
+use std::{cmp::Ordering, collections::BTreeMap, fmt};
+
+use crate::{
+    bits::UNVERIFIED_CLAIM_PREFIX,
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+};
+
+/// The claims extracted from the evidence of a GPU, keyed by claim name.
+pub type Claims = BTreeMap<String, String>;
+
+/// The comparison operator of a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Ge,
+    Le,
+    Gt,
+    Lt,
+    In,
+}
+
+/// A single appraisal rule, e.g., `cc_mode == on` or `device_id in [0x2331, 0x2330]`.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// The line of the rule in the policy file.
+    pub line: usize,
+    /// The claim the rule checks.
+    pub claim: String,
+    pub op: Op,
+    /// The reference values the claim is compared with.
+    pub values: Vec<String>,
+}
+
+/// An appraisal policy: a list of rules that must all pass.
+///
+/// The policy file has one rule per line, in the form `<claim> <op> <value>` where `<op>` is one
+/// of `==`, `!=`, `>=`, `<=`, `>`, `<` and `in` (followed by a list like `[a, b]`). Lines starting
+/// with `#` are comments. For example:
+///
+/// ```text
+/// cc_mode == on
+/// device_id in [0x2330, 0x2331]
+/// vbios_version >= 96.00.5E.00.01
+/// measurement.2 in [<digest>, <digest>]
+/// ```
+///
+/// Values compare as numbers, as dotted versions of hex components, or else as strings, see
+/// [`Rule::evaluate`].
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    pub rules: Vec<Rule>,
+}
+
+/// The result of evaluating a single rule.
+#[derive(Debug, Clone)]
+pub struct RuleResult<'a> {
+    pub rule: &'a Rule,
+    /// The value of the claim in the evidence, if present.
+    pub actual: Option<String>,
+    pub passed: bool,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Ge => ">=",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Lt => "<",
+            Op::In => "in",
+        };
+
+        write!(f, "{op}")
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.op {
+            Op::In => write!(f, "{} in [{}]", self.claim, self.values.join(", ")),
+            op => write!(f, "{} {} {}", self.claim, op, self.values.join(", ")),
+        }
+    }
+}
+
+impl Rule {
+    fn parse(line: usize, text: &str) -> Result<Self> {
+        let mut parts = text.splitn(3, char::is_whitespace);
+        let claim = parts.next().unwrap_or_default().to_string();
+        let op = match parts.next() {
+            Some("==") => Op::Eq,
+            Some("!=") => Op::Ne,
+            Some(">=") => Op::Ge,
+            Some("<=") => Op::Le,
+            Some(">") => Op::Gt,
+            Some("<") => Op::Lt,
+            Some("in") => Op::In,
+            op => {
+                return Err(NvTrustError::Policy(format!(
+                    "line {line}: unknown operator {:?}",
+                    op
+                )))
+            }
+        };
+        let value = parts
+            .next()
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .ok_or(NvTrustError::Policy(format!("line {line}: missing value")))?;
+
+        let values = match op {
+            Op::In => value
+                .strip_prefix('[')
+                .and_then(|v| v.strip_suffix(']'))
+                .ok_or(NvTrustError::Policy(format!(
+                    "line {line}: expected a list like [a, b]"
+                )))?
+                .split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            _ => vec![value.to_string()],
+        };
+        if values.is_empty() {
+            return Err(NvTrustError::Policy(format!("line {line}: empty list")));
+        }
+
+        Ok(Self {
+            line,
+            claim,
+            op,
+            values,
+        })
+    }
+
+    /// Evaluate the rule against the claim value.
+    ///
+    /// Two numbers, decimal or 0x-prefixed hex, compare as numbers. If either side is a dotted
+    /// version, both compare component by component as hex, a plain number being a version of one
+    /// component, so that `550 > 535.86` and `535 < 535.86`. Anything else compares as a
+    /// case-insensitive string.
+    pub fn evaluate(&self, actual: &str) -> bool {
+        let ordering = compare_values(actual, &self.values[0]);
+
+        match self.op {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ne => ordering != Ordering::Equal,
+            Op::Ge => ordering != Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Lt => ordering == Ordering::Less,
+            Op::In => self
+                .values
+                .iter()
+                .any(|v| compare_values(actual, v) == Ordering::Equal),
+        }
+    }
+}
+
+impl Policy {
+    pub fn parse(content: &str) -> Result<Self> {
+        let rules = content
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| Rule::parse(i, line))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { rules })
+    }
+
+    /// Evaluate every rule against the claims; a missing claim fails its rule.
+    pub fn evaluate(&self, claims: &Claims) -> Vec<RuleResult<'_>> {
+        self.rules
+            .iter()
+            .map(|rule| {
+                let actual = claims.get(&rule.claim).cloned();
+                let passed = actual
+                    .as_deref()
+                    .is_some_and(|actual| rule.evaluate(actual));
+
+                RuleResult {
+                    rule,
+                    actual,
+                    passed,
+                }
+            })
+            .collect()
+    }
+
+    /// Evaluate the claims, failing with the rules that do not pass.
+    pub fn check(&self, claims: &Claims) -> Result<()> {
+        let failed = self
+            .evaluate(claims)
+            .into_iter()
+            .filter(|result| !result.passed)
+            .map(|result| format!("line {}: {}", result.rule.line, result.rule))
+            .collect::<Vec<_>>();
+
+        match failed.is_empty() {
+            true => Ok(()),
+            false => Err(NvTrustError::Policy(format!(
+                "policy failed: {}",
+                failed.join("; ")
+            ))),
+        }
+    }
+}
+
+/// Compare two claim values, see [`Rule::evaluate`].
+fn compare_values(a: &str, b: &str) -> Ordering {
+    fn number(s: &str) -> Option<u64> {
+        match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        }
+    }
+
+    if let (Some(a), Some(b)) = (number(a), number(b)) {
+        return a.cmp(&b);
+    }
+
+    if a.contains('.') || b.contains('.') {
+        // VBIOS versions such as 96.00.5E.00.01 are hex components.
+        let components = |s: &str| {
+            s.split('.')
+                .map(|c| u64::from_str_radix(c, 16).ok())
+                .collect::<Option<Vec<_>>>()
+        };
+
+        if let (Some(a), Some(b)) = (components(a), components(b)) {
+            return a.cmp(&b);
+        }
+    }
+
+    a.to_lowercase().cmp(&b.to_lowercase())
+}
+
+/// Parse claims from "<claim> = <value>" lines.
+pub fn parse_claims(content: &str) -> Result<Claims> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            let (key, val) = line.split_once('=').ok_or(NvTrustError::Policy(format!(
+                "line {i}: expected '<claim> = <value>'"
+            )))?;
+            Ok((key.trim().to_string(), val.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Serialize claims as "<claim> = <value>" lines, the format [`parse_claims`] reads.
+pub fn format_claims(claims: &Claims) -> String {
+    claims
+        .iter()
+        .map(|(key, val)| format!("{key} = {val}\n"))
+        .collect()
+}
+
+impl GpuObject {
+    /// Collect the claims about the GPU that policies can check.
+    pub fn collect_claims(&self) -> Result<Claims> {
+        let device = self.get_device_handle();
+        let config = device.get_config();
+
+        let mut claims = Claims::new();
+        claims.insert("vendor_id".to_string(), format!("0x{:04x}", config.vendor));
+        claims.insert("device_id".to_string(), format!("0x{:04x}", config.device));
+        claims.insert("is_vf".to_string(), self.is_vf().to_string());
+        claims.insert("cc_mode".to_string(), self.query_cc_mode()?.to_string());
+
+        match self.query_device_identity() {
+            Ok(pdi) => {
+                claims.insert("pdi".to_string(), pdi.to_hex());
+            }
+            Err(e) => log::debug!("No PDI claim: {e}"),
+        }
+
+        Ok(claims)
+    }
+}
+
+/// Prefix the claims with [`UNVERIFIED_CLAIM_PREFIX`], to put the register reads of
+/// [`GpuObject::collect_claims`] next to the verified claims without passing them off as such.
+pub fn unverified(claims: Claims) -> Claims {
+    claims
+        .into_iter()
+        .map(|(name, value)| (format!("{UNVERIFIED_CLAIM_PREFIX}{name}"), value))
+        .collect()
+}
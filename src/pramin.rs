@@ -0,0 +1,92 @@
+use crate::{arch::PraminRegs, bits::*, dev::GpuObject, error::Result};
+
+/// The PRAMIN window, which maps 1MB of VRAM at a movable base into BAR0.
+///
+/// The window register is saved when the window is opened and restored when it is dropped, so that
+/// a driver or firmware relying on it does not notice.
+pub struct PraminWindow<'a> {
+    gpu: &'a GpuObject,
+    regs: PraminRegs,
+    saved: u32,
+}
+
+impl<'a> PraminWindow<'a> {
+    pub fn new(gpu: &'a GpuObject) -> Result<Self> {
+        let regs = gpu.regs().pramin();
+        let saved = gpu.read32(regs.window)?;
+
+        Ok(Self { gpu, regs, saved })
+    }
+
+    /// Point the window at the 64KB-aligned block containing `fb_addr`, returning the BAR0 offset
+    /// of `fb_addr` and the number of bytes that are mapped from there on.
+    fn map(&self, fb_addr: u64) -> Result<(u64, usize)> {
+        let base = fb_addr & !NV_HOST_MEM_WINDOW_MASK;
+        let offset = fb_addr - base;
+
+        self.gpu
+            .write32(self.regs.window, (base >> NV_HOST_MEM_WINDOW_SHIFT) as u32)?;
+        Ok((self.regs.start + offset, (self.regs.len - offset) as usize))
+    }
+
+    /// Read `len` bytes at the given framebuffer physical address, moving the window as needed.
+    pub fn read(&self, fb_addr: u64, len: usize) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(len);
+
+        while data.len() < len {
+            let (offset, mapped) = self.map(fb_addr + data.len() as u64)?;
+            let chunk = (len - data.len()).min(mapped);
+            data.extend(self.gpu.read_window(offset, chunk)?);
+        }
+
+        Ok(data)
+    }
+
+    /// Write `data` at the given framebuffer physical address, moving the window as needed.
+    ///
+    /// VRAM is written in dwords; the bytes around an unaligned start or end are read back first so
+    /// that they are preserved.
+    pub fn write(&self, fb_addr: u64, data: &[u8]) -> Result<()> {
+        let mut written = 0;
+
+        while written < data.len() {
+            let (offset, mapped) = self.map(fb_addr + written as u64)?;
+            let chunk = (data.len() - written).min(mapped);
+
+            let start = offset & !0x3;
+            let skip = (offset - start) as usize;
+            let mut dwords = self
+                .gpu
+                .read_window(start, (skip + chunk).next_multiple_of(4))?;
+            dwords[skip..skip + chunk].copy_from_slice(&data[written..written + chunk]);
+            self.gpu.write(start, &dwords)?;
+
+            written += chunk;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for PraminWindow<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.gpu.write32(self.regs.window, self.saved) {
+            log::warn!(
+                "Cannot restore the PRAMIN window of {}: {e}",
+                self.gpu.get_bdf()
+            );
+        }
+    }
+}
+
+impl GpuObject {
+    /// Read `len` bytes of VRAM at the given framebuffer offset through the PRAMIN window.
+    pub fn read_vram(&self, fb_addr: u64, len: usize) -> Result<Vec<u8>> {
+        PraminWindow::new(self)?.read(fb_addr, len)
+    }
+
+    /// Write `data` to VRAM at the given framebuffer offset through the PRAMIN window.
+    pub fn write_vram(&self, fb_addr: u64, data: &[u8]) -> Result<()> {
+        PraminWindow::new(self)?.write(fb_addr, data)
+    }
+}
@@ -0,0 +1,184 @@
+use std::path::Path;
+
+use nix::unistd::Uid;
+
+use crate::{
+    bits::*,
+    dev,
+    doctor::{Check, Status},
+};
+
+/// The build configuration of the running kernel, from `/boot/config-<release>`.
+pub struct KernelConfig {
+    pub path: String,
+    text: String,
+}
+
+impl KernelConfig {
+    pub fn load() -> Option<Self> {
+        let release = std::fs::read_to_string(KERNEL_RELEASE).ok()?;
+        let path = format!("/boot/config-{}", release.trim());
+        let text = std::fs::read_to_string(&path).ok()?;
+
+        Some(Self { path, text })
+    }
+
+    /// Whether the option is built in or a module.
+    pub fn is_enabled(&self, option: &str) -> bool {
+        self.text
+            .lines()
+            .any(|line| line == format!("{option}=y") || line == format!("{option}=m"))
+    }
+}
+
+/// The lockdown mode of the kernel, e.g., `integrity` or `confidentiality`, if it is locked down.
+pub fn lockdown() -> Option<String> {
+    let modes = std::fs::read_to_string(LOCKDOWN_FILE).ok()?;
+    // The active mode is in brackets: `none [integrity] confidentiality`.
+    let mode = modes.split('[').nth(1)?.split(']').next()?;
+
+    (mode != "none").then(|| mode.to_string())
+}
+
+fn kernel_cmdline_has(arg: &str) -> bool {
+    std::fs::read_to_string(KERNEL_CMDLINE)
+        .is_ok_and(|cmdline| cmdline.split_whitespace().any(|a| a == arg))
+}
+
+/// Why `/dev/mem` refused us, and what to use instead.
+pub fn devmem_blocker() -> String {
+    const ALTERNATIVE: &str =
+        "map the BAR through the sysfs resource file of the device, or bind it to vfio-pci";
+
+    if !Uid::effective().is_root() {
+        return "only root can open /dev/mem".to_string();
+    }
+    if let Some(mode) = lockdown() {
+        return format!(
+            "the kernel is locked down ({mode}), which disables /dev/mem entirely; {ALTERNATIVE}"
+        );
+    }
+
+    let config = KernelConfig::load();
+    let relaxed = kernel_cmdline_has("iomem=relaxed");
+    if let Some(config) = &config {
+        if config.is_enabled("CONFIG_IO_STRICT_DEVMEM") && !relaxed {
+            return format!(
+                "CONFIG_IO_STRICT_DEVMEM denies /dev/mem access to BARs claimed by a driver; boot with `iomem=relaxed`, unbind the driver, or {ALTERNATIVE}"
+            );
+        }
+        if config.is_enabled("CONFIG_STRICT_DEVMEM") && !relaxed {
+            return format!(
+                "CONFIG_STRICT_DEVMEM restricts /dev/mem; boot with `iomem=relaxed`, or {ALTERNATIVE}"
+            );
+        }
+    }
+
+    format!("no lockdown or STRICT_DEVMEM found, so an LSM may deny it; {ALTERNATIVE}")
+}
+
+/// Check what stands between us and MMIO through `/dev/mem`: kernel lockdown and STRICT_DEVMEM.
+pub fn check_devmem() -> Vec<Check> {
+    let mut checks = vec![];
+
+    checks.push(match lockdown() {
+        None => Check::new("lockdown", Status::Pass, "not locked down".to_string(), ""),
+        Some(mode) => Check::new(
+            "lockdown",
+            Status::Warn,
+            format!("locked down ({mode}), /dev/mem is disabled"),
+            "The BARs are mapped through their sysfs resource files instead; disable Secure Boot to lift the lockdown.",
+        ),
+    });
+
+    let relaxed = kernel_cmdline_has("iomem=relaxed");
+    checks.push(match KernelConfig::load() {
+        None => Check::new(
+            "strict devmem",
+            Status::Warn,
+            "cannot read the kernel config".to_string(),
+            "",
+        ),
+        Some(config) if config.is_enabled("CONFIG_IO_STRICT_DEVMEM") && !relaxed => Check::new(
+            "strict devmem",
+            Status::Warn,
+            "CONFIG_IO_STRICT_DEVMEM blocks the BARs of bound devices".to_string(),
+            "The BARs are mapped through their sysfs resource files instead; boot with `iomem=relaxed` to use /dev/mem.",
+        ),
+        Some(config) if config.is_enabled("CONFIG_STRICT_DEVMEM") && !relaxed => Check::new(
+            "strict devmem",
+            Status::Warn,
+            "CONFIG_STRICT_DEVMEM restricts /dev/mem to MMIO ranges".to_string(),
+            "Boot with `iomem=relaxed` if BAR access fails with EPERM.",
+        ),
+        Some(_) => Check::new(
+            "strict devmem",
+            Status::Pass,
+            match relaxed {
+                true => "relaxed by iomem=relaxed".to_string(),
+                false => "not enabled".to_string(),
+            },
+            "",
+        ),
+    });
+
+    checks
+}
+
+/// Check ACS on the root and downstream ports above the device, which decides the IOMMU group the
+/// device lands in, and so whether it can be passed to vfio on its own, and whether its
+/// peer-to-peer traffic goes through the IOMMU.
+pub fn check_acs(bdf: &str) -> Check {
+    let device = Path::new(PCI_DEVICES).join(bdf);
+    if !device.exists() {
+        return Check::new(
+            "acs",
+            Status::Warn,
+            format!("{bdf} is not in {PCI_DEVICES}"),
+            "",
+        );
+    }
+
+    let ports = match dev::open_port(&device.to_string_lossy()).and_then(|dev| dev.topology()) {
+        Ok(ports) => ports,
+        Err(e) => {
+            return Check::new(
+                "acs",
+                Status::Warn,
+                format!("cannot walk the ports above {bdf}: {e}"),
+                "",
+            )
+        }
+    };
+    let missing = ports
+        .iter()
+        .filter(|port| port.is_insecure())
+        .map(|port| match port.acs {
+            Some(_) => format!("{} (no {})", port.bdf, port.missing_acs().join(", ")),
+            None => format!("{} (no ACS)", port.bdf),
+        })
+        .collect::<Vec<_>>();
+
+    let group = std::fs::read_dir(device.join("iommu_group/devices"))
+        .map(|devices| devices.count())
+        .unwrap_or(0);
+
+    if missing.is_empty() {
+        Check::new(
+            "acs",
+            Status::Pass,
+            format!("ACS on every port above {bdf}, {group} devices in its IOMMU group"),
+            "",
+        )
+    } else {
+        Check::new(
+            "acs",
+            Status::Warn,
+            format!(
+                "ACS off on {}; {group} devices in the IOMMU group of {bdf}",
+                missing.join(", ")
+            ),
+            "Enable ACS in the BIOS; vfio must take the whole IOMMU group, and peer-to-peer traffic can bypass the IOMMU.",
+        )
+    }
+}
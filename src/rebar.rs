@@ -0,0 +1,119 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{
+    backend,
+    bits::*,
+    dev::{Bar, PciDevice},
+    error::{NvTrustError, Result},
+};
+
+/// A BAR of the Resizable BAR capability.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ResizableBar {
+    /// The BAR register, e.g., 1 for BAR1.
+    pub bar: u32,
+    /// The current size, in bytes.
+    pub size: u64,
+    /// The sizes the BAR supports, in bytes, from the smallest.
+    pub supported: Vec<u64>,
+}
+
+/// Show a BAR size the way the kernel does, e.g., `256M` or `64G`.
+pub fn format_size(size: u64) -> String {
+    match size {
+        size if size >= 1 << 40 && size % (1 << 40) == 0 => format!("{}T", size >> 40),
+        size if size >= 1 << 30 && size % (1 << 30) == 0 => format!("{}G", size >> 30),
+        size => format!("{}M", size >> 20),
+    }
+}
+
+impl PciDevice {
+    fn rebar_offset(&self) -> Result<u64> {
+        self.find_ext_cap(PCI_EXT_CAP_ID_REBAR)?
+            .ok_or(NvTrustError::NotSupported {
+                what: "BAR resizing".to_string(),
+                device: self.get_bdf().to_string(),
+                reason: "it has no Resizable BAR capability".to_string(),
+            })
+    }
+
+    /// Read the BARs that can be resized, with their current and supported sizes.
+    pub fn resizable_bars(&self) -> Result<Vec<ResizableBar>> {
+        let offset = self.rebar_offset()?;
+        let first = self.read_config32(offset + PCI_REBAR_CTRL)?;
+        let count = ((first & PCI_REBAR_CTRL_NBAR_MASK) >> 5).clamp(1, 6);
+
+        (0..count as u64)
+            .map(|i| {
+                let cap = self.read_config32(offset + PCI_REBAR_CAP + i * 8)?;
+                let ctrl = self.read_config32(offset + PCI_REBAR_CTRL + i * 8)?;
+                // Bit n + 4 of the capability register is a size of 2^n MB.
+                let supported = (4..32)
+                    .filter(|bit| cap & (1 << bit) != 0)
+                    .map(|bit| 1u64 << (bit - 4 + 20))
+                    .collect();
+
+                Ok(ResizableBar {
+                    bar: ctrl & PCI_REBAR_CTRL_BAR_IDX,
+                    size: 1 << (((ctrl & PCI_REBAR_CTRL_BAR_SIZE) >> 8) + 20),
+                    supported,
+                })
+            })
+            .collect()
+    }
+
+    /// Resize a BAR and return it as the kernel placed it afterwards.
+    ///
+    /// The kernel has to release the BAR, and whatever else shares its bridge window, and
+    /// assign it again, so this goes through the sysfs `resource<N>_resize` file of Linux 6.1
+    /// and later, and only works while no driver is bound. The addresses of all the BARs may
+    /// change, so the device should be opened again afterwards.
+    pub fn resize_bar(&self, bar: u32, size: u64) -> Result<Bar> {
+        let not_supported = |reason: String| NvTrustError::NotSupported {
+            what: format!("resizing BAR{bar}"),
+            device: self.get_bdf().to_string(),
+            reason,
+        };
+
+        let rebar = self
+            .resizable_bars()?
+            .into_iter()
+            .find(|rebar| rebar.bar == bar)
+            .ok_or_else(|| not_supported("the BAR is not resizable".to_string()))?;
+        if !rebar.supported.contains(&size) {
+            let supported = rebar.supported.iter().map(|size| format_size(*size));
+            return Err(NvTrustError::InvalidArgument(format!(
+                "BAR{bar} cannot be {}; it can be {}",
+                format_size(size),
+                supported.collect::<Vec<_>>().join(", ")
+            )));
+        }
+        if let Some(driver) = self.get_driver() {
+            return Err(not_supported(format!("unbind it from {driver} first")));
+        }
+
+        let file = Path::new(self.get_name()).join(format!("resource{bar}_resize"));
+        if !file.exists() {
+            return Err(not_supported(
+                "the kernel has no resource resizing, which needs Linux 6.1".to_string(),
+            ));
+        }
+
+        log::info!(
+            "Resizing BAR{bar} of {} from {} to {}",
+            self.get_bdf(),
+            format_size(rebar.size),
+            format_size(size)
+        );
+        std::fs::write(&file, (size >> 20).trailing_zeros().to_string())?;
+
+        let bars = backend::parse_bars(&std::fs::read_to_string(
+            Path::new(self.get_name()).join("resource"),
+        )?)?;
+        bars.into_iter()
+            .find(|b| b.size != 0 && b.resource == bar as usize)
+            .ok_or_else(|| not_supported("the kernel did not assign the BAR again".to_string()))
+    }
+}
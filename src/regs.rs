@@ -0,0 +1,137 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{OnceLock, RwLock},
+};
+
+use crate::{
+    bits::*,
+    error::{NvTrustError, Result},
+};
+
+/// The BAR0 registers known by name, e.g., `NV_PMC_BOOT_0`.
+const BUILTIN: &[(&str, u64)] = &[
+    ("NV_PMC_BOOT_0", NV_PMC_BOOT_0),
+    ("NV_PMC_BOOT_1", NV_PMC_BOOT_1),
+    ("NV_PMC_ENABLE", NV_PMC_ENABLE),
+    ("NV_PMC_DEVICE_ENABLE", NV_PMC_DEVICE_ENABLE),
+    ("NV_PTIMER_TIME_0", NV_PTIMER_TIME_0),
+    ("NV_PTIMER_TIME_1", NV_PTIMER_TIME_1),
+    ("NV_HOST_MEM", NV_HOST_MEM),
+    ("NV_PBUS_SW_SCRATCH", NV_PBUS_SW_SCRATCH),
+    ("NV_PBUS_PCI_NV_20", NV_PBUS_PCI_NV_20),
+    ("NV_CC_MODE", NV_CC_MODE),
+    (
+        "NV_PGC6_AON_SECURE_SCRATCH_GROUP_05",
+        NV_PGC6_AON_SECURE_SCRATCH_GROUP_05,
+    ),
+    (
+        "NV_PGC6_AON_SECURE_SCRATCH_GROUP_20",
+        NV_PGC6_AON_SECURE_SCRATCH_GROUP_20,
+    ),
+    ("NV_THERM_I2CS_SCRATCH", NV_THERM_I2CS_SCRATCH),
+    ("NV_FUSE_OPT_PDI_0", NV_FUSE_OPT_PDI_0),
+    ("NV_FUSE_OPT_PDI_1", NV_FUSE_OPT_PDI_1),
+    ("NV_VF_CC_MODE", NV_VF_CC_MODE),
+    ("NV_VF_READY", NV_VF_READY),
+    ("NV_VF_PROTECTED_MEM_SIZE", NV_VF_PROTECTED_MEM_SIZE),
+    ("NV_FSP_QUEUE_HEAD", NV_FSP_QUEUE_HEAD),
+    ("NV_FSP_QUEUE_TAIL", NV_FSP_QUEUE_TAIL),
+    ("NV_FSP_MSGQ_HEAD", NV_FSP_MSGQ_HEAD),
+    ("NV_FSP_MSGQ_TAIL", NV_FSP_MSGQ_TAIL),
+    ("NV_FSP_SCRATCH_GROUP_2", NV_FSP_SCRATCH_GROUP_2),
+];
+
+/// The register names, by name and by offset; the first name of an offset is the one shown.
+#[derive(Debug, Default)]
+struct Registers {
+    by_name: BTreeMap<String, u64>,
+    by_offset: BTreeMap<u64, String>,
+}
+
+impl Registers {
+    fn define(&mut self, name: &str, offset: u64) {
+        let name = name.to_uppercase();
+        self.by_offset.entry(offset).or_insert_with(|| name.clone());
+        self.by_name.insert(name, offset);
+    }
+}
+
+fn registers() -> &'static RwLock<Registers> {
+    static REGISTERS: OnceLock<RwLock<Registers>> = OnceLock::new();
+
+    REGISTERS.get_or_init(|| {
+        let mut registers = Registers::default();
+        for (name, offset) in BUILTIN {
+            registers.define(name, *offset);
+        }
+
+        RwLock::new(registers)
+    })
+}
+
+/// Name a register, e.g., one of a newer chip that is not built in.
+pub fn define(name: &str, offset: u64) {
+    registers().write().unwrap().define(name, offset);
+}
+
+/// Load register names from a file of `<name> <offset>` lines; lines starting with `#` are
+/// comments.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<()> {
+    let content = std::fs::read_to_string(path.as_ref())?;
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = || {
+            NvTrustError::InvalidArgument(format!(
+                "{} line {}: expected '<name> <offset>'",
+                path.as_ref().display(),
+                i + 1
+            ))
+        };
+        let (name, offset) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+        define(name, parse_number(offset.trim()).ok_or_else(invalid)?);
+    }
+
+    Ok(())
+}
+
+/// Parse a decimal or 0x-prefixed hex number.
+pub fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Get the offset of a register given by name or as a number.
+pub fn resolve(register: &str) -> Result<u64> {
+    if let Some(offset) = parse_number(register) {
+        return Ok(offset);
+    }
+
+    registers()
+        .read()
+        .unwrap()
+        .by_name
+        .get(&register.to_uppercase())
+        .copied()
+        .ok_or_else(|| NvTrustError::InvalidArgument(format!("unknown register {register}")))
+}
+
+/// Get the name of the register at the offset, if it has one.
+pub fn name_of(offset: u64) -> Option<String> {
+    registers().read().unwrap().by_offset.get(&offset).cloned()
+}
+
+/// Show a register as `NAME (0x<offset>)`, or just the offset if it has no name.
+pub fn describe(offset: u64) -> String {
+    match name_of(offset) {
+        Some(name) => format!("{name} (0x{offset:x})"),
+        None => format!("0x{offset:x}"),
+    }
+}
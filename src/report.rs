@@ -0,0 +1,1070 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::json;
+
+use nvtrust::{
+    aer, arch::Arch, bits, dev, doctor, error::NvTrustError, fsp, fwlog, link, policy, rebar, regs,
+    spdm, topology, vbios,
+};
+
+use crate::{table, Format};
+
+pub fn print_fw_log(log: &fwlog::FwLogBuffer, elf: &fwlog::LogElf) {
+    let records = fwlog::decode_log(log, elf);
+    log::info!("{}: {} records", log.name, records.len());
+
+    for record in records {
+        log::info!(
+            "[{}] {}:{} {}",
+            record.time(),
+            record.file,
+            record.line,
+            record.message
+        );
+    }
+}
+
+/// Evaluate the claims against the policy, failing if any rule does not pass.
+pub fn appraise(policy: &policy::Policy, claims: &policy::Claims, explain: bool) -> Result<()> {
+    let results = policy.evaluate(claims);
+    let failed = results.iter().filter(|result| !result.passed).count();
+
+    for result in results.iter() {
+        if !explain && result.passed {
+            continue;
+        }
+
+        let actual = match &result.actual {
+            Some(actual) => format!("claim {} = {actual}", result.rule.claim),
+            None => format!("claim {} is missing", result.rule.claim),
+        };
+
+        if result.passed {
+            log::info!("PASS line {}: {} ({actual})", result.rule.line, result.rule);
+        } else {
+            log::error!("FAIL line {}: {} ({actual})", result.rule.line, result.rule);
+        }
+    }
+
+    if failed != 0 {
+        return Err(anyhow!("{failed} of {} rules failed", results.len()));
+    }
+
+    log::info!("All {} rules passed.", results.len());
+    Ok(())
+}
+
+/// Color a CC mode: green when on, yellow in DevTools mode and red otherwise.
+fn cc_mode_cell(mode: bits::CcMode) -> table::Cell {
+    let color = match mode {
+        bits::CcMode::CC_MODE_ON => table::Color::Green,
+        bits::CcMode::CC_MODE_DEV_TOOLS => table::Color::Yellow,
+        _ => table::Color::Red,
+    };
+
+    table::Cell::colored(mode, color)
+}
+
+/// An entry of `list-gpus --format json`.
+#[derive(Serialize)]
+struct GpuEntry {
+    /// The index for --gpu, none for NVSwitches.
+    index: Option<usize>,
+    bdf: String,
+    name: Option<String>,
+    device_id: u16,
+    function: &'static str,
+    driver: Option<String>,
+    cc_mode: Option<bits::CcMode>,
+}
+
+/// The output of `query-gpu-info --format json`.
+#[derive(Serialize)]
+struct GpuInfo<'a> {
+    bdf: &'a str,
+    name: Option<&'a str>,
+    architecture: Arch,
+    vendor_id: u16,
+    device_id: u16,
+    vbios_version: String,
+    board_id: Option<u16>,
+    cert_blocks: Vec<vbios::RomImage>,
+}
+
+pub fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+pub fn print_cc_mode(gpu: &dev::GpuObject, format: Format) -> Result<()> {
+    let cc_mode = gpu.query_cc_mode()?;
+    // Only the PF can read the knobs, which select the mode of the next reset.
+    let pending = if !gpu.is_vf() && gpu.get_arch().has_cc() {
+        Some(gpu.pending_cc_mode()?)
+    } else {
+        None
+    };
+    let reset_required = pending.is_some_and(|pending| pending != cc_mode);
+
+    match format {
+        Format::Table => {
+            log::info!("CC mode: {:?}", cc_mode);
+            if let Some(pending) = pending {
+                log::info!("Pending CC mode: {:?}", pending);
+            }
+            if reset_required {
+                log::warn!("The pending CC mode only becomes active after a reset, see reset-after-cc-mode-switch.");
+            }
+        }
+        Format::Json => print_json(&json!({
+            "bdf": gpu.get_bdf(),
+            "cc_mode": cc_mode,
+            "pending_cc_mode": pending,
+            "reset_required": reset_required,
+        }))?,
+    }
+
+    Ok(())
+}
+
+pub fn print_ppcie_mode(gpu: &dev::GpuObject, format: Format) -> Result<()> {
+    let current = gpu.query_ppcie_mode()?;
+    let pending = gpu.pending_ppcie_mode()?;
+
+    match format {
+        Format::Table => {
+            let on_off = |on: bool| if on { "on" } else { "off" };
+            log::info!("PPCIe mode: {}", on_off(current));
+            if pending != current {
+                log::warn!(
+                    "PPCIe mode {} takes effect upon the next reset.",
+                    on_off(pending)
+                );
+            }
+        }
+        Format::Json => print_json(&json!({
+            "bdf": gpu.get_bdf(),
+            "ppcie_mode": current,
+            "pending": pending,
+        }))?,
+    }
+
+    Ok(())
+}
+
+pub fn print_firewall(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let status = gpu.query_firewall()?;
+    if format == Format::Json {
+        return print_json(&json!({"bdf": gpu.get_bdf(), "firewall": status}));
+    }
+
+    let on_off = |on: bool| if on { "engaged" } else { "off" };
+    log::info!("BAR0 decoupler: {}", on_off(status.engaged));
+    match status.pending {
+        Some(pending) if pending != status.engaged => {
+            log::warn!("BAR0 decoupler {} upon the next reset.", on_off(pending))
+        }
+        _ => {}
+    }
+
+    let mut table = table::Table::new(&["window", "range", "access"]);
+    for window in status.windows.iter() {
+        table.push([
+            table::Cell::new(window.name),
+            table::Cell::new(format!("0x{:06x}-0x{:06x}", window.begin, window.end)),
+            match (window.accessible, window.error) {
+                (true, _) => table::Cell::colored("accessible", table::Color::Green),
+                (false, _) if status.engaged => {
+                    table::Cell::colored("blocked by the CC firewall", table::Color::Yellow)
+                }
+                (false, error) => table::Cell::colored(error.unwrap_or("error"), table::Color::Red),
+            },
+        ]);
+    }
+
+    table.print(color);
+    Ok(())
+}
+
+pub fn print_cc_settings(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let settings = gpu.query_cc_settings()?;
+
+    if format == Format::Json {
+        let knobs = settings
+            .iter()
+            .map(|(knob, value)| (knob.name(), value))
+            .collect::<BTreeMap<_, _>>();
+        return print_json(&json!({"bdf": gpu.get_bdf(), "settings": knobs}));
+    }
+
+    let mut table = table::Table::new(&["knob", "value"]);
+    for (knob, value) in settings {
+        table.push([format!("{knob:?}"), format!("0x{value:x}")]);
+    }
+
+    table.print(color);
+    Ok(())
+}
+
+pub fn print_prc_knobs(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let knobs = gpu.query_prc_knobs()?;
+    if format == Format::Json {
+        return print_json(&json!({"bdf": gpu.get_bdf(), "knobs": knobs}));
+    }
+
+    let value = |value: Option<u16>| value.map_or("-".to_string(), |value| format!("0x{value:x}"));
+    let mut table = table::Table::new(&["knob", "id", "current", "pending"]);
+    for state in knobs.iter() {
+        table.push([
+            table::Cell::new(state.knob.name()),
+            table::Cell::new((state.knob as u16).to_string()),
+            table::Cell::new(value(state.current)),
+            match (state.pending, state.needs_reset()) {
+                (None, _) => table::Cell::new("unsupported"),
+                (Some(_), true) => table::Cell::colored(value(state.pending), table::Color::Yellow),
+                (Some(_), false) => table::Cell::new(value(state.pending)),
+            },
+        ]);
+    }
+
+    table.print(color);
+    if knobs.iter().any(fsp::KnobState::needs_reset) {
+        log::warn!("Some knobs only take effect upon the next reset of the GPU.");
+    }
+    Ok(())
+}
+
+pub fn print_gpu_info(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let device = gpu.get_device_handle();
+    let vbios = gpu.dump_vbios()?;
+
+    if format == Format::Json {
+        return print_json(&GpuInfo {
+            bdf: gpu.get_bdf(),
+            name: device.get_product_name(),
+            architecture: gpu.get_arch(),
+            vendor_id: device.get_config().vendor,
+            device_id: device.get_config().device,
+            vbios_version: vbios.version()?,
+            board_id: vbios.board_id()?,
+            cert_blocks: vbios.cert_blocks(),
+        });
+    }
+
+    let mut table = table::Table::new(&["field", "value"]);
+    table.push(["name", device.get_product_name().unwrap_or("unknown")]);
+    table.push(["architecture".to_string(), gpu.get_arch().to_string()]);
+    table.push([
+        "device".to_string(),
+        format!(
+            "{:04x}:{:04x}",
+            device.get_config().vendor,
+            device.get_config().device
+        ),
+    ]);
+    table.push(["vbios version".to_string(), vbios.version()?]);
+    table.push([
+        "board id".to_string(),
+        match vbios.board_id()? {
+            Some(id) => format!("0x{id:04x}"),
+            None => "unknown".to_string(),
+        },
+    ]);
+    table.push([
+        "cert blocks".to_string(),
+        format!("{} found (not verified)", vbios.cert_blocks().len()),
+    ]);
+    for cert in vbios.cert_blocks() {
+        table.push([
+            "certificate block".to_string(),
+            format!("0x{:x} (0x{:x} bytes)", cert.offset, cert.len),
+        ]);
+    }
+
+    table.print(color);
+    Ok(())
+}
+
+pub fn print_config(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let dump = gpu.get_device_handle().dump_config()?;
+    if format == Format::Json {
+        return print_json(&dump);
+    }
+
+    if dump.size <= bits::PCI_CFG_SPACE_SIZE as usize {
+        log::warn!(
+            "Only the first {} bytes of the config space are readable",
+            dump.size
+        );
+    }
+
+    let mut table = table::Table::new(&["offset", "capability", "field", "value"]);
+    for field in dump.header.iter() {
+        table.push(["", "header", field.name, &field.value]);
+    }
+    for cap in dump.capabilities.iter() {
+        let kind = if cap.extended { "extended" } else { "legacy" };
+        let name = format!("{} ({kind} 0x{:02x})", cap.name, cap.id);
+        let offset = format!("0x{:03x}", cap.offset);
+        if cap.fields.is_empty() {
+            table.push([offset.as_str(), &name, "", ""]);
+        }
+        for (i, field) in cap.fields.iter().enumerate() {
+            match i {
+                0 => table.push([offset.as_str(), &name, field.name, &field.value]),
+                _ => table.push(["", "", field.name, &field.value]),
+            }
+        }
+    }
+
+    table.print(color);
+    Ok(())
+}
+
+pub fn print_aer(status: &aer::AerStatus, color: bool) {
+    let fatal = status.fatal();
+    let uncorrectable = aer::bit_names(&status.uncorrectable)
+        .into_iter()
+        .map(|name| match aer::bit_names(&fatal).contains(&name) {
+            true => format!("{name} (fatal)"),
+            false => name.to_string(),
+        })
+        .collect::<Vec<_>>();
+    let correctable = aer::bit_names(&status.correctable)
+        .into_iter()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    let errors = |value: u32, names: Vec<String>| match value {
+        0 => table::Cell::colored("none", table::Color::Green),
+        _ => table::Cell::colored(
+            format!("0x{value:08x} {}", names.join(" ")),
+            table::Color::Red,
+        ),
+    };
+
+    let mut table = table::Table::new(&["register", "value"]);
+    table.push([
+        table::Cell::from("uncorrectable status"),
+        errors(status.uncorrectable.bits(), uncorrectable),
+    ]);
+    table.push([
+        table::Cell::from("correctable status"),
+        errors(status.correctable.bits(), correctable),
+    ]);
+    table.push([
+        "uncorrectable mask".to_string(),
+        format!("0x{:08x}", status.uncorrectable_mask.bits()),
+    ]);
+    table.push([
+        "uncorrectable severity".to_string(),
+        format!("0x{:08x}", status.severity.bits()),
+    ]);
+    table.push([
+        "correctable mask".to_string(),
+        format!("0x{:08x}", status.correctable_mask.bits()),
+    ]);
+
+    table.print(color);
+}
+
+pub fn print_msi(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let device = gpu.get_device_handle();
+    let (msi, msix) = (device.msi()?, device.msix()?);
+    if format == Format::Json {
+        return print_json(&json!({"bdf": gpu.get_bdf(), "msi": msi, "msix": msix}));
+    }
+
+    let mut table = table::Table::new(&["field", "value"]);
+    match msi {
+        Some(msi) => {
+            table.push(["msi".to_string(), format!("enabled: {}", msi.enabled)]);
+            table.push([
+                "msi vectors".to_string(),
+                format!("{} of {}", msi.vectors_enabled, msi.vectors),
+            ]);
+            table.push([
+                "msi message".to_string(),
+                format!("0x{:x} data 0x{:04x}", msi.address, msi.data),
+            ]);
+            if let (Some(mask), Some(pending)) = (msi.mask, msi.pending) {
+                table.push([
+                    "msi mask".to_string(),
+                    format!("0x{mask:08x} pending 0x{pending:08x}"),
+                ]);
+            }
+        }
+        None => table.push(["msi", "not supported"]),
+    }
+
+    let Some(msix) = msix else {
+        table.push(["msi-x", "not supported"]);
+        table.print(color);
+        return Ok(());
+    };
+
+    table.push([
+        "msi-x".to_string(),
+        format!(
+            "enabled: {}, function mask: {}",
+            msix.enabled, msix.function_mask
+        ),
+    ]);
+    table.push([
+        "msi-x table".to_string(),
+        format!("BAR{} 0x{:x}", msix.table_bar, msix.table_offset),
+    ]);
+    table.push([
+        "msi-x pba".to_string(),
+        format!("BAR{} 0x{:x}", msix.pba_bar, msix.pba_offset),
+    ]);
+    table.print(color);
+
+    let mut table = table::Table::new(&["vector", "address", "data", "masked", "pending"]);
+    for vector in msix.vectors.iter() {
+        table.push([
+            table::Cell::new(vector.index),
+            table::Cell::new(format!("0x{:x}", vector.address)),
+            table::Cell::new(format!("0x{:08x}", vector.data)),
+            match vector.masked {
+                true => table::Cell::colored("yes", table::Color::Yellow),
+                false => table::Cell::new("no"),
+            },
+            match vector.pending {
+                true => table::Cell::colored("yes", table::Color::Red),
+                false => table::Cell::new("no"),
+            },
+        ]);
+    }
+
+    println!();
+    table.print(color);
+    Ok(())
+}
+
+pub fn print_link(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let link = gpu.get_device_handle().link_status()?;
+    if format == Format::Json {
+        return print_json(&json!({"bdf": gpu.get_bdf(), "link": link}));
+    }
+
+    let compare = |current: String, max: String, degraded: bool| match degraded {
+        true => table::Cell::colored(format!("{current} (max {max})"), table::Color::Yellow),
+        false => table::Cell::colored(format!("{current} (max {max})"), table::Color::Green),
+    };
+    let aspm = |supported: bool, enabled: bool| match (supported, enabled) {
+        (false, _) => "not supported",
+        (true, false) => "disabled",
+        (true, true) => "enabled",
+    };
+
+    let mut table = table::Table::new(&["field", "value"]);
+    table.push([
+        table::Cell::from("speed"),
+        compare(
+            link.speed.to_string(),
+            link.max_speed.to_string(),
+            link.speed < link.max_speed,
+        ),
+    ]);
+    table.push([
+        table::Cell::from("width"),
+        compare(
+            format!("x{}", link.width),
+            format!("x{}", link.max_width),
+            link.width < link.max_width,
+        ),
+    ]);
+    table.push(["aspm l0s", aspm(link.l0s_supported, link.l0s_enabled)]);
+    table.push(["aspm l1", aspm(link.l1_supported, link.l1_enabled)]);
+    table.push(["training", if link.training { "yes" } else { "no" }]);
+    table.print(color);
+
+    if link.is_degraded() {
+        log::warn!("The link of {} is degraded", gpu.get_label());
+    }
+    Ok(())
+}
+
+pub fn retrain_link(gpu: &dev::GpuObject, color: bool) -> Result<()> {
+    let port = gpu.get_device_handle().upstream_port()?.ok_or(anyhow!(
+        "{} has no upstream port to retrain the link from",
+        gpu.get_label()
+    ))?;
+    let device = gpu.get_device_handle().link_status()?;
+    let (before, after) = port.retrain_link()?;
+    let (before, after) = (before.limited_by(&device), after.limited_by(&device));
+
+    let mut table = table::Table::new(&["", "speed", "width"]);
+    for (name, link) in [("before", before), ("after", after)] {
+        let cell = |text: String| match link.is_degraded() {
+            true => table::Cell::colored(text, table::Color::Yellow),
+            false => table::Cell::colored(text, table::Color::Green),
+        };
+        table.push([
+            table::Cell::from(name),
+            cell(link.speed.to_string()),
+            cell(format!("x{}", link.width)),
+        ]);
+    }
+    table.push([
+        "max".to_string(),
+        after.max_speed.to_string(),
+        format!("x{}", after.max_width),
+    ]);
+    table.print(color);
+
+    if after.is_degraded() {
+        log::warn!(
+            "The link of {} is still degraded after retraining",
+            gpu.get_label()
+        );
+    }
+    Ok(())
+}
+
+pub fn print_boot_status(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let status = gpu.query_boot_status()?;
+    if format == Format::Json {
+        return print_json(&json!({"bdf": gpu.get_bdf(), "boot": status}));
+    }
+
+    let done = |done: bool| match done {
+        true => table::Cell::colored("done", table::Color::Green),
+        false => table::Cell::colored("not done", table::Color::Red),
+    };
+
+    let mut table = table::Table::new(&["stage", "value", "status"]);
+    table.push([
+        table::Cell::new("devinit (GFW boot progress)"),
+        table::Cell::new(format!("0x{:08x}", status.gfw_progress)),
+        done(status.devinit_done()),
+    ]);
+    if let (Some(value), Some(secure_boot)) = (status.fsp_status, status.secure_boot_done()) {
+        table.push([
+            table::Cell::new("FSP secure boot"),
+            table::Cell::new(format!("0x{value:08x}")),
+            done(secure_boot),
+        ]);
+        let errors = status
+            .fsp_errors
+            .iter()
+            .map(|error| format!("0x{error:08x}"));
+        table.push([
+            table::Cell::new("FSP error scratch"),
+            table::Cell::new(errors.collect::<Vec<_>>().join(" ")),
+            match status.fsp_failed() {
+                true => table::Cell::colored("error", table::Color::Red),
+                false => table::Cell::colored("none", table::Color::Green),
+            },
+        ]);
+    }
+    table.push([
+        table::Cell::new("boot complete"),
+        table::Cell::new(""),
+        done(status.complete),
+    ]);
+
+    table.print(color);
+    Ok(())
+}
+
+pub fn print_gsp(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let gsp = gpu.query_gsp()?;
+    if format == Format::Json {
+        return print_json(&json!({
+            "bdf": gpu.get_bdf(),
+            "gsp": gsp,
+            "running": gsp.falcon.is_running(),
+            "halted": gsp.falcon.is_halted(),
+            "loaded": gsp.is_loaded(),
+        }));
+    }
+
+    let state = match (gsp.falcon.is_running(), gsp.falcon.is_halted()) {
+        (true, _) => table::Cell::colored("running", table::Color::Green),
+        (false, true) => table::Cell::colored("halted", table::Color::Red),
+        (false, false) => table::Cell::colored("not started", table::Color::Yellow),
+    };
+    let mut table = table::Table::new(&["component", "value", "state"]);
+    table.push([
+        table::Cell::new("GSP core"),
+        table::Cell::new(format!(
+            "cpuctl 0x{:08x}, riscv 0x{:08x}",
+            gsp.falcon.cpuctl, gsp.falcon.riscv_cpuctl
+        )),
+        state,
+    ]);
+    table.push([
+        table::Cell::new("GSP-RM"),
+        table::Cell::new(format!("init args 0x{:x}", gsp.init_args)),
+        match gsp.is_loaded() {
+            true => table::Cell::colored("loaded", table::Color::Green),
+            false => table::Cell::new("not loaded"),
+        },
+    ]);
+    table.push([
+        table::Cell::new("firmware version"),
+        table::Cell::new(format!("0x{:08x}", gsp.falcon.os)),
+        table::Cell::new(""),
+    ]);
+
+    table.print(color);
+    Ok(())
+}
+
+pub fn print_rebar(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let bars = gpu.get_device_handle().resizable_bars()?;
+    if format == Format::Json {
+        return print_json(&json!({"bdf": gpu.get_bdf(), "resizable_bars": bars}));
+    }
+
+    let mut table = table::Table::new(&["bar", "size", "supported"]);
+    for bar in bars.iter() {
+        let supported = bar.supported.iter().map(|size| rebar::format_size(*size));
+        table.push([
+            format!("BAR{}", bar.bar),
+            rebar::format_size(bar.size),
+            supported.collect::<Vec<_>>().join(" "),
+        ]);
+    }
+
+    table.print(color);
+    Ok(())
+}
+
+pub fn print_topology(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let ports = gpu.get_device_handle().topology()?;
+    if format == Format::Json {
+        return print_json(&json!({"bdf": gpu.get_bdf(), "ports": ports}));
+    }
+
+    let mut table = table::Table::new(&["port", "device", "type", "link"]);
+    let link = gpu.get_device_handle().link_status().ok();
+    table.push([
+        table::Cell::new(gpu.get_bdf()),
+        table::Cell::new(gpu.get_label()),
+        table::Cell::new("endpoint"),
+        link_cell(link.as_ref()),
+    ]);
+    for port in ports.iter() {
+        table.push([
+            table::Cell::new(&port.bdf),
+            table::Cell::new(format!("{:04x}:{:04x}", port.vendor, port.device)),
+            table::Cell::new(&port.kind),
+            link_cell(port.link.as_ref()),
+        ]);
+    }
+
+    table.print(color);
+    Ok(())
+}
+
+pub fn print_tree(format: Format, color: bool) -> Result<()> {
+    let roots = topology::tree()?;
+    if format == Format::Json {
+        return print_json(&json!({ "roots": roots }));
+    }
+    if roots.is_empty() {
+        return Err(NvTrustError::DeviceNotFound("No GPU found".to_string()).into());
+    }
+
+    fn push(table: &mut table::Table, node: &topology::Node, prefix: &str, last: Option<bool>) {
+        let (branch, indent) = match last {
+            None => ("", ""),
+            Some(false) => ("├─ ", "│  "),
+            Some(true) => ("└─ ", "   "),
+        };
+        let port = &node.port;
+        let acs = match (port.needs_acs(), port.is_insecure()) {
+            (false, _) => table::Cell::new("-"),
+            (true, false) => table::Cell::colored("on", table::Color::Green),
+            (true, true) => table::Cell::colored("off", table::Color::Red),
+        };
+
+        table.push([
+            table::Cell::new(format!("{prefix}{branch}{}", port.bdf)),
+            table::Cell::new(&port.kind),
+            table::Cell::new(format!(
+                "{:04x}:{:04x}{}",
+                port.vendor,
+                port.device,
+                node.name
+                    .map(|name| format!(" ({name})"))
+                    .unwrap_or_default()
+            )),
+            link_cell(port.link.as_ref()),
+            acs,
+            table::Cell::new(if port.ide { "yes" } else { "no" }),
+        ]);
+
+        let prefix = format!("{prefix}{indent}");
+        for (i, child) in node.children.iter().enumerate() {
+            push(table, child, &prefix, Some(i + 1 == node.children.len()));
+        }
+    }
+
+    let mut table = table::Table::new(&["device", "type", "id", "link", "acs", "ide"]);
+    for root in roots.iter() {
+        push(&mut table, root, "", None);
+    }
+
+    table.print(color);
+    Ok(())
+}
+
+fn link_cell(link: Option<&link::LinkStatus>) -> table::Cell {
+    match link {
+        Some(link) => table::Cell::colored(
+            format!("{} x{}", link.speed, link.width),
+            match link.is_degraded() {
+                true => table::Color::Yellow,
+                false => table::Color::Green,
+            },
+        ),
+        None => table::Cell::new("-"),
+    }
+}
+
+pub fn check_acs(gpu: &dev::GpuObject, color: bool) -> Result<()> {
+    let ports = gpu.get_device_handle().topology()?;
+    let mut table = table::Table::new(&["port", "type", "acs", "status"]);
+    for port in ports.iter() {
+        let status = match (port.needs_acs(), port.is_insecure()) {
+            (false, _) => table::Cell::new("not needed"),
+            (true, false) => table::Cell::colored("isolated", table::Color::Green),
+            (true, true) => table::Cell::colored(
+                match port.acs {
+                    Some(_) => format!("no {}", port.missing_acs().join(", ")),
+                    None => "no ACS".to_string(),
+                },
+                table::Color::Red,
+            ),
+        };
+        table.push([
+            table::Cell::new(&port.bdf),
+            table::Cell::new(&port.kind),
+            table::Cell::new(
+                port.acs
+                    .map_or("-".to_string(), |acs| format!("0x{acs:04x}")),
+            ),
+            status,
+        ]);
+    }
+    table.print(color);
+
+    let insecure = ports.iter().filter(|port| port.is_insecure()).count();
+    if insecure > 0 {
+        return Err(anyhow!(
+            "{insecure} ports above {} let its peer-to-peer traffic bypass the IOMMU; enable ACS in the BIOS",
+            gpu.get_bdf()
+        ));
+    }
+
+    log::info!("Every port above {} isolates it.", gpu.get_bdf());
+    Ok(())
+}
+
+pub fn print_iommu(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let group = gpu.get_device_handle().iommu_group()?;
+    if format == Format::Json {
+        return print_json(&json!({
+            "bdf": gpu.get_bdf(),
+            "group": group.group,
+            "type": group.kind,
+            "viable": group.is_viable(),
+            "devices": group.devices,
+        }));
+    }
+
+    log::info!(
+        "IOMMU group {} ({})",
+        group.group,
+        group.kind.as_deref().unwrap_or("unknown type")
+    );
+    let mut table = table::Table::new(&["bdf", "device", "class", "driver", "viable"]);
+    for device in group.devices.iter() {
+        table.push([
+            table::Cell::new(&device.bdf),
+            table::Cell::new(format!("{:04x}:{:04x}", device.vendor, device.device)),
+            table::Cell::new(format!("{:06x}", device.class)),
+            table::Cell::new(device.driver.as_deref().unwrap_or("none")),
+            match device.viable {
+                true => table::Cell::colored("yes", table::Color::Green),
+                false => table::Cell::colored("no", table::Color::Red),
+            },
+        ]);
+    }
+    table.print(color);
+
+    match group.is_viable() {
+        true => log::info!("The group is viable for vfio."),
+        false => log::warn!(
+            "The group is not viable for vfio; bind vfio-pci to the other devices in it, or enable ACS to split it."
+        ),
+    }
+    Ok(())
+}
+
+/// Print the register, and then every change of it with the time since the start, until it
+/// meets the condition, if any.
+pub fn watch_register(
+    gpu: &dev::GpuObject,
+    register: u64,
+    interval: std::time::Duration,
+    until: Option<(u32, u32)>,
+) -> Result<()> {
+    let name = regs::describe(register);
+    let start = std::time::Instant::now();
+    let mut last = None;
+
+    loop {
+        let val = gpu.read32(register)?;
+        let elapsed = start.elapsed().as_secs_f64();
+        match last {
+            None => println!("[{elapsed:10.3}s] {name} = 0x{val:08x}"),
+            Some(last) if last != val => {
+                println!("[{elapsed:10.3}s] {name} = 0x{val:08x} (was 0x{last:08x})")
+            }
+            Some(_) => {}
+        }
+        last = Some(val);
+
+        if let Some((mask, value)) = until {
+            if val & mask == value {
+                log::info!("{name} & 0x{mask:x} reads 0x{value:x} after {elapsed:.3}s.");
+                return Ok(());
+            }
+        }
+
+        std::thread::sleep(interval);
+    }
+}
+
+pub fn print_spdm_info(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let device = gpu.get_device_handle();
+    let mut requester = spdm::SpdmRequester::new(device.doe()?);
+    requester.init()?;
+
+    let algorithms = requester.algorithms();
+    let digests = requester.get_digests()?;
+    if format == Format::Json {
+        return print_json(&json!({
+            "bdf": gpu.get_bdf(),
+            "version": format!("{}.{}", requester.version() >> 4, requester.version() & 0xf),
+            "capabilities": requester.capabilities(),
+            "algorithms": algorithms,
+            "digests": digests
+                .iter()
+                .map(|(slot, digest)| (slot.to_string(), spdm::to_hex(digest)))
+                .collect::<BTreeMap<_, _>>(),
+        }));
+    }
+
+    let mut table = table::Table::new(&["field", "value"]);
+    table.push([
+        "version".to_string(),
+        format!("{}.{}", requester.version() >> 4, requester.version() & 0xf),
+    ]);
+    table.push([
+        "capabilities".to_string(),
+        format!("0x{:08x}", requester.capabilities()),
+    ]);
+    table.push([
+        "base asym".to_string(),
+        format!("0x{:08x}", algorithms.base_asym),
+    ]);
+    table.push([
+        "base hash".to_string(),
+        format!("0x{:08x}", algorithms.base_hash),
+    ]);
+    for (slot, digest) in digests {
+        table.push([format!("slot {slot} digest"), spdm::to_hex(&digest)]);
+    }
+
+    table.print(color);
+    Ok(())
+}
+
+pub fn print_scratch(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let scratch = gpu.dump_scratch()?;
+
+    if format == Format::Json {
+        let registers = scratch
+            .iter()
+            .map(|scratch| {
+                json!({
+                    "group": scratch.group.name,
+                    "index": scratch.index,
+                    "offset": scratch.offset,
+                    "value": scratch.value,
+                    "blocked": scratch.error.is_some(),
+                    "error": scratch.error,
+                })
+            })
+            .collect::<Vec<_>>();
+        return print_json(&json!({"bdf": gpu.get_bdf(), "scratch": registers}));
+    }
+
+    let mut table = table::Table::new(&["register", "offset", "value", "description"]);
+    for scratch in scratch {
+        let value = match (scratch.value, scratch.error) {
+            (Some(value), _) => table::Cell::new(format!("0x{value:08x}")),
+            (None, error) => table::Cell::colored(
+                format!("blocked ({})", error.unwrap_or("unknown")),
+                table::Color::Red,
+            ),
+        };
+        table.push([
+            table::Cell::new(format!("{}({})", scratch.group.name, scratch.index)),
+            table::Cell::new(format!("0x{:06x}", scratch.offset)),
+            value,
+            table::Cell::new(scratch.group.description),
+        ]);
+    }
+
+    table.print(color);
+    Ok(())
+}
+
+pub fn print_vgpu(gpu: &dev::GpuObject, format: Format, color: bool) -> Result<()> {
+    let state = gpu.query_vgpu_state()?;
+    let entries = state.attestation_entry_points();
+
+    if format == Format::Json {
+        return print_json(&json!({
+            "bdf": gpu.get_bdf(),
+            "ready": state.ready,
+            "cc_mode": state.cc_mode,
+            "protected_mem_mb": state.protected_mem_mb,
+            "doe_offset": state.doe_offset,
+            "driver": state.driver,
+            "attestation": entries,
+        }));
+    }
+
+    if entries.is_empty() {
+        log::warn!("No attestation entry point available.");
+    }
+    let mut table = table::Table::new(&["field", "value"]);
+    table.push(["ready".to_string(), state.ready.to_string()]);
+    table.push(["cc mode".to_string(), state.cc_mode.to_string()]);
+    table.push([
+        "protected memory".to_string(),
+        format!("{} MB", state.protected_mem_mb),
+    ]);
+    for entry in entries {
+        table.push(["attestation".to_string(), entry]);
+    }
+
+    table.print(color);
+    Ok(())
+}
+
+pub fn host_check(color: bool) -> Result<()> {
+    let checks = doctor::check_host_readiness();
+    let mut table = table::Table::new(&["check", "status", "detail", "fix"]);
+
+    for check in &checks {
+        let status_color = match check.status {
+            doctor::Status::Pass => table::Color::Green,
+            doctor::Status::Warn => table::Color::Yellow,
+            doctor::Status::Fail => table::Color::Red,
+        };
+        table.push([
+            table::Cell::new(check.name),
+            table::Cell::colored(check.status, status_color),
+            table::Cell::new(&check.detail),
+            table::Cell::new(check.fix),
+        ]);
+    }
+    table.print(color);
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == doctor::Status::Fail)
+        .count();
+    if failed > 0 {
+        return Err(anyhow!("{failed} host checks failed"));
+    }
+
+    Ok(())
+}
+
+pub fn list_gpus(format: Format, color: bool) -> Result<()> {
+    let mut table = table::Table::new(&[
+        "index", "bdf", "name", "device", "function", "driver", "cc mode",
+    ]);
+    let mut json = vec![];
+
+    // The NVSwitches go last, as they cannot be selected by --gpu.
+    let (gpus, nvswitches): (Vec<_>, Vec<_>) = dev::list_sysfs_devices()?
+        .into_iter()
+        .partition(|device| !device.is_nvswitch());
+    let devices = gpus
+        .into_iter()
+        .enumerate()
+        .map(|(i, device)| (Some(i), device))
+        .chain(nvswitches.into_iter().map(|device| (None, device)))
+        .collect::<Vec<_>>();
+
+    // The devices bound to a GPU driver are listed from sysfs alone, without touching BAR0.
+    let opened = dev::par_map(&devices, |(_, device)| {
+        dev::check_driver(&device.path).ok()?;
+        Some(dev::open_gpu(&device.path).and_then(|gpu| Ok((gpu.is_vf(), gpu.query_cc_mode()?))))
+    });
+
+    for ((index, device), opened) in devices.into_iter().zip(opened) {
+        if let Some(Err(e)) = &opened {
+            log::warn!("Cannot query the CC mode of {}: {e}", device.bdf());
+        }
+        let is_vf = match &opened {
+            Some(Ok((is_vf, _))) => *is_vf,
+            _ => device.is_vf(),
+        };
+        let function = match (device.is_nvswitch(), is_vf) {
+            (true, _) => "NVSwitch",
+            (false, true) => "VF",
+            (false, false) => "PF",
+        };
+
+        json.push(GpuEntry {
+            index,
+            bdf: device.bdf().to_string(),
+            name: device.name().map(str::to_string),
+            device_id: device.device_id,
+            function,
+            driver: device.driver.clone(),
+            cc_mode: opened
+                .as_ref()
+                .and_then(|opened| opened.as_ref().ok())
+                .map(|(_, mode)| *mode),
+        });
+        let cc_mode = match opened {
+            Some(Ok((_, mode))) => cc_mode_cell(mode),
+            Some(Err(_)) => table::Cell::colored("error", table::Color::Red),
+            None => table::Cell::new("-"),
+        };
+
+        table.push([
+            table::Cell::new(index.map_or("-".to_string(), |i| i.to_string())),
+            table::Cell::new(device.bdf()),
+            table::Cell::new(device.name().unwrap_or("unknown")),
+            table::Cell::new(format!("{:04x}", device.device_id)),
+            table::Cell::new(function),
+            table::Cell::new(device.driver.as_deref().unwrap_or("none")),
+            cc_mode,
+        ]);
+    }
+
+    if table.is_empty() {
+        return Err(NvTrustError::DeviceNotFound("No GPU found".to_string()).into());
+    }
+
+    match format {
+        Format::Table => table.print(color),
+        Format::Json => print_json(&json!({ "gpus": json }))?,
+    }
+    Ok(())
+}
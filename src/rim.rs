@@ -0,0 +1,248 @@
+use std::{fs, path::PathBuf};
+
+use base64::Engine;
+use ring::digest;
+
+use serde::Deserialize;
+
+use crate::{
+    bits::*,
+    corim,
+    error::{NvTrustError, Result},
+    evidence::Evidence,
+    nras::http_agent,
+    spdm::to_hex,
+    verifier::ReferenceValues,
+    xmldsig,
+};
+
+/// The IDs of the RIMs matching the firmware that produced the evidence, as named by the NVIDIA
+/// RIM service.
+#[derive(Debug, Clone, Default)]
+pub struct RimIds {
+    pub driver: Option<String>,
+    pub vbios: Option<String>,
+}
+
+impl RimIds {
+    /// The IDs for the firmware versions reported in the opaque data of the evidence.
+    pub fn from_evidence(evidence: &Evidence) -> Self {
+        let chip = evidence.arch.to_uppercase();
+        let driver = evidence
+            .opaque_string(NV_OPAQUE_FIELD_DRIVER_VERSION)
+            .map(|version| format!("NV_GPU_DRIVER_{}_{version}", chip_name(&chip)));
+
+        let vbios = (|| {
+            let version = evidence.opaque_field(NV_OPAQUE_FIELD_VBIOS_VERSION)?;
+            if version.len() < 5 {
+                return None;
+            }
+            let project = evidence.opaque_string(NV_OPAQUE_FIELD_PROJECT)?;
+            let project_sku = evidence.opaque_string(NV_OPAQUE_FIELD_PROJECT_SKU)?;
+            let chip_sku = evidence.opaque_string(NV_OPAQUE_FIELD_CHIP_SKU)?;
+
+            // The same layout as the version in the BIT of the VBIOS.
+            Some(format!(
+                "NV_GPU_VBIOS_{project}_{project_sku}_{chip_sku}_{:02X}{:02X}{:02X}{:02X}{:02X}",
+                version[3], version[2], version[1], version[0], version[4]
+            ))
+        })();
+
+        Self { driver, vbios }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.driver.iter().chain(self.vbios.iter())
+    }
+}
+
+fn chip_name(arch: &str) -> &'static str {
+    match arch {
+        "BLACKWELL" => "GB100",
+        _ => "GH100",
+    }
+}
+
+/// The forms NVIDIA publishes RIMs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RimFormat {
+    /// A SWID tag (XML) with an enveloped XML signature.
+    Swid,
+    /// A CoRIM (CBOR) signed as COSE_Sign1.
+    Corim,
+}
+
+impl RimFormat {
+    const ALL: [Self; 2] = [Self::Swid, Self::Corim];
+
+    /// Tell the form of a RIM from its content.
+    pub fn detect(rim: &[u8]) -> Self {
+        match std::str::from_utf8(rim) {
+            Ok(xml) if xml.trim_start().starts_with('<') => Self::Swid,
+            _ => Self::Corim,
+        }
+    }
+
+    /// The extension of the RIMs of this form in the cache.
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Swid => "xml",
+            Self::Corim => "corim",
+        }
+    }
+
+    /// Verify the signature of a RIM of this form, see [`verify_swid`] and [`verify_corim`].
+    pub fn verify(self, rim: &[u8], root_ca: &[u8]) -> Result<ReferenceValues> {
+        match self {
+            Self::Swid => verify_swid(
+                std::str::from_utf8(rim)
+                    .map_err(|_| NvTrustError::Rim("the SWID tag is not text".to_string()))?,
+                root_ca,
+            ),
+            Self::Corim => verify_corim(rim, root_ca),
+        }
+    }
+}
+
+/// The local RIM cache: the RIMs downloaded from the RIM service, one `<id>.xml` or `<id>.corim`
+/// per RIM, as it was served.
+///
+/// The signature of each RIM is verified both when it is stored and when it is loaded, see
+/// [`RimFormat::verify`].
+pub struct RimCache {
+    dir: PathBuf,
+    root_ca: Vec<u8>,
+}
+
+impl RimCache {
+    /// A cache in `dir` whose RIMs must carry signing certificates issued by `root_ca` (DER).
+    pub fn new(dir: &str, root_ca: &[u8]) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+            root_ca: root_ca.to_vec(),
+        }
+    }
+
+    fn path(&self, id: &str, format: RimFormat) -> Result<PathBuf> {
+        if id.is_empty() || id.contains('/') || id.contains("..") {
+            return Err(NvTrustError::Rim(format!("invalid RIM ID {id}")));
+        }
+
+        Ok(self.dir.join(format!("{id}.{}", format.extension())))
+    }
+
+    /// The cached RIM of the given ID and its form, if any.
+    fn find(&self, id: &str) -> Result<Option<(PathBuf, RimFormat)>> {
+        for format in RimFormat::ALL {
+            let path = self.path(id, format)?;
+            if path.exists() {
+                return Ok(Some((path, format)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.find(id).is_ok_and(|found| found.is_some())
+    }
+
+    /// Download the RIM of the given ID into the cache, checking it first.
+    pub fn fetch(&self, url: &str, proxy: Option<&str>, id: &str) -> Result<()> {
+        // Checks the ID before it goes into the URL.
+        self.path(id, RimFormat::Swid)?;
+        let url = format!("{}/{id}", url.trim_end_matches('/'));
+
+        log::debug!("Fetching {url}");
+        let response = match http_agent(proxy)?.get(&url).call() {
+            Ok(response) => response.into_string()?,
+            Err(ureq::Error::Status(404, _)) => {
+                return Err(NvTrustError::Http(format!(
+                    "the RIM service has no RIM {id}"
+                )))
+            }
+            Err(e) => return Err(NvTrustError::Http(format!("cannot fetch {url}: {e}"))),
+        };
+
+        let response: RimResponse = serde_json::from_str(&response)
+            .map_err(|e| NvTrustError::Rim(format!("invalid response for {id}: {e}")))?;
+        let rim = base64::engine::general_purpose::STANDARD
+            .decode(response.rim)
+            .map_err(|e| NvTrustError::Rim(format!("invalid RIM encoding: {e}")))?;
+        if let Some(sha256) = response.sha256 {
+            let actual = to_hex(digest::digest(&digest::SHA256, &rim).as_ref());
+            if !actual.eq_ignore_ascii_case(&sha256) {
+                return Err(NvTrustError::Rim(format!(
+                    "the SHA-256 of {id} is {actual}, expected {sha256}"
+                )));
+            }
+        }
+
+        let format = RimFormat::detect(&rim);
+        format
+            .verify(&rim, &self.root_ca)
+            .map_err(|e| NvTrustError::Rim(format!("RIM {id}: {e}")))?;
+
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(id, format)?;
+        fs::write(&path, rim)?;
+        // A RIM of the other form would shadow the new one.
+        for other in RimFormat::ALL.into_iter().filter(|other| *other != format) {
+            let stale = self.path(id, other)?;
+            if stale.exists() {
+                fs::remove_file(stale)?;
+            }
+        }
+        log::info!("RIM {id} written to {}.", path.display());
+
+        Ok(())
+    }
+
+    /// Load the RIM of the given ID from the cache, checking it first.
+    pub fn load(&self, id: &str) -> Result<ReferenceValues> {
+        let (path, format) = self.find(id)?.ok_or_else(|| {
+            NvTrustError::Rim(format!("RIM {id} is not cached in {}", self.dir.display()))
+        })?;
+        let rim = fs::read(&path).map_err(|e| {
+            NvTrustError::Rim(format!("cannot read RIM {id} from {}: {e}", path.display()))
+        })?;
+
+        format
+            .verify(&rim, &self.root_ca)
+            .map_err(|e| NvTrustError::Rim(format!("RIM {id}: {e}")))
+    }
+}
+
+/// The answer of the RIM service: the base64 RIM and its SHA-256.
+#[derive(Deserialize)]
+struct RimResponse {
+    rim: String,
+    sha256: Option<String>,
+}
+
+/// Verify the XML signature of a SWID RIM against the pinned root of the RIM signers (DER), and
+/// read the reference values from what it signs.
+pub fn verify_swid(xml: &str, root_ca: &[u8]) -> Result<ReferenceValues> {
+    let signed = xmldsig::verify(xml, root_ca)?;
+
+    Ok(ReferenceValues {
+        signature_verified: true,
+        ..ReferenceValues::from_swid(&signed)?
+    })
+}
+
+/// Verify the COSE signature of a CoRIM against the pinned root of the RIM signers (DER), and read
+/// the reference values from what it signs.
+pub fn verify_corim(rim: &[u8], root_ca: &[u8]) -> Result<ReferenceValues> {
+    let signed = corim::verify(rim, root_ca)?;
+
+    Ok(ReferenceValues {
+        signature_verified: true,
+        ..ReferenceValues::from_corim(&signed)?
+    })
+}
+
+/// Read the reference values of a RIM file in either form, verifying its signature.
+pub fn load_rim(rim: &[u8], root_ca: &[u8]) -> Result<ReferenceValues> {
+    RimFormat::detect(rim).verify(rim, root_ca)
+}
@@ -0,0 +1,95 @@
+use crate::{
+    bits::*,
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+};
+
+/// A group of scratch registers used to hand off state between the firmware and the drivers.
+#[derive(Debug, Clone, Copy)]
+pub struct ScratchGroup {
+    /// The name of the group.
+    pub name: &'static str,
+    /// The offset of the first register of the group in BAR0.
+    pub base: u64,
+    /// The number of registers in the group.
+    pub count: u64,
+    /// What the group is used for.
+    pub description: &'static str,
+}
+
+/// The scratch register groups relevant to CC.
+pub const SCRATCH_GROUPS: &[ScratchGroup] = &[
+    ScratchGroup {
+        name: "AON_SECURE_SCRATCH_GROUP_05",
+        base: NV_PGC6_AON_SECURE_SCRATCH_GROUP_05,
+        count: 4,
+        description: "GFW boot progress",
+    },
+    ScratchGroup {
+        name: "AON_SECURE_SCRATCH_GROUP_20",
+        base: NV_PGC6_AON_SECURE_SCRATCH_GROUP_20,
+        count: 1,
+        description: "CC mode handed off by the FSP",
+    },
+    ScratchGroup {
+        name: "THERM_I2CS_SCRATCH",
+        base: NV_THERM_I2CS_SCRATCH,
+        count: 1,
+        description: "FSP boot status",
+    },
+    ScratchGroup {
+        name: "FSP_SCRATCH_GROUP_2",
+        base: NV_FSP_SCRATCH_GROUP_2,
+        count: NV_FSP_SCRATCH_GROUP_2_LEN,
+        description: "FSP error and status codes",
+    },
+    ScratchGroup {
+        name: "PBUS_SW_SCRATCH",
+        base: NV_PBUS_SW_SCRATCH,
+        count: 32,
+        description: "VBIOS and driver software scratch",
+    },
+];
+
+/// The value of a single scratch register.
+#[derive(Debug, Clone)]
+pub struct ScratchValue {
+    pub group: &'static ScratchGroup,
+    pub index: u64,
+    pub offset: u64,
+    /// The value, unless the read returned an error code instead.
+    pub value: Option<u32>,
+    /// What the read returned instead of a value, see [`NvidiaMmioErrorCode`].
+    pub error: Option<&'static str>,
+}
+
+impl GpuObject {
+    /// Read all the scratch register groups.
+    pub fn dump_scratch(&self) -> Result<Vec<ScratchValue>> {
+        self.ensure_pf("Secure scratch access")?;
+
+        let mut values = vec![];
+
+        for group in SCRATCH_GROUPS {
+            for index in 0..group.count {
+                let offset = group.base + index * 4;
+                // A register blocked by the PRI firewall is reported, not fatal.
+                let (value, error) = match self.checked_read32(offset) {
+                    Ok(value) => (Some(value), None),
+                    Err(NvTrustError::MmioError { cause, .. }) => (None, Some(cause)),
+                    Err(e) => return Err(e),
+                };
+
+                values.push(ScratchValue {
+                    group,
+                    index,
+                    offset,
+                    value,
+                    error,
+                });
+            }
+        }
+
+        Ok(values)
+    }
+}
@@ -0,0 +1,273 @@
+use std::{fmt, time::Duration};
+
+use crate::{
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+    regs,
+};
+
+/// A single step of a register script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Read a register and report its value.
+    Read {
+        offset: u64,
+    },
+    Write {
+        offset: u64,
+        value: u32,
+    },
+    /// Wait until the masked register reads the value, failing after `timeout` seconds.
+    Poll {
+        offset: u64,
+        value: u32,
+        mask: u32,
+        timeout: u64,
+    },
+    Sleep(Duration),
+    /// Fail unless the masked register reads the value.
+    Assert {
+        offset: u64,
+        value: u32,
+        mask: u32,
+    },
+}
+
+/// A step together with where it is in the script.
+#[derive(Debug, Clone)]
+pub struct Command {
+    pub line: usize,
+    pub step: Step,
+}
+
+/// A register script, e.g., a bring-up or CC-enable sequence.
+///
+/// The script has one step per line; lines starting with `#` are comments. Numbers are decimal
+/// or 0x-prefixed hex, and the offsets are in BAR0, or the names of [`crate::regs`]:
+///
+/// ```text
+/// read NV_PMC_BOOT_0
+/// poll NV_THERM_I2CS_SCRATCH 0xff mask 0xff timeout 5   # the FSP has booted
+/// write NV_HOST_MEM 0x1e0000                            # move the PRAMIN window
+/// sleep 100                                             # milliseconds
+/// assert NV_CC_MODE 0x1 mask 0x3                        # CC mode on
+/// ```
+///
+/// The mask of `poll` and `assert` defaults to 0xffffffff and the timeout of `poll` to 5s.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    pub commands: Vec<Command>,
+}
+
+/// The outcome of a step.
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    pub command: Command,
+    pub passed: bool,
+    /// What the step read or why it failed.
+    pub detail: String,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Read { offset } => write!(f, "read {}", regs::describe(*offset)),
+            Step::Write { offset, value } => {
+                write!(f, "write {} 0x{value:x}", regs::describe(*offset))
+            }
+            Step::Poll {
+                offset,
+                value,
+                mask,
+                timeout,
+            } => write!(
+                f,
+                "poll {} 0x{value:x} mask 0x{mask:x} timeout {timeout}",
+                regs::describe(*offset)
+            ),
+            Step::Sleep(duration) => write!(f, "sleep {}", duration.as_millis()),
+            Step::Assert {
+                offset,
+                value,
+                mask,
+            } => write!(
+                f,
+                "assert {} 0x{value:x} mask 0x{mask:x}",
+                regs::describe(*offset)
+            ),
+        }
+    }
+}
+
+fn number(line: usize, s: Option<&str>) -> Result<u64> {
+    let s = s.ok_or(NvTrustError::Script(format!(
+        "line {line}: missing argument"
+    )))?;
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| NvTrustError::Script(format!("line {line}: invalid number {s:?}")))
+}
+
+impl Step {
+    fn parse(line: usize, text: &str) -> Result<Self> {
+        let mut words = text.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let offset = |words: &mut std::str::SplitWhitespace| {
+            let word = words.next().ok_or(NvTrustError::Script(format!(
+                "line {line}: missing register"
+            )))?;
+            regs::resolve(word).map_err(|e| NvTrustError::Script(format!("line {line}: {e}")))
+        };
+        let dword = |words: &mut std::str::SplitWhitespace| {
+            u32::try_from(number(line, words.next())?).map_err(|_| {
+                NvTrustError::Script(format!("line {line}: the value does not fit in 32 bits"))
+            })
+        };
+
+        let step = match command {
+            "read" => Step::Read {
+                offset: offset(&mut words)?,
+            },
+            "write" => Step::Write {
+                offset: offset(&mut words)?,
+                value: dword(&mut words)?,
+            },
+            "sleep" => Step::Sleep(Duration::from_millis(number(line, words.next())?)),
+            "poll" | "assert" => {
+                let offset = offset(&mut words)?;
+                let value = dword(&mut words)?;
+                let (mut mask, mut timeout) = (u32::MAX, 5);
+                while let Some(option) = words.next() {
+                    match option {
+                        "mask" => mask = dword(&mut words)?,
+                        "timeout" if command == "poll" => timeout = number(line, words.next())?,
+                        _ => {
+                            return Err(NvTrustError::Script(format!(
+                                "line {line}: unknown option {option:?}"
+                            )))
+                        }
+                    }
+                }
+
+                match command {
+                    "poll" => Step::Poll {
+                        offset,
+                        value,
+                        mask,
+                        timeout,
+                    },
+                    _ => Step::Assert {
+                        offset,
+                        value,
+                        mask,
+                    },
+                }
+            }
+            _ => {
+                return Err(NvTrustError::Script(format!(
+                    "line {line}: unknown command {command:?}"
+                )))
+            }
+        };
+
+        if let Some(extra) = words.next() {
+            return Err(NvTrustError::Script(format!(
+                "line {line}: unexpected {extra:?}"
+            )));
+        }
+
+        Ok(step)
+    }
+}
+
+impl Script {
+    pub fn parse(content: &str) -> Result<Self> {
+        let commands = content
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or_default().trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(i, line)| {
+                Ok(Command {
+                    line: i,
+                    step: Step::parse(i, line)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { commands })
+    }
+
+    /// Whether the script writes to the GPU.
+    pub fn writes(&self) -> bool {
+        self.commands
+            .iter()
+            .any(|command| matches!(command.step, Step::Write { .. }))
+    }
+
+    /// Run the script on the GPU. Failed polls and asserts do not stop it, but a register that
+    /// cannot be accessed does, since the rest of the sequence would make no sense.
+    pub fn run(&self, gpu: &GpuObject) -> Vec<StepResult> {
+        let mut results = vec![];
+
+        for command in self.commands.iter() {
+            let outcome = match command.step {
+                Step::Read { offset } => {
+                    gpu.read32(offset).map(|value| Ok(format!("0x{value:08x}")))
+                }
+                Step::Write { offset, value } => {
+                    gpu.write32(offset, value).map(|_| Ok(String::new()))
+                }
+                Step::Poll {
+                    offset,
+                    value,
+                    mask,
+                    timeout,
+                } => {
+                    let name = format!("0x{offset:x} to read 0x{value:x}");
+                    match gpu.poll_register(&name, offset, value, timeout, 0.01, mask) {
+                        Ok(()) => Ok(Ok(String::new())),
+                        Err(NvTrustError::Timeout(_)) => gpu
+                            .read32(offset)
+                            .map(|actual| Err(format!("timed out at 0x{actual:08x}"))),
+                        Err(e) => Err(e),
+                    }
+                }
+                Step::Sleep(duration) => {
+                    std::thread::sleep(duration);
+                    Ok(Ok(String::new()))
+                }
+                Step::Assert {
+                    offset,
+                    value,
+                    mask,
+                } => gpu
+                    .read32(offset)
+                    .map(|actual| match actual & mask == value {
+                        true => Ok(format!("0x{actual:08x}")),
+                        false => Err(format!("read 0x{actual:08x}")),
+                    }),
+            };
+
+            let stop = outcome.is_err();
+            let (passed, detail) = match outcome {
+                Ok(Ok(detail)) => (true, detail),
+                Ok(Err(detail)) => (false, detail),
+                Err(e) => (false, e.to_string()),
+            };
+            results.push(StepResult {
+                command: command.clone(),
+                passed,
+                detail,
+            });
+
+            if stop {
+                break;
+            }
+        }
+
+        results
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fs::OpenOptions, os::fd::AsRawFd};
+
+use crate::{
+    bits::*,
+    error::{NvTrustError, Result},
+};
+
+/// `struct snp_report_req` of the kernel.
+#[repr(C)]
+struct SnpReportReq {
+    user_data: [u8; SNP_REPORT_DATA_SIZE],
+    vmpl: u32,
+    rsvd: [u8; 28],
+}
+
+/// `struct snp_report_resp` of the kernel.
+#[repr(C)]
+struct SnpReportResp {
+    data: [u8; SNP_REPORT_RESP_SIZE],
+}
+
+/// `struct snp_guest_request_ioctl` of the kernel.
+#[repr(C)]
+struct SnpGuestRequest {
+    msg_version: u8,
+    req_data: u64,
+    resp_data: u64,
+    /// The firmware error in the low half and the VMM error in the high half.
+    exitinfo2: u64,
+}
+
+nix::ioctl_readwrite!(snp_get_report, b'S', 0x0, SnpGuestRequest);
+
+/// Get an attestation report of this SNP guest, signed by the PSP, with the given user data, e.g.,
+/// a nonce or the hash of the evidence the report should bind.
+pub fn get_report(user_data: &[u8; SNP_REPORT_DATA_SIZE], vmpl: u32) -> Result<Vec<u8>> {
+    let device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(SEV_GUEST_DEVICE)
+        .map_err(|e| {
+            NvTrustError::Tee(format!(
+                "cannot open {SEV_GUEST_DEVICE}: {e}; is this an SNP guest?"
+            ))
+        })?;
+
+    let req = SnpReportReq {
+        user_data: *user_data,
+        vmpl,
+        rsvd: [0; 28],
+    };
+    let mut resp = SnpReportResp {
+        data: [0; SNP_REPORT_RESP_SIZE],
+    };
+    let mut request = SnpGuestRequest {
+        msg_version: 1,
+        req_data: &req as *const _ as u64,
+        resp_data: &mut resp as *mut _ as u64,
+        exitinfo2: 0,
+    };
+
+    // SAFETY: the request points at live request and response buffers of the sizes the kernel
+    // expects.
+    if let Err(e) = unsafe { snp_get_report(device.as_raw_fd(), &mut request) } {
+        return Err(NvTrustError::Tee(format!(
+            "SNP_GET_REPORT failed: {e} (firmware error 0x{:x}, VMM error 0x{:x})",
+            request.exitinfo2 & 0xffffffff,
+            request.exitinfo2 >> 32
+        )));
+    }
+
+    // The response is `struct msg_report_resp`: a status, the size of the report and the report.
+    let status = u32::from_le_bytes(resp.data[0..4].try_into().unwrap());
+    let size = u32::from_le_bytes(resp.data[4..8].try_into().unwrap()) as usize;
+    if status != 0 {
+        return Err(NvTrustError::Tee(format!(
+            "the PSP failed the report request with status 0x{status:x}"
+        )));
+    }
+
+    resp.data
+        .get(SNP_REPORT_OFFSET..SNP_REPORT_OFFSET + size)
+        .map(|report| report.to_vec())
+        .ok_or_else(|| NvTrustError::Tee(format!("the PSP returned a report of {size} bytes")))
+}
@@ -0,0 +1,554 @@
+use std::{collections::BTreeMap, io::Read};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bits::*,
+    doe::{DoeMailbox, DoeProtocol},
+    error::{NvTrustError, Result},
+};
+
+/// The algorithms selected by the responder in NEGOTIATE_ALGORITHMS.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Algorithms {
+    pub measurement_hash: u32,
+    pub base_asym: u32,
+    pub base_hash: u32,
+}
+
+impl Algorithms {
+    /// The size of the digests of the selected hash algorithm.
+    pub fn hash_size(&self) -> usize {
+        match self.base_hash {
+            SPDM_HASH_SHA_256 => 32,
+            SPDM_HASH_SHA_384 => 48,
+            SPDM_HASH_SHA_512 => 64,
+            _ => 0,
+        }
+    }
+
+    /// The size of the signatures of the selected asymmetric algorithm.
+    pub fn signature_size(&self) -> usize {
+        match self.base_asym {
+            SPDM_ASYM_ECDSA_P256 => 64,
+            SPDM_ASYM_ECDSA_P384 => 96,
+            _ => 0,
+        }
+    }
+}
+
+/// The response to CHALLENGE, signed by the device with the key of the challenged slot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChallengeAuth {
+    pub slot: u8,
+    pub cert_chain_hash: Vec<u8>,
+    pub nonce: [u8; SPDM_NONCE_SIZE],
+    pub measurement_summary_hash: Vec<u8>,
+    pub opaque: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// A measurement block in the DMTF format.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeasurementBlock {
+    pub index: u8,
+    /// The DMTF measurement value type, e.g., 0x00 for immutable ROM and 0x01 for mutable
+    /// firmware; bit 7 tells whether the value is a raw bit stream rather than a digest.
+    pub value_type: u8,
+    pub value: Vec<u8>,
+}
+
+/// The response to GET_MEASUREMENTS.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Measurements {
+    pub blocks: Vec<MeasurementBlock>,
+    pub nonce: [u8; SPDM_NONCE_SIZE],
+    pub opaque: Vec<u8>,
+    /// The signature over the measurement transcript, if a signed response was requested.
+    pub signature: Option<Vec<u8>>,
+    /// The request followed by the whole response, which NVIDIA's verifiers take as the
+    /// attestation report.
+    pub report: Vec<u8>,
+}
+
+impl Measurements {
+    /// Parse an attestation report: a GET_MEASUREMENTS request followed by the MEASUREMENTS
+    /// response, which ends with a signature of `signature_size` bytes if the request asked for
+    /// one. Anything after the response, e.g., the padding of the DOE object, is dropped.
+    pub fn from_report(report: &[u8], signature_size: usize) -> Result<Self> {
+        let signed = take(report, 2, 1)?[0] & SPDM_MEASUREMENTS_SIGNED != 0;
+        // A signed request carries the nonce and the slot.
+        let request_len = if signed { 4 + SPDM_NONCE_SIZE + 1 } else { 4 };
+        let response = &report[request_len.min(report.len())..];
+        if take(report, 1, 1)?[0] != SPDM_GET_MEASUREMENTS
+            || take(response, 1, 1)?[0] != SPDM_MEASUREMENTS
+        {
+            return Err(NvTrustError::Spdm(
+                "not a GET_MEASUREMENTS request and MEASUREMENTS response".to_string(),
+            ));
+        }
+
+        let count = take(response, 4, 1)?[0] as usize;
+        let len = take(response, 5, 3)?;
+        let record_len = u32::from_le_bytes([len[0], len[1], len[2], 0]) as usize;
+        let record = take(response, 8, record_len)?;
+
+        let mut blocks = vec![];
+        let mut offset = 0;
+        for _ in 0..count {
+            let size = read16(record, offset + 2)? as usize;
+            let measurement = take(record, offset + 4, size)?;
+            let value_size = read16(measurement, 1)? as usize;
+
+            blocks.push(MeasurementBlock {
+                index: record[offset],
+                value_type: measurement[0],
+                value: take(measurement, 3, value_size)?.to_vec(),
+            });
+            offset += 4 + size;
+        }
+
+        let mut offset = 8 + record_len;
+        let nonce = take(response, offset, SPDM_NONCE_SIZE)?.try_into().unwrap();
+        offset += SPDM_NONCE_SIZE;
+        let opaque_len = read16(response, offset)? as usize;
+        let opaque = take(response, offset + 2, opaque_len)?.to_vec();
+        offset += 2 + opaque_len;
+
+        let signature = if signed {
+            Some(take(response, offset, signature_size)?.to_vec())
+        } else {
+            None
+        };
+        let end = request_len + offset + signature.as_ref().map_or(0, |s| s.len());
+
+        Ok(Self {
+            blocks,
+            nonce,
+            opaque,
+            signature,
+            report: report[..end].to_vec(),
+        })
+    }
+
+    /// The nonce of the request, which the signature binds the report to, if it was signed.
+    pub fn request_nonce(&self) -> Option<&[u8]> {
+        self.signature
+            .as_ref()
+            .and_then(|_| self.report.get(4..4 + SPDM_NONCE_SIZE))
+    }
+
+    /// The part of the report that the signature covers: all of it but the signature.
+    pub fn signed_report(&self) -> &[u8] {
+        let len = self.signature.as_ref().map_or(0, |s| s.len());
+        &self.report[..self.report.len() - len]
+    }
+
+    /// The measurements as a set of hex digests by index, as recorded in the history database.
+    pub fn to_set(&self) -> BTreeMap<u32, String> {
+        self.blocks
+            .iter()
+            .map(|block| (block.index as u32, to_hex(&block.value)))
+            .collect()
+    }
+}
+
+/// An SPDM 1.1/1.2 requester over the CMA/SPDM protocol of a DOE mailbox.
+///
+/// The messages since GET_VERSION are kept in the transcript, which is what the signature of
+/// CHALLENGE_AUTH covers, so that the evidence can be verified later.
+pub struct SpdmRequester<'a> {
+    doe: DoeMailbox<'a>,
+    version: u8,
+    capabilities: u32,
+    algorithms: Algorithms,
+    transcript: Vec<u8>,
+    /// The length of the version, capabilities and algorithms messages at the start of the
+    /// transcript.
+    vca_len: usize,
+    /// The messages covered by the signature of MEASUREMENTS.
+    measurement_transcript: Vec<u8>,
+}
+
+impl<'a> SpdmRequester<'a> {
+    pub fn new(doe: DoeMailbox<'a>) -> Self {
+        Self {
+            doe,
+            version: SPDM_VERSION_10,
+            capabilities: 0,
+            algorithms: Algorithms::default(),
+            transcript: vec![],
+            vca_len: 0,
+            measurement_transcript: vec![],
+        }
+    }
+
+    /// Run GET_VERSION, GET_CAPABILITIES and NEGOTIATE_ALGORITHMS, which any other request needs.
+    pub fn init(&mut self) -> Result<()> {
+        self.transcript.clear();
+        self.get_version()?;
+        self.get_capabilities()?;
+        self.negotiate_algorithms()?;
+        self.vca_len = self.transcript.len();
+
+        log::debug!(
+            "SPDM {:x}.{:x}, capabilities 0x{:x}, {:?}",
+            self.version >> 4,
+            self.version & 0xf,
+            self.capabilities,
+            self.algorithms
+        );
+        Ok(())
+    }
+
+    #[inline]
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    #[inline]
+    pub fn capabilities(&self) -> u32 {
+        self.capabilities
+    }
+
+    #[inline]
+    pub fn algorithms(&self) -> Algorithms {
+        self.algorithms
+    }
+
+    /// The messages exchanged since GET_VERSION.
+    #[inline]
+    pub fn transcript(&self) -> &[u8] {
+        &self.transcript
+    }
+
+    /// The messages covered by the signature of the last signed MEASUREMENTS.
+    #[inline]
+    pub fn measurement_transcript(&self) -> &[u8] {
+        &self.measurement_transcript
+    }
+
+    /// Send a request and receive the response, which must have the expected code.
+    ///
+    /// The DOE object is dword sized, so the response may carry padding at the end.
+    pub fn request(&mut self, request: &[u8], expected: u8) -> Result<Vec<u8>> {
+        let payload = request
+            .chunks(4)
+            .map(|chunk| {
+                let mut dword = [0; 4];
+                dword[..chunk.len()].copy_from_slice(chunk);
+                u32::from_le_bytes(dword)
+            })
+            .collect::<Vec<_>>();
+
+        let response = self
+            .doe
+            .exchange(DoeProtocol::CMA_SPDM, &payload)?
+            .into_iter()
+            .flat_map(u32::to_le_bytes)
+            .collect::<Vec<_>>();
+
+        if response.len() < 4 {
+            return Err(NvTrustError::Spdm(format!(
+                "response of {} bytes is too short",
+                response.len()
+            )));
+        }
+
+        match response[1] {
+            code if code == expected => Ok(response),
+            SPDM_ERROR => Err(NvTrustError::Spdm(format!(
+                "request 0x{:02x} failed with error 0x{:02x} (data 0x{:02x})",
+                request[1], response[2], response[3]
+            ))),
+            code => Err(NvTrustError::Spdm(format!(
+                "unexpected response 0x{code:02x} to request 0x{:02x}",
+                request[1]
+            ))),
+        }
+    }
+
+    /// Send a request that is part of the transcript, recording `len` bytes of the response.
+    fn request_recorded(&mut self, request: &[u8], expected: u8, len: usize) -> Result<Vec<u8>> {
+        let response = self.request(request, expected)?;
+        let response = take(&response, 0, len)?.to_vec();
+
+        self.transcript.extend_from_slice(request);
+        self.transcript.extend_from_slice(&response);
+        Ok(response)
+    }
+
+    fn get_version(&mut self) -> Result<()> {
+        let response = self.request(&[SPDM_VERSION_10, SPDM_GET_VERSION, 0, 0], SPDM_VERSION)?;
+        let count = *take(&response, 5, 1)?.first().unwrap() as usize;
+        let entries = take(&response, 6, count * 2)?;
+
+        // Each entry has the major and minor versions in its high byte.
+        self.version = entries
+            .chunks(2)
+            .map(|entry| entry[1])
+            .filter(|version| [SPDM_VERSION_11, SPDM_VERSION_12].contains(version))
+            .max()
+            .ok_or(NvTrustError::Spdm(format!(
+                "no supported version in {:x?}",
+                entries
+            )))?;
+
+        self.transcript
+            .extend_from_slice(&[SPDM_VERSION_10, SPDM_GET_VERSION, 0, 0]);
+        self.transcript
+            .extend_from_slice(take(&response, 0, 6 + count * 2)?);
+        Ok(())
+    }
+
+    fn get_capabilities(&mut self) -> Result<()> {
+        let mut request = vec![self.version, SPDM_GET_CAPABILITIES, 0, 0, 0, 0, 0, 0];
+        request.extend(SPDM_REQUESTER_CAPS.to_le_bytes());
+        if self.version >= SPDM_VERSION_12 {
+            // The data transfer size and the maximum message size, one DOE object at most.
+            let max = (PCI_DOE_MAX_LENGTH as u32 - 2) * 4;
+            request.extend(max.to_le_bytes());
+            request.extend(max.to_le_bytes());
+        }
+
+        let len = request.len();
+        let response = self.request_recorded(&request, SPDM_CAPABILITIES, len)?;
+        self.capabilities = read32(&response, 8)?;
+        Ok(())
+    }
+
+    fn negotiate_algorithms(&mut self) -> Result<()> {
+        // DHE secp384r1, AEAD AES-256-GCM, requester ECDSA P-384 and the SPDM key schedule.
+        let structs: [(u8, u16); 4] = [(2, 0x0010), (3, 0x0002), (4, 0x0080), (5, 0x0001)];
+        let len = 32 + structs.len() * 4;
+
+        let mut request = vec![
+            self.version,
+            SPDM_NEGOTIATE_ALGORITHMS,
+            structs.len() as u8,
+            0,
+        ];
+        request.extend((len as u16).to_le_bytes());
+        request.extend([SPDM_MEASUREMENT_SPEC_DMTF, 0]);
+        request.extend((SPDM_ASYM_ECDSA_P256 | SPDM_ASYM_ECDSA_P384).to_le_bytes());
+        request.extend((SPDM_HASH_SHA_256 | SPDM_HASH_SHA_384 | SPDM_HASH_SHA_512).to_le_bytes());
+        request.resize(32, 0);
+        for (kind, supported) in structs {
+            request.extend([kind, 0x20]);
+            request.extend(supported.to_le_bytes());
+        }
+
+        let response = self.request(&request, SPDM_ALGORITHMS)?;
+        let response_len = read16(&response, 4)? as usize;
+        self.transcript.extend_from_slice(&request);
+        self.transcript
+            .extend_from_slice(take(&response, 0, response_len)?);
+
+        self.algorithms = Algorithms {
+            measurement_hash: read32(&response, 8)?,
+            base_asym: read32(&response, 12)?,
+            base_hash: read32(&response, 16)?,
+        };
+        if self.algorithms.hash_size() == 0 || self.algorithms.signature_size() == 0 {
+            return Err(NvTrustError::Spdm(format!(
+                "unsupported algorithms selected: {:?}",
+                self.algorithms
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Get the digests of the certificate chains, by slot.
+    pub fn get_digests(&mut self) -> Result<Vec<(u8, Vec<u8>)>> {
+        let request = [self.version, SPDM_GET_DIGESTS, 0, 0];
+        let response = self.request(&request, SPDM_DIGESTS)?;
+
+        let hash_size = self.algorithms.hash_size();
+        let slots = (0..8u8)
+            .filter(|slot| response[3] & (1 << slot) != 0)
+            .collect::<Vec<_>>();
+        let len = 4 + slots.len() * hash_size;
+
+        self.transcript.extend_from_slice(&request);
+        self.transcript.extend_from_slice(take(&response, 0, len)?);
+
+        slots
+            .iter()
+            .enumerate()
+            .map(|(i, slot)| {
+                Ok((
+                    *slot,
+                    take(&response, 4 + i * hash_size, hash_size)?.to_vec(),
+                ))
+            })
+            .collect()
+    }
+
+    /// Get the certificate chain of the given slot.
+    ///
+    /// The chain is returned as is: its length, the hash of the root certificate and the DER
+    /// certificates from the root to the leaf.
+    pub fn get_certificate(&mut self, slot: u8) -> Result<Vec<u8>> {
+        let mut chain = vec![];
+
+        loop {
+            let mut request = vec![self.version, SPDM_GET_CERTIFICATE, slot, 0];
+            request.extend((chain.len() as u16).to_le_bytes());
+            request.extend(SPDM_CERT_PORTION.to_le_bytes());
+
+            let response = self.request(&request, SPDM_CERTIFICATE)?;
+            let portion = read16(&response, 4)? as usize;
+            let remainder = read16(&response, 6)?;
+
+            self.transcript.extend_from_slice(&request);
+            self.transcript
+                .extend_from_slice(take(&response, 0, 8 + portion)?);
+            chain.extend_from_slice(take(&response, 8, portion)?);
+
+            if remainder == 0 {
+                return Ok(chain);
+            }
+            if portion == 0 {
+                return Err(NvTrustError::Spdm(format!(
+                    "empty certificate portion with {remainder} bytes remaining"
+                )));
+            }
+        }
+    }
+
+    /// Challenge the device to sign the transcript with the key of the given slot.
+    ///
+    /// `summary` selects the measurement summary hash: 0 for none, 1 for the TCB measurements and
+    /// 0xff for all of them.
+    pub fn challenge(
+        &mut self,
+        slot: u8,
+        summary: u8,
+        nonce: [u8; SPDM_NONCE_SIZE],
+    ) -> Result<ChallengeAuth> {
+        let mut request = vec![self.version, SPDM_CHALLENGE, slot, summary];
+        request.extend_from_slice(&nonce);
+
+        let response = self.request(&request, SPDM_CHALLENGE_AUTH)?;
+
+        let hash_size = self.algorithms.hash_size();
+        let summary_size = if summary == 0 { 0 } else { hash_size };
+        let mut offset = 4;
+        let mut field = |len: usize| -> Result<Vec<u8>> {
+            let data = take(&response, offset, len)?.to_vec();
+            offset += len;
+            Ok(data)
+        };
+
+        let cert_chain_hash = field(hash_size)?;
+        let nonce = field(SPDM_NONCE_SIZE)?.try_into().unwrap();
+        let measurement_summary_hash = field(summary_size)?;
+        let opaque_len = read16(&field(2)?, 0)? as usize;
+        let opaque = field(opaque_len)?;
+        let signed_len = 4 + hash_size + SPDM_NONCE_SIZE + summary_size + 2 + opaque_len;
+        let signature = field(self.algorithms.signature_size())?;
+
+        // The signature covers the transcript up to, but excluding, the signature itself.
+        self.transcript.extend_from_slice(&request);
+        self.transcript
+            .extend_from_slice(take(&response, 0, signed_len)?);
+
+        Ok(ChallengeAuth {
+            slot: response[2] & 0xf,
+            cert_chain_hash,
+            nonce,
+            measurement_summary_hash,
+            opaque,
+            signature,
+        })
+    }
+
+    /// Get all the measurement blocks, signed with the key of the given slot if `nonce` is given.
+    pub fn get_measurements(
+        &mut self,
+        slot: u8,
+        nonce: Option<[u8; SPDM_NONCE_SIZE]>,
+    ) -> Result<Measurements> {
+        let mut request = vec![
+            self.version,
+            SPDM_GET_MEASUREMENTS,
+            if nonce.is_some() {
+                SPDM_MEASUREMENTS_SIGNED
+            } else {
+                0
+            },
+            SPDM_MEASUREMENTS_ALL,
+        ];
+        if let Some(nonce) = nonce {
+            request.extend_from_slice(&nonce);
+            request.push(slot);
+        }
+
+        let mut report = request;
+        report.extend_from_slice(&self.request(&report, SPDM_MEASUREMENTS)?);
+        let measurements = Measurements::from_report(&report, self.algorithms.signature_size())?;
+
+        // Since SPDM 1.2 the signature also covers the version and algorithm negotiation.
+        self.measurement_transcript.clear();
+        if self.version >= SPDM_VERSION_12 {
+            let vca = self.vca_len;
+            self.measurement_transcript
+                .extend_from_slice(&self.transcript[..vca]);
+        }
+        self.measurement_transcript
+            .extend_from_slice(measurements.signed_report());
+
+        Ok(measurements)
+    }
+}
+
+/// Get a fresh nonce from the kernel.
+pub fn random_nonce() -> Result<[u8; SPDM_NONCE_SIZE]> {
+    let mut nonce = [0; SPDM_NONCE_SIZE];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut nonce)?;
+
+    Ok(nonce)
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    let hex = hex.trim().trim_start_matches("0x");
+    if hex.len() & 1 != 0 {
+        return Err(NvTrustError::InvalidArgument(format!(
+            "odd number of hex digits in {hex}"
+        )));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| NvTrustError::InvalidArgument(format!("invalid hex: {hex}")))
+        })
+        .collect()
+}
+
+fn take(data: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    data.get(offset..offset + len)
+        .ok_or(NvTrustError::Spdm(format!(
+            "message of {} bytes is too short for 0x{:x} bytes at 0x{:x}",
+            data.len(),
+            len,
+            offset
+        )))
+}
+
+fn read16(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = take(data, offset, 2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = take(data, offset, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
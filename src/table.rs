@@ -0,0 +1,145 @@
+use std::{fmt::Write, io::IsTerminal};
+
+/// The color a cell is rendered with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Plain,
+    Green,
+    Yellow,
+    Red,
+}
+
+impl Color {
+    fn ansi(&self) -> &'static str {
+        match self {
+            Color::Plain => "",
+            Color::Green => "\x1b[32m",
+            Color::Yellow => "\x1b[33m",
+            Color::Red => "\x1b[31m",
+        }
+    }
+}
+
+/// A single cell of a table.
+#[derive(Debug, Clone)]
+pub struct Cell {
+    pub text: String,
+    pub color: Color,
+}
+
+impl Cell {
+    pub fn new<T: ToString>(text: T) -> Self {
+        Self {
+            text: text.to_string(),
+            color: Color::Plain,
+        }
+    }
+
+    pub fn colored<T: ToString>(text: T, color: Color) -> Self {
+        Self {
+            text: text.to_string(),
+            color,
+        }
+    }
+}
+
+impl From<&str> for Cell {
+    fn from(text: &str) -> Self {
+        Cell::new(text)
+    }
+}
+
+impl From<String> for Cell {
+    fn from(text: String) -> Self {
+        Cell::new(text)
+    }
+}
+
+/// A table of human-readable output with left-aligned columns.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Self {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: vec![],
+        }
+    }
+
+    /// Append a row; missing cells are left empty and extra cells are dropped.
+    pub fn push<I, C>(&mut self, row: I)
+    where
+        I: IntoIterator<Item = C>,
+        C: Into<Cell>,
+    {
+        let mut row = row.into_iter().map(Into::into).collect::<Vec<Cell>>();
+        row.resize(self.headers.len(), Cell::new(""));
+        self.rows.push(row);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Render the table, with colors if `color` is set.
+    pub fn render(&self, color: bool) -> String {
+        let mut widths = self
+            .headers
+            .iter()
+            .map(|h| h.chars().count())
+            .collect::<Vec<_>>();
+        for row in self.rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.text.chars().count());
+            }
+        }
+
+        let mut out = String::new();
+        let headers = self.headers.iter().map(|h| Cell::new(h.to_uppercase()));
+        render_row(&mut out, headers, &widths, color);
+        for row in self.rows.iter() {
+            render_row(&mut out, row.iter().cloned(), &widths, color);
+        }
+
+        out
+    }
+
+    /// Print the table to stdout, colored according to `color`.
+    pub fn print(&self, color: bool) {
+        print!("{}", self.render(color));
+    }
+}
+
+/// Whether output should be colored: not disabled by `--no-color` or `NO_COLOR`, and stdout is a
+/// terminal.
+pub fn use_color(no_color: bool) -> bool {
+    !no_color && std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+}
+
+fn render_row<I>(out: &mut String, cells: I, widths: &[usize], color: bool)
+where
+    I: Iterator<Item = Cell>,
+{
+    let mut line = String::new();
+
+    for (i, (cell, width)) in cells.zip(widths.iter()).enumerate() {
+        if i != 0 {
+            line.push_str("  ");
+        }
+
+        let pad = width - cell.text.chars().count();
+        if color && cell.color != Color::Plain {
+            let _ = write!(line, "{}{}\x1b[0m", cell.color.ansi(), cell.text);
+        } else {
+            line.push_str(&cell.text);
+        }
+        line.extend(std::iter::repeat_n(' ', pad));
+    }
+
+    out.push_str(line.trim_end());
+    out.push('\n');
+}
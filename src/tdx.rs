@@ -0,0 +1,55 @@
+use std::{fs::OpenOptions, os::fd::AsRawFd};
+
+use crate::{
+    bits::*,
+    error::{NvTrustError, Result},
+    platform::{self, HostEvidence},
+};
+
+/// `struct tdx_report_req` of the kernel.
+#[repr(C)]
+struct TdxReportReq {
+    reportdata: [u8; TDX_REPORT_DATA_SIZE],
+    tdreport: [u8; TDX_REPORT_SIZE],
+}
+
+nix::ioctl_readwrite!(tdx_get_report0, b'T', 1, TdxReportReq);
+
+/// Get the TD report of this trust domain with the given report data. The TD report is only
+/// MACed for the local platform; remote verifiers need the quote of [`get_quote`].
+pub fn get_td_report(report_data: &[u8; TDX_REPORT_DATA_SIZE]) -> Result<Vec<u8>> {
+    let device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(TDX_GUEST_DEVICE)
+        .map_err(|e| {
+            NvTrustError::Tee(format!(
+                "cannot open {TDX_GUEST_DEVICE}: {e}; is this a TD?"
+            ))
+        })?;
+
+    let mut req = TdxReportReq {
+        reportdata: *report_data,
+        tdreport: [0; TDX_REPORT_SIZE],
+    };
+
+    // SAFETY: the request is the structure of the size the kernel expects.
+    unsafe { tdx_get_report0(device.as_raw_fd(), &mut req) }
+        .map_err(|e| NvTrustError::Tee(format!("TDX_CMD_GET_REPORT0 failed: {e}")))?;
+
+    Ok(req.tdreport.to_vec())
+}
+
+/// Convert a TD report with the given report data into a quote signed by the quoting enclave,
+/// through configfs-tsm.
+pub fn get_quote(report_data: &[u8; TDX_REPORT_DATA_SIZE]) -> Result<HostEvidence> {
+    let evidence = platform::host_report(report_data)?;
+    if evidence.provider != "tdx_guest" {
+        return Err(NvTrustError::Tee(format!(
+            "the TSM provider is {}, not tdx_guest",
+            evidence.provider
+        )));
+    }
+
+    Ok(evidence)
+}
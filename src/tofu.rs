@@ -0,0 +1,46 @@
+use crate::{
+    error::Result,
+    history::{compare, HistoryDb, MeasurementChange, MeasurementSet},
+};
+
+/// The outcome of a trust-on-first-use appraisal.
+#[derive(Debug, Clone)]
+pub enum TofuVerdict {
+    /// No baseline existed; the observed measurements became the baseline.
+    BaselineRecorded,
+    /// The observed measurements match the baseline.
+    Match,
+    /// The observed measurements differ from the baseline.
+    Changed(Vec<MeasurementChange>),
+}
+
+/// Appraise the measurements of a GPU by trusting the first set ever observed for it.
+///
+/// This is meant for air-gapped labs without access to reference values: the first measurement
+/// set observed for a GPU is recorded as its baseline, and any later change is reported. Every
+/// observation is logged in the history database.
+pub fn appraise(db: &HistoryDb, identity: &str, observed: &MeasurementSet) -> Result<TofuVerdict> {
+    let verdict = match db.baseline(identity)? {
+        None => {
+            db.set_baseline(identity, observed)?;
+            TofuVerdict::BaselineRecorded
+        }
+        Some(baseline) => {
+            let changes = compare(&baseline, observed);
+            if changes.is_empty() {
+                TofuVerdict::Match
+            } else {
+                TofuVerdict::Changed(changes)
+            }
+        }
+    };
+
+    let event = match verdict {
+        TofuVerdict::BaselineRecorded => "baseline",
+        TofuVerdict::Match => "match",
+        TofuVerdict::Changed(_) => "changed",
+    };
+    db.append(identity, event, observed)?;
+
+    Ok(verdict)
+}
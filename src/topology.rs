@@ -0,0 +1,259 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    bits::*,
+    dev::{self, GpuObject, PciDevice},
+    error::{NvTrustError, Result},
+    link::LinkStatus,
+};
+
+/// A PCIe port between a device and the host bridge.
+#[derive(Debug, Clone, Serialize)]
+pub struct Port {
+    pub bdf: String,
+    pub vendor: u16,
+    pub device: u16,
+    /// The device/port type of the Express capability, e.g., `root port`.
+    pub kind: String,
+    /// The link below the port, if it has the Express capability.
+    pub link: Option<LinkStatus>,
+    /// The ACS control register, if the port has the capability.
+    pub acs: Option<u16>,
+    /// Whether the port has the IDE capability, to encrypt the link.
+    pub ide: bool,
+}
+
+/// A device of the PCIe tree from the root ports down to the NVIDIA devices.
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    #[serde(flatten)]
+    pub port: Port,
+    /// The marketing name of an NVIDIA device, e.g., `H100 PCIe`.
+    pub name: Option<&'static str>,
+    pub children: Vec<Node>,
+}
+
+/// Build the PCIe tree from the root ports down to all the NVIDIA GPUs and NVSwitches.
+pub fn tree() -> Result<Vec<Node>> {
+    tree_of(&dev::list_nvidia_devices()?)
+}
+
+/// Build the PCIe tree from the root ports down to the given devices, by sysfs path.
+pub fn tree_of<P: AsRef<Path>>(devices: &[P]) -> Result<Vec<Node>> {
+    let mut roots: Vec<Node> = vec![];
+    for device in devices {
+        let mut chain = ports_above(device);
+        chain.reverse();
+        chain.push(device.as_ref().canonicalize()?);
+
+        let mut level = &mut roots;
+        for path in chain {
+            let port = dev::open_port(&path.to_string_lossy())?.describe_port();
+            let index = match level.iter().position(|node| node.port.bdf == port.bdf) {
+                Some(index) => index,
+                None => {
+                    let name = match port.vendor {
+                        NVIDIA_VENDOR_ID => dev::device_name(port.device),
+                        _ => None,
+                    };
+                    level.push(Node {
+                        port,
+                        name,
+                        children: vec![],
+                    });
+                    level.len() - 1
+                }
+            };
+            level = &mut level[index].children;
+        }
+    }
+
+    fn sort(nodes: &mut [Node]) {
+        nodes.sort_by(|a, b| a.port.bdf.cmp(&b.port.bdf));
+        for node in nodes {
+            sort(&mut node.children);
+        }
+    }
+    sort(&mut roots);
+
+    Ok(roots)
+}
+
+impl Port {
+    /// Whether peer-to-peer traffic through the port must go up to the IOMMU for the devices
+    /// below to be isolated: root and switch downstream ports route it, upstream ports do not.
+    pub fn needs_acs(&self) -> bool {
+        matches!(self.kind.as_str(), "root port" | "downstream port")
+    }
+
+    /// The ACS controls that isolation needs but the port has off, by name.
+    pub fn missing_acs(&self) -> Vec<&'static str> {
+        let control = self.acs.unwrap_or(0);
+        [
+            (PCI_ACS_SV, "source validation"),
+            (PCI_ACS_RR, "request redirect"),
+            (PCI_ACS_CR, "completion redirect"),
+            (PCI_ACS_UF, "upstream forwarding"),
+        ]
+        .into_iter()
+        .filter(|(bit, _)| control & bit == 0)
+        .map(|(_, name)| name)
+        .collect()
+    }
+
+    /// Whether the port lets devices below it talk to each other behind the IOMMU.
+    pub fn is_insecure(&self) -> bool {
+        self.needs_acs() && self.acs.unwrap_or(0) & ACS_CTRL_ISOLATION != ACS_CTRL_ISOLATION
+    }
+}
+
+fn is_bdf(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() == 12 && bytes[4] == b':' && bytes[7] == b':' && bytes[10] == b'.'
+}
+
+/// Get the sysfs paths of the ports above a device, from the one it hangs off up to the root
+/// port.
+pub fn ports_above<P: AsRef<Path>>(device: P) -> Vec<PathBuf> {
+    // The path is `/sys/devices/pci0000:00/<root port>/<switch ports...>/<bdf>`.
+    let Ok(path) = device.as_ref().canonicalize() else {
+        return vec![];
+    };
+
+    path.ancestors()
+        .skip(1)
+        .take_while(|port| {
+            port.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(is_bdf)
+        })
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+impl PciDevice {
+    /// Get the sysfs path of the port the device hangs off, e.g., a root port or a switch
+    /// downstream port, or `None` if the device sits on a root bus.
+    pub fn upstream_port_path(&self) -> Option<PathBuf> {
+        ports_above(self.get_name()).into_iter().next()
+    }
+
+    /// Open the port the device hangs off, see [`Self::upstream_port_path`].
+    pub fn upstream_port(&self) -> Result<Option<PciDevice>> {
+        match self.upstream_port_path() {
+            Some(path) => Ok(Some(dev::open_port(&path.to_string_lossy())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Describe the ports above the device, from the nearest.
+    pub fn topology(&self) -> Result<Vec<Port>> {
+        ports_above(self.get_name())
+            .iter()
+            .map(|path| {
+                let port = dev::open_port(&path.to_string_lossy())?;
+                Ok(port.describe_port())
+            })
+            .collect()
+    }
+
+    /// Get the ports above the device that do not isolate it with ACS, from the nearest, e.g., a
+    /// switch that routes peer-to-peer traffic of the GPU past the IOMMU.
+    pub fn insecure_ports(&self) -> Result<Vec<Port>> {
+        Ok(self
+            .topology()?
+            .into_iter()
+            .filter(Port::is_insecure)
+            .collect())
+    }
+
+    fn describe_port(&self) -> Port {
+        let kind = match self.express_offset() {
+            Ok(offset) => match self.read_config16(offset + PCI_EXP_FLAGS) {
+                Ok(flags) => match (flags & PCI_EXP_FLAGS_TYPE) >> 4 {
+                    0x0 => "endpoint".to_string(),
+                    0x1 => "legacy endpoint".to_string(),
+                    0x4 => "root port".to_string(),
+                    0x5 => "upstream port".to_string(),
+                    0x6 => "downstream port".to_string(),
+                    0x7 => "PCIe to PCI bridge".to_string(),
+                    kind => format!("type {kind}"),
+                },
+                Err(_) => "unknown".to_string(),
+            },
+            Err(_) => "PCI bridge".to_string(),
+        };
+
+        Port {
+            bdf: self.get_bdf().to_string(),
+            vendor: self.get_config().vendor,
+            device: self.get_config().device,
+            kind,
+            link: self.link_status().ok(),
+            acs: self
+                .ext_caps()
+                .get(&(PCI_EXT_CAP_ID_ACS as u16))
+                .and_then(|offset| self.read_config16(offset + PCI_ACS_CTRL).ok()),
+            ide: self.ext_caps().contains_key(&(PCI_EXT_CAP_ID_IDE as u16)),
+        }
+    }
+
+    /// Reset everything below this port with a secondary bus reset, e.g., when the sysfs reset
+    /// and FLR do not bring a device back.
+    ///
+    /// The devices below are not restored; see [`GpuObject::secondary_bus_reset`].
+    pub fn reset_secondary_bus(&self) -> Result<()> {
+        let ctrl = self.read_config16(PCI_BRIDGE_CONTROL)?;
+
+        log::info!("Resetting the secondary bus of {}", self.get_bdf());
+        self.write_config16(PCI_BRIDGE_CONTROL, ctrl | PCI_BRIDGE_CTL_BUS_RESET)?;
+        std::thread::sleep(Duration::from_millis(PCI_SBR_ASSERT_DELAY));
+        self.write_config16(PCI_BRIDGE_CONTROL, ctrl & !PCI_BRIDGE_CTL_BUS_RESET)?;
+
+        // The devices below get 100ms before they must answer, and then the link must train.
+        std::thread::sleep(Duration::from_millis(PCI_RESET_READY_DELAY));
+        if let Ok(offset) = self.express_offset() {
+            self.wait_for_link_training(offset)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl GpuObject {
+    /// Reset the GPU with a secondary bus reset of its upstream port, restoring its config space
+    /// afterwards.
+    pub fn secondary_bus_reset(&self) -> Result<()> {
+        self.ensure_pf("Secondary bus reset")?;
+
+        let device = self.get_device_handle();
+        let port = device.upstream_port()?.ok_or(NvTrustError::NotSupported {
+            what: "Secondary bus reset".to_string(),
+            device: self.get_label(),
+            reason: "it has no upstream port".to_string(),
+        })?;
+
+        // Everything else on the bus is reset too, and nobody restores it.
+        if let Some(path) = device.upstream_port_path() {
+            for sibling in std::fs::read_dir(path)?.flatten() {
+                let name = sibling.file_name().to_string_lossy().to_string();
+                if is_bdf(&name) && name != device.get_bdf() {
+                    log::warn!(
+                        "{name} shares the bus of {} and is reset too",
+                        self.get_bdf()
+                    );
+                }
+            }
+        }
+
+        let saved = device.save_config_space()?;
+        port.reset_secondary_bus()?;
+        device.wait_for_config_space()?;
+        device.restore_config_space(&saved)
+    }
+}
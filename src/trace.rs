@@ -0,0 +1,307 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+use crate::{
+    backend::{BackendKind, DeviceBackend},
+    dev::Bar,
+    error::{NvTrustError, Result},
+    regs,
+    spdm::{from_hex, to_hex},
+};
+
+/// The trace every backend opened from now on records to, if any.
+static TRACE: OnceLock<Trace> = OnceLock::new();
+
+/// An MMIO trace: one line per access of any device, as
+///
+/// ```text
+/// <microseconds> <bdf> r <bar> <offset> <size> <value>    a register read
+/// <microseconds> <bdf> w <bar> <offset> <size> <value>    a register write
+/// <microseconds> <bdf> cr <offset> <hex bytes>           a config space read
+/// <microseconds> <bdf> cw <offset> <hex bytes>           a config space write
+/// <microseconds> <bdf> bar <index> <addr> <size> <resource> <is_64>
+/// <microseconds> <bdf> reset
+/// ```
+///
+/// with the numbers in hex except the timestamps, which count from the start of the trace. The
+/// accesses of named BAR0 registers end with a `# <name>` comment.
+struct Trace {
+    start: Instant,
+    file: Mutex<LineWriter<File>>,
+}
+
+impl Trace {
+    fn log(&self, bdf: &str, record: std::fmt::Arguments) {
+        let mut file = self.file.lock().unwrap();
+        let time = self.start.elapsed().as_micros();
+        if let Err(e) = writeln!(file, "{time} {bdf} {record}") {
+            log::warn!("Cannot write the MMIO trace: {e}");
+        }
+    }
+}
+
+/// Record the accesses of all the devices opened from now on to the given file.
+pub fn record_to<P: AsRef<Path>>(path: P) -> Result<()> {
+    let file = File::create(path.as_ref())?;
+    TRACE
+        .set(Trace {
+            start: Instant::now(),
+            file: Mutex::new(LineWriter::new(file)),
+        })
+        .map_err(|_| NvTrustError::InvalidArgument("already recording a trace".to_string()))
+}
+
+/// Wrap the backend of a device in a recorder if a trace is being recorded.
+pub fn wrap(bdf: &str, backend: Arc<dyn DeviceBackend>) -> Arc<dyn DeviceBackend> {
+    match TRACE.get() {
+        Some(_) => Arc::new(RecordingBackend {
+            bdf: bdf.to_string(),
+            inner: backend,
+        }),
+        None => backend,
+    }
+}
+
+/// A backend that logs every access of the backend it wraps to the trace.
+#[derive(Debug)]
+pub struct RecordingBackend {
+    bdf: String,
+    inner: Arc<dyn DeviceBackend>,
+}
+
+impl RecordingBackend {
+    fn log(&self, record: std::fmt::Arguments) {
+        if let Some(trace) = TRACE.get() {
+            trace.log(&self.bdf, record);
+        }
+    }
+
+    /// The comment naming a register in the trace, if it has a name.
+    fn name(bar: usize, offset: u64) -> String {
+        match (bar, regs::name_of(offset)) {
+            (0, Some(name)) => format!("  # {name}"),
+            _ => String::new(),
+        }
+    }
+}
+
+impl DeviceBackend for RecordingBackend {
+    fn kind(&self) -> BackendKind {
+        self.inner.kind()
+    }
+
+    fn bars(&self) -> Result<[Bar; 6]> {
+        let bars = self.inner.bars()?;
+        for (i, bar) in bars.iter().enumerate().filter(|(_, bar)| bar.size != 0) {
+            self.log(format_args!(
+                "bar {i} {:x} {:x} {:x} {}",
+                bar.addr, bar.size, bar.resource, bar.is_64 as u8
+            ));
+        }
+
+        Ok(bars)
+    }
+
+    fn read_config(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let len = self.inner.read_config(offset, buf)?;
+        self.log(format_args!("cr {offset:x} {}", to_hex(&buf[..len])));
+
+        Ok(len)
+    }
+
+    fn write_config(&self, offset: u64, data: &[u8]) -> Result<()> {
+        self.log(format_args!("cw {offset:x} {}", to_hex(data)));
+        self.inner.write_config(offset, data)
+    }
+
+    fn read32(&self, bar: usize, offset: u64) -> Result<u32> {
+        let value = self.inner.read32(bar, offset)?;
+        let name = Self::name(bar, offset);
+        self.log(format_args!("r {bar} {offset:x} 4 {value:x}{name}"));
+
+        Ok(value)
+    }
+
+    fn write32(&self, bar: usize, offset: u64, value: u32) -> Result<()> {
+        let name = Self::name(bar, offset);
+        self.log(format_args!("w {bar} {offset:x} 4 {value:x}{name}"));
+        self.inner.write32(bar, offset, value)
+    }
+
+    fn read_bar(&self, bar: usize, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.inner.read_bar(bar, offset, buf)?;
+        for (i, dword) in buf.chunks_exact(4).enumerate() {
+            let value = u32::from_le_bytes(dword.try_into().unwrap());
+            self.log(format_args!(
+                "r {bar} {:x} 4 {value:x}",
+                offset + i as u64 * 4
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.log(format_args!("reset"));
+        self.inner.reset()
+    }
+}
+
+/// A backend that serves the register values recorded in a trace for one device.
+///
+/// The reads of each register return the recorded values in order, and then the last one again;
+/// registers that were never read return 0. The config space is what the recorded config reads
+/// returned. Writes are kept, so that a test can compare them with the recorded ones.
+#[derive(Debug)]
+pub struct ReplayBackend {
+    bars: [Bar; 6],
+    config: Mutex<Vec<u8>>,
+    reads: Mutex<HashMap<(usize, u64), VecDeque<u32>>>,
+    recorded_writes: Vec<(usize, u64, u32)>,
+    writes: Mutex<Vec<(usize, u64, u32)>>,
+    resets: AtomicUsize,
+}
+
+impl ReplayBackend {
+    /// Load the accesses of the device `bdf` from the trace.
+    pub fn from_trace<P: AsRef<Path>>(path: P, bdf: &str) -> Result<Self> {
+        let trace = std::fs::read_to_string(path.as_ref())?;
+        let invalid =
+            |line: &str| NvTrustError::InvalidArgument(format!("invalid trace line {line:?}"));
+        let hex = |s: &str| u64::from_str_radix(s, 16);
+
+        let mut bars = [Bar::default(); 6];
+        let mut config = vec![];
+        let mut reads = HashMap::<_, VecDeque<_>>::new();
+        let mut recorded_writes = vec![];
+
+        for line in trace.lines() {
+            let record = line.split('#').next().unwrap_or_default();
+            let fields = record.split_whitespace().collect::<Vec<_>>();
+            if fields.get(1) != Some(&bdf) {
+                continue;
+            }
+
+            match fields[2..] {
+                ["r", bar, offset, _, value] => reads
+                    .entry((hex(bar)? as usize, hex(offset)?))
+                    .or_default()
+                    .push_back(hex(value)? as u32),
+                ["w", bar, offset, _, value] => {
+                    recorded_writes.push((hex(bar)? as usize, hex(offset)?, hex(value)? as u32))
+                }
+                ["cr", offset, data] => {
+                    let offset = hex(offset)? as usize;
+                    let data = from_hex(data)?;
+                    if config.len() < offset + data.len() {
+                        config.resize(offset + data.len(), 0);
+                    }
+                    config[offset..offset + data.len()].copy_from_slice(&data);
+                }
+                ["bar", index, addr, size, resource, is_64] => {
+                    let index = hex(index)? as usize;
+                    *bars.get_mut(index).ok_or_else(|| invalid(line))? = Bar {
+                        addr: hex(addr)?,
+                        size: hex(size)?,
+                        is_64: is_64 == "1",
+                        resource: hex(resource)? as usize,
+                    };
+                }
+                ["cr", ..] | ["cw", ..] | ["reset"] => {}
+                _ => return Err(invalid(line)),
+            }
+        }
+
+        if config.is_empty() {
+            return Err(NvTrustError::DeviceNotFound(format!(
+                "{bdf} is not in the trace"
+            )));
+        }
+
+        Ok(Self {
+            bars,
+            config: Mutex::new(config),
+            reads: Mutex::new(reads),
+            recorded_writes,
+            writes: Mutex::default(),
+            resets: AtomicUsize::new(0),
+        })
+    }
+
+    /// The register writes of the recording, as `(bar, offset, value)`, in order.
+    pub fn recorded_writes(&self) -> &[(usize, u64, u32)] {
+        &self.recorded_writes
+    }
+
+    /// The register writes of the replay so far.
+    pub fn writes(&self) -> Vec<(usize, u64, u32)> {
+        self.writes.lock().unwrap().clone()
+    }
+
+    /// The number of resets of the replay so far.
+    pub fn resets(&self) -> usize {
+        self.resets.load(Ordering::Relaxed)
+    }
+}
+
+impl DeviceBackend for ReplayBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Replay
+    }
+
+    fn bars(&self) -> Result<[Bar; 6]> {
+        Ok(self.bars)
+    }
+
+    fn read_config(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let config = self.config.lock().unwrap();
+        let data = config.get(offset as usize..).unwrap_or_default();
+        let len = buf.len().min(data.len());
+        buf[..len].copy_from_slice(&data[..len]);
+
+        Ok(len)
+    }
+
+    fn write_config(&self, offset: u64, data: &[u8]) -> Result<()> {
+        let mut config = self.config.lock().unwrap();
+        let end = offset as usize + data.len();
+        if end > config.len() {
+            config.resize(end, 0);
+        }
+        config[offset as usize..end].copy_from_slice(data);
+
+        Ok(())
+    }
+
+    fn read32(&self, bar: usize, offset: u64) -> Result<u32> {
+        let mut reads = self.reads.lock().unwrap();
+        let Some(values) = reads.get_mut(&(bar, offset)) else {
+            return Ok(0);
+        };
+
+        Ok(match values.len() {
+            0 => 0,
+            1 => values[0],
+            _ => values.pop_front().unwrap(),
+        })
+    }
+
+    fn write32(&self, bar: usize, offset: u64, value: u32) -> Result<()> {
+        self.writes.lock().unwrap().push((bar, offset, value));
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.resets.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
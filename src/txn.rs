@@ -0,0 +1,229 @@
+use crate::{
+    bits::*,
+    dev::{self, GpuObject},
+    error::{NvTrustError, Result},
+    fsp::{FspRpc, PrcKnob},
+};
+
+/// The knobs touched by [`GpuObject::set_cc_mode`].
+const CC_KNOBS: [PrcKnob; 3] = [PrcKnob::CcDevMode, PrcKnob::CcMode, PrcKnob::Bar0Decoupler];
+
+/// The knobs touched by [`GpuObject::set_ppcie_mode`] on a GPU; an NVSwitch only has the last.
+const PPCIE_KNOBS: [PrcKnob; 4] = [
+    PrcKnob::CcDevMode,
+    PrcKnob::CcMode,
+    PrcKnob::Bar0Decoupler,
+    PrcKnob::Ppcie,
+];
+
+/// The knobs of a device before the transaction touched them.
+struct Snapshot<'a> {
+    gpu: &'a GpuObject,
+    knobs: Vec<(PrcKnob, u16)>,
+}
+
+impl<'a> Snapshot<'a> {
+    fn take(gpu: &'a GpuObject, knobs: &[PrcKnob], what: &str) -> Result<Self> {
+        gpu.ensure_pf(what)?;
+        gpu.ensure_cc(what)?;
+        gpu.wait_for_boot()?;
+
+        let rpc = FspRpc::new(gpu, NV_FSP_CHANNEL)?;
+        let knobs = knobs
+            .iter()
+            .map(|knob| Ok((*knob, rpc.prc_knob_read(*knob)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { gpu, knobs })
+    }
+
+    fn restore(&self) -> Result<()> {
+        let rpc = FspRpc::new(self.gpu, NV_FSP_CHANNEL)?;
+        for (knob, value) in self.knobs.iter() {
+            rpc.prc_knob_check_and_write(*knob, *value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Program all the given devices with `program`, or none of them: if any device fails, the knobs
+/// of the devices that were already programmed are rolled back.
+fn program_all(
+    gpus: &[GpuObject],
+    knobs: impl Fn(&GpuObject) -> &'static [PrcKnob] + Sync,
+    what: &str,
+    program: impl Fn(&GpuObject) -> Result<()>,
+) -> Result<()> {
+    // Take all the snapshots upfront so that a device we cannot even talk to aborts the
+    // transaction before anything is changed.
+    let snapshots = dev::par_map(gpus, |gpu| Snapshot::take(gpu, knobs(gpu), what))
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+
+    for (i, gpu) in gpus.iter().enumerate() {
+        if let Err(e) = program(gpu) {
+            log::error!("Failed to {what} on {}: {e}; rolling back.", gpu.get_bdf());
+
+            // The failed device may have been partially programmed, so it is rolled back too.
+            for snapshot in snapshots[..=i].iter().rev() {
+                match snapshot.restore() {
+                    Ok(()) => log::info!("Rolled back {}.", snapshot.gpu.get_bdf()),
+                    Err(re) => log::error!(
+                        "Failed to roll back {}: {re}. The board is in a mixed state!",
+                        snapshot.gpu.get_bdf()
+                    ),
+                }
+            }
+
+            return Err(NvTrustError::CcSwitchFailed {
+                device: gpu.get_label(),
+                reason: format!("cannot {what}: {e}"),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Program the CC mode on all the given devices, or on none of them.
+///
+/// The driver refuses to initialize a board whose devices disagree on the CC mode, so if any
+/// device fails, the knobs of the devices that were already programmed are rolled back before
+/// anything gets reset.
+pub fn set_cc_mode_all(gpus: &[GpuObject], mode: CcMode) -> Result<()> {
+    // NVSwitches have no CC mode; they take the PPCIe mode only.
+    if let Some(gpu) = gpus
+        .iter()
+        .find(|gpu| !PrcKnob::CcMode.supported_on(gpu.get_arch()))
+    {
+        return Err(NvTrustError::NotSupported {
+            what: "CC mode".to_string(),
+            device: gpu.get_label(),
+            reason: format!("{} devices do not support CC mode", gpu.get_arch()),
+        });
+    }
+
+    program_all(
+        gpus,
+        |_| &CC_KNOBS,
+        &format!("set CC mode {mode}"),
+        |gpu| {
+            gpu.set_cc_mode(mode)?;
+            log::info!("CC mode of {} set to {mode}.", gpu.get_bdf());
+            Ok(())
+        },
+    )
+}
+
+/// Program the PPCIe mode on all the given GPUs and NVSwitches of a board, or on none of them.
+///
+/// PPCIe protects the NVLink traffic between the GPUs through the NVSwitches, so a GPU or an
+/// NVSwitch left behind breaks the fabric for the whole board.
+pub fn set_ppcie_mode_all(devices: &[GpuObject], on: bool) -> Result<()> {
+    if let Some(device) = devices
+        .iter()
+        .find(|device| !PrcKnob::Ppcie.supported_on(device.get_arch()))
+    {
+        return Err(NvTrustError::NotSupported {
+            what: "PPCIe mode".to_string(),
+            device: device.get_label(),
+            reason: format!("{} GPUs do not support PPCIe", device.get_arch()),
+        });
+    }
+
+    let mode = on_off(on);
+    let knobs = |device: &GpuObject| -> &'static [PrcKnob] {
+        match device.is_nvswitch() {
+            true => &PPCIE_KNOBS[3..],
+            false => &PPCIE_KNOBS,
+        }
+    };
+    program_all(
+        devices,
+        knobs,
+        &format!("set PPCIe mode {mode}"),
+        |device| {
+            device.set_ppcie_mode(on)?;
+            log::info!("PPCIe mode of {} set to {mode}.", device.get_bdf());
+            Ok(())
+        },
+    )
+}
+
+/// Quiesce all the given devices, and then reset them at once and query each about the mode it
+/// came up in.
+fn reset_and_query<T: Send>(
+    gpus: &[GpuObject],
+    query: impl Fn(&GpuObject) -> Result<T> + Sync,
+) -> Result<Vec<T>> {
+    // Quiesce everything before resetting anything.
+    for gpu in gpus {
+        gpu.quiesce()?;
+    }
+
+    // Each reset and boot takes seconds, so the devices go through them at once.
+    dev::par_map(gpus, |gpu| {
+        gpu.reset()?;
+        gpu.wait_for_boot()?;
+        query(gpu)
+    })
+    .into_iter()
+    .collect()
+}
+
+/// Reset all the given devices so that the programmed mode becomes active, and optionally verify
+/// that every device reports it.
+pub fn reset_all(gpus: &[GpuObject], mode: CcMode, verify: bool) -> Result<()> {
+    let modes = reset_and_query(gpus, GpuObject::query_cc_mode)?;
+
+    let mut mismatch = vec![];
+    for (gpu, current) in gpus.iter().zip(modes) {
+        log::info!("{}: CC mode {current}", gpu.get_bdf());
+
+        if current != mode {
+            mismatch.push(format!("{} ({current})", gpu.get_bdf()));
+        }
+    }
+
+    if verify && !mismatch.is_empty() {
+        return Err(NvTrustError::CcSwitchFailed {
+            device: mismatch.join(", "),
+            reason: format!("CC mode {mode} not reported after the reset"),
+        });
+    }
+
+    Ok(())
+}
+
+/// Reset all the given devices so that the programmed PPCIe mode becomes active, and optionally
+/// verify that every device reports it.
+pub fn reset_all_ppcie(devices: &[GpuObject], on: bool, verify: bool) -> Result<()> {
+    let modes = reset_and_query(devices, GpuObject::query_ppcie_mode)?;
+
+    let mut mismatch = vec![];
+    for (device, current) in devices.iter().zip(modes) {
+        log::info!("{}: PPCIe mode {}", device.get_bdf(), on_off(current));
+
+        if current != on {
+            mismatch.push(format!("{} ({})", device.get_bdf(), on_off(current)));
+        }
+    }
+
+    if verify && !mismatch.is_empty() {
+        return Err(NvTrustError::CcSwitchFailed {
+            device: mismatch.join(", "),
+            reason: format!("PPCIe mode {} not reported after the reset", on_off(on)),
+        });
+    }
+
+    Ok(())
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
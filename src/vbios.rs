@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bits::*,
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+};
+
+/// A VBIOS image as read from the PROM, i.e., a chain of PCI expansion ROM images.
+#[derive(Debug, Clone)]
+pub struct Vbios {
+    pub image: Vec<u8>,
+    /// The ROM images in the chain.
+    pub images: Vec<RomImage>,
+}
+
+/// One PCI expansion ROM image of the VBIOS.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RomImage {
+    /// The offset of the image in the VBIOS.
+    pub offset: usize,
+    pub len: usize,
+    /// The code type from the PCI data structure, e.g., 0x00 for x86 and 0x03 for EFI.
+    pub code_type: u8,
+}
+
+/// A token of the BIT, pointing at one of the VBIOS tables.
+#[derive(Debug, Clone, Copy)]
+pub struct BitToken {
+    pub id: u8,
+    pub version: u8,
+    /// The offset of the data in the VBIOS.
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl Vbios {
+    /// Parse the raw PROM contents, trimming them to the end of the last ROM image.
+    pub fn from_bytes(prom: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+        let mut images = vec![];
+
+        loop {
+            let image = &prom[offset.min(prom.len())..];
+            if read16(image, 0) != Some(PCI_ROM_SIGNATURE) {
+                return Err(NvTrustError::InvalidVbios(format!(
+                    "no ROM signature 0x{:04x} at 0x{:x}",
+                    PCI_ROM_SIGNATURE, offset
+                )));
+            }
+
+            let pcir = read16(image, PCI_ROM_PCIR_OFFSET).unwrap_or_default() as usize;
+            if image.get(pcir..pcir + 4) != Some(PCI_ROM_PCIR_SIGNATURE.as_slice()) {
+                return Err(NvTrustError::InvalidVbios(format!(
+                    "no PCI data structure in the image at 0x{:x}",
+                    offset
+                )));
+            }
+
+            let len =
+                read16(image, pcir + PCI_ROM_PCIR_IMAGE_LEN).unwrap_or_default() as usize * 512;
+            let code_type = image
+                .get(pcir + PCI_ROM_PCIR_CODE_TYPE)
+                .copied()
+                .unwrap_or_default();
+            let indicator = image
+                .get(pcir + PCI_ROM_PCIR_INDICATOR)
+                .copied()
+                .unwrap_or(PCI_ROM_PCIR_LAST_IMAGE);
+            if len == 0 || offset + len > prom.len() {
+                return Err(NvTrustError::InvalidVbios(format!(
+                    "the image at 0x{:x} has an invalid length of 0x{:x}",
+                    offset, len
+                )));
+            }
+
+            images.push(RomImage {
+                offset,
+                len,
+                code_type,
+            });
+            offset += len;
+            if indicator & PCI_ROM_PCIR_LAST_IMAGE != 0 {
+                break;
+            }
+        }
+
+        Ok(Self {
+            image: prom[..offset].to_vec(),
+            images,
+        })
+    }
+
+    /// Parse the tokens of the BIT.
+    pub fn bit_tokens(&self) -> Result<Vec<BitToken>> {
+        let bit = self
+            .image
+            .windows(BIT_SIGNATURE.len())
+            .position(|w| w == BIT_SIGNATURE)
+            .ok_or(NvTrustError::InvalidVbios("no BIT found".to_string()))?;
+        let header = |offset| self.image.get(bit + offset).copied().unwrap_or_default() as usize;
+        let (header_size, token_size) = (header(BIT_HEADER_SIZE), header(BIT_HEADER_TOKEN_SIZE));
+
+        if token_size < 6 {
+            return Err(NvTrustError::InvalidVbios(format!(
+                "BIT tokens of {token_size} bytes are too short"
+            )));
+        }
+
+        (0..header(BIT_HEADER_TOKEN_COUNT))
+            .map(|i| {
+                let token = bit + header_size + i * token_size;
+                let entry = self
+                    .image
+                    .get(token..token + 6)
+                    .ok_or(NvTrustError::InvalidVbios(format!(
+                        "BIT token {i} is truncated"
+                    )))?;
+
+                Ok(BitToken {
+                    id: entry[0],
+                    version: entry[1],
+                    len: u16::from_le_bytes([entry[2], entry[3]]) as usize,
+                    offset: u16::from_le_bytes([entry[4], entry[5]]) as usize,
+                })
+            })
+            .collect()
+    }
+
+    /// Get the data of the BIT token with the given id.
+    pub fn bit_data(&self, id: u8) -> Result<Option<&[u8]>> {
+        Ok(self
+            .bit_tokens()?
+            .into_iter()
+            .find(|token| token.id == id)
+            .and_then(|token| self.image.get(token.offset..token.offset + token.len)))
+    }
+
+    /// Get the VBIOS build version as shown by nvidia-smi, e.g., `96.00.9F.00.01`.
+    pub fn version(&self) -> Result<String> {
+        let data = self
+            .bit_data(BIT_TOKEN_BIOSDATA)?
+            .filter(|data| data.len() >= 5)
+            .ok_or(NvTrustError::InvalidVbios(
+                "no BIOS data in the BIT".to_string(),
+            ))?;
+
+        Ok(format!(
+            "{:02X}.{:02X}.{:02X}.{:02X}.{:02X}",
+            data[3], data[2], data[1], data[0], data[4]
+        ))
+    }
+
+    /// Get the board ID, if the VBIOS has one.
+    pub fn board_id(&self) -> Result<Option<u16>> {
+        Ok(self
+            .bit_data(BIT_TOKEN_INTERNAL_USE)?
+            .and_then(|data| read16(data, BIT_INTERNAL_USE_BOARD_ID)))
+    }
+
+    /// Get the images that look like they hold the certificate chain and signatures, by their
+    /// code type alone. Their format is not public, so nothing in them is parsed or verified.
+    pub fn cert_blocks(&self) -> Vec<RomImage> {
+        self.images
+            .iter()
+            .filter(|image| image.code_type == PCI_ROM_CODE_TYPE_NV_CERT)
+            .copied()
+            .collect()
+    }
+}
+
+fn read16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+impl GpuObject {
+    /// Read the VBIOS from the flash through the PROM window.
+    ///
+    /// The ROM shadow is disabled for the read, so that we get the flash contents rather than the
+    /// copy in VRAM, and restored afterwards.
+    pub fn dump_vbios(&self) -> Result<Vbios> {
+        self.ensure_pf("VBIOS access")?;
+
+        let pci_nv_20 = self.read32(NV_PBUS_PCI_NV_20)?;
+        self.write32(
+            NV_PBUS_PCI_NV_20,
+            pci_nv_20 & !NV_PBUS_PCI_NV_20_ROM_SHADOW_ENABLED,
+        )?;
+
+        let prom = self.read_window(NV_PROM_DATA, NV_PROM_SIZE as usize);
+        self.write32(NV_PBUS_PCI_NV_20, pci_nv_20)?;
+
+        Vbios::from_bytes(&prom?)
+    }
+}
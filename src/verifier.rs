@@ -0,0 +1,392 @@
+use std::{collections::BTreeMap, fs};
+
+use ring::{digest, signature};
+use x509_parser::parse_x509_certificate;
+
+use crate::{
+    bits::*,
+    certs::{self, Verdict},
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+    evidence::Evidence,
+    identity,
+    policy::{self, Claims, Policy},
+    rim::{self, RimCache, RimIds},
+    spdm::Measurements,
+};
+
+/// The golden measurements of a firmware component, by measurement index.
+///
+/// An index may list several alternatives, e.g., for the two possible states of a fuse; the
+/// observed measurement has to match one of them.
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceValues {
+    /// The component, e.g., the driver or the VBIOS, and its version.
+    pub name: String,
+    pub measurements: BTreeMap<u32, Vec<String>>,
+    /// Whether the signature of the RIM was verified, see [`crate::rim::RimFormat::verify`].
+    pub signature_verified: bool,
+}
+
+impl ReferenceValues {
+    /// Read the reference values from a RIM in either of the forms NVIDIA publishes: a SWID tag
+    /// (XML) or a CoRIM (CBOR).
+    pub fn parse(rim: &[u8]) -> Result<Self> {
+        match std::str::from_utf8(rim) {
+            Ok(xml) if xml.trim_start().starts_with('<') => Self::from_swid(xml),
+            _ => Self::from_corim(rim),
+        }
+    }
+
+    /// Read the reference values from an NVIDIA RIM, a SWID tag whose `Resource` elements carry the
+    /// index, whether it is active and the alternatives as `Hash0`, `Hash1`, and so on.
+    pub fn from_swid(xml: &str) -> Result<Self> {
+        let name = attribute(element(xml, "SoftwareIdentity").unwrap_or_default(), "name")
+            .unwrap_or_default();
+        let version = attribute(
+            element(xml, "SoftwareIdentity").unwrap_or_default(),
+            "version",
+        )
+        .unwrap_or_default();
+
+        let mut measurements = BTreeMap::new();
+        for resource in xml.split("<Resource").skip(1) {
+            let resource = &resource[..resource.find('>').unwrap_or(resource.len())];
+            if attribute(resource, "type") != Some("Measurement") {
+                continue;
+            }
+            if attribute(resource, "active") == Some("False") {
+                continue;
+            }
+
+            let index = attribute(resource, "index")
+                .ok_or_else(|| {
+                    NvTrustError::Rim(format!("a measurement without an index in {name}"))
+                })?
+                .parse::<u32>()?;
+            let alternatives = (0..)
+                .map_while(|i| attribute(resource, &format!("Hash{i}")))
+                .map(|hash| hash.to_lowercase())
+                .collect::<Vec<_>>();
+            if alternatives.is_empty() {
+                return Err(NvTrustError::Rim(format!(
+                    "measurement {index} of {name} has no hash"
+                )));
+            }
+
+            measurements.insert(index, alternatives);
+        }
+
+        Ok(Self {
+            name: format!("{name} {version}").trim().to_string(),
+            measurements,
+            signature_verified: false,
+        })
+    }
+}
+
+/// Verify the evidence without any network access, the way NRAS would, and return the NRAS
+/// claims. The evidence passes if `x-nvidia-overall-att-result` is `true`.
+///
+/// Only what the signature covers is trusted: the measurements are parsed again from the signed
+/// report, and the other fields of the evidence must agree with it. The report must be signed
+/// over `nonce`, the one the caller asked for. The measurements are appraised against the union of
+/// the given reference values, e.g., of the driver and of the VBIOS RIMs.
+pub fn verify(
+    evidence: &Evidence,
+    nonce: &[u8; SPDM_NONCE_SIZE],
+    root_ca: &[u8],
+    references: &[ReferenceValues],
+) -> Claims {
+    let mut claims = Claims::new();
+    let mut claim = |name: &str, passed: bool| {
+        claims.insert(format!("x-nvidia-{name}"), passed.to_string());
+        passed
+    };
+
+    let report = match parse_report(evidence) {
+        Ok(report) => Some(report),
+        Err(e) => {
+            log::warn!("The report of {} is malformed: {e}", evidence.bdf);
+            None
+        }
+    };
+    let parsed = claim("gpu-attestation-report-parsed", report.is_some());
+
+    let mut pdi = None;
+    let chain = match certs::verify_chain(&evidence.certificates, root_ca) {
+        Ok(Verdict::Trusted { pdi: leaf }) => {
+            pdi = leaf;
+            true
+        }
+        Ok(Verdict::Untrusted(reason)) => {
+            log::warn!(
+                "The certificate chain of {} is untrusted: {reason}",
+                evidence.bdf
+            );
+            false
+        }
+        Err(e) => {
+            log::warn!(
+                "Cannot verify the certificate chain of {}: {e}",
+                evidence.bdf
+            );
+            false
+        }
+    };
+    let chain = claim("gpu-attestation-report-cert-chain-validated", chain);
+
+    let signature = match verify_signature(evidence) {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("The report of {} does not verify: {e}", evidence.bdf);
+            false
+        }
+    };
+    let signature = claim("gpu-attestation-report-signature-verified", signature);
+
+    let signed_nonce = report.as_ref().and_then(Measurements::request_nonce);
+    let nonce = signed_nonce == Some(&nonce[..]);
+    if !nonce {
+        log::warn!(
+            "The report of {} is not signed over the nonce",
+            evidence.bdf
+        );
+    }
+    let nonce = claim("gpu-attestation-report-nonce-match", nonce);
+
+    let available = !references.is_empty();
+    claim("gpu-rim-measurements-available", available);
+    // Reference values read without `rim::verify_swid` are only as good as where they came from.
+    // Policies that need authenticated RIMs can require this claim.
+    let verified = references
+        .iter()
+        .all(|reference| reference.signature_verified);
+    claim("gpu-rim-signature-verified", available && verified);
+    if available && !verified {
+        log::warn!("The signatures of some RIMs are not verified; they are trusted as given.");
+    }
+
+    let observed = report
+        .as_ref()
+        .map(Measurements::to_set)
+        .unwrap_or_default();
+    let mut mismatches = vec![];
+    for reference in references {
+        for (index, alternatives) in reference.measurements.iter() {
+            match observed.get(index) {
+                Some(observed) if alternatives.contains(&observed.to_lowercase()) => {}
+                observed => {
+                    log::warn!(
+                        "Measurement {index} of {} is {}, {} expects one of {}",
+                        evidence.bdf,
+                        observed.map_or("missing", |o| o.as_str()),
+                        reference.name,
+                        alternatives.join(", ")
+                    );
+                    mismatches.push(index.to_string());
+                }
+            }
+        }
+    }
+    let measurements = claim(
+        "gpu-measurements-match",
+        parsed && available && mismatches.is_empty(),
+    );
+    claims.insert(
+        "x-nvidia-mismatch-measurement-records".to_string(),
+        mismatches.join(","),
+    );
+
+    let overall = parsed && chain && signature && nonce && measurements;
+    claims.insert(
+        "x-nvidia-overall-att-result".to_string(),
+        overall.to_string(),
+    );
+    claims.insert(
+        "measres".to_string(),
+        if measurements { "success" } else { "fail" }.to_string(),
+    );
+    // The identity of the device is the one its trusted leaf certificate is issued to.
+    if let Some(pdi) = pdi {
+        claims.insert("pdi".to_string(), pdi);
+    }
+
+    claims
+}
+
+/// What the evidence of a GPU is verified against.
+#[derive(Default)]
+pub struct Verification {
+    /// The pinned root CA of the device certificates (DER).
+    pub root_ca: Vec<u8>,
+    /// The reference values of the measurements.
+    pub references: Vec<ReferenceValues>,
+    /// A cache of the RIMs of the running firmware, whose reference values are added.
+    pub rim_cache: Option<RimCache>,
+    /// The policy the claims must pass, if any.
+    pub policy: Option<Policy>,
+}
+
+impl Verification {
+    /// Read the pinned root CA (PEM), the RIMs, verified against the pinned root of the RIM
+    /// signers (PEM), and the policy from their files.
+    ///
+    /// The root of the RIM signers is only read if there are RIMs to verify.
+    pub fn load(
+        root_ca: &str,
+        rims: &[String],
+        rim_cache: Option<&str>,
+        rim_root_ca: &str,
+        policy: Option<&str>,
+    ) -> Result<Self> {
+        let rim_root = match rims.is_empty() && rim_cache.is_none() {
+            true => vec![],
+            false => identity::decode_pem(&fs::read(rim_root_ca)?)?,
+        };
+
+        Ok(Self {
+            root_ca: identity::decode_pem(&fs::read(root_ca)?)?,
+            references: rims
+                .iter()
+                .map(|path| rim::load_rim(&fs::read(path)?, &rim_root))
+                .collect::<Result<Vec<_>>>()?,
+            rim_cache: rim_cache.map(|dir| RimCache::new(dir, &rim_root)),
+            policy: match policy {
+                Some(policy) => Some(Policy::parse(&fs::read_to_string(policy)?)?),
+                None => None,
+            },
+        })
+    }
+}
+
+/// Collect the evidence of the GPU over the nonce and verify it, see [`verify`], against the
+/// reference values and the cached RIMs of its firmware.
+///
+/// The claims of the signed evidence are added, and so are the register reads of
+/// [`GpuObject::collect_claims`], as [`policy::unverified`] claims. The policy is not evaluated.
+pub fn verify_local(
+    gpu: &GpuObject,
+    nonce: [u8; SPDM_NONCE_SIZE],
+    verification: &Verification,
+) -> Result<(Evidence, Claims)> {
+    let evidence = gpu.collect_evidence(0, nonce)?;
+
+    let mut references = verification.references.clone();
+    if let Some(cache) = &verification.rim_cache {
+        for id in RimIds::from_evidence(&evidence).iter() {
+            references.push(cache.load(id)?);
+        }
+    }
+
+    let mut claims = verify(&evidence, &nonce, &verification.root_ca, &references);
+    claims.extend(policy::unverified(gpu.collect_claims()?));
+    claims.extend(evidence.claims());
+
+    Ok((evidence, claims))
+}
+
+/// Parse the measurements from the report, and check that the signature covers the report and
+/// that the other fields of the evidence are the ones the report carries.
+fn parse_report(evidence: &Evidence) -> Result<Measurements> {
+    let report = Measurements::from_report(&evidence.report, evidence.algorithms.signature_size())?;
+    let mismatch = |what: &str| {
+        Err(NvTrustError::Spdm(format!(
+            "the {what} is not the signed one"
+        )))
+    };
+
+    if report.report.len() != evidence.report.len() {
+        return mismatch("report");
+    }
+    if report.signature.as_deref() != Some(&evidence.signature[..]) {
+        return mismatch("signature");
+    }
+    // The transcript ends with the report, or the signature is over something else.
+    if !evidence.transcript.ends_with(report.signed_report()) {
+        return mismatch("transcript");
+    }
+    if report.request_nonce() != Some(&evidence.nonce[..]) {
+        return mismatch("nonce");
+    }
+    if report.opaque != evidence.opaque {
+        return mismatch("opaque data");
+    }
+    if report.to_set() != evidence.measurements {
+        return mismatch("measurement set");
+    }
+
+    Ok(report)
+}
+
+/// Verify the signature of the report with the key of the leaf certificate.
+///
+/// Up to SPDM 1.1 the signature is over the transcript. Since SPDM 1.2 it is over a prefix naming
+/// the version and the context, followed by the hash of the transcript.
+fn verify_signature(evidence: &Evidence) -> Result<()> {
+    let leaf = evidence
+        .certificates
+        .last()
+        .ok_or_else(|| NvTrustError::Spdm("no certificates".to_string()))?;
+    let (_, leaf) = parse_x509_certificate(leaf)
+        .map_err(|e| NvTrustError::Spdm(format!("invalid leaf: {e}")))?;
+    let key = &leaf.public_key().subject_public_key.data;
+
+    let (algorithm, hash): (&signature::EcdsaVerificationAlgorithm, _) =
+        match evidence.algorithms.base_asym {
+            SPDM_ASYM_ECDSA_P256 => (&signature::ECDSA_P256_SHA256_FIXED, &digest::SHA256),
+            SPDM_ASYM_ECDSA_P384 => (&signature::ECDSA_P384_SHA384_FIXED, &digest::SHA384),
+            asym => {
+                return Err(NvTrustError::Spdm(format!(
+                    "unsupported signature algorithm 0x{asym:x}"
+                )))
+            }
+        };
+
+    let message = if evidence.spdm_version >= SPDM_VERSION_12 {
+        let version = format!(
+            "dmtf-spdm-v{}.{}.*",
+            evidence.spdm_version >> 4,
+            evidence.spdm_version & 0xf
+        );
+        let mut message = version.repeat(4).into_bytes();
+        message.resize(
+            message.len() + SPDM_SIGNING_CONTEXT_SIZE - SPDM_MEASUREMENTS_SIGNING_CONTEXT.len(),
+            0,
+        );
+        message.extend_from_slice(SPDM_MEASUREMENTS_SIGNING_CONTEXT.as_bytes());
+        message.extend_from_slice(digest::digest(hash, &evidence.transcript).as_ref());
+        message
+    } else {
+        evidence.transcript.clone()
+    };
+
+    signature::UnparsedPublicKey::new(algorithm, key)
+        .verify(&message, &evidence.signature)
+        .map_err(|_| NvTrustError::Spdm("bad signature".to_string()))
+}
+
+/// The start tag of the first element with the given name.
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{name}"))?;
+    let end = xml[start..].find('>')?;
+
+    Some(&xml[start..start + end])
+}
+
+/// The value of an attribute in a start tag, ignoring any namespace prefix.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let needle = format!("{name}=\"");
+    let mut rest = tag;
+
+    while let Some(pos) = rest.find(&needle) {
+        let value = &rest[pos + needle.len()..];
+        let boundary = rest[..pos].ends_with(|c: char| c.is_whitespace() || c == ':');
+        if boundary {
+            return value.find('"').map(|end| &value[..end]);
+        }
+        rest = value;
+    }
+
+    None
+}
@@ -0,0 +1,259 @@
+use std::{
+    ffi::CString,
+    fs::{File, OpenOptions},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::fs::FileExt,
+    },
+    path::Path,
+};
+
+use crate::{
+    bits::*,
+    dev::Mapping,
+    error::{NvTrustError, Result},
+};
+
+/// `struct vfio_group_status` of the kernel.
+#[repr(C)]
+struct VfioGroupStatus {
+    argsz: u32,
+    flags: u32,
+}
+
+/// `struct vfio_region_info` of the kernel.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VfioRegionInfo {
+    argsz: u32,
+    pub flags: u32,
+    pub index: u32,
+    cap_offset: u32,
+    pub size: u64,
+    /// The offset of the region in the device file.
+    pub offset: u64,
+}
+
+nix::ioctl_none_bad!(
+    vfio_get_api_version,
+    nix::request_code_none!(VFIO_TYPE, VFIO_BASE)
+);
+nix::ioctl_write_int_bad!(
+    vfio_check_extension,
+    nix::request_code_none!(VFIO_TYPE, VFIO_BASE + 1)
+);
+nix::ioctl_write_int_bad!(
+    vfio_set_iommu,
+    nix::request_code_none!(VFIO_TYPE, VFIO_BASE + 2)
+);
+nix::ioctl_readwrite_bad!(
+    vfio_group_get_status,
+    nix::request_code_none!(VFIO_TYPE, VFIO_BASE + 3),
+    VfioGroupStatus
+);
+nix::ioctl_write_ptr_bad!(
+    vfio_group_set_container,
+    nix::request_code_none!(VFIO_TYPE, VFIO_BASE + 4),
+    i32
+);
+nix::ioctl_write_ptr_bad!(
+    vfio_group_get_device_fd,
+    nix::request_code_none!(VFIO_TYPE, VFIO_BASE + 6),
+    std::ffi::c_char
+);
+nix::ioctl_readwrite_bad!(
+    vfio_device_get_region_info,
+    nix::request_code_none!(VFIO_TYPE, VFIO_BASE + 8),
+    VfioRegionInfo
+);
+nix::ioctl_none_bad!(
+    vfio_device_reset,
+    nix::request_code_none!(VFIO_TYPE, VFIO_BASE + 11)
+);
+
+/// A PCI device bound to vfio-pci, accessed through its VFIO device file: the BARs and the config
+/// space are regions of the file and the device sits behind its own IOMMU domain, so nothing
+/// touches physical memory directly.
+///
+/// VFIO groups can only be opened once, so the device is opened when it is found and shared by
+/// all the mappings.
+#[derive(Debug)]
+pub struct VfioDevice {
+    bdf: String,
+    // The container and the group must outlive the device file.
+    _container: File,
+    _group: File,
+    device: File,
+}
+
+impl VfioDevice {
+    /// Open the device at the given sysfs path, which must be bound to vfio-pci.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let bdf = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let rw = |path: &Path| OpenOptions::new().read(true).write(true).open(path);
+        let err = |what: &str, e: nix::Error| NvTrustError::Vfio(format!("{bdf}: {what}: {e}"));
+
+        let group = std::fs::read_link(path.join("iommu_group"))?;
+        let group = group
+            .file_name()
+            .ok_or_else(|| NvTrustError::Vfio(format!("{bdf} has no IOMMU group")))?;
+        let group = Path::new(VFIO_DIR).join(group);
+
+        let container = rw(Path::new(VFIO_CONTAINER))?;
+        // SAFETY: these ioctls take no or integer arguments.
+        let version = unsafe { vfio_get_api_version(container.as_raw_fd()) }
+            .map_err(|e| err("VFIO_GET_API_VERSION", e))?;
+        if version != VFIO_API_VERSION {
+            return Err(NvTrustError::Vfio(format!(
+                "unknown VFIO API version {version}"
+            )));
+        }
+        let iommu = [VFIO_TYPE1V2_IOMMU, VFIO_TYPE1_IOMMU]
+            .into_iter()
+            .find(|iommu| {
+                unsafe { vfio_check_extension(container.as_raw_fd(), *iommu) }.is_ok_and(|r| r > 0)
+            })
+            .ok_or_else(|| NvTrustError::Vfio("no type 1 IOMMU support".to_string()))?;
+
+        let group = rw(&group).map_err(|e| {
+            NvTrustError::Vfio(format!(
+                "cannot open {}: {e}; is the group in use by a VM?",
+                group.display()
+            ))
+        })?;
+        let mut status = VfioGroupStatus {
+            argsz: std::mem::size_of::<VfioGroupStatus>() as _,
+            flags: 0,
+        };
+        // SAFETY: the status is a live `struct vfio_group_status`.
+        unsafe { vfio_group_get_status(group.as_raw_fd(), &mut status) }
+            .map_err(|e| err("VFIO_GROUP_GET_STATUS", e))?;
+        if status.flags & VFIO_GROUP_FLAGS_VIABLE == 0 {
+            return Err(NvTrustError::Vfio(format!(
+                "the IOMMU group of {bdf} is not viable: bind all its devices to vfio-pci"
+            )));
+        }
+
+        let container_fd = container.as_raw_fd();
+        // SAFETY: the argument points at the container file descriptor.
+        unsafe { vfio_group_set_container(group.as_raw_fd(), &container_fd) }
+            .map_err(|e| err("VFIO_GROUP_SET_CONTAINER", e))?;
+        unsafe { vfio_set_iommu(container.as_raw_fd(), iommu) }
+            .map_err(|e| err("VFIO_SET_IOMMU", e))?;
+
+        let name = CString::new(bdf.clone())
+            .map_err(|_| NvTrustError::InvalidArgument(format!("invalid BDF {bdf}")))?;
+        // SAFETY: the argument is a NUL-terminated string, and the ioctl returns a new file
+        // descriptor we take ownership of.
+        let device = unsafe { vfio_group_get_device_fd(group.as_raw_fd(), name.as_ptr()) }
+            .map_err(|e| err("VFIO_GROUP_GET_DEVICE_FD", e))?;
+        let device = unsafe { File::from_raw_fd(device) };
+
+        Ok(Self {
+            bdf,
+            _container: container,
+            _group: group,
+            device,
+        })
+    }
+
+    /// Get the region of a BAR, `VFIO_PCI_BAR0_REGION_INDEX` to `VFIO_PCI_BAR5_REGION_INDEX`, or of
+    /// the config space, [`VFIO_PCI_CONFIG_REGION_INDEX`].
+    pub fn region_info(&self, index: u32) -> Result<VfioRegionInfo> {
+        let mut info = VfioRegionInfo {
+            argsz: std::mem::size_of::<VfioRegionInfo>() as _,
+            index,
+            ..Default::default()
+        };
+
+        // SAFETY: the info is a live `struct vfio_region_info`.
+        unsafe { vfio_device_get_region_info(self.device.as_raw_fd(), &mut info) }.map_err(
+            |e| NvTrustError::Vfio(format!("{}: VFIO_DEVICE_GET_REGION_INFO: {e}", self.bdf)),
+        )?;
+
+        Ok(info)
+    }
+
+    /// Map `len` bytes at the page-aligned `offset` of a region.
+    pub fn map_region(
+        &self,
+        index: u32,
+        offset: u64,
+        len: usize,
+        writable: bool,
+    ) -> Result<Mapping> {
+        let info = self.checked_region(index, offset, len)?;
+        if info.flags & VFIO_REGION_INFO_FLAG_MMAP == 0 {
+            return Err(NvTrustError::NotSupported {
+                what: format!("Mapping region {index}"),
+                device: self.bdf.clone(),
+                reason: "vfio-pci does not allow it".to_string(),
+            });
+        }
+
+        let fd: OwnedFd = self.device.try_clone()?.into();
+        Mapping::from_fd(
+            &format!("region {index} of {}", self.bdf),
+            fd,
+            info.offset + offset,
+            len,
+            writable,
+        )
+    }
+
+    /// Read a region with `pread`, for the regions that cannot be mapped, e.g., the config space.
+    pub fn read_region(&self, index: u32, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let info = self.checked_region(index, offset, buf.len())?;
+        if info.flags & VFIO_REGION_INFO_FLAG_READ == 0 {
+            return Err(NvTrustError::Vfio(format!(
+                "region {index} of {} is not readable",
+                self.bdf
+            )));
+        }
+
+        Ok(self.device.read_exact_at(buf, info.offset + offset)?)
+    }
+
+    /// Write a region with `pwrite`.
+    pub fn write_region(&self, index: u32, offset: u64, buf: &[u8]) -> Result<()> {
+        let info = self.checked_region(index, offset, buf.len())?;
+        if info.flags & VFIO_REGION_INFO_FLAG_WRITE == 0 {
+            return Err(NvTrustError::Vfio(format!(
+                "region {index} of {} is not writable",
+                self.bdf
+            )));
+        }
+
+        Ok(self.device.write_all_at(buf, info.offset + offset)?)
+    }
+
+    /// Reset the device through vfio-pci, which saves and restores the config space around it.
+    pub fn reset(&self) -> Result<()> {
+        // SAFETY: the ioctl takes no argument.
+        unsafe { vfio_device_reset(self.device.as_raw_fd()) }
+            .map_err(|e| NvTrustError::Vfio(format!("{}: VFIO_DEVICE_RESET: {e}", self.bdf)))?;
+
+        Ok(())
+    }
+
+    fn checked_region(&self, index: u32, offset: u64, len: usize) -> Result<VfioRegionInfo> {
+        let info = self.region_info(index)?;
+        if offset
+            .checked_add(len as u64)
+            .is_none_or(|end| end > info.size)
+        {
+            return Err(NvTrustError::OutOfRange {
+                bar: "the VFIO region",
+                offset,
+                size: len as _,
+                limit: info.size,
+            });
+        }
+
+        Ok(info)
+    }
+}
@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use crate::{
+    bits::*,
+    dev::GpuObject,
+    error::{NvTrustError, Result},
+};
+
+/// The CC state of a confidential vGPU, as visible from within the guest.
+#[derive(Debug, Clone)]
+pub struct VgpuState {
+    /// Whether the host has made the VF ready to accept work.
+    pub ready: bool,
+    /// The CC mode of the physical GPU backing the VF.
+    pub cc_mode: CcMode,
+    /// The size of the protected memory assigned to the VF, in MB.
+    pub protected_mem_mb: u32,
+    /// The offset of the DOE mailbox in config space, if the VF exposes one for SPDM.
+    pub doe_offset: Option<u64>,
+    /// The driver bound to the VF; attestation through the driver needs it to be `nvidia`.
+    pub driver: Option<String>,
+}
+
+impl VgpuState {
+    /// The ways a guest can attest this vGPU.
+    pub fn attestation_entry_points(&self) -> Vec<String> {
+        let mut entries = vec![];
+
+        if let Some(offset) = self.doe_offset {
+            entries.push(format!(
+                "SPDM over the DOE mailbox at config offset 0x{offset:x}"
+            ));
+        }
+        if self.driver.as_deref() == Some("nvidia") && Path::new("/dev/nvidiactl").exists() {
+            entries.push("SPDM through the nvidia driver (/dev/nvidiactl)".to_string());
+        }
+
+        entries
+    }
+}
+
+impl GpuObject {
+    /// Query the CC state visible to a vGPU VF.
+    pub fn query_vgpu_state(&self) -> Result<VgpuState> {
+        if !self.is_vf() {
+            return Err(NvTrustError::NotSupported {
+                what: "The vGPU state".to_string(),
+                device: self.get_label(),
+                reason: "it is not a virtual function".to_string(),
+            });
+        }
+
+        let device = self.get_device_handle();
+
+        Ok(VgpuState {
+            ready: self.read32(NV_VF_READY)? & 0x1 == 0x1,
+            cc_mode: self.query_cc_mode()?,
+            protected_mem_mb: self.read32(NV_VF_PROTECTED_MEM_SIZE)?,
+            doe_offset: device.find_ext_cap(PCI_EXT_CAP_ID_DOE)?,
+            driver: device.get_driver(),
+        })
+    }
+}
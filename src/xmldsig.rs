@@ -0,0 +1,721 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use base64::Engine;
+use ring::{digest, signature};
+use x509_parser::parse_x509_certificate;
+
+use crate::{
+    bits::*,
+    certs::{self, Verdict},
+    error::{NvTrustError, Result},
+};
+
+/// The nesting limit of the parser, which is far beyond what a RIM needs.
+const MAX_DEPTH: usize = 64;
+
+/// A node of an XML document, as far as canonicalization needs it.
+#[derive(Debug)]
+enum Node {
+    Element(Element),
+    Text(String),
+    Comment(String),
+    Pi(String, String),
+}
+
+#[derive(Debug)]
+struct Element {
+    /// The qualified name, e.g., `ds:Signature`.
+    name: String,
+    /// The attributes in document order, namespace declarations included.
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+/// The namespaces in scope, by prefix; the default namespace has the empty prefix.
+type Scope = BTreeMap<String, String>;
+
+impl Element {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            _ => None,
+        })
+    }
+
+    fn child(&self, name: &str) -> Result<&Element> {
+        self.elements()
+            .find(|element| local(&element.name) == name)
+            .ok_or_else(|| invalid(format!("no {name} in {}", self.name)))
+    }
+
+    fn text(&self) -> String {
+        self.children
+            .iter()
+            .filter_map(|node| match node {
+                Node::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The base64 content of the element, e.g., of a `DigestValue`.
+    fn base64(&self) -> Result<Vec<u8>> {
+        base64::engine::general_purpose::STANDARD
+            .decode(self.text().split_whitespace().collect::<String>())
+            .map_err(|e| invalid(format!("invalid base64 in {}: {e}", self.name)))
+    }
+
+    /// Add the namespaces the element declares to the scope.
+    fn declare(&self, scope: &mut Scope) {
+        for (name, value) in self.attributes.iter() {
+            let prefix = match name.strip_prefix("xmlns") {
+                Some("") => "",
+                Some(prefix) => match prefix.strip_prefix(':') {
+                    Some(prefix) => prefix,
+                    None => continue,
+                },
+                None => continue,
+            };
+
+            match value.is_empty() && !prefix.is_empty() {
+                true => scope.remove(prefix),
+                false => scope.insert(prefix.to_string(), value.clone()),
+            };
+        }
+    }
+
+    /// The path from the element to the first descendant, or itself, that matches.
+    fn find<'a>(&'a self, matches: &impl Fn(&Element) -> bool) -> Option<Vec<&'a Element>> {
+        if matches(self) {
+            return Some(vec![self]);
+        }
+
+        self.elements().find_map(|child| {
+            let mut path = child.find(matches)?;
+            path.insert(0, self);
+            Some(path)
+        })
+    }
+}
+
+fn prefix(name: &str) -> &str {
+    name.split_once(':').map_or("", |(prefix, _)| prefix)
+}
+
+fn local(name: &str) -> &str {
+    name.split_once(':').map_or(name, |(_, local)| local)
+}
+
+fn is_namespace_declaration(name: &str) -> bool {
+    name == "xmlns" || name.starts_with("xmlns:")
+}
+
+fn invalid(reason: String) -> NvTrustError {
+    NvTrustError::Rim(format!("invalid XML signature: {reason}"))
+}
+
+fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r')
+}
+
+/// A parser of the XML subset RIMs are written in: no DTDs, and so no entities beyond the
+/// predefined ones.
+struct Parser<'a> {
+    xml: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.xml[self.offset..]
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.rest().starts_with(token);
+        if found {
+            self.offset += token.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        match self.eat(token) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected {token}"))),
+        }
+    }
+
+    fn error(&self, reason: &str) -> NvTrustError {
+        invalid(format!("{reason} at byte {}", self.offset))
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.offset += rest.len() - rest.trim_start_matches(is_space).len();
+    }
+
+    fn until(&mut self, end: &str) -> Result<&'a str> {
+        let rest = self.rest();
+        let len = rest
+            .find(end)
+            .ok_or_else(|| self.error(&format!("no closing {end}")))?;
+        self.offset += len + end.len();
+        Ok(&rest[..len])
+    }
+
+    fn name(&mut self) -> Result<&'a str> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| is_space(c) || matches!(c, '=' | '>' | '/' | '?' | '<'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected a name"));
+        }
+        self.offset += len;
+        Ok(&rest[..len])
+    }
+
+    fn document(&mut self) -> Result<Vec<Node>> {
+        self.eat("\u{feff}");
+        if self.eat("<?xml") {
+            self.until("?>")?;
+        }
+
+        let mut nodes = vec![];
+        loop {
+            self.skip_space();
+            if self.rest().is_empty() {
+                break;
+            } else if self.eat("<!--") {
+                nodes.push(Node::Comment(self.until("-->")?.to_string()));
+            } else if self.eat("<?") {
+                nodes.push(self.pi()?);
+            } else if self.rest().starts_with("<!") {
+                return Err(self.error("DTDs are not supported"));
+            } else if self.eat("<") {
+                if nodes.iter().any(|node| matches!(node, Node::Element(_))) {
+                    return Err(self.error("a second root element"));
+                }
+                nodes.push(Node::Element(self.element(0)?));
+            } else {
+                return Err(self.error("text outside the root element"));
+            }
+        }
+
+        if !nodes.iter().any(|node| matches!(node, Node::Element(_))) {
+            return Err(self.error("no root element"));
+        }
+        Ok(nodes)
+    }
+
+    fn pi(&mut self) -> Result<Node> {
+        let target = self.name()?;
+        if target.eq_ignore_ascii_case("xml") {
+            return Err(self.error("misplaced XML declaration"));
+        }
+        let data = self.until("?>")?.trim_start_matches(is_space);
+
+        Ok(Node::Pi(target.to_string(), data.to_string()))
+    }
+
+    fn element(&mut self, depth: usize) -> Result<Element> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("XML nested too deeply"));
+        }
+
+        let mut element = Element {
+            name: self.name()?.to_string(),
+            attributes: vec![],
+            children: vec![],
+        };
+
+        loop {
+            self.skip_space();
+            if self.eat("/>") {
+                return Ok(element);
+            }
+            if self.eat(">") {
+                break;
+            }
+
+            let name = self.name()?.to_string();
+            self.skip_space();
+            self.expect("=")?;
+            self.skip_space();
+            let quote = if self.eat("\"") {
+                "\""
+            } else {
+                self.expect("'")?;
+                "'"
+            };
+            let raw = self.until(quote)?;
+            if raw.contains('<') {
+                return Err(self.error("< in an attribute value"));
+            }
+            if element.attribute(&name).is_some() {
+                return Err(self.error(&format!("duplicate attribute {name}")));
+            }
+            // Attribute values are normalized before references are expanded.
+            let value = unescape(&raw.replace(['\t', '\n'], " "))?;
+            element.attributes.push((name, value));
+        }
+
+        loop {
+            if self.eat("</") {
+                let name = self.name()?;
+                self.skip_space();
+                self.expect(">")?;
+                if name != element.name {
+                    return Err(self.error(&format!("</{name}> closes <{}>", element.name)));
+                }
+                return Ok(element);
+            } else if self.eat("<!--") {
+                element
+                    .children
+                    .push(Node::Comment(self.until("-->")?.to_string()));
+            } else if self.eat("<![CDATA[") {
+                element
+                    .children
+                    .push(Node::Text(self.until("]]>")?.to_string()));
+            } else if self.eat("<?") {
+                element.children.push(self.pi()?);
+            } else if self.rest().starts_with("<!") {
+                return Err(self.error("DTDs are not supported"));
+            } else if self.eat("<") {
+                element
+                    .children
+                    .push(Node::Element(self.element(depth + 1)?));
+            } else {
+                let rest = self.rest();
+                let len = rest
+                    .find('<')
+                    .ok_or_else(|| self.error(&format!("unclosed <{}>", element.name)))?;
+                self.offset += len;
+                element.children.push(Node::Text(unescape(&rest[..len])?));
+            }
+        }
+    }
+}
+
+/// Expand the character and predefined entity references.
+fn unescape(text: &str) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let len = rest[start..]
+            .find(';')
+            .ok_or_else(|| invalid("unterminated entity reference".to_string()))?;
+        let entity = &rest[start + 1..start + len];
+        out.push(match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => match entity.strip_prefix("#x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => entity.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+            }
+            .and_then(char::from_u32)
+            .ok_or_else(|| invalid(format!("unknown entity &{entity};")))?,
+        });
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+/// Parse the document, with its line ends normalized as an XML processor does.
+fn parse(xml: &str) -> Result<Vec<Node>> {
+    let xml = xml.replace("\r\n", "\n").replace('\r', "\n");
+    Parser {
+        xml: &xml,
+        offset: 0,
+    }
+    .document()
+}
+
+/// Exclusive XML canonicalization (exc-c14n) of a document or of an element in it.
+struct Canonicalizer<'a> {
+    /// The element the enveloped-signature transform leaves out.
+    exclude: Option<&'a Element>,
+    comments: bool,
+    /// The `InclusiveNamespaces` prefixes, rendered as by inclusive canonicalization.
+    inclusive: BTreeSet<String>,
+    out: String,
+}
+
+impl<'a> Canonicalizer<'a> {
+    fn new(algorithm: &Element, exclude: Option<&'a Element>) -> Result<Self> {
+        let comments = match algorithm.attribute("Algorithm") {
+            Some(XMLDSIG_EXC_C14N) => false,
+            Some(XMLDSIG_EXC_C14N_WITH_COMMENTS) => true,
+            other => {
+                return Err(invalid(format!(
+                    "unsupported canonicalization {}",
+                    other.unwrap_or("none")
+                )))
+            }
+        };
+        let inclusive = algorithm
+            .elements()
+            .find(|element| local(&element.name) == "InclusiveNamespaces")
+            .and_then(|element| element.attribute("PrefixList"))
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|prefix| match prefix {
+                "#default" => String::new(),
+                prefix => prefix.to_string(),
+            })
+            .collect();
+
+        Ok(Self {
+            exclude,
+            comments,
+            inclusive,
+            out: String::new(),
+        })
+    }
+
+    /// Canonicalize the whole document.
+    fn document(mut self, nodes: &[Node]) -> Result<String> {
+        let mut after_root = false;
+        for node in nodes {
+            match node {
+                Node::Element(element) => {
+                    self.element(element, &Scope::new(), &Scope::new())?;
+                    after_root = true;
+                }
+                Node::Comment(_) if !self.comments => {}
+                node => {
+                    if after_root {
+                        self.out.push('\n');
+                    }
+                    self.node(node, &Scope::new(), &Scope::new())?;
+                    if !after_root {
+                        self.out.push('\n');
+                    }
+                }
+            }
+        }
+
+        Ok(self.out)
+    }
+
+    /// Canonicalize the last element of the path from the root, in the scope of its ancestors.
+    fn subtree(mut self, path: &[&Element]) -> Result<String> {
+        let (element, ancestors) = path.split_last().expect("empty path");
+        let mut scope = Scope::new();
+        for ancestor in ancestors {
+            ancestor.declare(&mut scope);
+        }
+
+        self.element(element, &scope, &Scope::new())?;
+        Ok(self.out)
+    }
+
+    fn node(&mut self, node: &Node, scope: &Scope, rendered: &Scope) -> Result<()> {
+        match node {
+            Node::Element(element) => self.element(element, scope, rendered)?,
+            Node::Text(text) => escape(&mut self.out, text, false),
+            Node::Comment(comment) if self.comments => {
+                self.out.push_str(&format!("<!--{comment}-->"))
+            }
+            Node::Comment(_) => {}
+            Node::Pi(target, data) if data.is_empty() => {
+                self.out.push_str(&format!("<?{target}?>"))
+            }
+            Node::Pi(target, data) => self.out.push_str(&format!("<?{target} {data}?>")),
+        }
+
+        Ok(())
+    }
+
+    fn element(&mut self, element: &Element, scope: &Scope, rendered: &Scope) -> Result<()> {
+        if self
+            .exclude
+            .is_some_and(|exclude| std::ptr::eq(exclude, element))
+        {
+            return Ok(());
+        }
+
+        let mut scope = scope.clone();
+        element.declare(&mut scope);
+
+        // Only the namespaces the element and its attributes use are rendered.
+        let attributes = element
+            .attributes
+            .iter()
+            .filter(|(name, _)| !is_namespace_declaration(name))
+            .collect::<Vec<_>>();
+        let mut prefixes = BTreeSet::from([prefix(&element.name)]);
+        prefixes.extend(
+            attributes
+                .iter()
+                .map(|(name, _)| prefix(name))
+                .filter(|prefix| !prefix.is_empty()),
+        );
+        prefixes.extend(
+            self.inclusive
+                .iter()
+                .filter(|prefix| prefix.is_empty() || scope.contains_key(*prefix))
+                .map(String::as_str),
+        );
+
+        let mut rendered = rendered.clone();
+        let mut declarations = vec![];
+        for prefix in prefixes.into_iter().filter(|prefix| *prefix != "xml") {
+            let uri = scope.get(prefix).map_or("", String::as_str);
+            if !prefix.is_empty() && uri.is_empty() {
+                return Err(invalid(format!("unbound prefix {prefix}")));
+            }
+            if rendered.get(prefix).map_or("", String::as_str) == uri {
+                continue;
+            }
+
+            rendered.insert(prefix.to_string(), uri.to_string());
+            declarations.push((prefix, uri));
+        }
+
+        // Attributes sort by namespace URI, then by local name; unqualified ones come first.
+        let mut sorted = attributes
+            .iter()
+            .map(|(name, value)| {
+                let uri = match prefix(name) {
+                    "" => "",
+                    "xml" => XML_NS,
+                    prefix => scope.get(prefix).map_or("", String::as_str),
+                };
+                ((uri, local(name)), name, value)
+            })
+            .collect::<Vec<_>>();
+        sorted.sort_by(|a, b| a.0.cmp(&b.0));
+
+        self.out.push('<');
+        self.out.push_str(&element.name);
+        for (prefix, uri) in declarations {
+            match prefix {
+                "" => self.out.push_str(" xmlns=\""),
+                prefix => self.out.push_str(&format!(" xmlns:{prefix}=\"")),
+            }
+            escape(&mut self.out, uri, true);
+            self.out.push('"');
+        }
+        for (_, name, value) in sorted {
+            self.out.push_str(&format!(" {name}=\""));
+            escape(&mut self.out, value, true);
+            self.out.push('"');
+        }
+        self.out.push('>');
+
+        for child in element.children.iter() {
+            self.node(child, &scope, &rendered)?;
+        }
+        self.out.push_str(&format!("</{}>", element.name));
+
+        Ok(())
+    }
+}
+
+fn escape(out: &mut String, text: &str, attribute: bool) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' if !attribute => out.push_str("&gt;"),
+            '"' if attribute => out.push_str("&quot;"),
+            '\t' if attribute => out.push_str("&#x9;"),
+            '\n' if attribute => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Verify the enveloped XML signature of a document, e.g., of a SWID RIM, against the pinned root
+/// of its signers, and return the signed document in canonical form.
+///
+/// The signature must cover the whole document, and only what it returns is signed: comments and
+/// the signature itself are left out, so the caller must read the document from the result rather
+/// than from `xml`.
+pub fn verify(xml: &str, root_ca: &[u8]) -> Result<String> {
+    let nodes = parse(xml)?;
+    let root = nodes
+        .iter()
+        .find_map(|node| match node {
+            Node::Element(element) => Some(element),
+            _ => None,
+        })
+        .expect("no root element");
+
+    let mut scope = Scope::new();
+    root.declare(&mut scope);
+    let signatures = root
+        .elements()
+        .filter(|element| local(&element.name) == "Signature")
+        .filter(|element| {
+            let mut scope = scope.clone();
+            element.declare(&mut scope);
+            scope.get(prefix(&element.name)).map(String::as_str) == Some(XMLDSIG_NS)
+        })
+        .collect::<Vec<_>>();
+    let signature = match signatures[..] {
+        [signature] => signature,
+        [] => return Err(NvTrustError::Rim("the RIM is not signed".to_string())),
+        _ => return Err(invalid("more than one signature".to_string())),
+    };
+    let signed_info = signature.child("SignedInfo")?;
+
+    let mut document = None;
+    for reference in signed_info
+        .elements()
+        .filter(|element| local(&element.name) == "Reference")
+    {
+        let (canonical, whole) = digest_reference(&nodes, root, signature, reference)?;
+        if whole {
+            document = Some(canonical);
+        }
+    }
+    let document = document.ok_or_else(|| invalid("the document is not signed".to_string()))?;
+
+    // X509Data lists the signing certificate first.
+    let mut chain = signature
+        .child("KeyInfo")?
+        .child("X509Data")?
+        .elements()
+        .filter(|element| local(&element.name) == "X509Certificate")
+        .map(Element::base64)
+        .collect::<Result<Vec<_>>>()?;
+    let leaf = chain
+        .first()
+        .cloned()
+        .ok_or_else(|| invalid("no signing certificate".to_string()))?;
+    chain.reverse();
+    if let Verdict::Untrusted(reason) = certs::verify_chain(&chain, root_ca)? {
+        return Err(NvTrustError::Rim(format!(
+            "the RIM signer is untrusted: {reason}"
+        )));
+    }
+
+    let path = [root, signature, signed_info];
+    let canonical =
+        Canonicalizer::new(signed_info.child("CanonicalizationMethod")?, None)?.subtree(&path)?;
+    let (_, leaf) = parse_x509_certificate(&leaf)
+        .map_err(|e| NvTrustError::Certificate(format!("the RIM signer: {e}")))?;
+    verify_signature(
+        signed_info.child("SignatureMethod")?,
+        &leaf.public_key().subject_public_key.data,
+        canonical.as_bytes(),
+        &signature.child("SignatureValue")?.base64()?,
+    )?;
+
+    Ok(document)
+}
+
+/// Check the digest of a reference, and return what it covers in canonical form and whether
+/// that is the whole document.
+fn digest_reference(
+    nodes: &[Node],
+    root: &Element,
+    signature: &Element,
+    reference: &Element,
+) -> Result<(String, bool)> {
+    let uri = reference
+        .attribute("URI")
+        .ok_or_else(|| invalid("a reference without a URI".to_string()))?;
+
+    let mut enveloped = false;
+    let mut c14n = None;
+    if let Ok(transforms) = reference.child("Transforms") {
+        for transform in transforms.elements() {
+            match transform.attribute("Algorithm") {
+                Some(XMLDSIG_ENVELOPED_SIGNATURE) => enveloped = true,
+                _ => c14n = Some(transform),
+            }
+        }
+    }
+    let c14n = c14n.ok_or_else(|| invalid(format!("reference {uri:?} is not canonicalized")))?;
+    let mut canonicalizer = Canonicalizer::new(c14n, enveloped.then_some(signature))?;
+    // Neither the whole document nor an element by its ID includes the comments.
+    canonicalizer.comments = false;
+
+    let (canonical, whole) = match uri.strip_prefix('#') {
+        None if uri.is_empty() => (canonicalizer.document(nodes)?, true),
+        None => return Err(invalid(format!("unsupported reference {uri:?}"))),
+        Some(id) => {
+            let path = root
+                .find(&|element| {
+                    ["Id", "ID", "id", "xml:id"]
+                        .iter()
+                        .any(|name| element.attribute(name) == Some(id))
+                })
+                .ok_or_else(|| invalid(format!("no element with the ID {id}")))?;
+            (canonicalizer.subtree(&path)?, path.len() == 1)
+        }
+    };
+
+    let algorithm = match reference.child("DigestMethod")?.attribute("Algorithm") {
+        Some(XMLDSIG_SHA256) => &digest::SHA256,
+        Some(XMLDSIG_SHA384) => &digest::SHA384,
+        Some(XMLDSIG_SHA512) => &digest::SHA512,
+        other => {
+            return Err(invalid(format!(
+                "unsupported digest {}",
+                other.unwrap_or("none")
+            )))
+        }
+    };
+    if digest::digest(algorithm, canonical.as_bytes()).as_ref()
+        != reference.child("DigestValue")?.base64()?
+    {
+        return Err(NvTrustError::Rim(format!(
+            "the digest of reference {uri:?} does not match"
+        )));
+    }
+
+    Ok((canonical, whole))
+}
+
+fn verify_signature(method: &Element, key: &[u8], message: &[u8], value: &[u8]) -> Result<()> {
+    // XML-DSig encodes ECDSA signatures as r || s, but some signers use DER.
+    let algorithms: &[&dyn signature::VerificationAlgorithm] = match method.attribute("Algorithm") {
+        Some(XMLDSIG_ECDSA_SHA256) => &[
+            &signature::ECDSA_P256_SHA256_FIXED,
+            &signature::ECDSA_P256_SHA256_ASN1,
+        ],
+        Some(XMLDSIG_ECDSA_SHA384) => &[
+            &signature::ECDSA_P384_SHA384_FIXED,
+            &signature::ECDSA_P384_SHA384_ASN1,
+        ],
+        Some(XMLDSIG_RSA_SHA256) => &[&signature::RSA_PKCS1_2048_8192_SHA256],
+        Some(XMLDSIG_RSA_SHA384) => &[&signature::RSA_PKCS1_2048_8192_SHA384],
+        Some(XMLDSIG_RSA_SHA512) => &[&signature::RSA_PKCS1_2048_8192_SHA512],
+        other => {
+            return Err(invalid(format!(
+                "unsupported signature {}",
+                other.unwrap_or("none")
+            )))
+        }
+    };
+
+    match algorithms.iter().any(|algorithm| {
+        signature::UnparsedPublicKey::new(*algorithm, key)
+            .verify(message, value)
+            .is_ok()
+    }) {
+        true => Ok(()),
+        false => Err(NvTrustError::Rim(
+            "the RIM signature does not verify".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,905 @@
+//! Tests of the local verifier against synthetic evidence, signed with a throwaway key, of the
+//! RIM decoders and of policies.
+
+use std::collections::BTreeMap;
+
+use base64::Engine;
+use nvtrust::{
+    bits::*,
+    certs::{self, Verdict},
+    corim::Cbor,
+    error::NvTrustError,
+    evidence::Evidence,
+    policy::{self, Claims, Policy},
+    rim,
+    spdm::{to_hex, Algorithms},
+    verifier::{self, ReferenceValues},
+};
+use ring::{
+    digest,
+    rand::SystemRandom,
+    signature::{self, KeyPair},
+};
+
+const NONCE: [u8; SPDM_NONCE_SIZE] = [0x5a; SPDM_NONCE_SIZE];
+
+/// Encode a DER item.
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match content.len() {
+        len if len < 0x80 => out.push(len as u8),
+        len if len < 0x100 => out.extend([0x81, len as u8]),
+        len => out.extend([0x82, (len >> 8) as u8, len as u8]),
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn seq(items: &[Vec<u8>]) -> Vec<u8> {
+    der(0x30, &items.concat())
+}
+
+fn oid(oid: &[u8]) -> Vec<u8> {
+    der(0x06, oid)
+}
+
+fn name(cn: &str) -> Vec<u8> {
+    seq(&[der(
+        0x31,
+        &seq(&[oid(&[0x55, 0x04, 0x03]), der(0x0c, cn.as_bytes())]),
+    )])
+}
+
+/// The basic constraints extension, of a CA with the path length limit or of an end entity.
+fn basic_constraints(ca: bool, path_len: Option<u8>) -> Vec<u8> {
+    let mut value = vec![];
+    if ca {
+        value.push(der(0x01, &[0xff]));
+    }
+    if let Some(len) = path_len {
+        value.push(der(0x02, &[len]));
+    }
+    seq(&[oid(&[0x55, 0x1d, 0x13]), der(0x04, &seq(&value))])
+}
+
+/// The key usage extension with the given bits, e.g., 0x80 for digitalSignature and 0x04 for
+/// keyCertSign.
+fn key_usage(bits: u8) -> Vec<u8> {
+    seq(&[
+        oid(&[0x55, 0x1d, 0x0f]),
+        der(0x04, &der(0x03, &[bits.trailing_zeros() as u8, bits])),
+    ])
+}
+
+fn p384_key(rng: &SystemRandom) -> signature::EcdsaKeyPair {
+    let pkcs8 =
+        signature::EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P384_SHA384_ASN1_SIGNING, rng)
+            .unwrap();
+    signature::EcdsaKeyPair::from_pkcs8(
+        &signature::ECDSA_P384_SHA384_ASN1_SIGNING,
+        pkcs8.as_ref(),
+        rng,
+    )
+    .unwrap()
+}
+
+/// A P-384 certificate of the key with the extensions, issued by the signer.
+fn certificate(
+    subject: &str,
+    key: &signature::EcdsaKeyPair,
+    issuer: &str,
+    signer: &signature::EcdsaKeyPair,
+    extensions: &[Vec<u8>],
+    rng: &SystemRandom,
+) -> Vec<u8> {
+    let ecdsa_sha384 = seq(&[oid(&[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03])]);
+    let spki = seq(&[
+        seq(&[
+            oid(&[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01]),
+            oid(&[0x2b, 0x81, 0x04, 0x00, 0x22]),
+        ]),
+        der(0x03, &[&[0], key.public_key().as_ref()].concat()),
+    ]);
+    let mut tbs = vec![
+        der(0xa0, &der(0x02, &[2])),
+        der(0x02, &[1]),
+        ecdsa_sha384.clone(),
+        name(issuer),
+        seq(&[der(0x17, b"250101000000Z"), der(0x18, b"20991231000000Z")]),
+        name(subject),
+        spki,
+    ];
+    if !extensions.is_empty() {
+        tbs.push(der(0xa3, &seq(extensions)));
+    }
+    let tbs = seq(&tbs);
+
+    let sig = signer.sign(rng, &tbs).unwrap();
+    seq(&[tbs, ecdsa_sha384, der(0x03, &[&[0], sig.as_ref()].concat())])
+}
+
+/// A self-signed P-384 CA certificate for the key, to be pinned as its own root.
+fn self_signed(pkcs8: &[u8], rng: &SystemRandom) -> Vec<u8> {
+    let key =
+        signature::EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P384_SHA384_ASN1_SIGNING, pkcs8, rng)
+            .unwrap();
+    let ca = [basic_constraints(true, None)];
+    certificate("nvtrust test", &key, "nvtrust test", &key, &ca, rng)
+}
+
+/// A signed MEASUREMENTS report of SPDM 1.1, where the signature is directly over the transcript.
+///
+/// Returns the evidence and the self-signed certificate of the key that signed it.
+fn evidence() -> (Evidence, Vec<u8>) {
+    let rng = SystemRandom::new();
+    let pkcs8 =
+        signature::EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P384_SHA384_FIXED_SIGNING, &rng)
+            .unwrap();
+    let key = signature::EcdsaKeyPair::from_pkcs8(
+        &signature::ECDSA_P384_SHA384_FIXED_SIGNING,
+        pkcs8.as_ref(),
+        &rng,
+    )
+    .unwrap();
+
+    let mut request = vec![
+        SPDM_VERSION_11,
+        SPDM_GET_MEASUREMENTS,
+        SPDM_MEASUREMENTS_SIGNED,
+        SPDM_MEASUREMENTS_ALL,
+    ];
+    request.extend_from_slice(&NONCE);
+    request.push(0);
+
+    // Two DMTF blocks, each a 4-byte digest.
+    let mut record = vec![];
+    for (index, digest) in [(1u8, [0x11u8; 4]), (2, [0x22; 4])] {
+        record.extend([index, 0x01, 7, 0, 0x01, 4, 0]);
+        record.extend_from_slice(&digest);
+    }
+    let opaque = b"opaque".to_vec();
+    let mut response = vec![SPDM_VERSION_11, SPDM_MEASUREMENTS, 0, 0, 2];
+    response.extend_from_slice(&(record.len() as u32).to_le_bytes()[..3]);
+    response.extend_from_slice(&record);
+    response.extend_from_slice(&[0xa5; SPDM_NONCE_SIZE]);
+    response.extend_from_slice(&(opaque.len() as u16).to_le_bytes());
+    response.extend_from_slice(&opaque);
+
+    let transcript = [request.clone(), response.clone()].concat();
+    let signature = key.sign(&rng, &transcript).unwrap().as_ref().to_vec();
+    let report = [transcript.clone(), signature.clone()].concat();
+    let cert = self_signed(pkcs8.as_ref(), &rng);
+
+    let evidence = Evidence {
+        bdf: "0000:01:00.0".to_string(),
+        arch: "hopper".to_string(),
+        nonce: NONCE,
+        spdm_version: SPDM_VERSION_11,
+        algorithms: Algorithms {
+            measurement_hash: 0,
+            base_asym: SPDM_ASYM_ECDSA_P384,
+            base_hash: SPDM_HASH_SHA_384,
+        },
+        report,
+        signature,
+        opaque,
+        transcript,
+        certificates: vec![cert.clone()],
+        measurements: BTreeMap::from([(1, to_hex(&[0x11; 4])), (2, to_hex(&[0x22; 4]))]),
+    };
+
+    (evidence, cert)
+}
+
+fn references() -> Vec<ReferenceValues> {
+    vec![ReferenceValues {
+        name: "driver".to_string(),
+        measurements: BTreeMap::from([
+            (1, vec![to_hex(&[0x11; 4])]),
+            (2, vec![to_hex(&[0x33; 4]), to_hex(&[0x22; 4])]),
+        ]),
+        signature_verified: false,
+    }]
+}
+
+fn passes(evidence: &Evidence, nonce: &[u8; SPDM_NONCE_SIZE], root: &[u8]) -> bool {
+    verifier::verify(evidence, nonce, root, &references())["x-nvidia-overall-att-result"] == "true"
+}
+
+#[test]
+fn verify_local() {
+    let (evidence, root) = evidence();
+
+    let claims = verifier::verify(&evidence, &NONCE, &root, &references());
+    for claim in [
+        "x-nvidia-gpu-attestation-report-parsed",
+        "x-nvidia-gpu-attestation-report-cert-chain-validated",
+        "x-nvidia-gpu-attestation-report-signature-verified",
+        "x-nvidia-gpu-attestation-report-nonce-match",
+        "x-nvidia-gpu-measurements-match",
+        "x-nvidia-overall-att-result",
+    ] {
+        assert_eq!(claims[claim], "true", "{claim}");
+    }
+    assert_eq!(claims["x-nvidia-gpu-rim-signature-verified"], "false");
+    // The test certificate is not issued to a PDI.
+    assert!(!claims.contains_key("pdi"));
+
+    // Without reference values nothing is appraised.
+    let claims = verifier::verify(&evidence, &NONCE, &root, &[]);
+    assert_eq!(claims["x-nvidia-overall-att-result"], "false");
+}
+
+#[test]
+fn verify_tampered_measurements() {
+    let (mut evidence, root) = evidence();
+
+    // The unsigned measurement set disagrees with the signed report.
+    evidence.measurements.insert(2, to_hex(&[0x33; 4]));
+    let claims = verifier::verify(&evidence, &NONCE, &root, &references());
+    assert_eq!(claims["x-nvidia-gpu-attestation-report-parsed"], "false");
+    assert_eq!(claims["x-nvidia-overall-att-result"], "false");
+
+    // The signed measurements are not the expected ones.
+    let (evidence, root) = self::evidence();
+    let mut references = references();
+    references[0]
+        .measurements
+        .insert(1, vec![to_hex(&[0x44; 4])]);
+    let claims = verifier::verify(&evidence, &NONCE, &root, &references);
+    assert_eq!(claims["x-nvidia-gpu-measurements-match"], "false");
+    assert_eq!(claims["x-nvidia-mismatch-measurement-records"], "1");
+    assert_eq!(claims["x-nvidia-overall-att-result"], "false");
+}
+
+#[test]
+fn verify_tampered_report() {
+    let (evidence, root) = evidence();
+
+    // A measurement edited in the report no longer matches the signed transcript.
+    let mut edited = evidence.clone();
+    let offset = edited.report.len() - edited.signature.len() - 60;
+    edited.report[offset] ^= 1;
+    assert!(!passes(&edited, &NONCE, &root));
+
+    // Nor do the opaque data.
+    let mut edited = evidence.clone();
+    edited.opaque = b"edited".to_vec();
+    assert!(!passes(&edited, &NONCE, &root));
+
+    // Editing both the report and the transcript breaks the signature.
+    let mut edited = evidence;
+    let offset = edited.transcript.len() - 20;
+    edited.transcript[offset] ^= 1;
+    let offset = edited.report.len() - edited.signature.len() - 20;
+    edited.report[offset] ^= 1;
+    let claims = verifier::verify(&edited, &NONCE, &root, &references());
+    assert_eq!(
+        claims["x-nvidia-gpu-attestation-report-signature-verified"],
+        "false"
+    );
+    assert_eq!(claims["x-nvidia-overall-att-result"], "false");
+}
+
+#[test]
+fn verify_nonce() {
+    let (evidence, root) = evidence();
+
+    // The report is signed over another nonce than the caller asked for.
+    let claims = verifier::verify(&evidence, &[0; SPDM_NONCE_SIZE], &root, &references());
+    assert_eq!(
+        claims["x-nvidia-gpu-attestation-report-nonce-match"],
+        "false"
+    );
+    assert_eq!(claims["x-nvidia-overall-att-result"], "false");
+
+    // The nonce of the evidence is not the signed one.
+    let mut edited = evidence;
+    edited.nonce = [0; SPDM_NONCE_SIZE];
+    assert!(!passes(&edited, &[0; SPDM_NONCE_SIZE], &root));
+}
+
+#[test]
+fn verify_signature() {
+    let (mut evidence, root) = evidence();
+
+    let last = evidence.report.len() - 1;
+    evidence.report[last] ^= 1;
+    *evidence.signature.last_mut().unwrap() ^= 1;
+    let claims = verifier::verify(&evidence, &NONCE, &root, &references());
+    assert_eq!(
+        claims["x-nvidia-gpu-attestation-report-signature-verified"],
+        "false"
+    );
+    assert_eq!(claims["x-nvidia-overall-att-result"], "false");
+
+    // A chain the pinned root did not issue.
+    let (evidence, _) = self::evidence();
+    let (_, other) = self::evidence();
+    let claims = verifier::verify(&evidence, &NONCE, &other, &references());
+    assert_eq!(
+        claims["x-nvidia-gpu-attestation-report-cert-chain-validated"],
+        "false"
+    );
+}
+
+#[test]
+fn evidence_json() {
+    let (evidence, root) = evidence();
+
+    let json = evidence.to_json().unwrap();
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["nonce"], to_hex(&NONCE));
+    assert_eq!(value["spdm_version"], "1.1");
+    assert!(value["report"].as_str().is_some());
+    assert_eq!(value["measurements"]["2"], to_hex(&[0x22; 4]));
+
+    // The file verifies like the evidence it was written from.
+    let parsed: Evidence = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.report, evidence.report);
+    assert_eq!(parsed.certificates, evidence.certificates);
+    assert!(passes(&parsed, &NONCE, &root));
+}
+
+/// Encode the head of a CBOR item with a definite argument.
+fn cbor(major: u8, arg: u64) -> Vec<u8> {
+    match arg {
+        0..=23 => vec![major << 5 | arg as u8],
+        24..=0xff => vec![major << 5 | 24, arg as u8],
+        _ => [vec![major << 5 | 25], (arg as u16).to_be_bytes().to_vec()].concat(),
+    }
+}
+
+fn cbor_text(text: &str) -> Vec<u8> {
+    [cbor(3, text.len() as u64), text.as_bytes().to_vec()].concat()
+}
+
+fn cbor_bytes(bytes: &[u8]) -> Vec<u8> {
+    [cbor(2, bytes.len() as u64), bytes.to_vec()].concat()
+}
+
+fn cbor_array(items: &[Vec<u8>]) -> Vec<u8> {
+    [cbor(4, items.len() as u64), items.concat()].concat()
+}
+
+fn cbor_map(entries: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+    let mut out = cbor(5, entries.len() as u64);
+    for (key, value) in entries {
+        out.extend_from_slice(key);
+        out.extend_from_slice(value);
+    }
+    out
+}
+
+fn cbor_tag(tag: u64, item: Vec<u8>) -> Vec<u8> {
+    [cbor(6, tag), item].concat()
+}
+
+/// A CoRIM with a single tag, given as the tagged item it wraps in a byte string.
+fn corim(id: Option<&str>, tag: u64, item: Vec<u8>) -> Vec<u8> {
+    let mut entries = vec![];
+    if let Some(id) = id {
+        entries.push((cbor(0, 0), cbor_text(id)));
+    }
+    entries.push((cbor(0, 1), cbor_array(&[cbor_tag(tag, cbor_bytes(&item))])));
+    cbor_tag(501, cbor_map(&entries))
+}
+
+/// A CoMID with a reference triple of the given measurements, each with its SHA-384 digests.
+fn comid(measurements: &[(u64, &[[u8; 4]])]) -> Vec<u8> {
+    let measurements = measurements
+        .iter()
+        .map(|(index, digests)| {
+            let digests = digests
+                .iter()
+                .map(|digest| cbor_array(&[cbor(0, 7), cbor_bytes(digest)]))
+                .collect::<Vec<_>>();
+            cbor_map(&[
+                (cbor(0, 0), cbor(0, *index)),
+                (cbor(0, 1), cbor_map(&[(cbor(0, 2), cbor_array(&digests))])),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    cbor_map(&[
+        (cbor(0, 1), cbor_map(&[(cbor(0, 0), cbor_text("comid-id"))])),
+        (
+            cbor(0, 4),
+            cbor_map(&[(
+                cbor(0, 0),
+                cbor_array(&[cbor_array(&[cbor_map(&[]), cbor_array(&measurements)])]),
+            )]),
+        ),
+    ])
+}
+
+#[test]
+fn cbor_lengths() {
+    // Definite and indefinite arrays decode alike.
+    let expected = Cbor::Array(vec![Cbor::Int(1), Cbor::Int(-100)]);
+    assert_eq!(Cbor::decode(&[0x82, 0x01, 0x38, 0x63]).unwrap(), expected);
+    assert_eq!(
+        Cbor::decode(&[0x9f, 0x01, 0x38, 0x63, 0xff]).unwrap(),
+        expected
+    );
+
+    // An indefinite byte string is the concatenation of its chunks.
+    assert_eq!(
+        Cbor::decode(&[0x5f, 0x42, 0xaa, 0xbb, 0x41, 0xcc, 0xff]).unwrap(),
+        Cbor::Bytes(vec![0xaa, 0xbb, 0xcc])
+    );
+
+    // An indefinite map, and a text string with a one-byte length.
+    let text = "a".repeat(24);
+    let map = [vec![0xbf], cbor(0, 1), cbor_text(&text), vec![0xff]].concat();
+    assert_eq!(Cbor::decode(&map).unwrap().get(1), Some(&Cbor::Text(text)));
+}
+
+#[test]
+fn cbor_errors() {
+    let cbor_error = |data: &[u8]| matches!(Cbor::decode(data), Err(NvTrustError::Cbor(_)));
+
+    // Nested one past the limit, and right at it.
+    let nested = |depth| [vec![0x81; depth], vec![0x00]].concat();
+    assert!(cbor_error(&nested(33)));
+    assert!(Cbor::decode(&nested(32)).is_ok());
+
+    // Truncated items, and lengths past the end.
+    assert!(cbor_error(&[0x82, 0x01]));
+    assert!(cbor_error(&[0x9f, 0x01]));
+    assert!(cbor_error(&[0x19, 0x01]));
+    assert!(cbor_error(&[0x5a, 0xff, 0xff, 0xff, 0xff]));
+
+    // Trailing bytes, an indefinite integer, and a text chunk in a byte string.
+    assert!(cbor_error(&[0x01, 0x02]));
+    assert!(cbor_error(&[0x1f]));
+    assert!(cbor_error(&[0x5f, 0x61, 0x61, 0xff]));
+}
+
+#[test]
+fn corim_comid() {
+    let rim = corim(
+        Some("driver"),
+        506,
+        comid(&[(1, &[[0x11; 4]]), (2, &[[0x33; 4], [0x22; 4]])]),
+    );
+
+    let values = ReferenceValues::parse(&rim).unwrap();
+    assert_eq!(values.name, "driver");
+    assert_eq!(values.measurements, references()[0].measurements);
+
+    // Without a CoRIM id, the CoMID names the reference values.
+    let rim = corim(None, 506, comid(&[(1, &[[0x11; 4]])]));
+    assert_eq!(ReferenceValues::parse(&rim).unwrap().name, "comid-id");
+
+    // A measurement without digests is rejected.
+    let broken = cbor_map(&[(
+        cbor(0, 4),
+        cbor_map(&[(
+            cbor(0, 0),
+            cbor_array(&[cbor_array(&[
+                cbor_map(&[]),
+                cbor_array(&[cbor_map(&[(cbor(0, 0), cbor(0, 1))])]),
+            ])]),
+        )]),
+    )]);
+    assert!(matches!(
+        ReferenceValues::parse(&corim(None, 506, broken)),
+        Err(NvTrustError::Rim(_))
+    ));
+}
+
+#[test]
+fn corim_signed() {
+    let payload = corim(Some("driver"), 506, comid(&[(1, &[[0x11; 4]])]));
+    let rim = cbor_tag(
+        18,
+        cbor_array(&[
+            cbor_bytes(&cbor_map(&[(cbor(0, 1), cbor(1, 34))])),
+            cbor_map(&[]),
+            cbor_bytes(&payload),
+            cbor_bytes(&[0; 96]),
+        ]),
+    );
+
+    assert_eq!(
+        ReferenceValues::parse(&rim).unwrap().measurements,
+        ReferenceValues::parse(&payload).unwrap().measurements
+    );
+
+    // A COSE_Sign1 without a payload.
+    let rim = cbor_tag(18, cbor_array(&[cbor_bytes(&[]), cbor_map(&[])]));
+    assert!(matches!(
+        ReferenceValues::parse(&rim),
+        Err(NvTrustError::Rim(_))
+    ));
+}
+
+#[test]
+fn corim_coswid() {
+    let measurement = |index: u64, active: bool, hashes: Vec<Vec<u8>>| {
+        let mut entries = vec![
+            (cbor(0, 29), cbor_text("Measurement")),
+            (cbor_text("index"), cbor(0, index)),
+            (cbor_text("active"), vec![if active { 0xf5 } else { 0xf4 }]),
+        ];
+        for (i, hash) in hashes.into_iter().enumerate() {
+            entries.push((cbor_text(&format!("Hash{i}")), hash));
+        }
+        cbor_map(&entries)
+    };
+    let coswid = cbor_map(&[
+        (cbor(0, 0), cbor_text("tag-id")),
+        (cbor(0, 1), cbor_text("driver")),
+        (cbor(0, 13), cbor_text("535.86")),
+        (
+            cbor(0, 6),
+            cbor_map(&[(
+                cbor(0, 19),
+                cbor_array(&[
+                    measurement(1, true, vec![cbor_bytes(&[0x11; 4])]),
+                    // Hashes as text are lowercased like the hex of byte strings.
+                    measurement(2, true, vec![cbor_text("AAAAAAAA"), cbor_text("22222222")]),
+                    measurement(3, false, vec![cbor_bytes(&[0x44; 4])]),
+                ]),
+            )]),
+        ),
+    ]);
+
+    let values = ReferenceValues::parse(&corim(None, 505, coswid)).unwrap();
+    assert_eq!(values.name, "driver 535.86");
+
+    // The CoSWID form reads as the same reference values as the CoMID form.
+    let comid = corim(
+        None,
+        506,
+        comid(&[(1, &[[0x11; 4]]), (2, &[[0xaa; 4], [0x22; 4]])]),
+    );
+    assert_eq!(
+        values.measurements,
+        ReferenceValues::parse(&comid).unwrap().measurements
+    );
+
+    // A measurement without hashes is rejected.
+    let coswid = cbor_map(&[(
+        cbor(0, 6),
+        cbor_map(&[(cbor(0, 19), measurement(1, true, vec![]))]),
+    )]);
+    assert!(matches!(
+        ReferenceValues::parse(&corim(None, 505, coswid)),
+        Err(NvTrustError::Rim(_))
+    ));
+}
+
+/// Whether the claim passes the single rule of the policy.
+fn passes_rule(rule: &str, claim: &str) -> bool {
+    let policy = Policy::parse(rule).unwrap();
+    let name = &policy.rules[0].claim;
+    let claims = Claims::from([(name.clone(), claim.to_string())]);
+    policy.evaluate(&claims)[0].passed
+}
+
+#[test]
+fn policy_values() {
+    // Numbers, decimal or hex.
+    assert!(passes_rule("device_id == 0x2330", "9008"));
+    assert!(passes_rule("device_id > 9", "10"));
+    assert!(!passes_rule("device_id < 0x9", "0xa"));
+
+    // Dotted versions compare by hex component, not as strings.
+    assert!(passes_rule(
+        "vbios_version >= 96.00.5E.00.01",
+        "96.00.9F.00.01"
+    ));
+    assert!(passes_rule(
+        "vbios_version >= 96.00.5E.00.01",
+        "96.00.5e.00.01"
+    ));
+    assert!(!passes_rule(
+        "vbios_version >= 96.00.5E.00.01",
+        "96.00.5D.00.02"
+    ));
+    assert!(passes_rule("driver_version > 535.9", "535.104"));
+
+    // A plain number is a version of one component.
+    assert!(passes_rule("driver_version > 535.86", "550"));
+    assert!(passes_rule("driver_version > 535.86", "1000"));
+    assert!(!passes_rule("driver_version >= 535.86", "535"));
+
+    // Anything else compares as a case-insensitive string.
+    assert!(passes_rule("cc_mode == on", "ON"));
+    assert!(passes_rule("cc_mode != on", "devtools"));
+    assert!(!passes_rule("arch == hopper", "blackwell"));
+}
+
+#[test]
+fn policy_in() {
+    let rule = "vbios_version in [96.00.5E.00.01, 96.00.9F.00.01]";
+    assert!(passes_rule(rule, "96.00.9f.00.01"));
+    assert!(!passes_rule(rule, "96.00.9F.00.02"));
+    assert!(passes_rule("device_id in [0x2330, 0x2331]", "9009"));
+
+    // A missing claim fails its rule.
+    let policy = Policy::parse("cc_mode == on").unwrap();
+    let results = policy.evaluate(&Claims::new());
+    assert!(!results[0].passed);
+    assert_eq!(results[0].actual, None);
+
+    // Register reads cannot stand in for verified claims.
+    let claims = policy::unverified(Claims::from([("cc_mode".to_string(), "on".to_string())]));
+    assert!(!policy.evaluate(&claims)[0].passed);
+    assert_eq!(claims["unverified.cc_mode"], "on");
+}
+
+#[test]
+fn policy_errors() {
+    let policy_error = |policy: &str| matches!(Policy::parse(policy), Err(NvTrustError::Policy(e)) if e.starts_with("line 2:"));
+
+    assert!(policy_error("# comment\ncc_mode ~= on"));
+    assert!(policy_error("# comment\ncc_mode"));
+    assert!(policy_error("# comment\ncc_mode =="));
+    assert!(policy_error("# comment\ndevice_id in 0x2330, 0x2331"));
+    assert!(policy_error("# comment\ndevice_id in [ , ]"));
+
+    // Comments and blank lines are not rules.
+    let policy = Policy::parse("# comment\n\n  cc_mode == on  \n").unwrap();
+    assert_eq!(policy.rules.len(), 1);
+    assert_eq!(policy.rules[0].line, 3);
+    assert_eq!(policy.rules[0].values, ["on"]);
+}
+
+const SWID_NS: &str = "http://standards.iso.org/iso/19770/-2/2015/schema.xsd";
+
+fn base64(data: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(data)
+}
+
+/// A SWID RIM of the `references()` measurements, with the signature and a comment.
+fn swid(signature: &str, comment: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<SoftwareIdentity xmlns="{SWID_NS}" xmlns:ds="{XMLDSIG_NS}" version='535.86' name="driver" >
+  <!--{comment}-->
+  <Payload>
+    <Resource type="Measurement" index="1" active="True" Hash0="11111111"/>
+    <Resource type="Measurement" index="2" active="True" Hash0="33333333" Hash1="22222222"/>
+  </Payload>
+  {signature}
+</SoftwareIdentity>
+"#
+    )
+}
+
+/// The `SignedInfo` of a signature over the whole RIM, either as signed or as embedded.
+fn signed_info(digest: &str, canonical: bool) -> String {
+    let empty = |name: &str, algorithm: &str| match canonical {
+        true => format!(r#"<ds:{name} Algorithm="{algorithm}"></ds:{name}>"#),
+        false => format!("<ds:{name} Algorithm='{algorithm}' />"),
+    };
+    let ns = match canonical {
+        true => format!(r#" xmlns:ds="{XMLDSIG_NS}""#),
+        false => String::new(),
+    };
+
+    format!(
+        r#"<ds:SignedInfo{ns}>{}{}<ds:Reference URI=""><ds:Transforms>{}{}</ds:Transforms>{}<ds:DigestValue>{digest}</ds:DigestValue></ds:Reference></ds:SignedInfo>"#,
+        empty("CanonicalizationMethod", XMLDSIG_EXC_C14N),
+        empty("SignatureMethod", XMLDSIG_ECDSA_SHA384),
+        empty("Transform", XMLDSIG_ENVELOPED_SIGNATURE),
+        empty("Transform", XMLDSIG_EXC_C14N),
+        empty("DigestMethod", XMLDSIG_SHA384),
+    )
+}
+
+/// A RIM with an enveloped signature, and the self-signed certificate of its signer.
+fn signed_swid(comment: &str) -> (String, Vec<u8>) {
+    let rng = SystemRandom::new();
+    let pkcs8 =
+        signature::EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P384_SHA384_FIXED_SIGNING, &rng)
+            .unwrap();
+    let key = signature::EcdsaKeyPair::from_pkcs8(
+        &signature::ECDSA_P384_SHA384_FIXED_SIGNING,
+        pkcs8.as_ref(),
+        &rng,
+    )
+    .unwrap();
+    let cert = self_signed(pkcs8.as_ref(), &rng);
+
+    // The RIM without the signature, in canonical form, whatever the comment.
+    let canonical = format!(
+        "<SoftwareIdentity xmlns=\"{SWID_NS}\" name=\"driver\" version=\"535.86\">\n  \n  <Payload>\n    \
+         <Resource Hash0=\"11111111\" active=\"True\" index=\"1\" type=\"Measurement\"></Resource>\n    \
+         <Resource Hash0=\"33333333\" Hash1=\"22222222\" active=\"True\" index=\"2\" type=\"Measurement\"></Resource>\n  \
+         </Payload>\n  \n</SoftwareIdentity>"
+    );
+    let digest = base64(digest::digest(&digest::SHA384, canonical.as_bytes()).as_ref());
+    let value = key
+        .sign(&rng, signed_info(&digest, true).as_bytes())
+        .unwrap();
+
+    let signature = format!(
+        "<ds:Signature>{}<ds:SignatureValue>{}</ds:SignatureValue><ds:KeyInfo><ds:X509Data>\
+         <ds:X509Certificate>{}</ds:X509Certificate></ds:X509Data></ds:KeyInfo></ds:Signature>",
+        signed_info(&digest, false),
+        base64(value.as_ref()),
+        base64(&cert),
+    );
+
+    (swid(&signature, comment), cert)
+}
+
+#[test]
+fn rim_signature() {
+    let (xml, root) = signed_swid(" a comment ");
+
+    let values = rim::verify_swid(&xml, &root).unwrap();
+    assert_eq!(values.name, "driver 535.86");
+    assert_eq!(values.measurements, references()[0].measurements);
+    assert!(values.signature_verified);
+    assert_eq!(
+        rim::load_rim(xml.as_bytes(), &root).unwrap().name,
+        values.name
+    );
+
+    // The verifier reports the RIMs as verified only if they all are.
+    let (evidence, device_root) = evidence();
+    let claims = verifier::verify(&evidence, &NONCE, &device_root, &[values]);
+    assert_eq!(claims["x-nvidia-gpu-rim-signature-verified"], "true");
+    assert_eq!(claims["x-nvidia-overall-att-result"], "true");
+
+    // A RIM signed by someone else.
+    let (_, other) = signed_swid("");
+    assert!(rim::verify_swid(&xml, &other).is_err());
+
+    // Neither unsigned RIMs nor unsigned CoRIMs are accepted.
+    assert!(rim::verify_swid(&swid("", ""), &root).is_err());
+    let corim = corim(None, 506, comid(&[(1, &[[0x11; 4]])]));
+    assert!(rim::load_rim(&corim, &root).is_err());
+}
+
+#[test]
+fn rim_signature_tampered() {
+    let (xml, root) = signed_swid("");
+
+    // An edited measurement no longer matches the digest.
+    let edited = xml.replace("11111111", "11111112");
+    assert!(matches!(
+        rim::verify_swid(&edited, &root),
+        Err(NvTrustError::Rim(e)) if e.contains("digest")
+    ));
+
+    // An edited SignedInfo no longer matches the signature.
+    let edited = xml.replace(
+        "<ds:Reference URI=\"\">",
+        "<ds:Reference URI=\"\" Id=\"x\">",
+    );
+    assert!(matches!(
+        rim::verify_swid(&edited, &root),
+        Err(NvTrustError::Rim(e)) if e.contains("does not verify")
+    ));
+
+    // Comments are not signed, so the measurements are read from the signed form alone.
+    let injected = r#" <Resource type="Measurement" index="3" active="True" Hash0="ff"/> "#;
+    let (xml, root) = signed_swid(injected);
+    assert!(ReferenceValues::from_swid(&xml)
+        .unwrap()
+        .measurements
+        .contains_key(&3));
+    let values = rim::verify_swid(&xml, &root).unwrap();
+    assert!(!values.measurements.contains_key(&3));
+}
+
+#[test]
+fn chain_constraints() {
+    let rng = SystemRandom::new();
+    let (root_key, ca_key, leaf_key) = (p384_key(&rng), p384_key(&rng), p384_key(&rng));
+    let ca = [basic_constraints(true, Some(0)), key_usage(0x04)];
+    let end_entity = [basic_constraints(false, None), key_usage(0x80)];
+
+    let root_ca = [basic_constraints(true, None)];
+    let root = certificate("root", &root_key, "root", &root_key, &root_ca, &rng);
+    let intermediate = certificate("ca", &ca_key, "root", &root_key, &ca, &rng);
+    let leaf = certificate("leaf", &leaf_key, "ca", &ca_key, &end_entity, &rng);
+    let trusted = |chain: &[&Vec<u8>]| {
+        let chain = chain.iter().map(|cert| cert.to_vec()).collect::<Vec<_>>();
+        matches!(
+            certs::verify_chain(&chain, &root).unwrap(),
+            Verdict::Trusted { .. }
+        )
+    };
+    assert!(trusted(&[&intermediate, &leaf]));
+    assert!(trusted(&[&root, &intermediate, &leaf]));
+
+    // A leaf cannot issue certificates, even though its signature verifies.
+    let child_key = p384_key(&rng);
+    let child = certificate("child", &child_key, "leaf", &leaf_key, &end_entity, &rng);
+    let open_ca = [basic_constraints(true, None), key_usage(0x04)];
+    let unlimited = certificate("ca", &ca_key, "root", &root_key, &open_ca, &rng);
+    assert!(trusted(&[&unlimited, &leaf]));
+    assert!(!trusted(&[&unlimited, &leaf, &child]));
+
+    // Nor can a CA below the path length limit, or one without keyCertSign.
+    let sub_ca = certificate("sub-ca", &child_key, "ca", &ca_key, &ca, &rng);
+    let sub_leaf = certificate("leaf", &leaf_key, "sub-ca", &child_key, &end_entity, &rng);
+    assert!(!trusted(&[&intermediate, &sub_ca, &sub_leaf]));
+    let no_cert_sign = [basic_constraints(true, None), key_usage(0x80)];
+    let signer = certificate("ca", &ca_key, "root", &root_key, &no_cert_sign, &rng);
+    assert!(!trusted(&[&signer, &leaf]));
+
+    // The issuer of each certificate must be the subject of the one before it.
+    let misnamed = certificate("leaf", &leaf_key, "other", &ca_key, &end_entity, &rng);
+    assert!(!trusted(&[&intermediate, &misnamed]));
+}
+
+/// A CoRIM signed as COSE_Sign1 with ES384, and the self-signed certificate of its signer.
+fn signed_corim(payload: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let rng = SystemRandom::new();
+    let pkcs8 =
+        signature::EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P384_SHA384_FIXED_SIGNING, &rng)
+            .unwrap();
+    let key = signature::EcdsaKeyPair::from_pkcs8(
+        &signature::ECDSA_P384_SHA384_FIXED_SIGNING,
+        pkcs8.as_ref(),
+        &rng,
+    )
+    .unwrap();
+    let cert = self_signed(pkcs8.as_ref(), &rng);
+
+    let protected = cbor_map(&[(cbor(0, 1), cbor(1, 34))]);
+    let sig_structure = cbor_array(&[
+        cbor_text("Signature1"),
+        cbor_bytes(&protected),
+        cbor_bytes(&[]),
+        cbor_bytes(payload),
+    ]);
+    let value = key.sign(&rng, &sig_structure).unwrap();
+
+    let rim = cbor_tag(
+        18,
+        cbor_array(&[
+            cbor_bytes(&protected),
+            cbor_map(&[(cbor(0, 33), cbor_bytes(&cert))]),
+            cbor_bytes(payload),
+            cbor_bytes(value.as_ref()),
+        ]),
+    );
+
+    (rim, cert)
+}
+
+#[test]
+fn corim_signature() {
+    let payload = corim(Some("driver"), 506, comid(&[(1, &[[0x11; 4]])]));
+    let (rim, root) = signed_corim(&payload);
+
+    let values = rim::load_rim(&rim, &root).unwrap();
+    assert_eq!(values.name, "driver");
+    assert!(values.signature_verified);
+    assert_eq!(
+        values.measurements,
+        ReferenceValues::parse(&payload).unwrap().measurements
+    );
+
+    // A CoRIM signed by someone else.
+    let (_, other) = signed_corim(&payload);
+    assert!(rim::verify_corim(&rim, &other).is_err());
+
+    // An edited measurement no longer matches the signature.
+    let at = rim.windows(4).position(|w| w == [0x11; 4]).unwrap();
+    let mut edited = rim.clone();
+    edited[at] = 0x12;
+    assert!(matches!(
+        rim::verify_corim(&edited, &root),
+        Err(NvTrustError::Rim(e)) if e.contains("does not verify")
+    ));
+
+    // The cache reads the CoRIMs it holds as CoRIMs.
+    let dir = std::env::temp_dir().join(format!("nvtrust-rim-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("NV_GPU_DRIVER.corim"), &rim).unwrap();
+    let cache = rim::RimCache::new(dir.to_str().unwrap(), &root);
+    assert!(cache.contains("NV_GPU_DRIVER"));
+    assert!(cache.load("NV_GPU_DRIVER").unwrap().signature_verified);
+    assert!(!cache.contains("NV_GPU_VBIOS"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
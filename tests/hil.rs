@@ -0,0 +1,149 @@
+//! Hardware-in-the-loop tests against a real H100.
+//!
+//! These tests are ignored by default. Run with `--ignored`, they still do nothing unless
+//! `NVTRUST_HIL=1` is set, and then run against the GPU given by `NVTRUST_HIL_BDF`, e.g.,
+//!
+//! ```shell
+//! sudo NVTRUST_HIL=1 NVTRUST_HIL_BDF=0000:01:00.0 cargo test --test nvtrust-hil -- --ignored
+//! ```
+//!
+//! They reset the GPU and cycle its CC mode, so they refuse to run while a driver is bound to it.
+//! The CC mode found at the start is restored at the end of the CC cycle test.
+
+use std::{
+    path::Path,
+    process::{Command, Output},
+    sync::Mutex,
+};
+
+/// The tests share one GPU, so they must not run concurrently.
+static GPU_LOCK: Mutex<()> = Mutex::new(());
+
+const CC_MODES: [&str; 3] = ["off", "on", "dev-tools"];
+
+/// Get the BDF of the GPU under test, or `None` if the tests are disabled.
+fn hil_gpu() -> Option<String> {
+    if std::env::var("NVTRUST_HIL").as_deref() != Ok("1") {
+        eprintln!("Skipped: set NVTRUST_HIL=1 to run the hardware-in-the-loop tests.");
+        return None;
+    }
+
+    let bdf = std::env::var("NVTRUST_HIL_BDF")
+        .expect("NVTRUST_HIL_BDF must name the GPU under test, e.g., 0000:01:00.0");
+    let path = Path::new("/sys/bus/pci/devices").join(&bdf);
+    assert!(path.exists(), "{bdf} is not a PCI device");
+
+    // The safety interlock: never touch a GPU that a driver may be using.
+    if let Ok(driver) = std::fs::read_link(path.join("driver")) {
+        panic!(
+            "Refusing to test {bdf}: it is bound to {}; unbind it first.",
+            driver.display()
+        );
+    }
+
+    Some(bdf)
+}
+
+fn nvtrust(bdf: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_nvtrust"))
+        .arg("--gpu-bdf")
+        .arg(bdf)
+        .args(args)
+        .output()
+        .expect("cannot run nvtrust")
+}
+
+fn nvtrust_ok(bdf: &str, args: &[&str]) -> Output {
+    let output = nvtrust(bdf, args);
+    assert!(
+        output.status.success(),
+        "nvtrust {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    output
+}
+
+/// Find the current CC mode through `query-cc-mode --check`.
+fn current_cc_mode(bdf: &str) -> &'static str {
+    CC_MODES
+        .into_iter()
+        .find(|mode| {
+            nvtrust(bdf, &["query-cc-mode", "--check", mode])
+                .status
+                .code()
+                == Some(0)
+        })
+        .expect("the GPU is in no known CC mode")
+}
+
+#[test]
+#[ignore = "needs an H100, see the module docs"]
+fn discovery() {
+    let _lock = GPU_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(bdf) = hil_gpu() else { return };
+
+    let output = nvtrust_ok(&bdf, &["--no-color", "list-gpus"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&bdf), "{bdf} is not listed:\n{stdout}");
+}
+
+#[test]
+#[ignore = "needs an H100, see the module docs"]
+fn query() {
+    let _lock = GPU_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(bdf) = hil_gpu() else { return };
+
+    current_cc_mode(&bdf);
+    nvtrust_ok(&bdf, &["--no-color", "query-cc-settings"]);
+    nvtrust_ok(&bdf, &["query-device-identity"]);
+}
+
+#[test]
+#[ignore = "needs an H100, see the module docs"]
+fn dump() {
+    let _lock = GPU_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(bdf) = hil_gpu() else { return };
+
+    let output = std::env::temp_dir().join("nvtrust-hil-fsp-emem.txt");
+    nvtrust_ok(&bdf, &["dump-fsp-emem", "-o", output.to_str().unwrap()]);
+    assert!(std::fs::metadata(&output).unwrap().len() > 0);
+    let _ = std::fs::remove_file(&output);
+
+    nvtrust_ok(&bdf, &["dump-scratch"]);
+}
+
+#[test]
+#[ignore = "needs an H100, see the module docs"]
+fn reset() {
+    let _lock = GPU_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(bdf) = hil_gpu() else { return };
+
+    let before = current_cc_mode(&bdf);
+    nvtrust_ok(&bdf, &["reset-with-os", "--yes"]);
+    assert_eq!(current_cc_mode(&bdf), before, "a reset changed the CC mode");
+}
+
+#[test]
+#[ignore = "needs an H100, see the module docs"]
+fn cc_cycle() {
+    let _lock = GPU_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(bdf) = hil_gpu() else { return };
+
+    let original = current_cc_mode(&bdf);
+    for mode in CC_MODES.into_iter().chain([original]) {
+        nvtrust_ok(
+            &bdf,
+            &[
+                "set-cc-mode",
+                "--mode",
+                mode,
+                "--reset",
+                "--verify",
+                "--yes",
+            ],
+        );
+        assert_eq!(current_cc_mode(&bdf), mode);
+    }
+}
@@ -0,0 +1,1140 @@
+//! Tests of the GPU logic against the in-memory backend, seeded from the fixtures in
+//! `tests/fixtures`.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use nvtrust::{
+    aer,
+    arch::Arch,
+    backend::{BackendKind, DeviceBackend, FspHandler, MockBackend},
+    bits::*,
+    dev::{self, is_mmio_error, GpuObject},
+    error::NvTrustError,
+    fsp::{FspRpc, PrcKnob},
+    link::LinkSpeed,
+    lock::DeviceLock,
+    pm::PowerState,
+    rebar, regs,
+    script::Script,
+    topology,
+    trace::{self, ReplayBackend},
+    txn,
+};
+
+const H100: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/h100");
+
+fn mock_gpu() -> (GpuObject, Arc<MockBackend>) {
+    let backend = Arc::new(MockBackend::from_fixture(H100).expect("cannot load the fixture"));
+    let device = dev::open_device_with(H100, backend.clone()).expect("cannot open the mock");
+    let gpu = GpuObject::new(device.into()).expect("cannot create the GPU");
+
+    (gpu, backend)
+}
+
+#[test]
+fn discovery() {
+    let (gpu, backend) = mock_gpu();
+
+    assert_eq!(backend.kind(), BackendKind::Mock);
+    assert_eq!(gpu.get_arch(), Arch::Hopper);
+    assert!(!gpu.is_vf());
+    assert_eq!(
+        gpu.get_device_handle().get_product_name(),
+        Some("H100 SXM5 80GB")
+    );
+    assert_eq!(gpu.get_bar0().size, 16 << 20);
+    assert!(gpu
+        .get_device_handle()
+        .caps()
+        .contains_key(&(PCI_CAP_ID_EXP as u8)));
+}
+
+#[test]
+fn cc_mode() {
+    let (gpu, backend) = mock_gpu();
+    assert_eq!(gpu.query_cc_mode().unwrap(), CcMode::CC_MODE_ON);
+
+    backend.set32(0, NV_CC_MODE, CcMode::CC_MODE_DEV_TOOLS.bits() as u32);
+    assert_eq!(gpu.query_cc_mode().unwrap(), CcMode::CC_MODE_DEV_TOOLS);
+
+    // The FSP has not booted yet.
+    backend.set32(0, NV_THERM_I2CS_SCRATCH, 0);
+    assert!(matches!(gpu.query_cc_mode(), Err(NvTrustError::Timeout(_))));
+}
+
+#[test]
+fn register_access() {
+    let (gpu, backend) = mock_gpu();
+
+    backend.set32(0, 0x100, 0x11223344);
+    gpu.write8(0x101, 0xaa).unwrap();
+    assert_eq!(backend.writes(), vec![(0, 0x100, 0x1122aa44)]);
+    assert_eq!(gpu.read16(0x102).unwrap(), 0x1122);
+    assert_eq!(gpu.read(0x101, 2).unwrap(), vec![0xaa, 0x22]);
+
+    assert!(matches!(
+        gpu.read32(0x102),
+        Err(NvTrustError::Misaligned { .. })
+    ));
+    assert!(matches!(
+        gpu.read(0x103, 4),
+        Err(NvTrustError::Misaligned { .. })
+    ));
+    assert!(matches!(
+        gpu.read32(16 << 20),
+        Err(NvTrustError::OutOfRange { .. })
+    ));
+}
+
+#[test]
+fn bar1() {
+    let (gpu, backend) = mock_gpu();
+
+    backend.set32(1, 0x1000, 0x04030201);
+    backend.set32(1, 0x1004, 0x08070605);
+    assert_eq!(gpu.read_bar1(0x1001, 5).unwrap(), vec![2, 3, 4, 5, 6]);
+    assert!(matches!(
+        gpu.read_bar1(gpu.get_bar1().size - 2, 4),
+        Err(NvTrustError::OutOfRange { .. })
+    ));
+}
+
+#[test]
+fn reset() {
+    let (gpu, backend) = mock_gpu();
+    let device = gpu.get_device_handle();
+
+    let saved = device.save_config_space().unwrap();
+    assert_eq!(saved.len(), PCI_CFG_SPACE_SIZE as usize);
+
+    gpu.sysfs_reset().unwrap();
+    assert_eq!(backend.resets(), 1);
+
+    // Lose the BAR0 address, as a reset does, and restore it.
+    backend.write_config(0x10, &[0; 4]).unwrap();
+    device.restore_config_space(&saved).unwrap();
+
+    let mut bar0 = [0; 4];
+    backend.read_config(0x10, &mut bar0).unwrap();
+    assert_eq!(u32::from_le_bytes(bar0), 0xfb000000);
+}
+
+#[test]
+fn restore_config_space() {
+    let (gpu, backend) = mock_gpu();
+    // An AER capability with the unsupported request errors masked, and some payload settings.
+    backend
+        .write_config(0x100, &[0x01, 0x00, 0x01, 0x00])
+        .unwrap();
+    backend
+        .write_config(0x108, &0x100000u32.to_le_bytes())
+        .unwrap();
+    backend
+        .write_config(0x48, &0x2930u16.to_le_bytes())
+        .unwrap();
+    let device = gpu.get_device_handle();
+    let saved = device.save_config_space().unwrap();
+
+    // A reset clears all of it, and the memory decoding along with BAR0.
+    backend.write_config(0x04, &[0; 2]).unwrap();
+    backend.write_config(0x10, &[0; 4]).unwrap();
+    backend.write_config(0x48, &[0; 2]).unwrap();
+    backend.write_config(0x108, &[0; 4]).unwrap();
+    device.restore_config_space(&saved).unwrap();
+
+    assert_eq!(device.read_config_space().unwrap(), saved);
+}
+
+#[test]
+fn record_replay() {
+    let path = std::env::temp_dir().join(format!("nvtrust-trace-{}", std::process::id()));
+    trace::record_to(&path).unwrap();
+
+    let mock: Arc<dyn DeviceBackend> = Arc::new(MockBackend::from_fixture(H100).unwrap());
+    let device = dev::open_device_with(H100, trace::wrap("h100", mock)).unwrap();
+    let gpu = GpuObject::new(device.into()).unwrap();
+    assert_eq!(gpu.query_cc_mode().unwrap(), CcMode::CC_MODE_ON);
+    gpu.write32(0x100, 0xdeadbeef).unwrap();
+
+    let replay = Arc::new(ReplayBackend::from_trace(&path, "h100").unwrap());
+    let device = dev::open_device_with(H100, replay.clone()).unwrap();
+    let gpu = GpuObject::new(device.into()).unwrap();
+    assert_eq!(replay.kind(), BackendKind::Replay);
+    assert_eq!(gpu.get_arch(), Arch::Hopper);
+    assert_eq!(gpu.query_cc_mode().unwrap(), CcMode::CC_MODE_ON);
+    gpu.write32(0x100, 0xdeadbeef).unwrap();
+    assert_eq!(replay.writes(), replay.recorded_writes());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn serde() {
+    let (gpu, _) = mock_gpu();
+
+    assert_eq!(
+        serde_json::to_string(&CcMode::CC_MODE_DEV_TOOLS).unwrap(),
+        "\"devtools\""
+    );
+    assert_eq!(
+        serde_json::from_str::<CcMode>("\"on\"").unwrap(),
+        CcMode::CC_MODE_ON
+    );
+    assert!(serde_json::from_str::<CcMode>("\"maybe\"").is_err());
+    assert_eq!(
+        serde_json::to_string(&gpu.get_arch()).unwrap(),
+        "\"hopper\""
+    );
+
+    let bar0 = serde_json::to_string(gpu.get_bar0()).unwrap();
+    let bar0 = serde_json::from_str::<dev::Bar>(&bar0).unwrap();
+    assert_eq!((bar0.size, bar0.is_64), (16 << 20, gpu.get_bar0().is_64));
+
+    let config = serde_json::to_value(gpu.get_device_handle().get_config()).unwrap();
+    assert_eq!(config["device"], 0x2330);
+    assert!(config.get("_pad0").is_none());
+}
+
+#[test]
+fn script() {
+    let (gpu, backend) = mock_gpu();
+    let script = Script::parse(
+        "# bring-up
+        read NV_PMC_BOOT_0
+        write 0x1700 0x1e0000
+        poll 0x200bc 0xff mask 0xff timeout 1
+        assert 0x1182cc 0x3 mask 0x3   # not in DevTools mode
+        sleep 1
+        read 0x2000000",
+    )
+    .unwrap();
+    assert!(script.writes());
+
+    let results = script.run(&gpu);
+    let passed = results.iter().map(|r| r.passed).collect::<Vec<_>>();
+    assert_eq!(passed, [true, true, true, false, true, false]);
+    assert_eq!(results[0].detail, "0x180000a1");
+    assert_eq!(results[3].command.line, 5);
+    assert_eq!(backend.writes(), vec![(0, 0x1700, 0x1e0000)]);
+
+    assert!(Script::parse("write 0x0").is_err());
+    assert!(Script::parse("assert 0x0 0x1 timeout 5").is_err());
+    assert!(Script::parse("read 0x0 0x1").is_err());
+    assert!(Script::parse("read NV_NO_SUCH_REGISTER").is_err());
+}
+
+#[test]
+fn register_names() {
+    assert_eq!(regs::resolve("NV_CC_MODE").unwrap(), NV_CC_MODE);
+    assert_eq!(regs::resolve("nv_pmc_boot_0").unwrap(), NV_PMC_BOOT_0);
+    assert_eq!(regs::resolve("0x1182cc").unwrap(), NV_CC_MODE);
+    assert!(regs::resolve("NV_TEST_SCRATCH").is_err());
+
+    // The alias of NV_CC_MODE does not rename it.
+    assert_eq!(regs::name_of(NV_CC_MODE).as_deref(), Some("NV_CC_MODE"));
+
+    regs::define("NV_TEST_SCRATCH", 0x1234);
+    assert_eq!(regs::resolve("NV_TEST_SCRATCH").unwrap(), 0x1234);
+    assert_eq!(regs::describe(0x1234), "NV_TEST_SCRATCH (0x1234)");
+    assert_eq!(regs::describe(0x1238), "0x1238");
+}
+
+#[test]
+fn config_dump() {
+    let backend = Arc::new(MockBackend::from_fixture(H100).unwrap());
+    // Chain an MSI-X capability after the PCIe one, and give the device extended capabilities.
+    backend.write_config(0x41, &[0x60]).unwrap();
+    backend
+        .write_config(
+            0x60,
+            &[0x11, 0x00, 0x07, 0x80, 0x00, 0, 0xb9, 0, 0x00, 0, 0xba, 0],
+        )
+        .unwrap();
+    backend
+        .write_config(0x100, &0x1402_0001u32.to_le_bytes())
+        .unwrap();
+    backend.write_config(0x104, &0x10u32.to_le_bytes()).unwrap();
+    backend
+        .write_config(0x140, &0x0001_0003u32.to_le_bytes())
+        .unwrap();
+    backend
+        .write_config(0x144, &0x1234_5678_9abc_def0u64.to_le_bytes())
+        .unwrap();
+
+    let device = dev::open_device_with(H100, backend).unwrap();
+    assert_eq!(device.caps().get(&(PCI_CAP_ID_MSIX as u8)), Some(&0x60));
+    assert_eq!(
+        device.find_ext_cap(PCI_EXT_CAP_ID_DSN).unwrap(),
+        Some(0x140)
+    );
+
+    let dump = device.dump_config().unwrap();
+    let field = |cap: &str, name: &str| {
+        let fields = match cap {
+            "header" => &dump.header,
+            _ => {
+                &dump
+                    .capabilities
+                    .iter()
+                    .find(|c| c.name == cap)
+                    .unwrap()
+                    .fields
+            }
+        };
+        fields
+            .iter()
+            .find(|f| f.name == name)
+            .unwrap()
+            .value
+            .clone()
+    };
+    assert_eq!(field("header", "vendor"), "0x10de");
+    assert_eq!(field("header", "bar0"), "0xfb000000");
+    assert_eq!(field("PCI Express", "type"), "endpoint");
+    assert_eq!(field("MSI-X", "enabled"), "true");
+    assert_eq!(field("MSI-X", "vectors"), "8");
+    assert_eq!(field("MSI-X", "table offset"), "0xb90000");
+    assert_eq!(
+        field("Advanced Error Reporting", "uncorrectable status"),
+        "0x00000010"
+    );
+    assert_eq!(field("Device Serial Number", "serial"), "123456789abcdef0");
+
+    let order = dump
+        .capabilities
+        .iter()
+        .map(|cap| (cap.extended, cap.offset))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        order,
+        [(false, 0x40), (false, 0x60), (true, 0x100), (true, 0x140)]
+    );
+
+    let json = serde_json::to_value(&dump).unwrap();
+    assert_eq!(json["capabilities"][2]["version"], 2);
+    assert!(json["capabilities"][0].get("version").is_none());
+}
+
+#[test]
+fn aer() {
+    let backend = Arc::new(MockBackend::from_fixture(H100).unwrap());
+    let (uncorrectable, correctable) = (
+        PciUncorrectableErrors::POISON_TLP | PciUncorrectableErrors::COMP_TIME,
+        PciCorrectableErrors::BAD_TLP,
+    );
+    backend
+        .write_config(0x100, &0x0002_0001u32.to_le_bytes())
+        .unwrap();
+    backend
+        .write_config(0x104, &uncorrectable.bits().to_le_bytes())
+        .unwrap();
+    backend
+        .write_config(
+            0x10c,
+            &PciUncorrectableErrors::POISON_TLP.bits().to_le_bytes(),
+        )
+        .unwrap();
+    backend
+        .write_config(0x110, &correctable.bits().to_le_bytes())
+        .unwrap();
+    backend.write_config(0x114, &[0; 4]).unwrap();
+
+    let device = dev::open_device_with(H100, backend.clone()).unwrap();
+    let status = device.aer_status().unwrap();
+    assert!(status.has_errors());
+    assert_eq!(status.uncorrectable, uncorrectable);
+    assert_eq!(status.fatal(), PciUncorrectableErrors::POISON_TLP);
+    assert_eq!(status.correctable, correctable);
+    assert_eq!(aer::bit_names(&status.correctable), ["BAD_TLP"]);
+
+    let json = serde_json::to_value(status).unwrap();
+    assert_eq!(
+        json["uncorrectable"],
+        serde_json::json!(["POISON_TLP", "COMP_TIME"])
+    );
+
+    // The status bits are write-1-to-clear, so clearing writes back the ones that are set.
+    assert_eq!(device.clear_aer().unwrap(), status);
+    let mut written = [0; 4];
+    backend.read_config(0x104, &mut written).unwrap();
+    assert_eq!(u32::from_le_bytes(written), uncorrectable.bits());
+
+    let (gpu, _) = mock_gpu();
+    assert!(matches!(
+        gpu.get_device_handle().aer_status(),
+        Err(NvTrustError::NotSupported { .. })
+    ));
+}
+
+#[test]
+fn msi() {
+    let backend = Arc::new(MockBackend::from_fixture(H100).unwrap());
+    // A 64-bit MSI with per-vector masking, then an MSI-X of 4 vectors with its table and PBA in
+    // BAR0.
+    backend.write_config(0x41, &[0x60]).unwrap();
+    backend
+        .write_config(0x60, &[0x05, 0x78, 0x81, 0x01, 0x00, 0x00, 0xe0, 0xfe])
+        .unwrap();
+    backend.write_config(0x68, &[0; 4]).unwrap();
+    backend
+        .write_config(0x6c, &0x4021u32.to_le_bytes())
+        .unwrap();
+    backend.write_config(0x70, &0x1u32.to_le_bytes()).unwrap();
+    backend.write_config(0x74, &0u32.to_le_bytes()).unwrap();
+    backend
+        .write_config(
+            0x78,
+            &[
+                0x11, 0x00, 0x03, 0x80, 0x00, 0x00, 0xb9, 0x00, 0x00, 0x00, 0xba, 0x00,
+            ],
+        )
+        .unwrap();
+    for i in 0..4u64 {
+        let entry = 0xb90000 + i * 16;
+        backend.set32(0, entry, 0xfee00000 + i as u32 * 0x1000);
+        backend.set32(0, entry + 8, 0x40 + i as u32);
+        backend.set32(0, entry + 12, (i == 2) as u32);
+    }
+    backend.set32(0, 0xba0000, 0b1000);
+
+    let device = dev::open_device_with(H100, backend).unwrap();
+    let msi = device.msi().unwrap().unwrap();
+    assert!(msi.enabled && msi.is_64);
+    assert_eq!(msi.address, 0xfee00000);
+    assert_eq!(msi.data, 0x4021);
+    assert_eq!(msi.mask, Some(1));
+
+    let msix = device.msix().unwrap().unwrap();
+    assert!(msix.enabled && !msix.function_mask);
+    assert_eq!((msix.table_bar, msix.table_offset), (0, 0xb90000));
+    assert_eq!(msix.vectors.len(), 4);
+    assert_eq!(msix.vectors[1].address, 0xfee01000);
+    assert_eq!(msix.vectors[1].data, 0x41);
+    let masked = msix.vectors.iter().map(|v| v.masked).collect::<Vec<_>>();
+    assert_eq!(masked, [false, false, true, false]);
+    let pending = msix.vectors.iter().map(|v| v.pending).collect::<Vec<_>>();
+    assert_eq!(pending, [false, false, false, true]);
+
+    let (gpu, _) = mock_gpu();
+    assert_eq!(gpu.get_device_handle().msix().unwrap(), None);
+}
+
+#[test]
+fn power_state() {
+    let backend = Arc::new(MockBackend::from_fixture(H100).unwrap());
+    // A PM capability in D0 with PME enabled, after the PCIe one.
+    backend.write_config(0x41, &[0x60]).unwrap();
+    backend
+        .write_config(0x60, &[0x01, 0x00, 0x03, 0x00, 0x00, 0x01, 0x00, 0x00])
+        .unwrap();
+
+    let device = dev::open_device_with(H100, backend.clone()).unwrap();
+    assert_eq!(device.power_state().unwrap(), PowerState::D0);
+
+    device.set_power_state(PowerState::D3Hot).unwrap();
+    assert_eq!(device.power_state().unwrap(), PowerState::D3Hot);
+    let mut ctrl = [0; 2];
+    backend.read_config(0x64, &mut ctrl).unwrap();
+    assert_eq!(u16::from_le_bytes(ctrl), 0x0103);
+
+    device.set_power_state(PowerState::D0).unwrap();
+    assert_eq!(device.power_state().unwrap(), PowerState::D0);
+    assert_eq!(PowerState::D3Hot.to_string(), "D3hot");
+}
+
+#[test]
+fn link_status() {
+    let (gpu, backend) = mock_gpu();
+    // A Gen4 x16 link with ASPM L1, trained down to Gen1.
+    backend.write_config(0x4c, &0x904u32.to_le_bytes()).unwrap();
+    backend
+        .write_config(0x50, &[0x02, 0x00, 0x01, 0x21])
+        .unwrap();
+
+    let link = gpu.get_device_handle().link_status().unwrap();
+    assert_eq!((link.speed, link.max_speed), (LinkSpeed(1), LinkSpeed(4)));
+    assert_eq!((link.width, link.max_width), (16, 16));
+    assert!(!link.l0s_supported && link.l1_supported && link.l1_enabled);
+    assert!(link.dll_active && !link.training);
+    assert!(link.is_degraded());
+    assert_eq!(link.max_speed.to_string(), "16GT/s");
+}
+
+#[test]
+fn retrain_link() {
+    // A Gen5 x16 downstream port of another vendor, whose link trained to Gen1.
+    let backend = Arc::new(MockBackend::from_fixture(H100).unwrap());
+    backend.write_config(0, &[0x86, 0x80, 0x00, 0x00]).unwrap();
+    backend.write_config(0x42, &[0x62, 0x00]).unwrap();
+    backend.write_config(0x4c, &0x905u32.to_le_bytes()).unwrap();
+    backend
+        .write_config(0x50, &[0x00, 0x00, 0x01, 0x21])
+        .unwrap();
+
+    assert!(dev::open_device_with(H100, backend.clone()).is_err());
+    let port = dev::open_port_with(H100, backend.clone()).unwrap();
+    let (before, after) = port.retrain_link().unwrap();
+    assert_eq!(before.speed, LinkSpeed(1));
+    assert_eq!(after.max_speed, LinkSpeed(5));
+
+    let mut ctrl = [0; 2];
+    backend.read_config(0x50, &mut ctrl).unwrap();
+    assert_eq!(
+        u16::from_le_bytes(ctrl) & PCI_EXP_LNKCTL_RL,
+        PCI_EXP_LNKCTL_RL
+    );
+
+    // A Gen4 GPU caps what the link can reach.
+    let (gpu, gpu_backend) = mock_gpu();
+    gpu_backend
+        .write_config(0x4c, &0x104u32.to_le_bytes())
+        .unwrap();
+    let device = gpu.get_device_handle().link_status().unwrap();
+    assert_eq!(after.limited_by(&device).max_speed, LinkSpeed(4));
+    assert_eq!(gpu.get_device_handle().upstream_port_path(), None);
+}
+
+#[test]
+fn rebar() {
+    // The resize goes through sysfs, so give the device a directory of its own.
+    let dir = std::env::temp_dir().join(format!("nvtrust-rebar-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for file in ["config", "resource", "bar0"] {
+        std::fs::copy(format!("{H100}/{file}"), dir.join(file)).unwrap();
+    }
+    std::fs::write(dir.join("resource1_resize"), "").unwrap();
+
+    // BAR1 at 256M, resizable to 64G, and BAR3 at 32M.
+    let backend = Arc::new(MockBackend::from_fixture(&dir).unwrap());
+    backend
+        .write_config(0x100, &0x0001_0015u32.to_le_bytes())
+        .unwrap();
+    for (offset, value) in [
+        (0x104, 1u32 << 12 | 1 << 20),
+        (0x108, 1 | 2 << 5 | 8 << 8),
+        (0x10c, 1 << 9),
+        (0x110, 3 | 5 << 8),
+    ] {
+        backend.write_config(offset, &value.to_le_bytes()).unwrap();
+    }
+
+    let device = dev::open_device_with(dir.to_str().unwrap(), backend).unwrap();
+    let bars = device.resizable_bars().unwrap();
+    assert_eq!(bars.len(), 2);
+    assert_eq!((bars[0].bar, bars[0].size), (1, 256 << 20));
+    assert_eq!(bars[0].supported, [256 << 20, 64 << 30]);
+    assert_eq!(
+        (bars[1].bar, rebar::format_size(bars[1].size)),
+        (3, "32M".to_string())
+    );
+
+    assert!(matches!(
+        device.resize_bar(1, 1 << 30),
+        Err(NvTrustError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        device.resize_bar(0, 64 << 30),
+        Err(NvTrustError::NotSupported { .. })
+    ));
+    let bar1 = device.resize_bar(1, 64 << 30).unwrap();
+    assert_eq!(bar1.addr, 0x38000000000);
+    assert_eq!(
+        std::fs::read_to_string(dir.join("resource1_resize")).unwrap(),
+        "16"
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// Lay out a sysfs tree of a downstream port with the GPU below it, returning the root, the port
+/// and the GPU; the port goes through its config file, with `extended` after the first 256 bytes,
+/// as on a real system.
+fn port_tree(name: &str, extended: &[u8]) -> (PathBuf, PathBuf, PathBuf) {
+    let root = std::env::temp_dir().join(format!("nvtrust-{name}-{}", std::process::id()));
+    let port = root.join("pci0000:00/0000:00:01.0");
+    let gpu_dir = port.join("0000:01:00.0");
+    std::fs::create_dir_all(&gpu_dir).unwrap();
+    for file in ["config", "resource", "bar0"] {
+        std::fs::copy(format!("{H100}/{file}"), gpu_dir.join(file)).unwrap();
+    }
+
+    let mut config = vec![0u8; 256];
+    config[..4].copy_from_slice(&[0x86, 0x80, 0x34, 0x12]);
+    config[0x0e] = 0x01;
+    config[0x34] = 0x40;
+    config[0x3e] = 0x03;
+    config[0x40..0x44].copy_from_slice(&[0x10, 0x00, 0x62, 0x00]);
+    config[0x4c..0x50].copy_from_slice(&0x905u32.to_le_bytes());
+    config[0x52..0x54].copy_from_slice(&[0x04, 0x21]);
+    config.extend_from_slice(extended);
+    std::fs::write(port.join("config"), config).unwrap();
+    std::fs::write(port.join("resource"), "").unwrap();
+
+    (root, port, gpu_dir)
+}
+
+#[test]
+fn secondary_bus_reset() {
+    let (root, port, gpu_dir) = port_tree("sbr", &[]);
+
+    let backend = Arc::new(MockBackend::from_fixture(&gpu_dir).unwrap());
+    let device = dev::open_device_with(gpu_dir.to_str().unwrap(), backend.clone()).unwrap();
+    assert_eq!(
+        device.upstream_port_path(),
+        Some(port.canonicalize().unwrap())
+    );
+
+    let topology = device.topology().unwrap();
+    assert_eq!(topology.len(), 1);
+    assert_eq!(topology[0].bdf, "0000:00:01.0");
+    assert_eq!(topology[0].kind, "downstream port");
+    assert_eq!(topology[0].link.unwrap().speed, LinkSpeed(4));
+
+    let gpu = GpuObject::new(device.into()).unwrap();
+    gpu.secondary_bus_reset().unwrap();
+    // The reset bit is cleared again and the rest of the bridge control is kept.
+    let config = std::fs::read(port.join("config")).unwrap();
+    assert_eq!(config[0x3e], 0x03);
+    assert_eq!(backend.resets(), 0);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn function_level_reset() {
+    let (gpu, backend) = mock_gpu();
+    assert!(matches!(
+        gpu.function_level_reset(),
+        Err(NvTrustError::NotSupported { .. })
+    ));
+
+    backend
+        .write_config(0x44, &PCI_EXP_DEVCAP_FLR.to_le_bytes())
+        .unwrap();
+    let mut command = [0; 2];
+    backend.read_config(0x04, &mut command).unwrap();
+
+    // The mock keeps the FLR bit that hardware clears, so the restore is what clears it.
+    gpu.function_level_reset().unwrap();
+    let mut ctrl = [0; 2];
+    backend.read_config(0x48, &mut ctrl).unwrap();
+    assert_eq!(u16::from_le_bytes(ctrl), 0);
+    // The command register is restored after the reset disabled it.
+    let mut restored = [0; 2];
+    backend.read_config(0x04, &mut restored).unwrap();
+    assert_eq!(restored, command);
+    assert_eq!(backend.resets(), 0);
+
+    gpu.get_device_handle().flr().unwrap();
+    backend.read_config(0x48, &mut ctrl).unwrap();
+    assert_ne!(u16::from_le_bytes(ctrl) & PCI_EXP_DEVCTL_BCR_FLR, 0);
+    backend.read_config(0x04, &mut restored).unwrap();
+    assert_eq!(u16::from_le_bytes(restored), PCI_COMMAND_INTX_DISABLE);
+}
+
+#[test]
+fn full_reset() {
+    let (gpu, backend) = mock_gpu();
+    // Nothing is bound to the fixture, so nothing is bound again.
+    let saved = gpu.get_device_handle().read_config_space().unwrap();
+
+    assert_eq!(gpu.full_reset().unwrap(), None);
+    assert_eq!(backend.resets(), 1);
+    assert_eq!(gpu.get_device_handle().read_config_space().unwrap(), saved);
+}
+
+#[test]
+fn bind_driver() {
+    let (gpu, _) = mock_gpu();
+    let device = gpu.get_device_handle();
+    assert_eq!(device.unbind_driver().unwrap(), None);
+    assert!(matches!(
+        device.bind_driver("nvtrust-no-such-driver"),
+        Err(NvTrustError::NotSupported { .. })
+    ));
+}
+
+#[test]
+fn enable_memory_space() {
+    let backend = Arc::new(MockBackend::from_fixture(H100).unwrap());
+    // Memory space decoding off, as after an unbind.
+    backend.write_config(0x04, &[0x04, 0x00]).unwrap();
+    let device = dev::open_device_with(H100, backend.clone()).unwrap();
+    let gpu = GpuObject::new(device.into()).unwrap();
+
+    let mut command = [0; 2];
+    backend.read_config(0x04, &mut command).unwrap();
+    assert_eq!(u16::from_le_bytes(command), 0x06);
+
+    // It is disabled again once the GPU is dropped.
+    drop(gpu);
+    backend.read_config(0x04, &mut command).unwrap();
+    assert_eq!(u16::from_le_bytes(command), 0x04);
+}
+
+#[test]
+fn iommu_group() {
+    // A GPU sharing its group with an audio function that snd_hda_intel holds.
+    let root = std::env::temp_dir().join(format!("nvtrust-iommu-{}", std::process::id()));
+    let (gpu_dir, audio) = (root.join("0000:01:00.0"), root.join("0000:01:00.1"));
+    let group = root.join("iommu_groups/7");
+    std::fs::create_dir_all(group.join("devices")).unwrap();
+    std::fs::create_dir_all(root.join("drivers/snd_hda_intel")).unwrap();
+    std::fs::create_dir_all(&audio).unwrap();
+    std::fs::create_dir_all(&gpu_dir).unwrap();
+    for file in ["config", "resource", "bar0"] {
+        std::fs::copy(format!("{H100}/{file}"), gpu_dir.join(file)).unwrap();
+    }
+    for (dir, class) in [(&gpu_dir, "0x030200"), (&audio, "0x040300")] {
+        std::fs::write(dir.join("vendor"), "0x10de\n").unwrap();
+        std::fs::write(dir.join("device"), "0x2331\n").unwrap();
+        std::fs::write(dir.join("class"), format!("{class}\n")).unwrap();
+        std::os::unix::fs::symlink(&group, dir.join("iommu_group")).unwrap();
+        std::os::unix::fs::symlink(dir, group.join("devices").join(dir.file_name().unwrap()))
+            .unwrap();
+    }
+    std::fs::write(group.join("type"), "DMA-FQ\n").unwrap();
+    std::os::unix::fs::symlink(root.join("drivers/snd_hda_intel"), audio.join("driver")).unwrap();
+
+    let backend = Arc::new(MockBackend::from_fixture(H100).unwrap());
+    let device = dev::open_device_with(gpu_dir.to_str().unwrap(), backend).unwrap();
+    let iommu = device.iommu_group().unwrap();
+    assert_eq!((iommu.group, iommu.kind.as_deref()), (7, Some("DMA-FQ")));
+    assert_eq!(iommu.devices.len(), 2);
+    assert_eq!(iommu.devices[0].bdf, "0000:01:00.0");
+    assert_eq!(iommu.devices[1].driver.as_deref(), Some("snd_hda_intel"));
+    assert!(!iommu.is_viable());
+
+    std::fs::remove_file(audio.join("driver")).unwrap();
+    assert!(device.iommu_group().unwrap().is_viable());
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn acs() {
+    // An ACS capability with request and completion redirect but no source validation.
+    let mut extended = vec![0x0d, 0x00, 0x01, 0x00, 0x1f, 0x00, 0x0c, 0x00];
+    let (root, _, gpu_dir) = port_tree("acs", &extended);
+    let backend = Arc::new(MockBackend::from_fixture(&gpu_dir).unwrap());
+    let device = dev::open_device_with(gpu_dir.to_str().unwrap(), backend).unwrap();
+
+    let ports = device.insecure_ports().unwrap();
+    assert_eq!(ports.len(), 1);
+    assert_eq!(ports[0].acs, Some(0x0c));
+    assert_eq!(
+        ports[0].missing_acs(),
+        ["source validation", "upstream forwarding"]
+    );
+
+    // Turn on the rest.
+    extended[6] = 0x1d;
+    port_tree("acs", &extended);
+    assert!(device.insecure_ports().unwrap().is_empty());
+    assert_eq!(device.topology().unwrap()[0].acs, Some(0x1d));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn topology_tree() {
+    let (root, _, gpu_dir) = port_tree("tree", &[]);
+    let tree = topology::tree_of(&[&gpu_dir]).unwrap();
+
+    assert_eq!(tree.len(), 1);
+    assert_eq!(tree[0].port.bdf, "0000:00:01.0");
+    assert!(tree[0].port.is_insecure() && !tree[0].port.ide);
+    assert_eq!(tree[0].children.len(), 1);
+    let gpu = &tree[0].children[0];
+    assert_eq!(gpu.port.bdf, "0000:01:00.0");
+    assert_eq!(gpu.port.vendor, NVIDIA_VENDOR_ID);
+    assert!(gpu.name.is_some() && gpu.children.is_empty());
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn device_lock() {
+    let dir = std::env::temp_dir().join(format!("nvtrust-lock-{}", std::process::id()));
+    let lock = DeviceLock::acquire_in(&dir, "0000:01:00.0").unwrap();
+
+    match DeviceLock::acquire_in(&dir, "0000:01:00.0") {
+        Err(NvTrustError::Locked { device, pid }) => {
+            assert_eq!(device, "0000:01:00.0");
+            assert_eq!(pid, Some(std::process::id()));
+        }
+        other => panic!("locked twice: {other:?}"),
+    }
+    // Other GPUs are not affected, and the lock goes with its holder.
+    DeviceLock::acquire_in(&dir, "0000:02:00.0").unwrap();
+    drop(lock);
+    DeviceLock::acquire_in(&dir, "0000:01:00.0").unwrap();
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn parallel_reset() {
+    let gpus = (0..4).map(|_| mock_gpu()).collect::<Vec<_>>();
+
+    // Every GPU is reset once, and the results come back in the order of the GPUs.
+    let bdfs = dev::par_map(&gpus, |(gpu, _)| {
+        gpu.sysfs_reset().unwrap();
+        gpu.get_bdf().to_string()
+    });
+    assert_eq!(bdfs.len(), gpus.len());
+    for ((gpu, backend), bdf) in gpus.iter().zip(bdfs) {
+        assert_eq!(gpu.get_bdf(), bdf);
+        assert_eq!(backend.resets(), 1);
+    }
+}
+
+#[test]
+fn wait_for_boot() {
+    let (gpu, _) = mock_gpu();
+    let timeout = std::time::Duration::from_millis(50);
+
+    // The FSP of a Hopper GPU sets the scratch register once it is done.
+    gpu.write32(NV_THERM_I2CS_SCRATCH, 0xff).unwrap();
+    gpu.wait_for_boot().unwrap();
+
+    gpu.write32(NV_THERM_I2CS_SCRATCH, 0x3).unwrap();
+    match gpu.poll_with_timeout("boot", NV_THERM_I2CS_SCRATCH, 0xffffffff, 0xff, timeout) {
+        Err(NvTrustError::Timeout(what)) => assert!(what.contains("0x00000003"), "{what}"),
+        other => panic!("booted at 0x3: {other:?}"),
+    }
+    let value = gpu
+        .poll_with_timeout("boot", NV_THERM_I2CS_SCRATCH, 0x1, 0x1, timeout)
+        .unwrap();
+    assert_eq!(value, 0x3);
+}
+
+#[test]
+fn mmio_error() {
+    let (gpu, backend) = mock_gpu();
+
+    gpu.write32(0x100, 0xbadf1201).unwrap();
+    match gpu.checked_read32(0x100) {
+        Err(NvTrustError::MmioError {
+            offset,
+            code,
+            cause,
+        }) => {
+            assert_eq!((offset, code), (0x100, 0xbadf1201));
+            assert!(cause.contains("NV_PMC_ENABLE"), "{cause}");
+        }
+        other => panic!("read an error code as {other:?}"),
+    }
+    assert_eq!(
+        NvidiaMmioErrorCode::decode(0xbad00142).map(|e| e.cause()),
+        Some(NvidiaMmioErrorCode::NONEXISTENT_REG.cause())
+    );
+    assert_eq!(
+        NvidiaMmioErrorCode::decode(0xbadf7777).map(|e| e.bits()),
+        Some(NvidiaMmioErrorCode::OTHER_ERROR.bits())
+    );
+    assert!(NvidiaMmioErrorCode::decode(0x00000001).is_none());
+
+    // A firewalled CC register must not read as CC off.
+    gpu.write32(NV_CC_MODE, 0xbadf5040).unwrap();
+    assert!(matches!(
+        gpu.query_cc_mode(),
+        Err(NvTrustError::MmioError { .. })
+    ));
+    assert!(is_mmio_error(0xbad00100) && is_mmio_error(0xbad0ac00));
+    assert!(!is_mmio_error(0xbad10000));
+
+    // A poll takes an error code for not ready yet, as while the GPU comes out of reset.
+    let register = NV_PGC6_AON_SECURE_SCRATCH_GROUP_05;
+    gpu.write32(register, 0xbad00100).unwrap();
+    let setter = {
+        let backend = backend.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            backend.set32(0, register, 0xff);
+        })
+    };
+    let value = gpu
+        .poll_with_timeout(
+            "GFW boot",
+            register,
+            0xff,
+            0xff,
+            std::time::Duration::from_secs(5),
+        )
+        .unwrap();
+    assert_eq!(value, 0xff);
+    setter.join().unwrap();
+
+    // It fails with the error code only if the register never reads a value in time, and a
+    // boot status does not take the error code for a value either.
+    gpu.write32(register, 0xbad00100).unwrap();
+    let timeout = std::time::Duration::from_millis(50);
+    assert!(matches!(
+        gpu.poll_with_timeout("GFW boot", register, 0xff, 0x00, timeout),
+        Err(NvTrustError::MmioError {
+            code: 0xbad00100,
+            ..
+        })
+    ));
+    assert!(matches!(
+        gpu.query_boot_status(),
+        Err(NvTrustError::MmioError { .. })
+    ));
+
+    // A blocked scratch register is reported as such.
+    let scratch = gpu.dump_scratch().unwrap();
+    let blocked = scratch
+        .iter()
+        .find(|scratch| scratch.offset == NV_PGC6_AON_SECURE_SCRATCH_GROUP_05)
+        .unwrap();
+    assert_eq!(blocked.value, None);
+    assert!(blocked.error.is_some());
+}
+
+#[test]
+fn boot_status() {
+    let (gpu, _) = mock_gpu();
+
+    gpu.write32(NV_PGC6_AON_SECURE_SCRATCH_GROUP_05, 0xff)
+        .unwrap();
+    gpu.write32(NV_THERM_I2CS_SCRATCH, 0xff).unwrap();
+    let status = gpu.query_boot_status().unwrap();
+    assert!(status.devinit_done());
+    assert_eq!(status.secure_boot_done(), Some(true));
+    assert!(status.complete);
+
+    // The FSP stopped halfway and left an error code behind.
+    gpu.write32(NV_THERM_I2CS_SCRATCH, 0x3).unwrap();
+    gpu.write32(NV_FSP_SCRATCH_GROUP_2, 0x1234).unwrap();
+    let status = gpu.query_boot_status().unwrap();
+    assert_eq!(status.secure_boot_done(), Some(false));
+    assert!(status.fsp_failed());
+    assert!(!status.complete);
+}
+
+#[test]
+fn gsp_status() {
+    let (gpu, _) = mock_gpu();
+
+    gpu.write32(NV_GSP_BASE + NV_RISCV_CPUCTL, 0).unwrap();
+    gpu.write32(NV_GSP_BASE + NV_FALCON_MAILBOX0, 0).unwrap();
+    gpu.write32(NV_GSP_BASE + NV_FALCON_MAILBOX1, 0).unwrap();
+    let gsp = gpu.query_gsp().unwrap();
+    assert!(!gsp.falcon.is_running());
+    assert!(!gsp.is_loaded());
+
+    // A driver booted GSP-RM and left its init arguments behind.
+    gpu.write32(NV_GSP_BASE + NV_RISCV_CPUCTL, NV_RISCV_CPUCTL_ACTIVE)
+        .unwrap();
+    gpu.write32(NV_GSP_BASE + NV_FALCON_MAILBOX0, 0x1000)
+        .unwrap();
+    gpu.write32(NV_GSP_BASE + NV_FALCON_MAILBOX1, 0x2).unwrap();
+    let gsp = gpu.query_gsp().unwrap();
+    assert!(gsp.falcon.is_running());
+    assert_eq!(gsp.init_args, 0x2_0000_1000);
+
+    gpu.write32(NV_GSP_BASE + NV_RISCV_CPUCTL, NV_RISCV_CPUCTL_HALTED)
+        .unwrap();
+    let gsp = gpu.query_gsp().unwrap();
+    assert!(!gsp.falcon.is_running());
+    assert!(gsp.falcon.is_halted());
+}
+
+/// Answer PRC knob reads and writes the way the FSP does, from the given knob values.
+fn prc_handler(knobs: Arc<Mutex<HashMap<u16, u16>>>) -> FspHandler {
+    Box::new(move |nvdm_type, payload| {
+        let knob = payload[1] as u16;
+        let mut knobs = knobs.lock().unwrap();
+        let data = match payload[0] {
+            PRC_SUBMSG_ID_KNOB_READ => {
+                vec![knob as u32 | (*knobs.get(&knob).unwrap_or(&0) as u32) << 16]
+            }
+            PRC_SUBMSG_ID_KNOB_WRITE => {
+                knobs.insert(knob, (payload[1] >> 16) as u16);
+                vec![]
+            }
+            _ => return (NVDM_TYPE_FSP_RESPONSE, vec![0, nvdm_type as u32, 0x1]),
+        };
+
+        let mut response = vec![0, nvdm_type as u32, 0];
+        response.extend(data);
+        (NVDM_TYPE_FSP_RESPONSE, response)
+    })
+}
+
+#[test]
+fn fsp_prc_knobs() {
+    let (gpu, backend) = mock_gpu();
+    let knobs = Arc::new(Mutex::new(HashMap::new()));
+    backend.emulate_fsp(prc_handler(knobs.clone()));
+
+    // The dev mode knob is off already, so it is not written.
+    gpu.set_cc_mode(CcMode::CC_MODE_ON).unwrap();
+    assert_eq!(gpu.pending_cc_mode().unwrap(), CcMode::CC_MODE_ON);
+    assert_eq!(
+        *knobs.lock().unwrap(),
+        HashMap::from([
+            (PrcKnob::CcMode as u16, 1),
+            (PrcKnob::Bar0Decoupler as u16, 1)
+        ])
+    );
+
+    // Only the knobs that change are written.
+    let writes = |backend: &MockBackend| {
+        backend
+            .fsp_messages()
+            .iter()
+            .filter(|(_, payload)| payload[0] == PRC_SUBMSG_ID_KNOB_WRITE)
+            .count()
+    };
+    let before = writes(&backend);
+    gpu.set_cc_mode(CcMode::CC_MODE_DEV_TOOLS).unwrap();
+    assert_eq!(writes(&backend) - before, 2);
+    assert_eq!(gpu.pending_cc_mode().unwrap(), CcMode::CC_MODE_DEV_TOOLS);
+}
+
+#[test]
+fn fsp_rpc() {
+    let (gpu, backend) = mock_gpu();
+    backend.emulate_fsp(Box::new(|nvdm_type, payload| match payload.first() {
+        Some(0xdead) => (NVDM_TYPE_FSP_RESPONSE, vec![0, nvdm_type as u32, 0x5]),
+        _ => {
+            let mut response = vec![0, nvdm_type as u32, 0];
+            response.extend_from_slice(payload);
+            (NVDM_TYPE_FSP_RESPONSE, response)
+        }
+    }));
+    let rpc = FspRpc::new(&gpu, NV_FSP_CHANNEL).unwrap();
+
+    // A message larger than the EMEM window goes in several packets, both ways.
+    let payload = (0..600).collect::<Vec<u32>>();
+    assert_eq!(rpc.command(0x20, &payload).unwrap(), payload);
+    assert_eq!(backend.fsp_messages(), vec![(0x20, payload)]);
+
+    match rpc.command(0x20, &[0xdead]) {
+        Err(NvTrustError::FspTransaction { source, dump }) => {
+            assert!(source.to_string().contains("error 0x5"), "{source}");
+            assert!(dump.contains("FSP_QUEUE_HEAD"), "{dump}");
+        }
+        other => panic!("the FSP error got lost: {other:?}"),
+    }
+
+    // Nothing answers a message nobody sent.
+    let rpc = rpc.with_timeout(std::time::Duration::from_millis(20));
+    assert!(matches!(rpc.receive(), Err(NvTrustError::Timeout(_))));
+}
+
+#[test]
+fn prc_knobs() {
+    let (gpu, backend) = mock_gpu();
+    let knobs = Arc::new(Mutex::new(HashMap::from([
+        (PrcKnob::CcMode as u16, 0),
+        (PrcKnob::CcModeAllowInband as u16, 1),
+    ])));
+    // Firmware without PPCIe rejects its knobs.
+    let prc = prc_handler(knobs);
+    backend.emulate_fsp(Box::new(move |nvdm_type, payload| {
+        match payload[1] as u16 {
+            knob if knob == PrcKnob::Ppcie as u16 || knob == PrcKnob::PpcieAllowInband as u16 => {
+                (NVDM_TYPE_FSP_RESPONSE, vec![0, nvdm_type as u32, 0x3])
+            }
+            _ => prc(nvdm_type, payload),
+        }
+    }));
+
+    // The fixture is in CC mode, which the knob turns off upon the next reset.
+    let states = gpu.query_prc_knobs().unwrap();
+    assert_eq!(states.len(), PrcKnob::ALL.len());
+    let state = |knob| *states.iter().find(|state| state.knob == knob).unwrap();
+    assert_eq!(state(PrcKnob::CcMode).current, Some(1));
+    assert_eq!(state(PrcKnob::CcMode).pending, Some(0));
+    assert!(state(PrcKnob::CcMode).needs_reset());
+    assert_eq!(state(PrcKnob::CcModeAllowInband).pending, Some(1));
+    assert!(!state(PrcKnob::CcModeAllowInband).needs_reset());
+    assert_eq!(state(PrcKnob::Ppcie).pending, None);
+}
+
+#[test]
+fn set_prc_knob() {
+    let (gpu, backend) = mock_gpu();
+    let knobs = Arc::new(Mutex::new(HashMap::new()));
+    backend.emulate_fsp(prc_handler(knobs.clone()));
+
+    gpu.set_prc_knob("cc_dev_mode".parse().unwrap(), 1).unwrap();
+    assert_eq!(knobs.lock().unwrap()[&(PrcKnob::CcDevMode as u16)], 1);
+
+    // Nothing is sent for a knob or value that is not allowed.
+    let sent = backend.fsp_messages().len();
+    for (knob, value) in [(PrcKnob::CcMode, 2), (PrcKnob::CcModeAllowInband, 1)] {
+        assert!(matches!(
+            gpu.set_prc_knob(knob, value),
+            Err(NvTrustError::InvalidArgument(_))
+        ));
+    }
+    assert_eq!(backend.fsp_messages().len(), sent);
+    assert!("cc_mod".parse::<PrcKnob>().is_err());
+}
+
+#[test]
+fn ppcie_mode() {
+    let (gpu, backend) = mock_gpu();
+    let knobs = Arc::new(Mutex::new(HashMap::from([
+        (PrcKnob::CcMode as u16, 1),
+        (PrcKnob::Bar0Decoupler as u16, 1),
+    ])));
+    backend.emulate_fsp(prc_handler(knobs.clone()));
+
+    // PPCIe replaces the CC mode the fixture is in.
+    assert!(!gpu.query_ppcie_mode().unwrap());
+    txn::set_ppcie_mode_all(std::slice::from_ref(&gpu), true).unwrap();
+    assert!(gpu.pending_ppcie_mode().unwrap());
+    {
+        let knobs = knobs.lock().unwrap();
+        assert_eq!(knobs[&(PrcKnob::CcMode as u16)], 0);
+        assert_eq!(knobs[&(PrcKnob::Bar0Decoupler as u16)], 0);
+        assert_eq!(knobs[&(PrcKnob::Ppcie as u16)], 1);
+    }
+
+    let register = gpu.regs().cc_mode(false).unwrap_or(NV_CC_MODE);
+    backend.set32(0, register, NV_CC_MODE_PPCIE);
+    assert!(gpu.query_ppcie_mode().unwrap());
+}
+
+#[test]
+fn firewall() {
+    let (gpu, backend) = mock_gpu();
+    let knobs = Arc::new(Mutex::new(HashMap::from([(
+        PrcKnob::Bar0Decoupler as u16,
+        1,
+    )])));
+    backend.emulate_fsp(prc_handler(knobs));
+    backend.set32(0, NV_FUSE_OPT_PDI_0, 0xbadf5040);
+
+    let status = gpu.query_firewall().unwrap();
+    assert!(status.engaged);
+    assert_eq!(status.pending, Some(true));
+    let blocked = status.blocked().collect::<Vec<_>>();
+    assert_eq!(blocked.len(), 1);
+    assert!(blocked[0].contains(NV_FUSE_OPT_PDI_0));
+
+    // The firewall is blamed, and a blocked write is not even attempted.
+    assert!(matches!(
+        gpu.firewalled_read32(NV_FUSE_OPT_PDI_0),
+        Err(NvTrustError::Firewalled { .. })
+    ));
+    let writes = backend.writes().len();
+    assert!(gpu.firewalled_write32(NV_FUSE_OPT_PDI_0, 0).is_err());
+    assert_eq!(backend.writes().len(), writes);
+
+    // Without the decoupler, the error is what the GPU reported.
+    backend.set32(0, NV_CC_MODE, CcMode::CC_MODE_DEV_TOOLS.bits() as u32);
+    assert!(matches!(
+        gpu.firewalled_read32(NV_FUSE_OPT_PDI_0),
+        Err(NvTrustError::MmioError { .. })
+    ));
+}